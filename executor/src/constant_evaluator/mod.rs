@@ -1,12 +1,17 @@
-use std::{collections::HashMap, fmt::Display, rc::Rc};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Display,
+    rc::Rc,
+};
 
 use itertools::Itertools;
 use powdr_ast::{
     analyzed::{
         types::{ArrayType, Type, TypedExpression},
-        Analyzed, Expression, FunctionValueDefinition, PolyID,
+        Analyzed, Expression, FunctionValueDefinition, PolyID, PolynomialType, Reference,
+        SymbolKind,
     },
-    parsed::IndexAccess,
+    parsed::{utils::expr_any, IndexAccess},
 };
 use powdr_number::{DegreeType, FieldElement};
 use powdr_pil_analyzer::evaluator::{self, Custom, EvalError, SymbolLookup, Value};
@@ -45,6 +50,89 @@ pub fn generate<T: FieldElement>(analyzed: &Analyzed<T>) -> Vec<(String, Vec<T>)
         .collect::<Vec<_>>()
 }
 
+/// Like [generate], but evaluates the columns for the given degree and computes
+/// columns that do not depend on other fixed columns concurrently instead of one
+/// after the other.
+/// Columns defined by arrays and columns referencing other fixed columns (directly
+/// or through other definitions) are evaluated afterwards in source order, with
+/// all previously computed columns available.
+/// The result is identical to that of [generate] and can be passed to
+/// [crate::witgen::WitnessGenerator::new].
+pub fn evaluate_fixed_columns_parallel<T: FieldElement>(
+    analyzed: &Analyzed<T>,
+    degree: DegreeType,
+) -> Vec<(String, Vec<T>)> {
+    let columns = analyzed
+        .constant_polys_in_source_order()
+        .into_iter()
+        .filter_map(|(poly, value)| value.as_ref().map(|value| (poly, value)))
+        .flat_map(|(poly, value)| {
+            poly.array_elements()
+                .enumerate()
+                .map(move |(index, (name, id))| {
+                    let index = poly.is_array().then_some(index as u64);
+                    (name, id, value, index)
+                })
+        })
+        .collect::<Vec<_>>();
+    let (independent, dependent): (Vec<_>, Vec<_>) = columns
+        .into_iter()
+        .partition(|(_, _, value, _)| is_row_independent(analyzed, value));
+
+    let no_columns = HashMap::new();
+    let mut computed_columns = independent
+        .into_par_iter()
+        .map(|(name, id, value, index)| {
+            let values = generate_values(analyzed, degree, &name, value, index, &no_columns);
+            (name, (id, values))
+        })
+        .collect::<HashMap<_, _>>();
+    for (name, id, value, index) in dependent {
+        let values = generate_values(analyzed, degree, &name, value, index, &computed_columns);
+        assert!(computed_columns.insert(name, (id, values)).is_none());
+    }
+
+    computed_columns
+        .into_iter()
+        .sorted_by_key(|(_, (id, _))| *id)
+        .map(|(name, (_, values))| (name, values))
+        .collect::<Vec<_>>()
+}
+
+/// Returns true if the column is defined by an expression that does not reference
+/// any fixed column, also not through other definitions.
+fn is_row_independent<T>(analyzed: &Analyzed<T>, body: &FunctionValueDefinition<T>) -> bool {
+    fn references_fixed_column<T>(
+        analyzed: &Analyzed<T>,
+        e: &Expression<T>,
+        visited: &mut HashSet<String>,
+    ) -> bool {
+        expr_any(e, |e| {
+            let Expression::Reference(Reference::Poly(reference)) = e else {
+                return false;
+            };
+            if !visited.insert(reference.name.clone()) {
+                return false;
+            }
+            match analyzed.definitions.get(&reference.name) {
+                Some((symbol, _)) if symbol.kind == SymbolKind::Poly(PolynomialType::Constant) => {
+                    true
+                }
+                Some((_, Some(FunctionValueDefinition::Expression(TypedExpression { e, .. })))) => {
+                    references_fixed_column(analyzed, e, visited)
+                }
+                _ => false,
+            }
+        })
+    }
+    match body {
+        FunctionValueDefinition::Expression(TypedExpression { e, .. }) => {
+            !references_fixed_column(analyzed, e, &mut HashSet::new())
+        }
+        _ => false,
+    }
+}
+
 fn generate_values<T: FieldElement>(
     analyzed: &Analyzed<T>,
     degree: DegreeType,
@@ -628,4 +716,39 @@ mod test {
             ("F.y[1]".to_string(), convert([1, 2, 3, 4].to_vec()))
         );
     }
+
+    #[test]
+    pub fn parallel_matches_serial() {
+        let src = r#"
+            constant %N = 10;
+            namespace std::convert(%N);
+            let int = [];
+            namespace F(%N);
+            col fixed seq(i) { i };
+            col fixed alt = [0, 1, 0, 1, 0, 1] + [0]*;
+            col fixed doub(i) { std::convert::int(seq((2 * i) % %N)) + 1 };
+            let add_alt = |i, k| alt(i) + k;
+            col fixed with_alt(i) { add_alt(i, 7) };
+            col fixed fib(i) { if i < 2 { 1 } else { fib(i - 1) + fib(i - 2) } };
+            let x: int -> col = |k| |i| i + k;
+            let y: col[2] = [x(0), x(1)];
+        "#;
+        let analyzed = analyze_string::<GoldilocksField>(src);
+        let constants = evaluate_fixed_columns_parallel(&analyzed, analyzed.degree());
+        assert_eq!(constants, generate(&analyzed));
+        assert_eq!(
+            constants[3],
+            (
+                "F.with_alt".to_string(),
+                convert([7i32, 8, 7, 8, 7, 8, 7, 7, 7, 7].to_vec())
+            )
+        );
+        assert_eq!(
+            constants[4],
+            (
+                "F.fib".to_string(),
+                convert([1i32, 1, 2, 3, 5, 8, 13, 21, 34, 55].to_vec())
+            )
+        );
+    }
 }
@@ -8,7 +8,7 @@ use powdr_number::{BigInt, FieldElement};
 use super::global_constraints::RangeConstraintSet;
 use super::range_constraints::RangeConstraint;
 use super::Constraint;
-use super::{EvalError::*, EvalResult, EvalValue, IncompleteCause};
+use super::{EvalError, EvalError::*, EvalResult, EvalValue, IncompleteCause};
 
 /// An expression affine in the committed polynomials (or symbolic variables in general).
 #[derive(Debug, Clone)]
@@ -151,7 +151,8 @@ where
     /// If the affine expression has only a single variable (with nonzero coefficient),
     /// returns the index of the variable and the assignment that evaluates the
     /// affine expression to zero.
-    /// Returns an error if the constraint is unsat
+    /// Returns an error if the constraint is unsat or the coefficient of the variable
+    /// cannot be inverted.
     pub fn solve(&self) -> EvalResult<T, K> {
         if !self.clean {
             return self.clean().solve();
@@ -161,6 +162,10 @@ where
         let first = nonzero.next();
         let second = nonzero.next();
         match (first, second) {
+            (Some((i, c)), None) if c.is_zero() => Err(NonInvertibleCoefficient(format!(
+                "{c} * {i} + {}",
+                self.offset
+            ))),
             (Some((i, c)), None) => {
                 // c * a + o = 0 <=> a = -o/c
                 Ok(EvalValue::complete([(
@@ -397,6 +402,12 @@ where
         let mut assignments = EvalValue::complete([]);
         let mut offset = (-self.offset).to_integer();
         for (i, coeff, constraint) in constraints {
+            if coeff.is_zero() {
+                return Err(NonInvertibleCoefficient(format!(
+                    "{coeff} * {i} + {}",
+                    self.offset
+                )));
+            }
             let mask = *constraint.multiple(coeff).mask();
             if !(mask & covered_bits).is_zero() {
                 return Ok(EvalValue::incomplete(
@@ -425,6 +436,24 @@ where
     }
 }
 
+/// Solves `expression = 0` for its unknown, i.e. for `sum(c_i * x_i) + k = 0` with
+/// exactly one variable `x` with nonzero coefficient `c`, returns the assignment
+/// `x = -k / c`. The variable is the one returned by
+/// [AffineExpression::nonzero_variables].
+/// If there are multiple unknowns, the expression cannot be solved and `None` is
+/// returned. If there are no unknowns, `None` is returned if the expression is zero,
+/// and [EvalError::ConstraintUnsatisfiable] otherwise.
+pub fn solve_affine<K, T>(
+    expression: &AffineExpression<K, T>,
+) -> Result<Option<Constraint<T>>, EvalError<T>>
+where
+    K: Copy + Ord + Display,
+    T: FieldElement,
+{
+    let mut result = expression.solve()?;
+    Ok(result.constraints.pop().map(|(_, constraint)| constraint))
+}
+
 impl<K, T> PartialEq for AffineExpression<K, T>
 where
     K: Copy + Ord,
@@ -574,6 +603,44 @@ mod test {
         input.iter().map(|x| (*x).into()).enumerate().collect()
     }
 
+    #[test]
+    pub fn solve_zero_coefficient() {
+        // The zero coefficient would be removed when cleaning the expression,
+        // so the expression is marked as clean to reach the division.
+        let a = AffineExpression::<_, GoldilocksField> {
+            coefficients: convert(vec![0]),
+            offset: 3.into(),
+            clean: true,
+        };
+        assert_eq!(
+            a.solve(),
+            Err(EvalError::NonInvertibleCoefficient("0 * 0 + 3".to_string()))
+        );
+    }
+
+    #[test]
+    pub fn solve_affine_cases() {
+        // 2 * x + 6 = 0
+        let one_unknown = AffineExpression::from_variable_id(0) * GoldilocksField::from(2)
+            + GoldilocksField::from(6).into();
+        assert_eq!(
+            solve_affine(&one_unknown),
+            Ok(Some(Constraint::Assignment(GoldilocksField::from(-3))))
+        );
+        // x + y + 1 = 0
+        let two_unknowns = AffineExpression::from_variable_id(0)
+            + AffineExpression::from_variable_id(1)
+            + GoldilocksField::from(1).into();
+        assert_eq!(solve_affine(&two_unknowns), Ok(None));
+        let zero = AffineExpression::<usize, GoldilocksField>::from(GoldilocksField::from(0));
+        assert_eq!(solve_affine(&zero), Ok(None));
+        let nonzero = AffineExpression::<usize, GoldilocksField>::from(GoldilocksField::from(7));
+        assert_eq!(
+            solve_affine(&nonzero),
+            Err(EvalError::ConstraintUnsatisfiable("7".to_string()))
+        );
+    }
+
     #[test]
     pub fn test_affine_assign() {
         let mut a = AffineExpression::<_, GoldilocksField> {
@@ -105,14 +105,11 @@ mod tests {
     use crate::{
         constant_evaluator::generate,
         witgen::{
-            data_structures::column_map::FixedColumnMap,
             data_structures::finalizable_data::FinalizableData,
             global_constraints::GlobalConstraints,
-            identity_processor::Machines,
-            machines::FixedLookup,
             rows::RowFactory,
             sequence_iterator::{DefaultSequenceIterator, ProcessingSequenceIterator},
-            unused_query_callback, FixedData, MutableState, QueryCallback,
+            unused_query_callback, FixedData, MutableStateBuilder, QueryCallback,
         },
     };
 
@@ -132,7 +129,7 @@ mod tests {
     /// Constructs a processor for a given PIL, then calls a function on it.
     fn do_with_processor<T: FieldElement, Q: QueryCallback<T>, R>(
         src: &str,
-        mut query_callback: Q,
+        query_callback: Q,
         f: impl Fn(BlockProcessor<T, Q>, BTreeMap<String, PolyID>, u64, usize) -> R,
     ) -> R {
         let analyzed = analyze_string(src);
@@ -140,17 +137,13 @@ mod tests {
             .into_iter()
             .map(|(n, c)| (n.to_string(), c))
             .collect::<Vec<_>>();
-        let fixed_data = FixedData::new(&analyzed, &constants, vec![]);
+        let fixed_data = FixedData::new(&analyzed, analyzed.degree(), &constants, None);
 
         // No global range constraints
-        let global_range_constraints = GlobalConstraints {
-            witness_constraints: fixed_data.witness_map_with(None),
-            fixed_constraints: FixedColumnMap::new(None, fixed_data.fixed_cols.len()),
-        };
+        let global_range_constraints = GlobalConstraints::unconstrained(&fixed_data);
 
         // No submachines
-        let mut fixed_lookup = FixedLookup::new(global_range_constraints.clone());
-        let mut machines = vec![];
+        let mut state_builder = MutableStateBuilder::new(&fixed_data, query_callback);
 
         let row_factory = RowFactory::new(&fixed_data, global_range_constraints);
         let columns = (0..fixed_data.witness_cols.len())
@@ -164,11 +157,7 @@ mod tests {
             (0..fixed_data.degree).map(|i| row_factory.fresh_row(i)),
         );
 
-        let mut mutable_state = MutableState {
-            fixed_lookup: &mut fixed_lookup,
-            machines: Machines::from(machines.iter_mut()),
-            query_callback: &mut query_callback,
-        };
+        let mut mutable_state = state_builder.state();
         let row_offset = 0;
         let identities = analyzed.identities.iter().collect::<Vec<_>>();
         let witness_cols = fixed_data.witness_cols.keys().collect();
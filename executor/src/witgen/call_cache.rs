@@ -0,0 +1,187 @@
+use std::collections::HashMap;
+
+use powdr_ast::analyzed::{
+    AlgebraicExpression as Expression, AlgebraicReference, Identity, IdentityKind,
+};
+use powdr_number::FieldElement;
+
+use super::affine_expression::AffineExpression;
+use super::{Constraint, EvalResult, EvalValue, WitnessGenerator};
+
+/// The values of the arguments of calls into pure machines (see
+/// [super::machines::Machine::is_pure]), by identity and the values of the known
+/// arguments. It is shared by all machines during witness generation, so that a call
+/// with the same inputs is only processed once, see
+/// [super::WitnessGenerator::with_pure_machine_memoization].
+/// Only lookups are cached (not permutations, which require a row for each call),
+/// and only if each unknown argument is a single variable.
+pub struct CallCache<T> {
+    enabled: bool,
+    arguments: HashMap<(u64, Vec<Option<T>>), Vec<T>>,
+    hits: u64,
+}
+
+impl<T: FieldElement> CallCache<T> {
+    pub fn new(enabled: bool) -> Self {
+        Self {
+            enabled,
+            arguments: Default::default(),
+            hits: 0,
+        }
+    }
+
+    /// The number of calls that were answered from the cache.
+    pub fn hits(&self) -> u64 {
+        self.hits
+    }
+
+    /// Returns the result of a previous call through `identity` with the same known
+    /// arguments, if there is one.
+    pub fn get<'a>(
+        &mut self,
+        identity: &Identity<Expression<T>>,
+        left: &[AffineExpression<&'a AlgebraicReference, T>],
+    ) -> Option<EvalResult<'a, T>> {
+        let arguments = self.arguments.get(&self.key(identity, left)?)?;
+        self.hits += 1;
+        let mut result = EvalValue::complete(vec![]);
+        for (l, value) in left.iter().zip(arguments) {
+            if !l.is_constant() {
+                match (l.clone() - (*value).into()).solve() {
+                    Ok(constraints) => result.combine(constraints),
+                    Err(e) => return Some(Err(e)),
+                }
+            }
+        }
+        Some(Ok(result))
+    }
+
+    /// Records the result of a call through `identity` into a pure machine.
+    pub fn insert<'a>(
+        &mut self,
+        identity: &Identity<Expression<T>>,
+        left: &[AffineExpression<&'a AlgebraicReference, T>],
+        result: &EvalValue<&'a AlgebraicReference, T>,
+    ) {
+        if !result.is_complete() {
+            return;
+        }
+        let Some(key) = self.key(identity, left) else {
+            return;
+        };
+        let arguments = left
+            .iter()
+            .map(|l| {
+                let mut l = l.clone();
+                for (variable, constraint) in &result.constraints {
+                    if let Constraint::Assignment(value) = constraint {
+                        l.assign(*variable, *value);
+                    }
+                }
+                l.constant_value()
+            })
+            .collect::<Option<Vec<_>>>();
+        if let Some(arguments) = arguments {
+            self.arguments.insert(key, arguments);
+        }
+    }
+
+    /// The key of a call, or `None` if the call cannot be cached.
+    fn key(
+        &self,
+        identity: &Identity<Expression<T>>,
+        left: &[AffineExpression<&AlgebraicReference, T>],
+    ) -> Option<(u64, Vec<Option<T>>)> {
+        if !self.enabled || identity.kind != IdentityKind::Plookup {
+            return None;
+        }
+        let known = left
+            .iter()
+            .map(|l| match l.constant_value() {
+                Some(value) => Some(Some(value)),
+                None => (l.nonzero_variables().len() == 1).then_some(None),
+            })
+            .collect::<Option<Vec<_>>>()?;
+        Some((identity.id, known))
+    }
+}
+
+impl<'a, 'b, T: FieldElement> WitnessGenerator<'a, 'b, T> {
+    /// Reuses the results of calls into pure machines (see
+    /// [super::machines::Machine::is_pure], e.g. block machines without queries that only
+    /// look up into fixed columns): A lookup with the same known inputs as an earlier
+    /// lookup through the same identity is answered without calling the machine again.
+    /// This can save a lot of time if a machine is called repeatedly with the same
+    /// inputs, but the machine then only contains a block for the first of these calls.
+    /// This is only sound because lookups just require a matching row, which the block
+    /// of the first call provides for each row of the caller. It is not sound if other
+    /// constraints depend on the number of blocks of the machine.
+    /// The number of reused results is reported in
+    /// [super::GenerationStats::pure_call_cache_hits].
+    pub fn with_pure_machine_memoization(self) -> Self {
+        WitnessGenerator {
+            memoize_pure_machines: true,
+            ..self
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use powdr_number::GoldilocksField;
+    use powdr_pil_analyzer::analyze_string;
+    use pretty_assertions::assert_eq;
+    use test_log::test;
+
+    use crate::constant_evaluator;
+    use crate::witgen::tests::generate_witness;
+    use crate::witgen::{unused_query_callback, GenerationStats};
+
+    use super::*;
+
+    #[test]
+    fn pure_machine_memoization() {
+        let src = r"
+namespace Main(8);
+    col fixed CALL = [1, 0]*;
+    col fixed A = [3, 3, 3, 3, 4, 4, 4, 4];
+    col witness a, b;
+    a = A;
+    CALL { a, b } in Sq.LATCH { Sq.x, Sq.y };
+namespace Sq(8);
+    col fixed LATCH = [0, 1]*;
+    col witness x, y;
+    y = x * x;
+";
+        let analyzed = analyze_string::<GoldilocksField>(src);
+        let fixed_cols = constant_evaluator::generate(&analyzed);
+        let query_callback = unused_query_callback();
+        let main_columns = |witness: Vec<(String, Vec<GoldilocksField>)>| {
+            witness
+                .into_iter()
+                .filter(|(name, _)| name.starts_with("Main."))
+                .collect::<Vec<_>>()
+        };
+        let machine_columns = |witness: &[(String, Vec<GoldilocksField>)]| {
+            witness
+                .iter()
+                .filter(|(name, _)| name.starts_with("Sq."))
+                .cloned()
+                .collect::<Vec<_>>()
+        };
+        let mut stats = GenerationStats::default();
+        let memoized = WitnessGenerator::new(&analyzed, &fixed_cols, &query_callback)
+            .with_pure_machine_memoization()
+            .with_statistics(&mut stats)
+            .generate();
+        assert_eq!(stats.pure_call_cache_hits, 2);
+        // The machine only contains the blocks of the first call with each input, as if
+        // each input was only passed once.
+        let single_calls = src.replace("CALL = [1, 0]*", "CALL = [1, 0, 0, 0]*");
+        assert_eq!(
+            machine_columns(&memoized),
+            machine_columns(&generate_witness(&single_calls))
+        );
+        assert_eq!(main_columns(memoized), main_columns(generate_witness(src)));
+    }
+}
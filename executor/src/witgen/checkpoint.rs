@@ -0,0 +1,621 @@
+//! Checkpoints of the main machine, from which witness generation can be resumed.
+//!
+//! Checkpoints are written as an append-only log: A header containing the column names,
+//! followed by segments of rows. Each row stores the values of the cells, which of them
+//! are known and the range constraints of the cells that are not known yet, and each
+//! segment ends with the calls into other machines made since the previous segment and
+//! the state of the solving loop after its last row. A segment that was only partially
+//! written (e.g. because the process was killed) is ignored when reading the checkpoint.
+//! All integers are encoded as little-endian `u64`.
+//!
+//! The state of the machines called by the main machine is not stored. Instead, the calls
+//! are replayed in the order they were made when resuming, which determines the same
+//! state again. The main machine solves its rows in order, so solving continues from the
+//! last stored row, no other position has to be stored.
+
+use std::collections::HashSet;
+use std::io::{self, ErrorKind, Read, Write};
+use std::marker::PhantomData;
+
+use bit_vec::BitVec;
+use num_bigint::BigUint;
+use powdr_ast::analyzed::{AlgebraicExpression as Expression, Identity, PolyID};
+use powdr_number::{BigInt, DegreeType, FieldElement};
+
+use super::data_structures::finalizable_data::FinalizableData;
+use super::identity_processor::IdentityProcessor;
+use super::range_constraints::RangeConstraint;
+use super::rows::{CellValue, Row, RowFactory, RowPair, UnknownStrategy};
+use super::{EvalError, FixedData, MutableState, QueryCallback, WitnessGenerator};
+
+/// A row of a [Checkpoint].
+pub struct CheckpointRow<T: FieldElement> {
+    /// The values of the cells, in the order of the columns.
+    pub values: Vec<T>,
+    /// Which of the cells are known.
+    pub known_cells: BitVec,
+    /// The range constraints of the cells that are not known, by the index of the column.
+    /// Only rows that are still being solved have them.
+    pub range_constraints: Vec<(usize, RangeConstraint<T>)>,
+}
+
+/// The rows computed by the main machine up to some point.
+pub struct Checkpoint<T: FieldElement> {
+    /// The names of the witness columns of the main machine, sorted by ID.
+    column_names: Vec<String>,
+    rows: Vec<CheckpointRow<T>>,
+    /// The calls into other machines (connecting identity and row) in the order they were
+    /// made, except for the calls of the last row, which is solved again when resuming.
+    calls: Vec<(u64, DegreeType)>,
+    /// The number of rows without progress (see
+    /// [crate::witgen::WitnessGenerator::with_max_rows_without_progress]) before the
+    /// last row, from which solving continues.
+    rows_without_progress: DegreeType,
+}
+
+impl<T: FieldElement> Checkpoint<T> {
+    /// Reads a checkpoint, ignoring an incomplete last segment.
+    /// The calls made in the last row are dropped, as solving continues from that row.
+    pub fn read(reader: &mut impl Read) -> io::Result<Self> {
+        let column_count = read_u64(reader)? as usize;
+        let column_names = (0..column_count)
+            .map(|_| {
+                let mut name = vec![0; read_u64(reader)? as usize];
+                reader.read_exact(&mut name)?;
+                String::from_utf8(name).map_err(|e| io::Error::new(ErrorKind::InvalidData, e))
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+
+        let mut rows = vec![];
+        let mut calls = vec![];
+        let mut rows_without_progress = 0;
+        loop {
+            match read_segment(reader, column_count) {
+                Ok((segment, segment_calls, segment_rows_without_progress)) => {
+                    rows.extend(segment);
+                    calls.extend(segment_calls);
+                    rows_without_progress = segment_rows_without_progress;
+                }
+                Err(e) if e.kind() == ErrorKind::UnexpectedEof => break,
+                Err(e) => return Err(e),
+            }
+        }
+        calls.retain(|(_, row)| row + 1 < rows.len() as DegreeType);
+        Ok(Self {
+            column_names,
+            rows,
+            calls,
+            rows_without_progress,
+        })
+    }
+
+    /// Writes the header and all rows of the checkpoint as a single segment.
+    pub fn write(&self, writer: &mut impl Write) -> io::Result<()> {
+        write_header(writer, &self.column_names)?;
+        write_segment(writer, &self.rows, &self.calls, self.rows_without_progress)
+    }
+
+    /// The number of rows stored in the checkpoint.
+    pub fn len(&self) -> usize {
+        self.rows.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+
+    /// The number of rows without progress before the row solving continues from.
+    pub(crate) fn rows_without_progress(&self) -> DegreeType {
+        self.rows_without_progress
+    }
+
+    /// Returns an error if the checkpoint was not written for a machine with the given
+    /// witnesses.
+    pub(crate) fn check_columns(
+        &self,
+        fixed_data: &FixedData<'_, T>,
+        witnesses: &HashSet<PolyID>,
+    ) -> Result<(), String> {
+        let column_names = FinalizableData::<T>::new(witnesses)
+            .column_ids()
+            .iter()
+            .map(|id| fixed_data.column_name(id).to_string())
+            .collect::<Vec<_>>();
+        if column_names == self.column_names {
+            Ok(())
+        } else {
+            Err(format!(
+                "The columns of the checkpoint do not match the columns of the main machine.\n\
+                 Checkpoint: {}\nMain machine: {}",
+                self.column_names.join(", "),
+                column_names.join(", ")
+            ))
+        }
+    }
+
+    /// Replays the calls into other machines made in the rows of the checkpoint, so that
+    /// the called machines are in the state they had when the checkpoint was written.
+    /// `identities` are the identities of the machine the checkpoint was written for.
+    pub(crate) fn replay_calls<'a, Q: QueryCallback<T>>(
+        &self,
+        fixed_data: &'a FixedData<'a, T>,
+        identities: &[&'a Identity<Expression<T>>],
+        witnesses: &HashSet<PolyID>,
+        row_factory: &RowFactory<'a, T>,
+        mutable_state: &mut MutableState<'a, '_, T, Q>,
+    ) -> Result<(), EvalError<T>> {
+        let column_ids = FinalizableData::<T>::new(witnesses).column_ids().to_vec();
+        let row = |i: DegreeType| {
+            let mut row = row_factory.fresh_row(i);
+            restore_row(&mut row, &column_ids, &self.rows[i as usize]);
+            row
+        };
+        let mut identity_processor = IdentityProcessor::new(fixed_data, mutable_state);
+        for (id, i) in &self.calls {
+            let identity = identities
+                .iter()
+                .find(|identity| identity.id == *id)
+                .copied()
+                .ok_or_else(|| {
+                    format!("The checkpoint contains a call through unknown identity {id}.")
+                })?;
+            let (current, next) = (row(*i), row(*i + 1));
+            let rows = RowPair::new(&current, &next, *i, fixed_data, UnknownStrategy::Unknown);
+            let result = identity_processor.process_identity(identity, &rows)?;
+            if !result.is_complete() {
+                return Err(format!(
+                    "Replaying the call {identity} in row {i} of the checkpoint did not complete."
+                )
+                .into());
+            }
+        }
+        Ok(())
+    }
+
+    /// Converts the checkpoint into the data of a machine with the given witnesses.
+    /// The first and the last row are kept in progress (with the range constraints of
+    /// their cells), all other rows are finalized.
+    pub(crate) fn into_data<'a>(
+        self,
+        witnesses: &HashSet<PolyID>,
+        row_factory: &RowFactory<'a, T>,
+    ) -> FinalizableData<'a, T> {
+        let mut data = FinalizableData::new(witnesses);
+        let row_count = self.rows.len();
+        for (i, checkpoint_row) in self.rows.into_iter().enumerate() {
+            if i == 0 || i + 1 == row_count {
+                let mut row = row_factory.fresh_row(i as DegreeType);
+                restore_row(&mut row, data.column_ids(), &checkpoint_row);
+                data.push(row);
+            } else {
+                data.push_finalized(checkpoint_row.values, checkpoint_row.known_cells);
+            }
+        }
+        data
+    }
+}
+
+fn restore_row<T: FieldElement>(
+    row: &mut Row<'_, T>,
+    column_ids: &[PolyID],
+    checkpoint_row: &CheckpointRow<T>,
+) {
+    let CheckpointRow {
+        values,
+        known_cells,
+        range_constraints,
+    } = checkpoint_row;
+    for ((id, value), is_known) in column_ids.iter().zip(values).zip(known_cells.iter()) {
+        if is_known {
+            row[id].value = CellValue::Known(*value);
+        }
+    }
+    for (index, constraint) in range_constraints {
+        row[&column_ids[*index]].value = CellValue::RangeConstraint(constraint.clone());
+    }
+}
+
+/// Writes checkpoints of the main machine every `interval` rows.
+pub struct CheckpointWriter<'w, T> {
+    writer: &'w mut (dyn Write + Send + 'static),
+    interval: DegreeType,
+    header_written: bool,
+    /// The (global) index of the first row that has not been written yet.
+    rows_written: DegreeType,
+    _marker: PhantomData<T>,
+}
+
+impl<'w, T: FieldElement> CheckpointWriter<'w, T> {
+    pub fn new(writer: &'w mut (dyn Write + Send + 'static), interval: DegreeType) -> Self {
+        assert!(interval > 0, "Checkpoint interval must be positive.");
+        Self {
+            writer,
+            interval,
+            header_written: false,
+            rows_written: 0,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Writes a checkpoint we resume from, so that later checkpoints extend it.
+    pub fn write_checkpoint(&mut self, checkpoint: &Checkpoint<T>) -> io::Result<()> {
+        assert!(!self.header_written);
+        checkpoint.write(&mut self.writer)?;
+        self.writer.flush()?;
+        self.header_written = true;
+        self.rows_written = checkpoint.len() as DegreeType;
+        Ok(())
+    }
+
+    /// Returns true if all rows before `row` should be written now.
+    pub fn is_due(&self, row: DegreeType) -> bool {
+        row % self.interval == 0 && row > self.rows_written
+    }
+
+    /// The (global) index of the first row that has not been written yet.
+    pub fn rows_written(&self) -> DegreeType {
+        self.rows_written
+    }
+
+    /// Writes the given rows (which start at [CheckpointWriter::rows_written]) as a new
+    /// segment, together with the calls into other machines (connecting identity and row)
+    /// made since the last segment and the number of rows without progress before the
+    /// last of the rows.
+    /// The column names are only used if the header has not been written yet.
+    pub fn write_rows(
+        &mut self,
+        column_names: impl FnOnce() -> Vec<String>,
+        rows: &[CheckpointRow<T>],
+        calls: &[(u64, DegreeType)],
+        rows_without_progress: DegreeType,
+    ) -> io::Result<()> {
+        if !self.header_written {
+            write_header(&mut self.writer, &column_names())?;
+            self.header_written = true;
+        }
+        write_segment(&mut self.writer, rows, calls, rows_without_progress)?;
+        self.writer.flush()?;
+        self.rows_written += rows.len() as DegreeType;
+        Ok(())
+    }
+}
+
+fn field_element_width<T: FieldElement>() -> usize {
+    (T::BITS as usize + 63) / 64 * 8
+}
+
+fn mask_width<T: FieldElement>() -> usize {
+    T::Integer::NUM_BITS / 8
+}
+
+fn write_header(writer: &mut impl Write, column_names: &[String]) -> io::Result<()> {
+    write_u64(writer, column_names.len() as u64)?;
+    for name in column_names {
+        write_u64(writer, name.len() as u64)?;
+        writer.write_all(name.as_bytes())?;
+    }
+    Ok(())
+}
+
+fn write_segment<T: FieldElement>(
+    writer: &mut impl Write,
+    rows: &[CheckpointRow<T>],
+    calls: &[(u64, DegreeType)],
+    rows_without_progress: DegreeType,
+) -> io::Result<()> {
+    write_u64(writer, rows.len() as u64)?;
+    for row in rows {
+        for value in &row.values {
+            writer.write_all(&value.to_bytes_le())?;
+        }
+        writer.write_all(&row.known_cells.to_bytes())?;
+        write_u64(writer, row.range_constraints.len() as u64)?;
+        for (index, constraint) in &row.range_constraints {
+            write_u64(writer, *index as u64)?;
+            let mut mask = constraint.mask().to_arbitrary_integer().to_bytes_le();
+            mask.resize(mask_width::<T>(), 0);
+            writer.write_all(&mask)?;
+            let (min, max) = constraint.range();
+            writer.write_all(&min.to_bytes_le())?;
+            writer.write_all(&max.to_bytes_le())?;
+        }
+    }
+    write_u64(writer, calls.len() as u64)?;
+    for (id, row) in calls {
+        write_u64(writer, *id)?;
+        write_u64(writer, *row)?;
+    }
+    write_u64(writer, rows_without_progress)
+}
+
+/// A segment of a checkpoint: The rows, the calls into other machines and the number of
+/// rows without progress.
+type Segment<T> = (Vec<CheckpointRow<T>>, Vec<(u64, DegreeType)>, DegreeType);
+
+/// Reads a segment. Returns an error of kind [ErrorKind::UnexpectedEof] if there is no
+/// complete segment left.
+fn read_segment<T: FieldElement>(
+    reader: &mut impl Read,
+    column_count: usize,
+) -> io::Result<Segment<T>> {
+    let width = field_element_width::<T>();
+    let row_count = read_u64(reader)?;
+    let rows = (0..row_count)
+        .map(|_| {
+            let mut values = vec![0; width * column_count];
+            reader.read_exact(&mut values)?;
+            let mut known_cells = vec![0; (column_count + 7) / 8];
+            reader.read_exact(&mut known_cells)?;
+            let mut known_cells = BitVec::from_bytes(&known_cells);
+            known_cells.truncate(column_count);
+            let range_constraints = (0..read_u64(reader)?)
+                .map(|_| read_range_constraint(reader, column_count))
+                .collect::<io::Result<_>>()?;
+            Ok(CheckpointRow {
+                values: values.chunks(width).map(T::from_bytes_le).collect(),
+                known_cells,
+                range_constraints,
+            })
+        })
+        .collect::<io::Result<_>>()?;
+    let calls = (0..read_u64(reader)?)
+        .map(|_| Ok((read_u64(reader)?, read_u64(reader)?)))
+        .collect::<io::Result<_>>()?;
+    Ok((rows, calls, read_u64(reader)?))
+}
+
+fn read_range_constraint<T: FieldElement>(
+    reader: &mut impl Read,
+    column_count: usize,
+) -> io::Result<(usize, RangeConstraint<T>)> {
+    let index = read_u64(reader)? as usize;
+    if index >= column_count {
+        return Err(io::Error::new(
+            ErrorKind::InvalidData,
+            format!(
+                "Range constraint for column {index}, but there are only {column_count} columns."
+            ),
+        ));
+    }
+    let mut mask = vec![0; mask_width::<T>()];
+    reader.read_exact(&mut mask)?;
+    let mask = T::Integer::try_from(BigUint::from_bytes_le(&mask))
+        .map_err(|()| io::Error::new(ErrorKind::InvalidData, "Invalid range constraint mask."))?;
+    let mut bounds = vec![0; 2 * field_element_width::<T>()];
+    reader.read_exact(&mut bounds)?;
+    let (min, max) = bounds.split_at(field_element_width::<T>());
+    Ok((
+        index,
+        RangeConstraint::from_parts(mask, T::from_bytes_le(min), T::from_bytes_le(max)),
+    ))
+}
+
+fn write_u64(writer: &mut impl Write, value: u64) -> io::Result<()> {
+    writer.write_all(&value.to_le_bytes())
+}
+
+fn read_u64(reader: &mut impl Read) -> io::Result<u64> {
+    let mut bytes = [0; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+impl<'a, 'b, T: FieldElement> WitnessGenerator<'a, 'b, T> {
+    /// Writes a [Checkpoint] of the main machine to `writer` every `interval` rows.
+    /// The checkpoints are appended to each other, so reading the written data with
+    /// [Checkpoint::read] returns the latest one.
+    pub fn with_checkpoints(
+        self,
+        writer: &'b mut (dyn std::io::Write + Send + 'static),
+        interval: DegreeType,
+    ) -> Self {
+        WitnessGenerator {
+            checkpoints: Some(CheckpointWriter::new(writer, interval)),
+            ..self
+        }
+    }
+
+    /// Continues witness generation from the given checkpoint instead of starting from scratch.
+    /// The calls of the main machine into other machines stored in the checkpoint are
+    /// replayed first, which asks the query callback for the queries of the called
+    /// machines again. A checkpoint without rows starts from the first row.
+    /// Witness generation fails if the checkpoint was written for different columns.
+    pub fn resume_from(self, checkpoint: Checkpoint<T>) -> Self {
+        WitnessGenerator {
+            checkpoint: Some(checkpoint),
+            ..self
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use powdr_number::GoldilocksField;
+    use powdr_pil_analyzer::analyze_string;
+    use pretty_assertions::assert_eq;
+    use test_log::test;
+
+    use crate::constant_evaluator;
+    use crate::witgen::tests::{generate_witness, values, ARRAY_SRC};
+    use crate::witgen::unused_query_callback;
+
+    use super::*;
+
+    #[test]
+    fn resume_from_checkpoint() {
+        let analyzed = analyze_string::<GoldilocksField>(ARRAY_SRC);
+        let fixed_cols = constant_evaluator::generate(&analyzed);
+        let query_callback = unused_query_callback();
+        let mut checkpoints = vec![];
+        let witness = WitnessGenerator::new(&analyzed, &fixed_cols, &query_callback)
+            .with_checkpoints(&mut checkpoints, 3)
+            .generate();
+        assert_eq!(witness, generate_witness(ARRAY_SRC));
+
+        // Simulate being killed while writing the second checkpoint.
+        checkpoints.pop();
+        let checkpoint = Checkpoint::read(&mut checkpoints.as_slice()).unwrap();
+        assert_eq!(checkpoint.len(), 3);
+
+        let resumed = WitnessGenerator::new(&analyzed, &fixed_cols, &query_callback)
+            .resume_from(checkpoint)
+            .generate();
+        assert_eq!(resumed, witness);
+    }
+
+    #[test]
+    fn resume_from_checkpoint_with_range_constraints() {
+        // The bits are only determined through their range constraints.
+        let src = r"
+namespace N(16);
+    col fixed X(i) { i % 4 };
+    col witness b0, b1, x;
+    b0 * (1 - b0) = 0;
+    b1 * (1 - b1) = 0;
+    x = b0 + 2 * b1;
+    x = X;
+";
+        let analyzed = analyze_string::<GoldilocksField>(src);
+        let fixed_cols = constant_evaluator::generate(&analyzed);
+        let query_callback = unused_query_callback();
+        let mut checkpoints = vec![];
+        let witness = WitnessGenerator::new(&analyzed, &fixed_cols, &query_callback)
+            .with_checkpoints(&mut checkpoints, 5)
+            .with_max_rows_without_progress(3)
+            .generate();
+        assert_eq!(witness, generate_witness(src));
+        assert_eq!(witness[1].1, values((0..16).map(|i| (i % 4) / 2)));
+
+        // Resume from the first two checkpoints, dropping the incomplete third one.
+        checkpoints.pop();
+        let checkpoint = Checkpoint::read(&mut checkpoints.as_slice()).unwrap();
+        assert_eq!(checkpoint.len(), 10);
+        let mut resumed_checkpoints = vec![];
+        let resumed = WitnessGenerator::new(&analyzed, &fixed_cols, &query_callback)
+            .resume_from(checkpoint)
+            .with_checkpoints(&mut resumed_checkpoints, 5)
+            .with_max_rows_without_progress(3)
+            .generate();
+        assert_eq!(resumed, witness);
+        // The resumed run extends the checkpoint it was resumed from.
+        assert_eq!(
+            Checkpoint::read(&mut resumed_checkpoints.as_slice())
+                .unwrap()
+                .len(),
+            15
+        );
+    }
+
+    #[test]
+    fn resume_from_checkpoint_with_calls() {
+        // The blocks of the machine are stored in the order of the calls, so the calls
+        // of the stored rows have to be replayed before resuming.
+        let src = r"
+namespace main(16);
+    col fixed FIRST = [1] + [0]*;
+    col fixed CALL = [1, 0]*;
+    col witness a, b;
+    FIRST * a = 0;
+    (1 - FIRST') * (a' - a - 1) = 0;
+    CALL { a, b } in Sq.LATCH { Sq.x, Sq.y };
+namespace Sq(16);
+    col fixed LATCH = [0, 1]*;
+    col witness x, y;
+    y = x * x;
+";
+        let analyzed = analyze_string::<GoldilocksField>(src);
+        let fixed_cols = constant_evaluator::generate(&analyzed);
+        let query_callback = unused_query_callback();
+        let mut checkpoints = vec![];
+        let witness = WitnessGenerator::new(&analyzed, &fixed_cols, &query_callback)
+            .with_checkpoints(&mut checkpoints, 5)
+            .generate();
+        assert_eq!(witness, generate_witness(src));
+
+        checkpoints.pop();
+        let checkpoint = Checkpoint::read(&mut checkpoints.as_slice()).unwrap();
+        assert_eq!(checkpoint.len(), 10);
+        // Each row calls the machine (with a zero selector in every other row). The call of
+        // the last row is made again when resuming.
+        assert_eq!(
+            checkpoint
+                .calls
+                .iter()
+                .map(|(_, row)| *row)
+                .collect::<Vec<_>>(),
+            (0..9).collect::<Vec<_>>()
+        );
+        let mut resumed_checkpoints = vec![];
+        let resumed = WitnessGenerator::new(&analyzed, &fixed_cols, &query_callback)
+            .resume_from(checkpoint)
+            .with_checkpoints(&mut resumed_checkpoints, 5)
+            .generate();
+        assert_eq!(resumed, witness);
+        let resumed_checkpoint = Checkpoint::read(&mut resumed_checkpoints.as_slice()).unwrap();
+        assert_eq!(resumed_checkpoint.len(), 15);
+        assert_eq!(
+            resumed_checkpoint
+                .calls
+                .iter()
+                .map(|(_, row)| *row)
+                .collect::<Vec<_>>(),
+            (0..14).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn resume_from_checkpoint_without_rows() {
+        let analyzed = analyze_string::<GoldilocksField>(ARRAY_SRC);
+        let fixed_cols = constant_evaluator::generate(&analyzed);
+        let query_callback = unused_query_callback();
+        let mut checkpoints = vec![];
+        let witness = WitnessGenerator::new(&analyzed, &fixed_cols, &query_callback)
+            .with_checkpoints(&mut checkpoints, 3)
+            .generate();
+
+        // Simulate being killed while writing the first segment.
+        let column_names = Checkpoint::<GoldilocksField>::read(&mut checkpoints.as_slice())
+            .unwrap()
+            .column_names;
+        let mut header = vec![];
+        write_header(&mut header, &column_names).unwrap();
+        let checkpoint = Checkpoint::read(&mut header.as_slice()).unwrap();
+        assert!(checkpoint.is_empty());
+
+        let resumed = WitnessGenerator::new(&analyzed, &fixed_cols, &query_callback)
+            .resume_from(checkpoint)
+            .generate();
+        assert_eq!(resumed, witness);
+    }
+
+    #[test]
+    fn resume_from_checkpoint_of_other_columns() {
+        let analyzed = analyze_string::<GoldilocksField>(ARRAY_SRC);
+        let fixed_cols = constant_evaluator::generate(&analyzed);
+        let query_callback = unused_query_callback();
+        let mut checkpoints = vec![];
+        WitnessGenerator::new(&analyzed, &fixed_cols, &query_callback)
+            .with_checkpoints(&mut checkpoints, 3)
+            .generate();
+        let checkpoint = Checkpoint::read(&mut checkpoints.as_slice()).unwrap();
+
+        let src = r"
+namespace N(8);
+    col fixed X(i) { i };
+    col witness x;
+    x = X;
+";
+        let analyzed = analyze_string::<GoldilocksField>(src);
+        let fixed_cols = constant_evaluator::generate(&analyzed);
+        let error = WitnessGenerator::new(&analyzed, &fixed_cols, &query_callback)
+            .resume_from(checkpoint)
+            .try_generate()
+            .unwrap_err();
+        assert!(matches!(
+            error,
+            EvalError::Generic(message) if message.starts_with(
+                "The columns of the checkpoint do not match the columns of the main machine."
+            )
+        ));
+    }
+}
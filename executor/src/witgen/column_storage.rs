@@ -0,0 +1,382 @@
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use memmap2::Mmap;
+use powdr_number::FieldElement;
+
+use super::WitnessGenerator;
+
+/// Where and from which size on completed witness columns are moved out of memory,
+/// see [crate::witgen::WitnessGenerator::with_spill_to_disk].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SpillConfig {
+    /// Columns that need more than this number of bytes are written to a file.
+    pub threshold_bytes: usize,
+    /// The directory the files are created in.
+    pub directory: PathBuf,
+}
+
+/// The values of a completed witness column, either in memory or in a memory-mapped
+/// file that is deleted when the column is dropped.
+#[derive(Debug)]
+pub enum ColumnStorage<T> {
+    InMemory(Vec<T>),
+    Spilled(SpilledColumn<T>),
+}
+
+impl<T: FieldElement> ColumnStorage<T> {
+    /// Stores the values, in a file if they exceed the threshold of `spill`.
+    pub(crate) fn new(values: Vec<T>, spill: Option<&SpillConfig>) -> Self {
+        match spill {
+            Some(spill)
+                if !values.is_empty()
+                    && values.len() * SpilledColumn::<T>::width() > spill.threshold_bytes =>
+            {
+                let column = SpilledColumn::write(&values, &spill.directory).unwrap_or_else(|e| {
+                    panic!(
+                        "Could not spill witness column to {}: {e}",
+                        spill.directory.display()
+                    )
+                });
+                ColumnStorage::Spilled(column)
+            }
+            _ => ColumnStorage::InMemory(values),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            ColumnStorage::InMemory(values) => values.len(),
+            ColumnStorage::Spilled(column) => column.len,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn is_spilled(&self) -> bool {
+        matches!(self, ColumnStorage::Spilled(_))
+    }
+
+    /// Returns the value in the given row. Panics if the row is out of range.
+    pub fn get(&self, row: usize) -> T {
+        match self {
+            ColumnStorage::InMemory(values) => values[row],
+            ColumnStorage::Spilled(column) => column.get(row),
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = T> + '_ {
+        (0..self.len()).map(|row| self.get(row))
+    }
+
+    /// Returns the values in memory, reading them from the file if they were spilled.
+    pub fn into_vec(self) -> Vec<T> {
+        match self {
+            ColumnStorage::InMemory(values) => values,
+            ColumnStorage::Spilled(column) => (0..column.len).map(|row| column.get(row)).collect(),
+        }
+    }
+}
+
+/// Collects the values of a column one by one. Once they exceed the threshold of the
+/// spill configuration, they are moved into a file and all further values are appended
+/// to it, so that the column is never held in memory completely.
+pub(crate) struct ColumnWriter<T> {
+    spill: Option<SpillConfig>,
+    /// The values, as long as they are not spilled.
+    values: Vec<T>,
+    /// The file the values are written to, once they are spilled.
+    file: Option<(PathBuf, BufWriter<File>)>,
+    len: usize,
+}
+
+impl<T: FieldElement> ColumnWriter<T> {
+    pub(crate) fn new(spill: Option<&SpillConfig>) -> Self {
+        Self {
+            spill: spill.cloned(),
+            values: vec![],
+            file: None,
+            len: 0,
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+
+    /// The number of values that are held in memory.
+    pub(crate) fn resident_len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub(crate) fn push(&mut self, value: T) {
+        self.len += 1;
+        if let Some((_, writer)) = &mut self.file {
+            writer
+                .write_all(&value.to_bytes_le())
+                .unwrap_or_else(|e| self.spill_failed(e));
+            return;
+        }
+        self.values.push(value);
+        let spill = match &self.spill {
+            Some(spill)
+                if self.values.len() * SpilledColumn::<T>::width() > spill.threshold_bytes =>
+            {
+                spill
+            }
+            _ => return,
+        };
+        let file = spill_file(&spill.directory).and_then(|(path, mut writer)| {
+            for value in &self.values {
+                writer.write_all(&value.to_bytes_le())?;
+            }
+            Ok((path, writer))
+        });
+        self.file = Some(file.unwrap_or_else(|e| self.spill_failed(e)));
+        self.values = vec![];
+    }
+
+    /// Replaces the value in the given row, which has to be pushed already.
+    pub(crate) fn set(&mut self, row: usize, value: T) {
+        assert!(
+            row < self.len,
+            "Row {row} out of range for column with {} rows.",
+            self.len
+        );
+        let Some((_, writer)) = &mut self.file else {
+            self.values[row] = value;
+            return;
+        };
+        let width = SpilledColumn::<T>::width();
+        let result = writer
+            .seek(SeekFrom::Start((row * width) as u64))
+            .and_then(|_| writer.write_all(&value.to_bytes_le()))
+            .and_then(|_| writer.seek(SeekFrom::End(0)).map(|_| ()));
+        result.unwrap_or_else(|e| self.spill_failed(e));
+    }
+
+    /// Returns the values, in a file if they were spilled.
+    pub(crate) fn finish(mut self) -> ColumnStorage<T> {
+        match self.file.take() {
+            None => ColumnStorage::InMemory(std::mem::take(&mut self.values)),
+            Some((path, writer)) => {
+                let column = writer
+                    .into_inner()
+                    .map_err(|e| e.into_error())
+                    .and_then(|file| file.sync_all())
+                    .and_then(|_| SpilledColumn::open(path, self.len))
+                    .unwrap_or_else(|e| self.spill_failed(e));
+                ColumnStorage::Spilled(column)
+            }
+        }
+    }
+
+    fn spill_failed(&self, e: io::Error) -> ! {
+        panic!(
+            "Could not spill witness column to {}: {e}",
+            self.spill.as_ref().unwrap().directory.display()
+        )
+    }
+}
+
+impl<T> Drop for ColumnWriter<T> {
+    fn drop(&mut self) {
+        // The file is only kept if the column was finished.
+        if let Some((path, _)) = self.file.take() {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+/// Used to create unique file names for the columns spilled by this process.
+static SPILLED_COLUMNS: AtomicUsize = AtomicUsize::new(0);
+
+/// Creates a new file for a spilled column in `directory`.
+fn spill_file(directory: &Path) -> io::Result<(PathBuf, BufWriter<File>)> {
+    let path = directory.join(format!(
+        "powdr-witness-{}-{}.bin",
+        std::process::id(),
+        SPILLED_COLUMNS.fetch_add(1, Ordering::Relaxed)
+    ));
+    let writer = BufWriter::new(File::create(&path)?);
+    Ok((path, writer))
+}
+
+/// A column whose values are stored in a file, each value as its little-endian bytes.
+#[derive(Debug)]
+pub struct SpilledColumn<T> {
+    path: PathBuf,
+    map: Mmap,
+    len: usize,
+    _marker: PhantomData<T>,
+}
+
+impl<T: FieldElement> SpilledColumn<T> {
+    /// The number of bytes used for each value.
+    fn width() -> usize {
+        T::zero().to_bytes_le().len()
+    }
+
+    fn write(values: &[T], directory: &Path) -> io::Result<Self> {
+        let (path, mut writer) = spill_file(directory)?;
+        for value in values {
+            writer.write_all(&value.to_bytes_le())?;
+        }
+        writer
+            .into_inner()
+            .map_err(|e| e.into_error())?
+            .sync_all()?;
+        Self::open(path, values.len())
+    }
+
+    /// Maps the file of a column with `len` values that were completely written.
+    fn open(path: PathBuf, len: usize) -> io::Result<Self> {
+        // Safety: The file is private to this column and not modified after this point.
+        let map = unsafe { Mmap::map(&File::open(&path)?)? };
+        Ok(SpilledColumn {
+            path,
+            map,
+            len,
+            _marker: PhantomData,
+        })
+    }
+
+    fn get(&self, row: usize) -> T {
+        assert!(
+            row < self.len,
+            "Row {row} out of range for column with {} rows.",
+            self.len
+        );
+        let width = Self::width();
+        T::from_bytes_le(&self.map[row * width..(row + 1) * width])
+    }
+}
+
+impl<T> Drop for SpilledColumn<T> {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_file(&self.path) {
+            log::warn!(
+                "Could not remove spilled witness column {}: {e}",
+                self.path.display()
+            );
+        }
+    }
+}
+
+impl<'a, 'b, T: FieldElement> WitnessGenerator<'a, 'b, T> {
+    /// Moves each completed witness column that needs more than `threshold_bytes` bytes
+    /// into a memory-mapped file in `directory`, so that the columns of the machines
+    /// that are already finalized do not have to be kept in memory.
+    /// The finalized rows of the main machines are already moved into the files while
+    /// solving (every 10000 rows), except for the first row and the rows that are not
+    /// written to a checkpoint yet. Other machines keep their rows in memory until they
+    /// are finalized, see [super::GenerationStats::peak_main_machine_rows].
+    /// Use [WitnessGenerator::generate_to_storage] to access the columns without
+    /// reading them back into memory.
+    pub fn with_spill_to_disk(self, threshold_bytes: usize, directory: impl Into<PathBuf>) -> Self {
+        WitnessGenerator {
+            spill: Some(SpillConfig {
+                threshold_bytes,
+                directory: directory.into(),
+            }),
+            ..self
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use powdr_number::GoldilocksField;
+    use powdr_pil_analyzer::analyze_string;
+    use pretty_assertions::assert_eq;
+    use test_log::test;
+
+    use crate::constant_evaluator;
+    use crate::witgen::tests::values;
+    use crate::witgen::{unused_query_callback, GenerationStats};
+
+    use super::*;
+
+    #[test]
+    fn spill_to_disk() {
+        let src = r"
+namespace Perm(8);
+    col fixed SQUARES(i) { i * i };
+    col witness a, b;
+    a = SQUARES;
+    { a } is { b };
+";
+        let analyzed = analyze_string::<GoldilocksField>(src);
+        let fixed_cols = constant_evaluator::generate(&analyzed);
+        let query_callback = unused_query_callback();
+        let directory = std::env::temp_dir().join(format!("powdr-spill-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let columns = WitnessGenerator::new(&analyzed, &fixed_cols, &query_callback)
+            .with_spill_to_disk(1, &directory)
+            .generate_to_storage();
+        assert!(columns.iter().all(|(_, column)| column.is_spilled()));
+        assert_eq!(std::fs::read_dir(&directory).unwrap().count(), 2);
+        let squares = values((0..8).map(|i| i * i));
+        assert_eq!(columns[1].1.get(3), squares[3]);
+        let columns = columns
+            .into_iter()
+            .map(|(name, column)| (name, column.into_vec()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            columns,
+            vec![
+                ("Perm.a".to_string(), squares.clone()),
+                ("Perm.b".to_string(), squares)
+            ]
+        );
+        // The files are removed together with the columns.
+        assert_eq!(std::fs::read_dir(&directory).unwrap().count(), 0);
+        std::fs::remove_dir(&directory).unwrap();
+    }
+
+    #[test]
+    fn spill_rows_while_solving() {
+        let src = r"
+namespace main(32768);
+    col fixed FIRST = [1] + [0]*;
+    col witness a;
+    FIRST * a = 0;
+    (1 - FIRST') * (a' - a - 1) = 0;
+";
+        let analyzed = analyze_string::<GoldilocksField>(src);
+        let fixed_cols = constant_evaluator::generate(&analyzed);
+        let query_callback = unused_query_callback();
+        let directory =
+            std::env::temp_dir().join(format!("powdr-spill-rows-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let mut stats = GenerationStats::default();
+        let columns = WitnessGenerator::new(&analyzed, &fixed_cols, &query_callback)
+            .with_spill_to_disk(1, &directory)
+            .with_statistics(&mut stats)
+            .generate_to_storage();
+        assert!(columns[0].1.is_spilled());
+        assert_eq!(columns[0].1.get(12345), values([12345])[0]);
+        // Finalized rows are spilled every 10000 rows, only the rows since then (and a
+        // few rows that are still needed) are held in memory.
+        assert!(stats.peak_main_machine_rows < 10100);
+
+        let mut full_stats = GenerationStats::default();
+        let witness = WitnessGenerator::new(&analyzed, &fixed_cols, &query_callback)
+            .with_statistics(&mut full_stats)
+            .generate();
+        // All rows and the first row again after wrapping around.
+        assert_eq!(full_stats.peak_main_machine_rows, 32769);
+        let columns = columns
+            .into_iter()
+            .map(|(name, column)| (name, column.into_vec()))
+            .collect::<Vec<_>>();
+        assert_eq!(columns, witness);
+        assert_eq!(std::fs::read_dir(&directory).unwrap().count(), 0);
+        std::fs::remove_dir(&directory).unwrap();
+    }
+}
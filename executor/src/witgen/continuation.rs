@@ -0,0 +1,159 @@
+use powdr_number::FieldElement;
+
+use super::query_router::query_name;
+use super::WitnessGenerator;
+
+/// The boundary of a chunk when a long execution is proven in several chunks
+/// (continuations), see [WitnessGenerator::with_continuation].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContinuationContext<T> {
+    /// The index of the chunk, starting at zero.
+    pub chunk_index: u64,
+    /// The values of the boundary columns of the main machine in the first row of the
+    /// chunk, by name. For all but the first chunk, this is the final state of the
+    /// previous chunk, see [ContinuationContext::next_chunk].
+    pub initial_state: Vec<(String, T)>,
+    /// The inputs of the bootloader, which answer the queries `("bootloader_input", i)`.
+    pub bootloader_inputs: Vec<T>,
+}
+
+impl<T: FieldElement> ContinuationContext<T> {
+    /// Returns the context of the chunk after this one, given the witness of this chunk:
+    /// Its initial state are the values of the boundary columns (the columns of
+    /// [ContinuationContext::initial_state]) in the last row of `witness`.
+    /// Panics if a boundary column is not part of the witness.
+    pub fn next_chunk(&self, witness: &[(String, Vec<T>)], bootloader_inputs: Vec<T>) -> Self {
+        let initial_state = self
+            .initial_state
+            .iter()
+            .map(|(name, _)| {
+                let (_, values) = witness.iter().find(|(n, _)| n == name).unwrap_or_else(|| {
+                    panic!(
+                        "Boundary column {name} is not part of the witness of chunk {}.",
+                        self.chunk_index
+                    )
+                });
+                (name.clone(), *values.last().unwrap())
+            })
+            .collect();
+        ContinuationContext {
+            chunk_index: self.chunk_index + 1,
+            initial_state,
+            bootloader_inputs,
+        }
+    }
+
+    /// Answers the query if it asks for a bootloader input, returns `None` otherwise.
+    pub(crate) fn query(&self, query: &str) -> Option<Result<Option<T>, String>> {
+        if query_name(query) != "bootloader_input" {
+            return None;
+        }
+        let index = query
+            .trim()
+            .trim_end_matches(')')
+            .split(',')
+            .nth(1)
+            .and_then(|index| index.trim().parse::<usize>().ok());
+        Some(match index {
+            Some(index) => self
+                .bootloader_inputs
+                .get(index)
+                .map(|v| Some(*v))
+                .ok_or_else(|| {
+                    format!(
+                        "Chunk {} only has {} bootloader inputs, but input {index} was requested.",
+                        self.chunk_index,
+                        self.bootloader_inputs.len()
+                    )
+                }),
+            None => Err(format!("Invalid bootloader input query: {query}")),
+        })
+    }
+}
+
+impl<'a, 'b, T: FieldElement> WitnessGenerator<'a, 'b, T> {
+    /// Generates the witness of a single chunk of an execution that is split into chunks
+    /// (continuations): The boundary columns of the main machine are set to the
+    /// initial state of `context` in the first row (see [WitnessGenerator::with_initial_row])
+    /// and the queries `("bootloader_input", i)` are answered from its bootloader inputs
+    /// instead of the query callback. The initial state of the next chunk can be obtained
+    /// from the witness of this chunk using [ContinuationContext::next_chunk].
+    /// Panics if a boundary column does not exist.
+    pub fn with_continuation(self, context: ContinuationContext<T>) -> Self {
+        let initial_row = context
+            .initial_state
+            .iter()
+            .map(|(name, value)| {
+                let (symbol, _) = self.analyzed.definitions.get(name).unwrap_or_else(|| {
+                    panic!(
+                        "Boundary column {name} of chunk {} does not exist.",
+                        context.chunk_index
+                    )
+                });
+                (symbol.into(), *value)
+            })
+            .collect();
+        log::info!("Generating the witness of chunk {}.", context.chunk_index);
+        WitnessGenerator {
+            continuation: Some(context),
+            ..self.with_initial_row(initial_row)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use powdr_number::GoldilocksField;
+    use powdr_pil_analyzer::analyze_string;
+    use pretty_assertions::assert_eq;
+    use test_log::test;
+
+    use crate::constant_evaluator;
+    use crate::witgen::tests::values;
+    use crate::witgen::unused_query_callback;
+
+    use super::*;
+
+    #[test]
+    fn continuation_chunks() {
+        let src = r#"
+namespace main(4);
+    col fixed LAST = [0, 0, 0, 1];
+    col witness pc, acc;
+    col witness x(i) query ("bootloader_input", i);
+    (1 - LAST) * (pc' - pc - 1) = 0;
+    (1 - LAST) * (acc' - acc - x) = 0;
+"#;
+        let analyzed = analyze_string::<GoldilocksField>(src);
+        let fixed_cols = constant_evaluator::generate(&analyzed);
+        let query_callback = unused_query_callback();
+        let first = ContinuationContext {
+            chunk_index: 0,
+            initial_state: vec![
+                ("main.pc".to_string(), 0.into()),
+                ("main.acc".to_string(), 0.into()),
+            ],
+            bootloader_inputs: values([1, 2, 3, 4]),
+        };
+        let chunk = |context: &ContinuationContext<GoldilocksField>| {
+            WitnessGenerator::new(&analyzed, &fixed_cols, &query_callback)
+                .with_continuation(context.clone())
+                .generate()
+        };
+        let first_witness = chunk(&first);
+        assert_eq!(first_witness[0].1, values([0, 1, 2, 3]));
+        assert_eq!(first_witness[1].1, values([0, 1, 3, 6]));
+        let second = first.next_chunk(&first_witness, values([5, 6, 7, 8]));
+        assert_eq!(second.chunk_index, 1);
+        assert_eq!(
+            second.initial_state,
+            vec![
+                ("main.pc".to_string(), 3.into()),
+                ("main.acc".to_string(), 6.into()),
+            ]
+        );
+        let second_witness = chunk(&second);
+        assert_eq!(second_witness[0].1, values([3, 4, 5, 6]));
+        assert_eq!(second_witness[1].1, values([6, 11, 17, 24]));
+    }
+}
@@ -5,9 +5,12 @@ use std::{
 
 use bit_vec::BitVec;
 use powdr_ast::analyzed::PolyID;
-use powdr_number::FieldElement;
+use powdr_number::{DegreeType, FieldElement};
 
-use crate::witgen::rows::Row;
+use crate::witgen::column_storage::{ColumnStorage, ColumnWriter, SpillConfig};
+use crate::witgen::range_constraints::RangeConstraint;
+use crate::witgen::rows::{CellValue, Row};
+use crate::witgen::witness_rows::RowSender;
 
 /// A row entry in [FinalizableData].
 #[derive(Clone)]
@@ -25,12 +28,34 @@ enum Entry<'a, T: FieldElement> {
 /// constraints is freed. The information which cells are known is preserved, though.
 /// Once a row has been finalized, any operation trying to access it again will fail at runtime.
 /// [FinalizableData::take_transposed] can be used to access the final cells.
-#[derive(Clone)]
+/// Rows at the start can also be discarded entirely, see [FinalizableData::discard_until],
+/// or moved out of memory, see [FinalizableData::spill_until] and
+/// [FinalizableData::send_until].
 pub struct FinalizableData<'a, T: FieldElement> {
-    /// The list of rows (either in progress or finalized)
+    /// The list of rows (either in progress or finalized), starting at index `discarded`.
+    /// If rows are spilled or sent, the first row is followed by the rows after them.
     data: Vec<Entry<'a, T>>,
+    /// The number of rows at the start that were discarded.
+    discarded: usize,
     /// The list of column IDs (in sorted order), used to index finalized rows.
     column_ids: Vec<PolyID>,
+    spilled: Option<SpilledRows<T>>,
+    /// The index of the row after the last row that was sent, if rows were sent.
+    sent: Option<usize>,
+    /// The largest number of rows that were held in memory at the same time.
+    peak_resident_rows: usize,
+}
+
+/// The finalized rows after the first row that were moved out of memory,
+/// see [FinalizableData::spill_until].
+struct SpilledRows<T> {
+    /// The values of each column, starting with a placeholder for the first row,
+    /// which is kept in memory.
+    columns: Vec<ColumnWriter<T>>,
+    /// Which cells of each column are known, starting with the first row.
+    known_cells: Vec<BitVec>,
+    /// The index of the row after the last spilled row.
+    end: usize,
 }
 
 impl<'a, T: FieldElement> FinalizableData<'a, T> {
@@ -45,19 +70,115 @@ impl<'a, T: FieldElement> FinalizableData<'a, T> {
         let mut column_ids = column_ids.iter().cloned().collect::<Vec<_>>();
         column_ids.sort();
         let data = rows.map(Entry::InProgress).collect::<Vec<_>>();
-        Self { data, column_ids }
+        Self {
+            peak_resident_rows: data.len(),
+            data,
+            discarded: 0,
+            column_ids,
+            spilled: None,
+            sent: None,
+        }
     }
 
+    /// The number of rows, including discarded, spilled and sent ones.
     pub fn len(&self) -> usize {
-        self.data.len()
+        self.discarded + self.spilled_len() + self.sent_len() + self.data.len()
+    }
+
+    /// The number of spilled rows.
+    fn spilled_len(&self) -> usize {
+        self.spilled.as_ref().map_or(0, |spilled| spilled.end - 1)
+    }
+
+    /// The number of sent rows.
+    fn sent_len(&self) -> usize {
+        self.sent.map_or(0, |end| end - 1)
+    }
+
+    /// The largest number of rows that were held in memory at the same time.
+    pub fn peak_resident_rows(&self) -> usize {
+        self.peak_resident_rows
+    }
+
+    /// The position of row `i` in `data`.
+    fn position(&self, i: usize) -> usize {
+        assert!(i >= self.discarded, "Row {i} already discarded.");
+        match &self.spilled {
+            Some(spilled) if i > 0 => {
+                assert!(i >= spilled.end, "Row {i} already spilled.");
+                i - spilled.end + 1
+            }
+            _ => match self.sent {
+                Some(end) if i > 0 => {
+                    assert!(i >= end, "Row {i} already sent.");
+                    i - end + 1
+                }
+                _ => i - self.discarded,
+            },
+        }
+    }
+
+    fn update_peak_resident_rows(&mut self) {
+        self.peak_resident_rows = self.peak_resident_rows.max(self.data.len());
     }
 
     pub fn is_empty(&self) -> bool {
-        self.data.is_empty()
+        self.len() == 0
+    }
+
+    fn entry(&self, i: usize) -> &Entry<'a, T> {
+        &self.data[self.position(i)]
+    }
+
+    fn entry_mut(&mut self, i: usize) -> &mut Entry<'a, T> {
+        let position = self.position(i);
+        &mut self.data[position]
     }
 
     pub fn push(&mut self, row: Row<'a, T>) {
         self.data.push(Entry::InProgress(row));
+        self.update_peak_resident_rows();
+    }
+
+    /// Appends a row that is already finalized, given in the format of
+    /// [FinalizableData::known_values].
+    pub fn push_finalized(&mut self, values: Vec<T>, known_cells: BitVec) {
+        assert_eq!(values.len(), self.column_ids.len());
+        assert_eq!(known_cells.len(), self.column_ids.len());
+        self.data.push(Entry::Finalized(values, known_cells));
+        self.update_peak_resident_rows();
+    }
+
+    /// The IDs of the stored columns, in sorted order.
+    pub fn column_ids(&self) -> &[PolyID] {
+        &self.column_ids
+    }
+
+    /// Returns the values of the given row (in the order of [FinalizableData::column_ids])
+    /// and a bit vector indicating which cells are known, no matter if the row is finalized.
+    pub fn known_values(&self, i: usize) -> (Vec<T>, BitVec) {
+        match self.entry(i) {
+            Entry::InProgress(row) => row_values(&self.column_ids, row),
+            Entry::Finalized(values, known_cells) => (values.clone(), known_cells.clone()),
+        }
+    }
+
+    /// Returns the range constraints of the cells of the given row that are not known
+    /// (by their index in [FinalizableData::column_ids]), or nothing if the row is
+    /// finalized.
+    pub fn range_constraints(&self, i: usize) -> Vec<(usize, RangeConstraint<T>)> {
+        match self.entry(i) {
+            Entry::InProgress(row) => self
+                .column_ids
+                .iter()
+                .enumerate()
+                .filter_map(|(index, c)| match &row[c].value {
+                    CellValue::RangeConstraint(constraint) => Some((index, constraint.clone())),
+                    CellValue::Known(_) | CellValue::Unknown => None,
+                })
+                .collect(),
+            Entry::Finalized(_, _) => vec![],
+        }
     }
 
     pub fn pop(&mut self) -> Option<Row<'a, T>> {
@@ -68,23 +189,30 @@ impl<'a, T: FieldElement> FinalizableData<'a, T> {
         }
     }
 
-    pub fn extend(&mut self, other: Self) {
-        self.data.extend(other.data);
+    pub fn extend(&mut self, mut other: Self) {
+        assert!(
+            other.discarded == 0 && other.spilled.is_none() && other.sent.is_none(),
+            "Cannot extend by data with discarded, spilled or sent rows."
+        );
+        self.data.extend(std::mem::take(&mut other.data));
+        self.peak_resident_rows = self.peak_resident_rows.max(other.peak_resident_rows);
+        self.update_peak_resident_rows();
     }
 
     pub fn remove(&mut self, i: usize) -> Row<'a, T> {
-        match self.data.remove(i) {
+        match self.data.remove(self.position(i)) {
             Entry::InProgress(row) => row,
             Entry::Finalized(_, _) => panic!("Row {} already finalized.", i),
         }
     }
 
     pub fn truncate(&mut self, len: usize) {
-        self.data.truncate(len);
+        let position = self.position(len);
+        self.data.truncate(position);
     }
 
     pub fn get_mut(&mut self, i: usize) -> Option<&mut Row<'a, T>> {
-        match &mut self.data[i] {
+        match self.entry_mut(i) {
             Entry::InProgress(row) => Some(row),
             Entry::Finalized(_, _) => panic!("Row {} already finalized.", i),
         }
@@ -99,7 +227,14 @@ impl<'a, T: FieldElement> FinalizableData<'a, T> {
     }
 
     pub fn mutable_row_pair(&mut self, i: usize) -> (&mut Row<'a, T>, &mut Row<'a, T>) {
-        let (before, after) = self.data.split_at_mut(i + 1);
+        let position = self.position(i);
+        assert_eq!(
+            self.position(i + 1),
+            position + 1,
+            "Row {} already spilled.",
+            i + 1
+        );
+        let (before, after) = self.data.split_at_mut(position + 1);
         let current = before.last_mut().unwrap();
         let next = after.first_mut().unwrap();
         match (current, next) {
@@ -109,13 +244,9 @@ impl<'a, T: FieldElement> FinalizableData<'a, T> {
     }
 
     pub fn finalize(&mut self, i: usize) -> bool {
-        if let Entry::InProgress(row) = &self.data[i] {
-            let (values, known_cells) = self
-                .column_ids
-                .iter()
-                .map(|c| (row[c].value.unwrap_or_default(), row[c].value.is_known()))
-                .unzip();
-            self.data[i] = Entry::Finalized(values, known_cells);
+        if let Entry::InProgress(_) = self.entry(i) {
+            let (values, known_cells) = self.known_values(i);
+            *self.entry_mut(i) = Entry::Finalized(values, known_cells);
             true
         } else {
             false
@@ -128,39 +259,207 @@ impl<'a, T: FieldElement> FinalizableData<'a, T> {
         }
     }
 
+    /// Frees the memory of all rows before `end`. Their indices stay valid (i.e., the index
+    /// of later rows does not change), but any operation trying to access them will fail
+    /// at runtime.
+    pub fn discard_until(&mut self, end: usize) {
+        assert!(
+            self.spilled.is_none() && self.sent.is_none(),
+            "Cannot discard spilled or sent rows."
+        );
+        if end > self.discarded {
+            self.data.drain(..end - self.discarded);
+            self.discarded = end;
+        }
+    }
+
+    /// Moves all rows from the second row up to `end` (which have to be finalized) out of
+    /// memory, into the files given by `spill` (see [ColumnWriter]). Their indices stay
+    /// valid, but any operation trying to access them will fail at runtime.
+    /// The first row is kept in memory, because it is usually modified at the end.
+    pub fn spill_until(&mut self, end: usize, spill: &SpillConfig) {
+        assert_eq!(self.discarded, 0, "Cannot spill discarded rows.");
+        assert!(self.sent.is_none(), "Cannot spill sent rows.");
+        let spilled = self.spilled.get_or_insert_with(|| SpilledRows {
+            columns: self
+                .column_ids
+                .iter()
+                .map(|_| {
+                    let mut column = ColumnWriter::new(Some(spill));
+                    column.push(T::zero());
+                    column
+                })
+                .collect(),
+            known_cells: vec![BitVec::from_elem(1, false); self.column_ids.len()],
+            end: 1,
+        });
+        let start = spilled.end;
+        if end <= start {
+            return;
+        }
+        spilled.end = end;
+        for entry in self.data.drain(1..end - start + 1) {
+            let Entry::Finalized(values, known_cells) = entry else {
+                panic!("Cannot spill a row that is not finalized.");
+            };
+            for ((column, column_known_cells), (value, known)) in spilled
+                .columns
+                .iter_mut()
+                .zip(&mut spilled.known_cells)
+                .zip(values.into_iter().zip(known_cells.iter()))
+            {
+                column.push(value);
+                column_known_cells.push(known);
+            }
+        }
+    }
+
+    /// Moves all rows from the second row up to `end` (which have to be finalized) out of
+    /// memory and sends them (by index) to `row_sender`, like [FinalizableData::spill_until].
+    /// The first row is kept in memory, because it is usually modified at the end.
+    pub fn send_until(&mut self, end: usize, row_sender: &RowSender<T>) {
+        assert_eq!(self.discarded, 0, "Cannot send discarded rows.");
+        assert!(self.spilled.is_none(), "Cannot send spilled rows.");
+        let start = self.sent.unwrap_or(1);
+        if end <= start {
+            return;
+        }
+        self.sent = Some(end);
+        for (i, entry) in (start..end).zip(self.data.drain(1..end - start + 1)) {
+            let Entry::Finalized(values, _) = entry else {
+                panic!("Cannot send a row that is not finalized.");
+            };
+            row_sender.send(i as DegreeType, &values);
+        }
+    }
+
+    /// Finalizes all rows that are still in memory and sends them to `row_sender`, like
+    /// [FinalizableData::send_until], leaving no data.
+    pub fn send_remaining(&mut self, row_sender: &RowSender<T>) {
+        assert_eq!(self.discarded, 0, "Cannot send discarded rows.");
+        assert!(self.spilled.is_none(), "Cannot send spilled rows.");
+        self.finalize_all();
+        // The first row is followed by the rows after the sent rows.
+        let rows = std::iter::once(0).chain(self.sent.unwrap_or(1)..);
+        for (i, entry) in rows.zip(std::mem::take(&mut self.data)) {
+            let Entry::Finalized(values, _) = entry else {
+                unreachable!()
+            };
+            row_sender.send(i as DegreeType, &values);
+        }
+    }
+
+    /// Takes all data out of the [FinalizableData] and returns it as a list of columns,
+    /// like [FinalizableData::take_transposed], but without reading spilled rows back
+    /// into memory. The values of unknown cells should be ignored.
+    /// If rows were spilled, the columns are stored in files, otherwise only the columns
+    /// that exceed the threshold of `spill`.
+    pub fn take_columns(
+        &mut self,
+        spill: Option<&SpillConfig>,
+    ) -> impl Iterator<Item = (PolyID, ColumnStorage<T>)> {
+        assert_eq!(self.discarded, 0, "Cannot take data with discarded rows.");
+        assert!(self.sent.is_none(), "Cannot take data with sent rows.");
+        self.finalize_all();
+        let mut rows = std::mem::take(&mut self.data)
+            .into_iter()
+            .map(|entry| match entry {
+                Entry::InProgress(_) => unreachable!(),
+                Entry::Finalized(values, _) => values,
+            });
+        let mut columns = match self.spilled.take() {
+            Some(spilled) => {
+                let mut columns = spilled.columns;
+                for (column, value) in columns.iter_mut().zip(rows.next().unwrap()) {
+                    column.set(0, value);
+                }
+                columns
+            }
+            None => self
+                .column_ids
+                .iter()
+                .map(|_| ColumnWriter::new(spill))
+                .collect::<Vec<_>>(),
+        };
+        for values in rows {
+            for (column, value) in columns.iter_mut().zip(values) {
+                column.push(value);
+            }
+        }
+        std::mem::take(&mut self.column_ids)
+            .into_iter()
+            .zip(columns)
+            .map(|(id, column)| (id, column.finish()))
+    }
+
+    /// Finalizes all rows that are still in progress.
+    fn finalize_all(&mut self) {
+        log::debug!("Finalizing remaining rows...");
+        let mut counter = 0;
+        for entry in &mut self.data {
+            if let Entry::InProgress(row) = entry {
+                let (values, known_cells) = row_values(&self.column_ids, row);
+                *entry = Entry::Finalized(values, known_cells);
+                counter += 1;
+            }
+        }
+        log::debug!("Needed to finalize {} / {} rows.", counter, self.data.len());
+    }
+
     /// Takes all data out of the [FinalizableData] and returns it as a list of columns.
     /// Columns are represented as a tuple of:
     /// - A list of values
     /// - A bit vector indicating which cells are known. Values of unknown cells should be ignored.
+    /// Spilled rows are read back into memory.
     pub fn take_transposed(&mut self) -> impl Iterator<Item = (PolyID, (Vec<T>, BitVec))> {
+        assert_eq!(self.discarded, 0, "Cannot take data with discarded rows.");
+        assert!(self.sent.is_none(), "Cannot take data with sent rows.");
         log::debug!(
             "Transposing {} rows with {} columns...",
-            self.data.len(),
+            self.len(),
             self.column_ids.len()
         );
-        log::debug!("Finalizing remaining rows...");
-        let mut counter = 0;
-        for i in 0..self.data.len() {
-            if self.finalize(i) {
-                counter += 1;
-            }
-        }
-        log::debug!("Needed to finalize {} / {} rows.", counter, self.data.len());
+        self.finalize_all();
 
         // Store transposed columns in vectors for performance reasons
-        let mut columns = vec![vec![]; self.column_ids.len()];
-        let mut known_cells_col = vec![BitVec::new(); self.column_ids.len()];
-        for row in std::mem::take(&mut self.data) {
-            match row {
+        let mut rows = std::mem::take(&mut self.data)
+            .into_iter()
+            .map(|entry| match entry {
                 Entry::InProgress(_) => unreachable!(),
-                Entry::Finalized(row, known_cells) => {
-                    for (col_index, (value, is_known)) in
-                        row.into_iter().zip(known_cells).enumerate()
-                    {
-                        known_cells_col[col_index].push(is_known);
-                        columns[col_index].push(value);
-                    }
-                }
+                Entry::Finalized(row, known_cells) => (row, known_cells),
+            });
+        let (mut columns, mut known_cells_col) = match self.spilled.take() {
+            Some(spilled) => {
+                let (first_row, first_known_cells) = rows.next().unwrap();
+                let columns = spilled
+                    .columns
+                    .into_iter()
+                    .zip(first_row)
+                    .map(|(mut column, value)| {
+                        column.set(0, value);
+                        column.finish().into_vec()
+                    })
+                    .collect::<Vec<_>>();
+                let known_cells = spilled
+                    .known_cells
+                    .into_iter()
+                    .zip(first_known_cells)
+                    .map(|(mut known_cells, is_known)| {
+                        known_cells.set(0, is_known);
+                        known_cells
+                    })
+                    .collect::<Vec<_>>();
+                (columns, known_cells)
+            }
+            None => (
+                vec![vec![]; self.column_ids.len()],
+                vec![BitVec::new(); self.column_ids.len()],
+            ),
+        };
+        for (row, known_cells) in rows {
+            for (col_index, (value, is_known)) in row.into_iter().zip(known_cells).enumerate() {
+                known_cells_col[col_index].push(is_known);
+                columns[col_index].push(value);
             }
         }
 
@@ -176,11 +475,20 @@ impl<'a, T: FieldElement> FinalizableData<'a, T> {
     }
 }
 
+/// Returns the values of the row (in the order of `column_ids`) and a bit vector
+/// indicating which cells are known.
+fn row_values<T: FieldElement>(column_ids: &[PolyID], row: &Row<'_, T>) -> (Vec<T>, BitVec) {
+    column_ids
+        .iter()
+        .map(|c| (row[c].value.unwrap_or_default(), row[c].value.is_known()))
+        .unzip()
+}
+
 impl<'a, T: FieldElement> Index<usize> for FinalizableData<'a, T> {
     type Output = Row<'a, T>;
 
     fn index(&self, index: usize) -> &Self::Output {
-        match &self.data[index] {
+        match self.entry(index) {
             Entry::InProgress(row) => row,
             Entry::Finalized(_, _) => panic!("Row {} already finalized.", index),
         }
@@ -189,7 +497,7 @@ impl<'a, T: FieldElement> Index<usize> for FinalizableData<'a, T> {
 
 impl<'a, T: FieldElement> IndexMut<usize> for FinalizableData<'a, T> {
     fn index_mut(&mut self, index: usize) -> &mut Self::Output {
-        match &mut self.data[index] {
+        match self.entry_mut(index) {
             Entry::InProgress(row) => row,
             Entry::Finalized(_, _) => panic!("Row {} already finalized.", index),
         }
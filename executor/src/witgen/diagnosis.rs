@@ -0,0 +1,136 @@
+use std::collections::{BTreeSet, HashMap};
+
+use powdr_ast::analyzed::PolyID;
+use powdr_number::{DegreeType, FieldElement};
+
+use super::{EvalError, WitnessGenerator};
+
+/// The cells that could not be determined when witness generation failed,
+/// see [WitnessGenerator::diagnose].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnosis<T: FieldElement> {
+    /// The cells (by column and row) referenced by identities that could not be
+    /// completed.
+    pub unsolved_cells: BTreeSet<(PolyID, DegreeType)>,
+    /// The cells (by column and row) whose queries were not answered by the query
+    /// callback.
+    pub unanswered_queries: BTreeSet<(PolyID, DegreeType)>,
+    /// The error generation failed with, `None` if it succeeded.
+    pub error: Option<EvalError<T>>,
+}
+
+impl<T: FieldElement> Diagnosis<T> {
+    /// Collects the cells from the error (and the errors it contains), with the witness
+    /// columns given by name.
+    pub(crate) fn new(
+        error: Option<EvalError<T>>,
+        witness_columns: &HashMap<String, PolyID>,
+        degree: DegreeType,
+    ) -> Self {
+        let mut diagnosis = Diagnosis {
+            unsolved_cells: Default::default(),
+            unanswered_queries: Default::default(),
+            error: None,
+        };
+        if let Some(error) = &error {
+            diagnosis.collect(error, witness_columns, degree);
+        }
+        Diagnosis { error, ..diagnosis }
+    }
+
+    /// Returns true if generation succeeded.
+    pub fn is_complete(&self) -> bool {
+        self.error.is_none()
+    }
+
+    fn collect(
+        &mut self,
+        error: &EvalError<T>,
+        witness_columns: &HashMap<String, PolyID>,
+        degree: DegreeType,
+    ) {
+        match error {
+            EvalError::IdentityStuck {
+                row, unknown_cells, ..
+            } => self.unsolved_cells.extend(
+                unknown_cells
+                    .iter()
+                    .map(|cell| (cell.poly_id, (row + cell.next as DegreeType) % degree)),
+            ),
+            EvalError::UnansweredQuery { column, row, .. } => {
+                if let Some(poly_id) = witness_columns.get(column) {
+                    self.unanswered_queries.insert((*poly_id, *row));
+                }
+            }
+            EvalError::RowFailed { errors, .. } | EvalError::Multiple(errors) => {
+                for error in errors {
+                    self.collect(error, witness_columns, degree);
+                }
+            }
+            EvalError::InMachine { error, .. } => self.collect(error, witness_columns, degree),
+            _ => {}
+        }
+    }
+}
+
+impl<'a, 'b, T: FieldElement> WitnessGenerator<'a, 'b, T> {
+    /// Like [WitnessGenerator::validate_only], but returns the cells that could not be
+    /// determined and the cells whose queries were not answered in the row in which
+    /// solving stopped, as reported by the stuck identities of the failing row.
+    pub fn diagnose(self) -> Diagnosis<T> {
+        let witness_columns = self
+            .analyzed
+            .committed_polys_in_source_order()
+            .iter()
+            .flat_map(|(poly, _)| poly.array_elements())
+            .collect();
+        let degree = self.degree();
+        Diagnosis::new(self.validate_only().err(), &witness_columns, degree)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use powdr_number::GoldilocksField;
+    use powdr_pil_analyzer::analyze_string;
+    use pretty_assertions::assert_eq;
+    use test_log::test;
+
+    use crate::constant_evaluator;
+    use crate::witgen::unused_query_callback;
+
+    use super::*;
+
+    #[test]
+    fn diagnose() {
+        let src = r"
+namespace N(4);
+    col witness x, z, w;
+    x = 1;
+    z + w = x;
+";
+        let analyzed = analyze_string::<GoldilocksField>(src);
+        let fixed_cols = constant_evaluator::generate(&analyzed);
+        let query_callback = unused_query_callback();
+        let poly_id = |name: &str| -> PolyID { (&analyzed.definitions[name].0).into() };
+        let diagnosis = WitnessGenerator::new(&analyzed, &fixed_cols, &query_callback).diagnose();
+        assert!(!diagnosis.is_complete());
+        assert_eq!(
+            diagnosis.unsolved_cells,
+            [(poly_id("N.z"), 0), (poly_id("N.w"), 0)].into()
+        );
+        assert!(diagnosis.unanswered_queries.is_empty());
+
+        let src = r#"
+namespace N(4);
+    col witness x(i) query ("hint", i);
+    col witness y;
+    y = x + 1;
+"#;
+        let analyzed = analyze_string::<GoldilocksField>(src);
+        let query_callback = |_: &str| -> Result<Option<GoldilocksField>, String> { Ok(None) };
+        let poly_id = |name: &str| -> PolyID { (&analyzed.definitions[name].0).into() };
+        let diagnosis = WitnessGenerator::new(&analyzed, &[], &query_callback).diagnose();
+        assert_eq!(diagnosis.unanswered_queries, [(poly_id("N.x"), 0)].into());
+    }
+}
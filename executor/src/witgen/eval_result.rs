@@ -1,7 +1,10 @@
 use std::fmt;
 
+use itertools::Itertools;
 use powdr_ast::analyzed::AlgebraicReference;
-use powdr_number::FieldElement;
+use powdr_ast::SourceRef;
+use powdr_number::{DegreeType, FieldElement};
+use powdr_parser_util::lines::indent;
 
 use super::range_constraints::RangeConstraint;
 
@@ -47,9 +50,31 @@ pub enum IncompleteCause<K = usize> {
     SolvingFailed,
     /// Some knowledge was learnt, but not a concrete value. Example: `Y = X` if we know that `Y` is boolean. We learn that `X` is boolean, but not its exact value.
     NotConcrete,
+    /// A row could not be completed. Arguments: the cells that are still unknown, each with the identities that reference it.
+    PendingUnknowns(Vec<PendingUnknown>),
     Multiple(Vec<IncompleteCause<K>>),
 }
 
+/// A cell whose value could not be determined, together with the incomplete identities
+/// that reference it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PendingUnknown {
+    /// The column and whether it is referenced in the next row.
+    pub cell: AlgebraicReference,
+    /// The identities that reference the cell, rendered as strings.
+    pub identities: Vec<String>,
+}
+
+impl fmt::Display for PendingUnknown {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} is referenced by:", self.cell)?;
+        for identity in &self.identities {
+            write!(f, "\n{}", indent(identity, "    "))?;
+        }
+        Ok(())
+    }
+}
+
 impl<K> IncompleteCause<K> {
     pub fn combine(self, right: IncompleteCause<K>) -> IncompleteCause<K> {
         match (self, right) {
@@ -160,10 +185,87 @@ pub enum EvalError<T: FieldElement> {
     ConflictingRangeConstraints,
     /// A division pattern was recognized but the solution does not satisfy the range constraints.
     InvalidDivision,
+    /// Solving an affine constraint requires dividing by a coefficient that has no inverse
+    /// in the field (i.e. is zero). Contains the constraint.
+    NonInvertibleCoefficient(String),
     /// Fixed lookup failed
     FixedLookupFailed(Vec<(String, T)>),
     /// Error getting information from the prover.
     ProverQueryError(String),
+    /// An identity was still incomplete after processing a row.
+    IdentityStuck {
+        /// The (global) index of the row.
+        row: DegreeType,
+        /// The identity, rendered as a string.
+        identity: String,
+        source: SourceRef,
+        /// The cells referenced by the identity whose values could not be determined.
+        unknown_cells: Vec<AlgebraicReference>,
+    },
+    /// Two constraints determine different values for the same cell.
+    ConflictingConstraints {
+        /// The name of the column.
+        cell: String,
+        /// The (global) index of the row of the cell.
+        row: DegreeType,
+        /// The value that was determined first and the conflicting value.
+        values: (T, T),
+        /// The identities (or other sources) that determined the two values.
+        identities: (String, String),
+    },
+    /// Solving a row failed for the given reasons.
+    RowFailed {
+        /// The (global) index of the row.
+        row: DegreeType,
+        errors: Vec<EvalError<T>>,
+    },
+    /// Processing was aborted because a limit (e.g. a timeout) was exceeded.
+    Timeout {
+        /// The (global) index of the row that was processed.
+        row: DegreeType,
+        reason: String,
+    },
+    /// Processing was aborted because the cancellation flag was set.
+    Cancelled {
+        /// The (global) index of the row that was processed.
+        row: DegreeType,
+    },
+    /// The query callback did not answer the query of a column whose value could not be
+    /// determined otherwise, i.e. the host did not supply a required hint.
+    UnansweredQuery {
+        /// The name of the column.
+        column: String,
+        /// The query, as passed to the query callback.
+        query_string: String,
+        /// The (global) index of the row.
+        row: DegreeType,
+    },
+    /// An identity without references to columns evaluates to a non-zero constant,
+    /// so no witness can satisfy it. Detected before any row is solved.
+    UnsatisfiablePil {
+        /// The identity, rendered as a string.
+        identity: String,
+        source: SourceRef,
+        /// The constant the identity evaluates to.
+        value: T,
+    },
+    /// An identity that only references fixed columns does not hold in a row.
+    /// Detected before any row is solved.
+    FixedIdentityViolated {
+        /// The identity, rendered as a string.
+        identity: String,
+        source: SourceRef,
+        /// The (global) index of the first row in which the identity does not hold.
+        row: DegreeType,
+        /// The value the identity evaluates to in that row.
+        value: T,
+    },
+    /// An error in a machine that was called through a lookup, together with the names
+    /// of the machines the error passed through, the outermost called machine first.
+    InMachine {
+        path: Vec<String>,
+        error: Box<EvalError<T>>,
+    },
     Generic(String),
     Multiple(Vec<EvalError<T>>),
 }
@@ -186,6 +288,50 @@ impl<T: FieldElement> EvalError<T> {
             (l, r) => EvalError::Multiple(vec![l, r]),
         }
     }
+
+    /// Records that the error occurred in (or in a machine called by) the given machine,
+    /// when it is returned through a lookup into that machine.
+    pub fn in_machine(self, machine: &str) -> EvalError<T> {
+        match self {
+            EvalError::InMachine { mut path, error } => {
+                path.insert(0, machine.to_string());
+                EvalError::InMachine { path, error }
+            }
+            error => EvalError::InMachine {
+                path: vec![machine.to_string()],
+                error: Box::new(error),
+            },
+        }
+    }
+
+    /// Applies `f` to the error, but keeps the path of machines the error passed through,
+    /// see [EvalError::in_machine].
+    pub(crate) fn map_keeping_machine_path(
+        self,
+        f: impl FnOnce(EvalError<T>) -> EvalError<T>,
+    ) -> EvalError<T> {
+        match self {
+            EvalError::InMachine { path, error } => EvalError::InMachine {
+                path,
+                error: Box::new(f(*error)),
+            },
+            error => f(error),
+        }
+    }
+
+    /// Returns the longest path of machines recorded in the error or the errors it
+    /// contains, see [EvalError::in_machine].
+    pub fn machine_path(&self) -> &[String] {
+        match self {
+            EvalError::InMachine { path, .. } => path,
+            EvalError::RowFailed { errors, .. } | EvalError::Multiple(errors) => errors
+                .iter()
+                .map(|e| e.machine_path())
+                .max_by_key(|path| path.len())
+                .unwrap_or_default(),
+            _ => &[],
+        }
+    }
 }
 
 impl<T: FieldElement> fmt::Display for EvalError<T> {
@@ -206,6 +352,12 @@ impl<T: FieldElement> fmt::Display for EvalError<T> {
             EvalError::InvalidDivision => {
                 write!(f, "A division pattern was recognized but the range constrainst are conflicting with the solution.",)
             }
+            EvalError::NonInvertibleCoefficient(e) => {
+                write!(
+                    f,
+                    "Cannot solve {e} = 0: the coefficient of the unknown variable is not invertible."
+                )
+            }
             EvalError::RowsExhausted => write!(f, "Table rows exhausted"),
             EvalError::FixedLookupFailed(input_assignment) => {
                 let query = input_assignment
@@ -221,6 +373,98 @@ impl<T: FieldElement> fmt::Display for EvalError<T> {
             EvalError::ProverQueryError(s) => {
                 write!(f, "Error getting external information from the prover: {s}")
             }
+            EvalError::IdentityStuck {
+                row,
+                identity,
+                source,
+                unknown_cells,
+            } => {
+                write!(f, "row {row}: identity `{identity}`")?;
+                if source.line > 0 {
+                    let file = source.file.as_deref().unwrap_or("<unknown file>");
+                    write!(f, " (at {file}:{}:{})", source.line, source.col)?;
+                }
+                if unknown_cells.is_empty() {
+                    write!(f, " could not be completed")
+                } else {
+                    write!(
+                        f,
+                        " could not determine {}",
+                        unknown_cells.iter().map(|c| format!("`{c}`")).join(", ")
+                    )
+                }
+            }
+            EvalError::ConflictingConstraints {
+                cell,
+                row,
+                values: (first_value, second_value),
+                identities: (first_identity, second_identity),
+            } => write!(
+                f,
+                "Conflicting constraints for {cell} in row {row}: `{first_identity}` implies \
+                 {cell} = {first_value}, but `{second_identity}` implies {cell} = {second_value}"
+            ),
+            EvalError::RowFailed { row, errors } => {
+                write!(f, "Row {row} failed:")?;
+                for e in errors {
+                    write!(f, "\n{}", indent(&e.to_string(), "    "))?;
+                }
+                Ok(())
+            }
+            EvalError::Timeout { row, reason } => {
+                write!(f, "Witness generation was aborted in row {row}: {reason}")
+            }
+            EvalError::Cancelled { row } => {
+                write!(f, "Witness generation was cancelled in row {row}.")
+            }
+            EvalError::UnansweredQuery {
+                column,
+                query_string,
+                row,
+            } => write!(
+                f,
+                "The query \"{query_string}\" for column {column} in row {row} was not answered, \
+                 but the value of the column is not determined by any identity."
+            ),
+            EvalError::UnsatisfiablePil {
+                identity,
+                source,
+                value,
+            } => {
+                write!(f, "The identity `{identity}`")?;
+                if source.line > 0 {
+                    let file = source.file.as_deref().unwrap_or("<unknown file>");
+                    write!(f, " (at {file}:{}:{})", source.line, source.col)?;
+                }
+                write!(
+                    f,
+                    " can never be satisfied, it evaluates to {value} in every row."
+                )
+            }
+            EvalError::FixedIdentityViolated {
+                identity,
+                source,
+                row,
+                value,
+            } => {
+                write!(f, "The identity `{identity}`")?;
+                if source.line > 0 {
+                    let file = source.file.as_deref().unwrap_or("<unknown file>");
+                    write!(f, " (at {file}:{}:{})", source.line, source.col)?;
+                }
+                write!(
+                    f,
+                    " only references fixed columns, but evaluates to {value} in row {row}."
+                )
+            }
+            EvalError::InMachine { path, error } => {
+                write!(
+                    f,
+                    "In machine {}:\n{}",
+                    path.join(" -> "),
+                    indent(&error.to_string(), "    ")
+                )
+            }
             EvalError::Generic(s) => write!(f, "{s}"),
         }
     }
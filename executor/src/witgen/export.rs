@@ -0,0 +1,96 @@
+//! An export of witnesses as CSV, to inspect them in a spreadsheet or a text editor.
+//! In contrast to the binary serialization, the output is not meant to be read back.
+
+use std::io::{self, BufWriter, ErrorKind, Write};
+
+use powdr_number::{DegreeType, FieldElement};
+
+/// Writes the witness columns (each with `degree` values) to `writer` as CSV: a header
+/// with the column names, followed by one line per row with the values in decimal.
+/// The values are written row by row, so no string of the whole output is built.
+pub fn write_witness_csv<T: FieldElement, W: Write>(
+    columns: &[(String, Vec<T>)],
+    degree: DegreeType,
+    writer: W,
+) -> io::Result<()> {
+    if let Some((name, values)) = columns
+        .iter()
+        .find(|(_, values)| values.len() as DegreeType != degree)
+    {
+        return Err(io::Error::new(
+            ErrorKind::InvalidInput,
+            format!(
+                "Column {name} has {} rows, but the degree is {degree}.",
+                values.len()
+            ),
+        ));
+    }
+    let mut writer = BufWriter::new(writer);
+    write_line(&mut writer, columns.iter().map(|(name, _)| escape(name)))?;
+    for row in 0..degree as usize {
+        write_line(
+            &mut writer,
+            columns
+                .iter()
+                .map(|(_, values)| values[row].to_integer().to_string()),
+        )?;
+    }
+    writer.flush()
+}
+
+fn write_line(writer: &mut impl Write, fields: impl Iterator<Item = String>) -> io::Result<()> {
+    for (i, field) in fields.enumerate() {
+        if i > 0 {
+            writer.write_all(b",")?;
+        }
+        writer.write_all(field.as_bytes())?;
+    }
+    writer.write_all(b"\n")
+}
+
+/// Quotes the field if it contains characters that are special in CSV.
+fn escape(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use powdr_number::GoldilocksField;
+
+    use super::*;
+
+    #[test]
+    fn header_and_rows() {
+        let columns = vec![
+            (
+                "main.b".to_string(),
+                (0..3).map(GoldilocksField::from).collect(),
+            ),
+            (
+                "main.a[0]".to_string(),
+                vec![7.into(), (-1).into(), 0.into()],
+            ),
+            ("main,c".to_string(), vec![1.into(); 3]),
+        ];
+        let mut buf = vec![];
+        write_witness_csv(&columns, 3, &mut buf).unwrap();
+        assert_eq!(
+            String::from_utf8(buf).unwrap(),
+            "main.b,main.a[0],\"main,c\"\n\
+             0,7,1\n\
+             1,18446744069414584320,1\n\
+             2,0,1\n"
+        );
+    }
+
+    #[test]
+    fn wrong_degree() {
+        let columns = vec![("main.a".to_string(), vec![GoldilocksField::from(1)])];
+        let err = write_witness_csv(&columns, 2, io::sink()).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    }
+}
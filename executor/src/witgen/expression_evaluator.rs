@@ -32,6 +32,9 @@ where
     }
     /// Tries to evaluate the expression to an expression affine in the witness polynomials,
     /// taking current values of polynomials into account.
+    /// Elements of (fixed or witness) column arrays are already resolved to references
+    /// to the element columns by the PIL analyzer, which evaluates the index at that
+    /// point, so there are no index accesses left to evaluate here.
     /// @returns an expression affine in the witness polynomials
     pub fn evaluate<'a>(&self, expr: &'a Expression<T>) -> AffineResult<&'a AlgebraicReference, T> {
         // @TODO if we iterate on processing the constraints in the same row,
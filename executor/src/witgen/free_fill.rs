@@ -0,0 +1,143 @@
+use std::collections::{HashMap, HashSet};
+
+use num_bigint::BigUint;
+use powdr_ast::analyzed::{AlgebraicExpression, Analyzed, Identity, PolyID};
+use powdr_number::{BigInt, DegreeType, FieldElement};
+
+use super::global_constraints::GlobalConstraints;
+use super::machines;
+use super::range_constraints::RangeConstraint;
+use super::WitnessGenerator;
+
+/// How the cells of witness columns that are only constrained by their range are
+/// filled, see [WitnessGenerator::with_free_fill].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FreeFill {
+    /// Sets all cells to zero, or to the lower bound of their range if it does not
+    /// contain zero.
+    Zero,
+    /// Sets the cells to pseudo-random values within their range. The value of each cell
+    /// only depends on the seed, the column and the row, so the same seed always results
+    /// in the same witness.
+    Random(u64),
+}
+
+impl FreeFill {
+    /// Returns the value of the cell of the column (by ID) in the given row.
+    pub(crate) fn value<T: FieldElement>(
+        &self,
+        column: u64,
+        row: DegreeType,
+        range: Option<&RangeConstraint<T>>,
+    ) -> T {
+        match (self, range) {
+            (FreeFill::Zero, None) => T::zero(),
+            (FreeFill::Zero, Some(range)) => {
+                let (min, max) = range.range();
+                // Zero is in a wrapping range if and only if the range starts after its end.
+                if min <= max && !min.is_zero() {
+                    min
+                } else {
+                    T::zero()
+                }
+            }
+            (FreeFill::Random(seed), None) => T::from(random(*seed, column, row)),
+            (FreeFill::Random(seed), Some(range)) => {
+                let (min, _) = range.range();
+                let width = range.range_width().to_arbitrary_integer();
+                let value = min + T::from(BigUint::from(random(*seed, column, row)) % width);
+                T::from(value.to_integer() & *range.mask())
+            }
+        }
+    }
+}
+
+/// Returns a pseudo-random number for the cell, using the finalizer of splitmix64.
+fn random(seed: u64, column: u64, row: DegreeType) -> u64 {
+    let mut x = seed ^ column.rotate_left(32) ^ row.wrapping_mul(0x9e37_79b9_7f4a_7c15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
+
+/// The witness columns that are not referenced by any of the identities, i.e. that are
+/// at most constrained by their range, by name, see [WitnessGenerator::with_free_fill].
+pub(crate) fn free_columns<'a, T: FieldElement>(
+    analyzed: &Analyzed<T>,
+    constraints: &GlobalConstraints<T>,
+    identities: impl Iterator<Item = &'a Identity<AlgebraicExpression<T>>>,
+) -> HashMap<String, (PolyID, Option<RangeConstraint<T>>)> {
+    let referenced = identities
+        .flat_map(machines::machine_extractor::refs_in_identity)
+        .collect::<HashSet<_>>();
+    analyzed
+        .committed_polys_in_source_order()
+        .iter()
+        .flat_map(|(poly, _)| poly.array_elements())
+        .filter(|(_, poly_id)| !referenced.contains(poly_id))
+        .map(|(name, poly_id)| {
+            let range = constraints.column_range_constraint(&poly_id);
+            (name, (poly_id, range))
+        })
+        .collect()
+}
+
+impl<'a, 'b, T: FieldElement> WitnessGenerator<'a, 'b, T> {
+    /// Fills the witness columns that are only constrained by their range (e.g. blinding
+    /// columns that are only range-checked by a lookup into a fixed column) according to
+    /// `fill`, instead of leaving them at zero. Values given as external witness take
+    /// precedence. With [FreeFill::Random], the witness is reproducible for the same seed.
+    pub fn with_free_fill(self, fill: FreeFill) -> Self {
+        WitnessGenerator {
+            free_fill: Some(fill),
+            ..self
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use powdr_number::GoldilocksField;
+    use powdr_pil_analyzer::analyze_string;
+    use pretty_assertions::assert_eq;
+    use test_log::test;
+
+    use crate::constant_evaluator;
+    use crate::witgen::tests::values;
+    use crate::witgen::unused_query_callback;
+
+    use super::*;
+
+    #[test]
+    fn free_fill() {
+        let src = r"
+namespace N(8);
+    col fixed BYTE(i) { i };
+    col witness x, r, u;
+    x = 1;
+    { r } in { BYTE };
+";
+        let analyzed = analyze_string::<GoldilocksField>(src);
+        let fixed_cols = constant_evaluator::generate(&analyzed);
+        let query_callback = unused_query_callback();
+        let generate = |fill| {
+            WitnessGenerator::new(&analyzed, &fixed_cols, &query_callback)
+                .with_free_fill(fill)
+                .generate()
+        };
+        let column = |witness: &[(String, Vec<GoldilocksField>)], name: &str| {
+            witness.iter().find(|(n, _)| n == name).unwrap().1.clone()
+        };
+
+        let witness = generate(FreeFill::Random(1));
+        assert_eq!(witness, generate(FreeFill::Random(1)));
+        assert_eq!(column(&witness, "N.x"), values([1; 8]));
+        assert!(column(&witness, "N.r").iter().all(|v| v.to_degree() < 8));
+        assert_ne!(column(&witness, "N.u"), values([0; 8]));
+        assert_ne!(witness, generate(FreeFill::Random(2)));
+
+        let witness = generate(FreeFill::Zero);
+        assert_eq!(column(&witness, "N.r"), values([0; 8]));
+        assert_eq!(column(&witness, "N.u"), values([0; 8]));
+    }
+}
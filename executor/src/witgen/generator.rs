@@ -13,17 +13,47 @@ use crate::witgen::EvalValue;
 
 use super::affine_expression::AffineExpression;
 use super::block_processor::BlockProcessor;
+use super::call_cache::CallCache;
+use super::checkpoint::{Checkpoint, CheckpointWriter};
+use super::column_storage::{ColumnStorage, SpillConfig};
 use super::data_structures::column_map::WitnessColumnMap;
 use super::global_constraints::GlobalConstraints;
 use super::machines::{FixedLookup, Machine};
+use super::progress::ProgressReporter;
 use super::rows::{Row, RowFactory};
 use super::sequence_iterator::{DefaultSequenceIterator, ProcessingSequenceIterator};
-use super::vm_processor::VmProcessor;
-use super::{EvalResult, FixedData, MutableState, QueryCallback};
+use super::statistics;
+use super::vm_processor::{
+    Limits, PaddingStrategy, SolvingDirection, VmProcessor, DEFAULT_WINDOW_SIZE,
+};
+use super::witness_rows::RowSender;
+use super::{EvalError, EvalResult, FixedData, MutableState, QueryCallback, RowObserver, RunMode};
 
 struct ProcessResult<'a, T: FieldElement> {
-    eval_value: EvalValue<&'a AlgebraicReference, T>,
+    /// An error is only returned if errors are not turned into panics (see [RunMode]).
+    eval_value: Result<EvalValue<&'a AlgebraicReference, T>, EvalError<T>>,
+    /// The rows computed so far, even if there was an error.
     block: FinalizableData<'a, T>,
+    /// The global index of the first padding row, if the program ended.
+    program_end: Option<DegreeType>,
+}
+
+/// Hooks that are only used when running the main machine from the first row.
+struct MainRun<'p, T> {
+    progress: Option<ProgressReporter<'p>>,
+    checkpoints: Option<CheckpointWriter<'p, T>>,
+    row_observer: Option<&'p mut dyn RowObserver<T>>,
+    mode: RunMode,
+    limits: Limits<'p>,
+    /// If set, only the rows up to (excluding) this row are solved.
+    end_row: Option<DegreeType>,
+    /// The number of rows without progress before the first row, if resumed from a
+    /// checkpoint.
+    rows_without_progress: DegreeType,
+    /// If set, rows are moved out of memory once they are finalized.
+    spill: Option<&'p SpillConfig>,
+    /// If set, rows are sent once they are finalized.
+    row_sender: Option<&'p RowSender<T>>,
 }
 
 pub struct Generator<'a, T: FieldElement> {
@@ -34,6 +64,21 @@ pub struct Generator<'a, T: FieldElement> {
     data: FinalizableData<'a, T>,
     latch: Option<Expression<T>>,
     name: String,
+    /// The direction in which the rows are solved when running from the first row.
+    direction: SolvingDirection,
+    /// How the rows after the end of the program are filled when running from the first row.
+    padding: Option<PaddingStrategy<'a, T>>,
+    /// The number of rows processed together when running from the first row.
+    window_size: usize,
+    /// The known cells of the first row when running from the first row.
+    initial_row: Option<Row<'a, T>>,
+    /// The number of rows used by the program, once [Generator::run] detected its end.
+    program_end: Option<DegreeType>,
+    /// Where finalized rows are moved when running from the first row.
+    spill: Option<SpillConfig>,
+    /// Receives the finalized rows when running from the first row, instead of returning
+    /// them as columns.
+    row_sender: Option<RowSender<T>>,
 }
 
 impl<'a, T: FieldElement> Machine<'a, T> for Generator<'a, T> {
@@ -41,6 +86,10 @@ impl<'a, T: FieldElement> Machine<'a, T> for Generator<'a, T> {
         &self.name
     }
 
+    fn latch(&self) -> Option<&Expression<T>> {
+        self.latch.as_ref()
+    }
+
     fn process_plookup<Q: QueryCallback<T>>(
         &mut self,
         mutable_state: &mut MutableState<'a, '_, T, Q>,
@@ -67,8 +116,13 @@ impl<'a, T: FieldElement> Machine<'a, T> for Generator<'a, T> {
                 left: left.to_vec(),
                 right,
             };
-            let ProcessResult { eval_value, block } =
-                self.process(first_row, 0, mutable_state, Some(outer_query), false);
+            let ProcessResult {
+                eval_value, block, ..
+            } = self.process(first_row, 0, mutable_state, Some(outer_query), None);
+            let eval_value = match eval_value {
+                Ok(eval_value) => eval_value,
+                Err(e) => return Some(Err(e)),
+            };
 
             if eval_value.is_complete() {
                 log::trace!("End processing VM '{}' (successfully)", self.name());
@@ -91,10 +145,13 @@ impl<'a, T: FieldElement> Machine<'a, T> for Generator<'a, T> {
         log::debug!("Finalizing VM: {}", self.name());
 
         // In this stage, we don't have access to other machines, as they might already be finalized.
+        let mut call_cache = CallCache::new(false);
         let mut mutable_state_no_machines = MutableState {
             fixed_lookup,
             machines: [].into_iter().into(),
             query_callback,
+            call_cache: &mut call_cache,
+            caller: None,
         };
 
         self.fill_remaining_rows(&mut mutable_state_no_machines);
@@ -115,6 +172,7 @@ impl<'a, T: FieldElement> Generator<'a, T> {
         witnesses: HashSet<PolyID>,
         global_range_constraints: &GlobalConstraints<T>,
         latch: Option<Expression<T>>,
+        initial_row: Option<Row<'a, T>>,
     ) -> Self {
         let data = FinalizableData::new(&witnesses);
         Self {
@@ -125,16 +183,346 @@ impl<'a, T: FieldElement> Generator<'a, T> {
             global_range_constraints: global_range_constraints.clone(),
             data,
             latch,
+            direction: SolvingDirection::Forward,
+            padding: None,
+            window_size: DEFAULT_WINDOW_SIZE,
+            initial_row,
+            program_end: None,
+            spill: None,
+            row_sender: None,
+        }
+    }
+
+    /// Sets the direction in which the rows are solved by [Generator::run] and the
+    /// other functions that run the machine from the first row.
+    /// Calls from other machines are always solved forward.
+    pub fn with_direction(self, direction: SolvingDirection) -> Self {
+        Self { direction, ..self }
+    }
+
+    /// Sets the padding of the rows after the end of the program when running from the
+    /// first row, see [PaddingStrategy].
+    pub fn with_padding(self, padding: Option<PaddingStrategy<'a, T>>) -> Self {
+        Self { padding, ..self }
+    }
+
+    /// Sets the number of rows whose identities are processed together when running
+    /// from the first row, see [VmProcessor::with_window_size].
+    pub fn with_window_size(self, window_size: usize) -> Self {
+        Self {
+            window_size,
+            ..self
+        }
+    }
+
+    /// Moves the rows into files once they are finalized when running from the first
+    /// row (see [VmProcessor::with_spill]). The columns returned by
+    /// [Generator::take_witness_columns] are stored in files if they exceed the threshold.
+    pub fn with_spill(self, spill: Option<SpillConfig>) -> Self {
+        Self { spill, ..self }
+    }
+
+    /// Sends the rows once they are finalized when running from the first row (see
+    /// [VmProcessor::with_row_sender]). [Generator::take_witness_columns] then sends the
+    /// remaining rows and returns no columns.
+    pub fn with_row_sender(self, row_sender: Option<RowSender<T>>) -> Self {
+        Self { row_sender, ..self }
+    }
+
+    /// Runs the machine without any arguments from the first row, or from the last row
+    /// of the checkpoint, if one is given and has rows.
+    /// Only returns an error if one of the limits is exceeded or if the checkpoint does
+    /// not belong to the machine or its calls cannot be replayed.
+    pub fn run<'b, Q: QueryCallback<T>>(
+        &mut self,
+        mutable_state: &mut MutableState<'a, 'b, T, Q>,
+        checkpoint: Option<Checkpoint<T>>,
+        mut progress: Option<ProgressReporter<'_>>,
+        mut checkpoints: Option<CheckpointWriter<'_, T>>,
+        mut row_observer: Option<&mut dyn RowObserver<T>>,
+        limits: Limits<'_>,
+    ) -> Result<(), EvalError<T>> {
+        if let Some(checkpoint) = &checkpoint {
+            checkpoint.check_columns(self.fixed_data, &self.witnesses)?;
+        }
+        record_start(self.name());
+        assert!(self.data.is_empty());
+        if let Some(progress) = &mut progress {
+            progress.machine_started(&self.name);
+        }
+        let mut rows_without_progress = 0;
+        let first_row = match checkpoint {
+            Some(checkpoint) if checkpoint.is_empty() => {
+                log::info!("The checkpoint does not contain any rows, starting from the first row");
+                self.compute_partial_first_row(mutable_state)
+            }
+            Some(checkpoint) => {
+                log::info!("Resuming from checkpoint with {} rows", checkpoint.len());
+                if let Some(checkpoints) = &mut checkpoints {
+                    checkpoints
+                        .write_checkpoint(&checkpoint)
+                        .unwrap_or_else(|e| panic!("Failed to write checkpoint: {e}"));
+                }
+                let row_factory =
+                    RowFactory::new(self.fixed_data, self.global_range_constraints.clone());
+                if let Err(e) = checkpoint.replay_calls(
+                    self.fixed_data,
+                    &self.identities,
+                    &self.witnesses,
+                    &row_factory,
+                    mutable_state,
+                ) {
+                    record_end(self.name());
+                    return Err(e);
+                }
+                rows_without_progress = checkpoint.rows_without_progress();
+                self.data = checkpoint.into_data(&self.witnesses, &row_factory);
+                // Processing continues from the last row of the checkpoint.
+                self.data.pop().unwrap()
+            }
+            None => self.compute_partial_first_row(mutable_state),
+        };
+        let ProcessResult {
+            eval_value,
+            block,
+            program_end,
+        } = self.process(
+            first_row,
+            self.data.len() as DegreeType,
+            mutable_state,
+            None,
+            Some(MainRun {
+                progress: progress.as_mut().map(ProgressReporter::reborrow),
+                checkpoints,
+                row_observer: row_observer
+                    .as_mut()
+                    .map(|observer| &mut **observer as &mut dyn RowObserver<T>),
+                mode: RunMode::Generate,
+                limits,
+                end_row: None,
+                rows_without_progress,
+                // Rows can only be spilled or sent when solving from the first row.
+                spill: self.spill.as_ref().filter(|_| self.data.is_empty()),
+                row_sender: self.row_sender.as_ref().filter(|_| self.data.is_empty()),
+            }),
+        );
+        if self.data.is_empty() {
+            self.data = block;
+        } else {
+            self.data.extend(block);
         }
+        self.program_end = program_end;
+        if let Some(progress) = &mut progress {
+            progress.machine_finished(&self.name);
+        }
+        record_end(self.name());
+        eval_value.map(|_| ())
+    }
+
+    /// The number of rows used by the program, if [Generator::run] detected its end
+    /// because a padding strategy is set.
+    pub fn program_end(&self) -> Option<DegreeType> {
+        self.program_end
+    }
+
+    /// Runs the machine from the first row like [Generator::run], but only checks that
+    /// all rows can be solved. Rows are discarded once they are not needed anymore,
+    /// so the machine is left without data.
+    /// Returns the error of the first row that could not be solved.
+    pub fn validate<Q: QueryCallback<T>>(
+        &mut self,
+        mutable_state: &mut MutableState<'a, '_, T, Q>,
+        progress: Option<ProgressReporter<'_>>,
+        row_observer: Option<&mut dyn RowObserver<T>>,
+        limits: Limits<'_>,
+    ) -> Result<(), EvalError<T>> {
+        self.run_until_error(
+            mutable_state,
+            progress,
+            row_observer,
+            RunMode::Validate,
+            limits,
+        )
+    }
+
+    /// Runs the machine from the first row like [Generator::run], but stops at the first
+    /// row that cannot be solved instead of panicking. The rows computed so far are kept
+    /// and can be retrieved with [Generator::take_partial_witness].
+    /// Returns the error of the row that could not be solved.
+    pub fn run_partial<Q: QueryCallback<T>>(
+        &mut self,
+        mutable_state: &mut MutableState<'a, '_, T, Q>,
+        progress: Option<ProgressReporter<'_>>,
+        row_observer: Option<&mut dyn RowObserver<T>>,
+        limits: Limits<'_>,
+    ) -> Result<(), EvalError<T>> {
+        self.run_until_error(
+            mutable_state,
+            progress,
+            row_observer,
+            RunMode::Partial,
+            limits,
+        )
     }
 
-    /// Runs the machine without any arguments from the first row.
-    pub fn run<'b, Q: QueryCallback<T>>(&mut self, mutable_state: &mut MutableState<'a, 'b, T, Q>) {
+    fn run_until_error<Q: QueryCallback<T>>(
+        &mut self,
+        mutable_state: &mut MutableState<'a, '_, T, Q>,
+        mut progress: Option<ProgressReporter<'_>>,
+        mut row_observer: Option<&mut dyn RowObserver<T>>,
+        mode: RunMode,
+        limits: Limits<'_>,
+    ) -> Result<(), EvalError<T>> {
         record_start(self.name());
         assert!(self.data.is_empty());
+        if let Some(progress) = &mut progress {
+            progress.machine_started(&self.name);
+        }
         let first_row = self.compute_partial_first_row(mutable_state);
-        self.data = self.process(first_row, 0, mutable_state, None, true).block;
+        let ProcessResult {
+            eval_value, block, ..
+        } = self.process(
+            first_row,
+            0,
+            mutable_state,
+            None,
+            Some(MainRun {
+                progress: progress.as_mut().map(ProgressReporter::reborrow),
+                checkpoints: None,
+                row_observer: row_observer
+                    .as_mut()
+                    .map(|observer| &mut **observer as &mut dyn RowObserver<T>),
+                mode,
+                limits,
+                end_row: None,
+                rows_without_progress: 0,
+                spill: None,
+                row_sender: None,
+            }),
+        );
+        if mode == RunMode::Partial {
+            self.data.extend(block);
+        }
+        if let Some(progress) = &mut progress {
+            progress.machine_finished(&self.name);
+        }
+        record_end(self.name());
+        eval_value.map(|_| ())
+    }
+
+    /// Solves only the rows `start..end` of the machine, which is run without arguments.
+    /// The values of row `start` that cannot be determined from the identities within
+    /// the row and the values of row `end` have to be provided externally: The row pair
+    /// `(start - 1, start)` is not processed, but `(end - 1, end)` is, so row `end` is
+    /// checked against the last row of the window.
+    /// The solved rows can be retrieved with [Generator::take_window_witness].
+    /// Only returns an error if one of the limits is exceeded.
+    pub fn run_window<Q: QueryCallback<T>>(
+        &mut self,
+        mutable_state: &mut MutableState<'a, '_, T, Q>,
+        start: DegreeType,
+        end: DegreeType,
+        limits: Limits<'_>,
+    ) -> Result<(), EvalError<T>> {
+        record_start(self.name());
+        assert!(self.data.is_empty());
+        let row_factory = RowFactory::new(self.fixed_data, self.global_range_constraints.clone());
+        let ProcessResult {
+            eval_value, block, ..
+        } = self.process(
+            row_factory.fresh_row(start),
+            start,
+            mutable_state,
+            None,
+            Some(MainRun {
+                progress: None,
+                checkpoints: None,
+                row_observer: None,
+                mode: RunMode::Generate,
+                limits,
+                end_row: Some(end),
+                rows_without_progress: 0,
+                spill: None,
+                row_sender: None,
+            }),
+        );
+        self.data.extend(block);
+        // Row `end` is not part of the window.
+        self.data.pop();
         record_end(self.name());
+        eval_value.map(|_| ())
+    }
+
+    /// Like `take_witness_col_values`, but the rows that were spilled while running
+    /// (see [Generator::with_spill]) are not read back into memory. The columns of the
+    /// machine are stored in files if rows were spilled or if they exceed the threshold.
+    /// If rows are sent (see [Generator::with_row_sender]), the remaining rows are sent
+    /// as well and no columns are returned.
+    pub fn take_witness_columns<Q: QueryCallback<T>>(
+        &mut self,
+        fixed_lookup: &mut FixedLookup<T>,
+        query_callback: &mut Q,
+    ) -> HashMap<String, ColumnStorage<T>> {
+        log::debug!("Finalizing VM: {}", self.name());
+        let mut call_cache = CallCache::new(false);
+        let mut mutable_state_no_machines = MutableState {
+            fixed_lookup,
+            machines: [].into_iter().into(),
+            query_callback,
+            call_cache: &mut call_cache,
+            caller: None,
+        };
+        self.fill_remaining_rows(&mut mutable_state_no_machines);
+        self.fix_first_row();
+        if let Some(row_sender) = &self.row_sender {
+            self.data.send_remaining(row_sender);
+            return HashMap::new();
+        }
+        self.data
+            .take_columns(self.spill.as_ref())
+            .map(|(id, column)| (self.fixed_data.column_name(&id).to_string(), column))
+            .collect()
+    }
+
+    /// The largest number of rows that were held in memory at the same time.
+    pub fn peak_resident_rows(&self) -> usize {
+        self.data.peak_resident_rows()
+    }
+
+    /// Returns the values of the rows solved by [Generator::run_window].
+    /// Unknown cells are set to zero.
+    pub fn take_window_witness(&mut self) -> HashMap<String, Vec<T>> {
+        self.data
+            .take_transposed()
+            .map(|(id, (values, known))| {
+                let column = values
+                    .into_iter()
+                    .zip(known.iter())
+                    .map(|(value, known)| if known { value } else { T::zero() })
+                    .collect();
+                (self.fixed_data.column_name(&id).to_string(), column)
+            })
+            .collect()
+    }
+
+    /// Returns the values of the witness columns computed so far, in contrast to
+    /// `take_witness_col_values` without finalizing the machine.
+    /// Unknown cells and rows that were not reached are `None`.
+    pub fn take_partial_witness(&mut self) -> HashMap<String, Vec<Option<T>>> {
+        let degree = self.fixed_data.degree as usize;
+        self.data
+            .take_transposed()
+            .map(|(id, (values, known))| {
+                let mut column = values
+                    .into_iter()
+                    .zip(known.iter())
+                    .map(|(value, known)| known.then_some(value))
+                    .collect::<Vec<_>>();
+                // This also removes the last row, which is the first row again.
+                column.resize(degree, None);
+                (self.fixed_data.column_name(&id).to_string(), column)
+            })
+            .collect()
     }
 
     fn fill_remaining_rows<Q: QueryCallback<T>>(
@@ -145,21 +533,24 @@ impl<'a, T: FieldElement> Generator<'a, T> {
             assert!(self.latch.is_some());
 
             let first_row = self.data.pop().unwrap();
-            let ProcessResult { block, eval_value } = self.process(
+            let ProcessResult {
+                block, eval_value, ..
+            } = self.process(
                 first_row,
                 self.data.len() as DegreeType,
                 mutable_state,
                 None,
-                false,
+                None,
             );
-            assert!(eval_value.is_complete());
+            assert!(eval_value.unwrap().is_complete());
 
             self.data.extend(block);
         }
     }
 
     /// Runs the solver on the row pair (degree - 1, 0) in order to partially compute the first
-    /// row from identities like `pc' = (1 - first_step') * <...>`.
+    /// row from identities like `pc' = (1 - first_step') * <...>`, starting from the initial
+    /// row, if one is given.
     fn compute_partial_first_row<Q: QueryCallback<T>>(
         &self,
         mutable_state: &mut MutableState<'a, '_, T, Q>,
@@ -175,7 +566,9 @@ impl<'a, T: FieldElement> Generator<'a, T> {
             &self.witnesses,
             [
                 row_factory.fresh_row(self.fixed_data.degree - 1),
-                row_factory.fresh_row(0),
+                self.initial_row
+                    .clone()
+                    .unwrap_or_else(|| row_factory.fresh_row(0)),
             ]
             .into_iter(),
         );
@@ -212,8 +605,53 @@ impl<'a, T: FieldElement> Generator<'a, T> {
         row_offset: DegreeType,
         mutable_state: &mut MutableState<'a, '_, T, Q>,
         outer_query: Option<OuterQuery<'a, T>>,
-        is_main_run: bool,
+        main_run: Option<MainRun<'_, T>>,
     ) -> ProcessResult<'a, T> {
+        let is_main_run = main_run.is_some();
+        let (
+            mut progress,
+            checkpoints,
+            mut row_observer,
+            mode,
+            limits,
+            end_row,
+            rows_without_progress,
+            spill,
+            row_sender,
+        ) = match main_run {
+            Some(MainRun {
+                progress,
+                checkpoints,
+                row_observer,
+                mode,
+                limits,
+                end_row,
+                rows_without_progress,
+                spill,
+                row_sender,
+            }) => (
+                progress,
+                checkpoints,
+                row_observer,
+                mode,
+                limits,
+                end_row,
+                rows_without_progress,
+                spill,
+                row_sender,
+            ),
+            None => (
+                None,
+                None,
+                None,
+                RunMode::Generate,
+                Limits::default(),
+                None,
+                0,
+                None,
+                None,
+            ),
+        };
         log::trace!(
             "Running main machine from row {row_offset} with the following initial values in the first row:\n{}", first_row.render_values(false, None)
         );
@@ -234,13 +672,58 @@ impl<'a, T: FieldElement> Generator<'a, T> {
         if let Some(outer_query) = outer_query {
             processor = processor.with_outer_query(outer_query);
         }
+        if let Some(progress) = &mut progress {
+            processor = processor.with_progress(&self.name, progress.reborrow());
+        }
+        if let Some(checkpoints) = checkpoints {
+            processor = processor.with_checkpoints(checkpoints);
+        }
+        if let Some(row_observer) = &mut row_observer {
+            processor = processor.with_row_observer(&mut **row_observer);
+        }
+        processor = processor
+            .with_limits(limits)
+            .with_rows_without_progress(rows_without_progress);
+        if let Some(end_row) = end_row {
+            processor = processor.with_end_row(end_row);
+        }
+        if let Some(spill) = spill {
+            processor = processor.with_spill(spill);
+        }
+        if let Some(row_sender) = row_sender {
+            processor = processor.with_row_sender(row_sender);
+        }
+        if is_main_run {
+            if let Some(sender) = &self.fixed_data.cell_sender {
+                processor = processor.with_cell_sender(sender);
+            }
+            processor = processor
+                .with_direction(self.direction)
+                .with_window_size(self.window_size);
+            if let Some(padding) = self.padding {
+                processor = processor.with_padding(padding);
+            }
+        }
+        match mode {
+            RunMode::Generate => {}
+            RunMode::Validate => processor = processor.with_validate_only(),
+            RunMode::Partial => processor = processor.with_errors_returned(),
+        }
         let eval_value = processor.run(is_main_run);
+        statistics::count_rows(&self.name, processor.rows_solved());
+        let program_end = processor.program_end();
         let block = processor.finish();
-        ProcessResult { eval_value, block }
+        ProcessResult {
+            eval_value,
+            block,
+            program_end,
+        }
     }
 
     /// At the end of the solving algorithm, we'll have computed the first row twice
     /// (as row 0 and as row <degree>). This function merges the two versions.
+    /// Conflicting values are usually detected while solving the main machine, but not
+    /// if it was resumed from a checkpoint.
     fn fix_first_row(&mut self) {
         assert_eq!(self.data.len() as DegreeType, self.fixed_data.degree + 1);
 
@@ -248,7 +731,11 @@ impl<'a, T: FieldElement> Generator<'a, T> {
         self.data[0] = WitnessColumnMap::from(self.data[0].values().zip(last_row.values()).map(
             |(cell1, cell2)| match (&cell1.value, &cell2.value) {
                 (CellValue::Known(v1), CellValue::Known(v2)) => {
-                    assert_eq!(v1, v2);
+                    assert_eq!(
+                        v1, v2,
+                        "Column {} is {v1} in the first row, but {v2} after wrapping around.",
+                        cell1.name
+                    );
                     cell1.clone()
                 }
                 (CellValue::Known(_), _) => cell1.clone(),
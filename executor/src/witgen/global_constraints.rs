@@ -7,15 +7,17 @@ use powdr_ast::analyzed::{
     IdentityKind, PolyID, PolynomialType,
 };
 
-use powdr_number::FieldElement;
+use powdr_ast::parsed::visitor::ExpressionVisitable;
+use powdr_number::{DegreeType, FieldElement};
 
 use crate::witgen::data_structures::column_map::{FixedColumnMap, WitnessColumnMap};
 
 use super::expression_evaluator::ExpressionEvaluator;
+use super::fixed_evaluator::FixedEvaluator;
 use super::range_constraints::RangeConstraint;
 use super::symbolic_evaluator::SymbolicEvaluator;
 use super::util::try_to_simple_poly;
-use super::{Constraint, FixedData};
+use super::{Constraint, EvalError, FixedData};
 
 /// Trait that provides a range constraint on a symbolic variable if given by ID.
 pub trait RangeConstraintSet<K, T: FieldElement> {
@@ -35,39 +37,107 @@ impl<'a, T: FieldElement> RangeConstraintSet<&AlgebraicReference, T>
     }
 }
 
+/// The role of a witness column, derived from how it is used in the identities,
+/// see [GlobalConstraints::column_role].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum ColumnRole {
+    /// A boolean column, constrained by an identity of the form `X * (1 - X) = 0`.
+    Selector,
+    /// A column without any of the other roles.
+    Plain,
+}
+
 #[derive(Clone)]
 pub struct GlobalConstraints<T: FieldElement> {
     pub witness_constraints: WitnessColumnMap<Option<RangeConstraint<T>>>,
     pub fixed_constraints: FixedColumnMap<Option<RangeConstraint<T>>>,
+    /// The witness columns that are constrained to be boolean by a polynomial identity.
+    pub selector_columns: BTreeSet<PolyID>,
 }
 
-impl<T: FieldElement> RangeConstraintSet<&AlgebraicReference, T> for GlobalConstraints<T> {
-    fn range_constraint(&self, id: &AlgebraicReference) -> Option<RangeConstraint<T>> {
-        assert!(!id.next);
-        let poly_id = id.poly_id;
+impl<T: FieldElement> GlobalConstraints<T> {
+    /// No range constraints on any column.
+    pub fn unconstrained(fixed_data: &FixedData<T>) -> Self {
+        GlobalConstraints {
+            witness_constraints: fixed_data.witness_map_with(None),
+            fixed_constraints: FixedColumnMap::new(None, fixed_data.fixed_cols.len()),
+            selector_columns: Default::default(),
+        }
+    }
+
+    /// Returns the range constraint that holds for all rows of the given column.
+    pub fn column_range_constraint(&self, poly_id: &PolyID) -> Option<RangeConstraint<T>> {
         match poly_id.ptype {
-            PolynomialType::Constant => self.fixed_constraints[&poly_id].clone(),
-            PolynomialType::Committed => self.witness_constraints[&poly_id].clone(),
+            PolynomialType::Constant => self.fixed_constraints[poly_id].clone(),
+            PolynomialType::Committed => self.witness_constraints[poly_id].clone(),
             PolynomialType::Intermediate => None,
         }
     }
+
+    /// Returns the role of the given column. Only witness columns can be selectors.
+    pub fn column_role(&self, poly_id: &PolyID) -> ColumnRole {
+        if self.selector_columns.contains(poly_id) {
+            ColumnRole::Selector
+        } else {
+            ColumnRole::Plain
+        }
+    }
+}
+
+impl<T: FieldElement> RangeConstraintSet<&AlgebraicReference, T> for GlobalConstraints<T> {
+    fn range_constraint(&self, id: &AlgebraicReference) -> Option<RangeConstraint<T>> {
+        assert!(!id.next);
+        self.column_range_constraint(&id.poly_id)
+    }
 }
 
 /// Determines global constraints on witness and fixed columns.
 /// Removes identities that only serve to create range constraints from
 /// the identities vector and returns the remaining identities.
+/// Polynomial identities that only reference fixed columns are checked on all rows
+/// and removed as well.
+/// Fails with [EvalError::UnsatisfiablePil] if an identity can never hold and with
+/// [EvalError::FixedIdentityViolated] if an identity on fixed columns does not hold.
 /// TODO at some point, we should check that they still hold.
 pub fn determine_global_constraints<'a, T: FieldElement>(
     fixed_data: &'a FixedData<T>,
     identities: Vec<&'a Identity<Expression<T>>>,
-) -> (GlobalConstraints<T>, Vec<&'a Identity<Expression<T>>>) {
+) -> Result<(GlobalConstraints<T>, Vec<&'a Identity<Expression<T>>>), EvalError<T>> {
+    if let Some(error) = identities
+        .iter()
+        .find_map(|identity| unsatisfiable(identity))
+    {
+        return Err(error);
+    }
+
+    let (fixed_only, identities): (Vec<_>, Vec<_>) = identities
+        .into_iter()
+        .partition(|identity| is_fixed_only(fixed_data, identity));
+    for identity in &fixed_only {
+        check_fixed_identity(fixed_data, identity)?;
+    }
+    log::debug!("Verified the following identities that only reference fixed columns:");
+    for id in &fixed_only {
+        log::debug!("  {id}");
+    }
+
     let mut known_constraints = BTreeMap::new();
     // For these columns, we know that they are not only constrained to those bits
     // but also have one row for each possible value.
     // It allows us to completely remove some lookups.
     let mut full_span = BTreeSet::new();
     for (poly_id, col) in fixed_data.fixed_cols.iter() {
-        if let Some((cons, full)) = process_fixed_column(col.values) {
+        let analyzed = match col.pattern() {
+            Some(pattern) if pattern.len() as DegreeType == col.len() => {
+                process_fixed_column(pattern)
+            }
+            // Two periods contain all the information needed about a periodic column.
+            Some(pattern) => process_fixed_column(&[pattern, pattern].concat()),
+            // Computed columns are only materialized temporarily.
+            None => process_fixed_column(&col.values().collect::<Vec<_>>()),
+        };
+        if let Some((cons, full)) = analyzed {
             assert!(known_constraints.insert(poly_id, cons).is_none());
             if full {
                 full_span.insert(poly_id);
@@ -101,10 +171,17 @@ pub fn determine_global_constraints<'a, T: FieldElement>(
     }
 
     log::debug!("Determined the following identities to be purely bit/range constraints:");
-    for id in removed_identities {
+    for id in &removed_identities {
         log::debug!("  {id}");
     }
 
+    // The only removed polynomial identities are the binary constraints.
+    let selector_columns = removed_identities
+        .iter()
+        .filter(|identity| identity.kind == IdentityKind::Polynomial)
+        .filter_map(|identity| is_binary_constraint(identity.expression_for_poly_id()))
+        .collect();
+
     let mut witness_constraints: WitnessColumnMap<Option<RangeConstraint<T>>> =
         fixed_data.witness_map_with(None);
     for (poly_id, con) in known_constraints {
@@ -119,19 +196,91 @@ pub fn determine_global_constraints<'a, T: FieldElement>(
         }
     }
 
-    (
+    Ok((
         GlobalConstraints {
             witness_constraints,
             fixed_constraints,
+            selector_columns,
         },
         retained_identities,
-    )
+    ))
+}
+
+/// Returns an error if the identity is a polynomial identity that evaluates to a
+/// non-zero constant, independently of the values of any columns (e.g. `1 = 2`).
+fn unsatisfiable<T: FieldElement>(identity: &Identity<Expression<T>>) -> Option<EvalError<T>> {
+    if identity.kind != IdentityKind::Polynomial {
+        return None;
+    }
+    let expr = identity.expression_for_poly_id();
+    let value = ExpressionEvaluator::new(SymbolicEvaluator)
+        .evaluate(expr)
+        .ok()?
+        .constant_value()?;
+    (!value.is_zero()).then(|| EvalError::UnsatisfiablePil {
+        identity: identity.to_string(),
+        source: identity.source.clone(),
+        value,
+    })
+}
+
+/// Returns true if the identity is a polynomial identity that references fixed
+/// columns (whose values are known), but no other columns.
+fn is_fixed_only<T: FieldElement>(
+    fixed_data: &FixedData<T>,
+    identity: &Identity<Expression<T>>,
+) -> bool {
+    if identity.kind != IdentityKind::Polynomial {
+        return false;
+    }
+    let mut references = 0;
+    let mut fixed_only = true;
+    identity.pre_visit_expressions(&mut |expr| match expr {
+        Expression::Reference(r) => {
+            references += 1;
+            fixed_only &= r.poly_id.ptype == PolynomialType::Constant
+                && !fixed_data.fixed_cols[&r.poly_id].is_empty();
+        }
+        Expression::PublicReference(_) | Expression::Challenge(_) => fixed_only = false,
+        _ => {}
+    });
+    fixed_only && references > 0
+}
+
+/// Evaluates an identity that only references fixed columns on all rows and fails
+/// with [EvalError::FixedIdentityViolated] in the first row in which it does not hold.
+fn check_fixed_identity<T: FieldElement>(
+    fixed_data: &FixedData<T>,
+    identity: &Identity<Expression<T>>,
+) -> Result<(), EvalError<T>> {
+    let expr = identity.expression_for_poly_id();
+    for row in 0..fixed_data.degree {
+        let value = ExpressionEvaluator::new(FixedEvaluator::new(fixed_data, row as usize))
+            .evaluate(expr)
+            .ok()
+            .and_then(|value| value.constant_value())
+            .unwrap();
+        if !value.is_zero() {
+            return Err(EvalError::FixedIdentityViolated {
+                identity: identity.to_string(),
+                source: identity.source.clone(),
+                row,
+                value,
+            });
+        }
+    }
+    Ok(())
 }
 
 /// Analyzes a fixed column and checks if its values correspond exactly
-/// to a certain bit pattern.
+/// to a certain bit pattern. If the column contains "negative" values (in the upper
+/// half of the field), the constraint is the signed interval of its values instead.
 /// TODO do this on the symbolic definition instead of the values.
 fn process_fixed_column<T: FieldElement>(fixed: &[T]) -> Option<(RangeConstraint<T>, bool)> {
+    if fixed.is_empty() {
+        // The values are not known, e.g. for columns of runtime lookup tables.
+        return None;
+    }
     if let Some(bit) = smallest_period_candidate(fixed) {
         let mask = T::Integer::from(((1 << bit) - 1) as u64);
         if fixed
@@ -142,6 +291,13 @@ fn process_fixed_column<T: FieldElement>(fixed: &[T]) -> Option<(RangeConstraint
             return Some((RangeConstraint::from_mask(mask), true));
         }
     }
+    if fixed.iter().any(|v| !v.is_in_lower_half()) {
+        // In the signed order, negative values come before the non-negative ones.
+        let signed_order = |v: &&T| (v.is_in_lower_half(), **v);
+        let min = fixed.iter().min_by_key(signed_order).unwrap();
+        let max = fixed.iter().max_by_key(signed_order).unwrap();
+        return Some((RangeConstraint::from_range(*min, *max), false));
+    }
     let mut mask = T::Integer::zero();
     for v in fixed.iter() {
         mask |= v.to_integer();
@@ -188,15 +344,24 @@ fn propagate_constraints<T: FieldElement>(
                 .iter()
                 .zip(identity.right.expressions.iter())
             {
-                if let (Some(left), Some(right)) =
-                    (try_to_simple_poly(left), try_to_simple_poly(right))
-                {
-                    if let Some(constraint) = known_constraints.get(&right.poly_id).cloned() {
-                        known_constraints
-                            .entry(left.poly_id)
-                            .and_modify(|existing| *existing = existing.conjunction(&constraint))
-                            .or_insert(constraint);
+                let Some(right) = try_to_simple_poly(right) else {
+                    continue;
+                };
+                let Some(constraint) = known_constraints.get(&right.poly_id).cloned() else {
+                    continue;
+                };
+                let constraints = match try_to_simple_poly(left) {
+                    Some(left) => vec![(left.poly_id, constraint)],
+                    None if identity.kind == IdentityKind::Plookup => {
+                        try_narrow_through_lookup(left, &constraint, &known_constraints)
                     }
+                    None => vec![],
+                };
+                for (p, c) in constraints {
+                    known_constraints
+                        .entry(p)
+                        .and_modify(|existing| *existing = existing.conjunction(&c))
+                        .or_insert(c);
                 }
             }
             if identity.kind == IdentityKind::Plookup && identity.right.expressions.len() == 1 {
@@ -286,6 +451,66 @@ fn try_transfer_constraints<T: FieldElement>(
         .collect()
 }
 
+/// Tries to narrow the range constraints of the witness columns in a linear expression
+/// whose values are looked up in a column with the range constraint `range`.
+/// This is how comparisons are usually enforced: `{ b - a - 1 } in { BYTE }` means
+/// `a < b` (if the difference does not wrap), so that `a = b - 1 - d` for some byte `d`
+/// is bounded by the constraints of `b` and `d`.
+/// Only columns with a coefficient of 1 or -1 are narrowed, and only if all other
+/// columns in the expression are constrained.
+fn try_narrow_through_lookup<T: FieldElement>(
+    expr: &Expression<T>,
+    range: &RangeConstraint<T>,
+    known_constraints: &BTreeMap<PolyID, RangeConstraint<T>>,
+) -> Vec<(PolyID, RangeConstraint<T>)> {
+    if expr.contains_next_ref() {
+        return vec![];
+    }
+    let Some(aff_expr) = ExpressionEvaluator::new(SymbolicEvaluator)
+        .evaluate(expr)
+        .ok()
+    else {
+        return vec![];
+    };
+    let coefficients = aff_expr.nonzero_coefficients();
+    let offset = coefficients
+        .iter()
+        .fold(aff_expr.clone(), |mut offset, (poly, _)| {
+            offset.assign(*poly, T::zero());
+            offset
+        })
+        .constant_value()
+        .unwrap();
+    coefficients
+        .iter()
+        .filter(|(poly, coeff)| poly.is_witness() && (coeff.is_one() || *coeff == -T::one()))
+        .filter_map(|(poly, coeff)| {
+            // coeff * poly = value - offset - (the other summands)
+            let others = coefficients
+                .iter()
+                .filter(|(other, _)| other != poly)
+                .map(|(other, c)| {
+                    known_constraints
+                        .get(&other.poly_id)
+                        .map(|con| con.multiple(-*c))
+                })
+                .collect::<Option<Vec<_>>>()?;
+            let constraint = others
+                .into_iter()
+                .chain((!offset.is_zero()).then(|| RangeConstraint::from_value(-offset)))
+                .fold(range.clone(), |sum, summand| sum.combine_sum(&summand));
+            Some((
+                poly.poly_id,
+                if coeff.is_one() {
+                    constraint
+                } else {
+                    -constraint
+                },
+            ))
+        })
+        .collect()
+}
+
 fn smallest_period_candidate<T: FieldElement>(fixed: &[T]) -> Option<u64> {
     if fixed.first() != Some(&0.into()) {
         return None;
@@ -340,6 +565,17 @@ mod test {
         );
     }
 
+    #[test]
+    fn signed_byte() {
+        let fixed = (-128..128).map(|v: i32| v.into()).collect::<Vec<_>>();
+        let constraint = RangeConstraint::<GoldilocksField>::from_range((-128).into(), 127.into());
+        assert_eq!(
+            process_fixed_column::<GoldilocksField>(&fixed),
+            Some((constraint.clone(), false))
+        );
+        assert_eq!(constraint.bit_width(), Some(8));
+    }
+
     fn constant_poly_id(i: u64) -> PolyID {
         PolyID {
             ptype: PolynomialType::Constant,
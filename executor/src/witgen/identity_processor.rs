@@ -3,18 +3,32 @@ use std::{collections::HashMap, sync::Mutex};
 use itertools::{Either, Itertools};
 use lazy_static::lazy_static;
 use powdr_ast::{
-    analyzed::{AlgebraicExpression as Expression, AlgebraicReference, Identity, IdentityKind},
+    analyzed::{
+        AlgebraicBinaryOperator, AlgebraicExpression as Expression, AlgebraicReference, Identity,
+        IdentityKind,
+    },
     parsed::SelectedExpressions,
 };
-use powdr_number::FieldElement;
+use powdr_number::{DegreeType, FieldElement};
 
 use crate::witgen::machines::Machine;
 
 use super::{
-    affine_expression::AffineExpression, machines::KnownMachine, rows::RowPair, EvalResult,
-    EvalValue, FixedData, IncompleteCause, MutableState, QueryCallback,
+    affine_expression::AffineExpression, machines::KnownMachine, rows::RowPair, statistics,
+    EvalResult, EvalValue, FixedData, IncompleteCause, MutableState, QueryCallback,
 };
 
+/// Collects the factors of a (possibly nested) product.
+fn product_factors<'a, T>(expression: &'a Expression<T>, factors: &mut Vec<&'a Expression<T>>) {
+    match expression {
+        Expression::BinaryOperation(left, AlgebraicBinaryOperator::Mul, right) => {
+            product_factors(left, factors);
+            product_factors(right, factors);
+        }
+        _ => factors.push(expression),
+    }
+}
+
 /// A list of mutable references to machines.
 pub struct Machines<'a, 'b, T: FieldElement> {
     machines: Vec<&'b mut KnownMachine<'a, T>>,
@@ -107,6 +121,7 @@ impl<'a, 'b, 'c, T: FieldElement, Q: QueryCallback<T>> IdentityProcessor<'a, 'b,
             }
         };
         report_identity_solving(identity, &result);
+        statistics::count_identity_evaluation();
         result
     }
 
@@ -115,12 +130,53 @@ impl<'a, 'b, 'c, T: FieldElement, Q: QueryCallback<T>> IdentityProcessor<'a, 'b,
         identity: &'a Identity<Expression<T>>,
         rows: &RowPair<T>,
     ) -> EvalResult<'a, T> {
-        match rows.evaluate(identity.expression_for_poly_id()) {
+        let expression = identity.expression_for_poly_id();
+        if let Some(result) = self.process_conditional_constraint(expression, rows) {
+            return result;
+        }
+        match rows.evaluate(expression) {
             Err(inclomplete_cause) => Ok(EvalValue::incomplete(inclomplete_cause)),
             Ok(evaluated) => evaluated.solve_with_range_constraints(rows),
         }
     }
 
+    /// Handles products like `sel * (a - b) = 0`, which means "if `sel` then `a = b`":
+    /// If one of the factors evaluates to zero, the identity is satisfied, independent of
+    /// whether the other factors can be evaluated. If all factors but one evaluate to
+    /// nonzero constants, the remaining factor is solved on its own.
+    /// Returns `None` if the expression is not a product or if more than one factor
+    /// is unknown, so that the whole expression is evaluated as usual.
+    fn process_conditional_constraint(
+        &self,
+        expression: &'a Expression<T>,
+        rows: &RowPair<T>,
+    ) -> Option<EvalResult<'a, T>> {
+        let mut factors = vec![];
+        product_factors(expression, &mut factors);
+        if factors.len() < 2 {
+            return None;
+        }
+        let mut unknown_factor = None;
+        let mut unknown_factors = 0;
+        for factor in factors {
+            match rows.evaluate(factor) {
+                Ok(value) => match value.constant_value() {
+                    Some(v) if v.is_zero() => return Some(Ok(EvalValue::complete(vec![]))),
+                    Some(_) => {}
+                    None => {
+                        unknown_factors += 1;
+                        unknown_factor = Some(value);
+                    }
+                },
+                Err(_) => unknown_factors += 1,
+            }
+        }
+        match (unknown_factors, unknown_factor) {
+            (1, Some(value)) => Some(value.solve_with_range_constraints(rows)),
+            _ => None,
+        }
+    }
+
     fn process_plookup(
         &mut self,
         identity: &'a Identity<Expression<T>>,
@@ -163,15 +219,24 @@ impl<'a, 'b, 'c, T: FieldElement, Q: QueryCallback<T>> IdentityProcessor<'a, 'b,
             &left,
             &identity.right,
         ) {
+            log_lookup(identity, rows.current_row_index, "the fixed lookup", &left);
+            return result;
+        }
+
+        if let Some(result) = self.mutable_state.call_cache.get(identity, &left) {
+            log_lookup(identity, rows.current_row_index, "the call cache", &left);
             return result;
         }
 
         for i in 0..self.mutable_state.machines.len() {
             let (current, others) = self.mutable_state.machines.split(i);
+            let pure = current.is_pure();
             let mut mutable_state = MutableState {
                 fixed_lookup: self.mutable_state.fixed_lookup,
                 machines: others,
                 query_callback: self.mutable_state.query_callback,
+                call_cache: self.mutable_state.call_cache,
+                caller: Some((identity.id, rows.current_row_index)),
             };
 
             if let Some(result) = current.process_plookup_timed(
@@ -180,7 +245,13 @@ impl<'a, 'b, 'c, T: FieldElement, Q: QueryCallback<T>> IdentityProcessor<'a, 'b,
                 &left,
                 &identity.right,
             ) {
-                return result;
+                log_lookup(identity, rows.current_row_index, current.name(), &left);
+                if let (true, Ok(result)) = (pure, &result) {
+                    self.mutable_state
+                        .call_cache
+                        .insert(identity, &left, result);
+                }
+                return result.map_err(|e| e.in_machine(current.name()));
             }
         }
 
@@ -278,3 +349,18 @@ pub fn get_and_reset_solving_statistics() -> HashMap<IdentityID, IdentityData> {
     let mut stat = STATISTICS.lock().unwrap();
     std::mem::take(&mut (*stat))
 }
+
+/// Logs which machine processed a lookup. The message is only rendered if trace logging
+/// is enabled (and it is removed entirely if trace logging is disabled statically through
+/// the features of the `log` crate), since this is called for every lookup.
+fn log_lookup<T: FieldElement>(
+    identity: &Identity<Expression<T>>,
+    row: DegreeType,
+    machine: &str,
+    left: &[AffineExpression<&AlgebraicReference, T>],
+) {
+    log::trace!(
+        "  Lookup in row {row} processed by {machine}: {identity} with arguments ({})",
+        left.iter().format(", ")
+    );
+}
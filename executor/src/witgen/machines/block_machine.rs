@@ -1,5 +1,6 @@
 use std::collections::{BTreeSet, HashMap, HashSet};
 
+use super::machine_extractor::refs_in_selected_expressions;
 use super::{EvalResult, FixedData, FixedLookup};
 use crate::witgen::affine_expression::AffineExpression;
 
@@ -10,6 +11,7 @@ use crate::witgen::identity_processor::IdentityProcessor;
 use crate::witgen::processor::OuterQuery;
 use crate::witgen::rows::{CellValue, RowFactory, RowPair, UnknownStrategy};
 use crate::witgen::sequence_iterator::{ProcessingSequenceCache, ProcessingSequenceIterator};
+use crate::witgen::statistics;
 use crate::witgen::util::try_to_simple_poly;
 use crate::witgen::{machines::Machine, EvalError, EvalValue, IncompleteCause};
 use crate::witgen::{MutableState, QueryCallback};
@@ -47,6 +49,9 @@ impl<'a, T: FieldElement> ProcessResult<'a, T> {
 pub struct BlockMachine<'a, T: FieldElement> {
     /// Block size, the period of the selector.
     block_size: usize,
+    /// The selector of the (first) connecting identity, which is 1 in the last row
+    /// of each block.
+    latch: Option<Expression<T>>,
     /// The right-hand side of the connecting identity, needed to identify
     /// when this machine is responsible.
     connecting_rhs: BTreeSet<SelectedExpressions<Expression<T>>>,
@@ -63,6 +68,8 @@ pub struct BlockMachine<'a, T: FieldElement> {
     processing_sequence_cache: ProcessingSequenceCache,
     fixed_data: &'a FixedData<'a, T>,
     name: String,
+    /// Whether the machine is pure, see [Machine::is_pure].
+    pure: bool,
 }
 
 impl<'a, T: FieldElement> BlockMachine<'a, T> {
@@ -124,6 +131,7 @@ impl<'a, T: FieldElement> BlockMachine<'a, T> {
                 BlockMachine {
                     name,
                     block_size,
+                    latch: connecting_identities[0].right.selector.clone(),
                     connecting_rhs,
                     identities: identities.to_vec(),
                     data,
@@ -134,11 +142,31 @@ impl<'a, T: FieldElement> BlockMachine<'a, T> {
                         identities.len(),
                     ),
                     fixed_data,
+                    pure: is_pure(fixed_data, identities, witness_cols),
                 }
             })
     }
 }
 
+/// A block machine is pure if its witness columns do not have queries and it does not
+/// call into machines other than lookups into fixed columns.
+fn is_pure<T: FieldElement>(
+    fixed_data: &FixedData<'_, T>,
+    identities: &[&Identity<Expression<T>>],
+    witness_cols: &HashSet<PolyID>,
+) -> bool {
+    witness_cols
+        .iter()
+        .all(|poly_id| fixed_data.witness_cols[poly_id].query.is_none())
+        && identities.iter().all(|identity| match identity.kind {
+            IdentityKind::Polynomial => true,
+            IdentityKind::Plookup => refs_in_selected_expressions(&identity.right)
+                .iter()
+                .all(|poly_id| poly_id.ptype == PolynomialType::Constant),
+            IdentityKind::Permutation | IdentityKind::Connect => false,
+        })
+}
+
 /// Check if `expr` is a reference to a function of the form
 /// f(i) { if (i + 1) % k == 0 { 1 } else { 0 } }
 /// for some k
@@ -161,11 +189,11 @@ fn try_to_period<T: FieldElement>(
                 return None;
             }
 
-            let values = fixed_data.fixed_cols[&poly.poly_id].values;
+            let column = &fixed_data.fixed_cols[&poly.poly_id];
 
-            let period = 1 + values.iter().position(|v| v.is_one())?;
-            values
-                .iter()
+            let period = 1 + column.values().position(|v| v.is_one())?;
+            column
+                .values()
                 .enumerate()
                 .all(|(i, v)| {
                     let expected = if (i + 1) % period == 0 {
@@ -173,7 +201,7 @@ fn try_to_period<T: FieldElement>(
                     } else {
                         0.into()
                     };
-                    *v == expected
+                    v == expected
                 })
                 .then_some(period)
         }
@@ -209,6 +237,14 @@ impl<'a, T: FieldElement> Machine<'a, T> for BlockMachine<'a, T> {
         &self.name
     }
 
+    fn latch(&self) -> Option<&Expression<T>> {
+        self.latch.as_ref()
+    }
+
+    fn is_pure(&self) -> bool {
+        self.pure
+    }
+
     fn take_witness_col_values<'b, Q: QueryCallback<T>>(
         &mut self,
         _fixed_lookup: &'b mut FixedLookup<T>,
@@ -463,7 +499,56 @@ impl<'a, T: FieldElement> BlockMachine<'a, T> {
 
         // 5. Append the new block (including the merged last row of the previous block)
         self.data.extend(new_block);
+        statistics::count_rows(&self.name, self.block_size as DegreeType);
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use powdr_number::GoldilocksField;
+    use powdr_pil_analyzer::analyze_string;
+
+    use crate::constant_evaluator;
+
+    use super::*;
+
+    #[test]
+    fn latch() {
+        let src = r"
+namespace Main(8);
+    col fixed CALL = [1, 0]*;
+    col witness a, b;
+    CALL { a, b } in Sq.LATCH { Sq.x, Sq.y };
+namespace Sq(8);
+    col fixed LATCH = [0, 1]*;
+    col witness x, y;
+    y = x * x;
+";
+        let analyzed = analyze_string::<GoldilocksField>(src);
+        let constants = constant_evaluator::generate(&analyzed);
+        let fixed_data = FixedData::new(&analyzed, analyzed.degree(), &constants, None);
+        let (connecting, identities): (Vec<_>, Vec<_>) = analyzed
+            .identities
+            .iter()
+            .partition(|identity| identity.kind == IdentityKind::Plookup);
+        let witnesses = fixed_data
+            .witness_cols
+            .iter()
+            .filter(|(_, column)| column.poly.name.starts_with("Sq."))
+            .map(|(poly_id, _)| poly_id)
+            .collect();
+        let machine = BlockMachine::try_new(
+            "Sq".to_string(),
+            &fixed_data,
+            &connecting,
+            &identities,
+            &witnesses,
+            &GlobalConstraints::unconstrained(&fixed_data),
+        )
+        .unwrap();
+        assert_eq!(machine.block_size, 2);
+        assert_eq!(machine.latch().unwrap().to_string(), "Sq.LATCH");
+    }
+}
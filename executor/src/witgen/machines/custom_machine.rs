@@ -0,0 +1,65 @@
+use std::collections::{HashMap, HashSet};
+
+use powdr_ast::analyzed::{
+    AlgebraicExpression as Expression, AlgebraicReference, Identity, IdentityKind, PolyID,
+};
+use powdr_ast::parsed::SelectedExpressions;
+use powdr_number::FieldElement;
+
+use crate::witgen::{affine_expression::AffineExpression, EvalResult, FixedData};
+
+/// The data a machine is created from when it is split out of the main machine.
+#[derive(Clone)]
+pub struct MachineParts<'a, T: FieldElement> {
+    /// The unique name of the machine.
+    pub name: String,
+    pub fixed_data: &'a FixedData<'a, T>,
+    /// The identities that call into the machine, i.e. whose right-hand side references
+    /// witness columns of the machine.
+    pub connecting_identities: Vec<&'a Identity<Expression<T>>>,
+    /// The identities that only reference witness columns of the machine.
+    pub identities: Vec<&'a Identity<Expression<T>>>,
+    /// The witness columns of the machine.
+    pub witnesses: HashSet<PolyID>,
+}
+
+/// A machine that is implemented outside of this crate, for example a hand-optimized
+/// solver for a specific gadget. It is registered using
+/// [crate::witgen::WitnessGenerator::with_custom_machine] and claims all witness columns
+/// of a namespace together with the identities that only reference those columns.
+///
+/// In contrast to the built-in machines, custom machines cannot call into other machines.
+pub trait CustomMachine<'a, T: FieldElement>: Send + Sync {
+    /// Returns the unique name of this machine, usually [MachineParts::name].
+    fn name(&self) -> &str;
+
+    /// Processes a call of one of the connecting identities, with the given kind and
+    /// right-hand side. Not all values on the left-hand side need to be known.
+    /// If the call can be processed, returns the values of the unknown left-hand side
+    /// expressions (see [AffineExpression::solve]) or an incomplete result if more
+    /// values are needed. Only returns an error if the call violates a constraint.
+    /// Returns `None` if this machine is not responsible for the call.
+    fn process_plookup(
+        &mut self,
+        kind: IdentityKind,
+        left: &[AffineExpression<&'a AlgebraicReference, T>],
+        right: &'a SelectedExpressions<Expression<T>>,
+    ) -> Option<EvalResult<'a, T>>;
+
+    /// Returns true if the result of a call only depends on the known values on the
+    /// left-hand side and calls do not have to be recorded, see
+    /// [crate::witgen::machines::Machine::is_pure]. Defaults to false.
+    fn is_pure(&self) -> bool {
+        false
+    }
+
+    /// Returns the values of all witness columns of the machine, by name.
+    /// Each column has to have [FixedData::degree] rows.
+    /// This is called once, after the main machine has been processed.
+    fn take_witness_col_values(&mut self) -> HashMap<String, Vec<T>>;
+}
+
+/// Creates a [CustomMachine] for each run of the witness generator.
+pub trait CustomMachineFactory<T: FieldElement>: Send + Sync {
+    fn create<'a>(&self, parts: MachineParts<'a, T>) -> Box<dyn CustomMachine<'a, T> + 'a>;
+}
@@ -0,0 +1,304 @@
+use std::collections::{HashMap, HashSet};
+
+use powdr_ast::analyzed::{
+    AlgebraicExpression as Expression, AlgebraicReference, Identity, IdentityKind, PolyID,
+    PolynomialType,
+};
+use powdr_ast::parsed::SelectedExpressions;
+use powdr_number::FieldElement;
+
+use crate::witgen::generator::Generator;
+use crate::witgen::global_constraints::GlobalConstraints;
+use crate::witgen::vm_processor::Limits;
+use crate::witgen::{
+    affine_expression::AffineExpression, call_cache::CallCache, util::try_to_simple_poly,
+    EvalError, EvalResult, EvalValue, FixedData, IncompleteCause, MutableState, QueryCallback,
+};
+
+use super::{FixedLookup, Machine};
+
+/// A machine for lookups into witness columns that do not depend on the lookups,
+/// i.e. a table that is built by its own identities (or provided as external witness):
+/// ```pil
+/// col fixed FIRST = [1] + [0]*;
+/// col witness t, sq;
+/// FIRST * t = 0;
+/// (1 - FIRST') * (t' - t - 1) = 0;
+/// sq = t * t;
+/// // in another machine:
+/// { x, y } in { t, sq };
+/// ```
+/// Since the table cannot be solved from the lookups, it is solved on all rows (like
+/// the main machine) when the first lookup is made. Lookups are then answered like
+/// lookups into fixed columns: They are deferred until at least one value on the
+/// left-hand side is known, and determine the other values if all matching rows agree.
+/// The right-hand sides cannot have selectors and only consist of witness columns.
+pub struct DynamicLookupMachine<'a, T: FieldElement> {
+    name: String,
+    fixed_data: &'a FixedData<'a, T>,
+    identities: Vec<&'a Identity<Expression<T>>>,
+    witnesses: HashSet<PolyID>,
+    global_range_constraints: GlobalConstraints<T>,
+    /// The right-hand sides of the connecting identities.
+    rhs: Vec<&'a SelectedExpressions<Expression<T>>>,
+    /// The values of the table, by column name, once it is solved.
+    table: Option<HashMap<String, Vec<T>>>,
+    /// For each right-hand side (by index) and set of known values on the left-hand side,
+    /// the rows of the table by the values of the corresponding columns.
+    indices: HashMap<(usize, Vec<bool>), HashMap<Vec<T>, Vec<usize>>>,
+}
+
+impl<'a, T: FieldElement> DynamicLookupMachine<'a, T> {
+    pub fn try_new(
+        name: String,
+        fixed_data: &'a FixedData<'a, T>,
+        connecting_identities: &[&'a Identity<Expression<T>>],
+        identities: &[&'a Identity<Expression<T>>],
+        witnesses: &HashSet<PolyID>,
+        global_range_constraints: &GlobalConstraints<T>,
+    ) -> Option<Self> {
+        let mut rhs = vec![];
+        for identity in connecting_identities {
+            if identity.kind != IdentityKind::Plookup || identity.right.selector.is_some() {
+                return None;
+            }
+            let only_witness_columns = identity.right.expressions.iter().all(|e| {
+                try_to_simple_poly(e).map_or(false, |p| {
+                    p.poly_id.ptype == PolynomialType::Committed && witnesses.contains(&p.poly_id)
+                })
+            });
+            if !only_witness_columns {
+                return None;
+            }
+            if !rhs.contains(&&identity.right) {
+                rhs.push(&identity.right);
+            }
+        }
+
+        Some(Self {
+            name,
+            fixed_data,
+            identities: identities.to_vec(),
+            witnesses: witnesses.clone(),
+            global_range_constraints: global_range_constraints.clone(),
+            rhs,
+            table: None,
+            indices: Default::default(),
+        })
+    }
+
+    /// Solves all rows of the table, if this has not been done yet.
+    fn solve_table<Q: QueryCallback<T>>(
+        &mut self,
+        mutable_state: &mut MutableState<'a, '_, T, Q>,
+    ) -> Result<(), EvalError<T>> {
+        if self.table.is_some() {
+            return Ok(());
+        }
+        log::debug!("Solving the table of {}", self.name);
+        let mut generator = Generator::new(
+            self.name.clone(),
+            self.fixed_data,
+            &self.identities,
+            self.witnesses.clone(),
+            &self.global_range_constraints,
+            None,
+            None,
+        );
+        generator.run(mutable_state, None, None, None, None, Limits::default())?;
+        self.table = Some(generator.take_witness_col_values(
+            &mut *mutable_state.fixed_lookup,
+            &mut *mutable_state.query_callback,
+        ));
+        Ok(())
+    }
+
+    fn process_plookup_internal<Q: QueryCallback<T>>(
+        &mut self,
+        mutable_state: &mut MutableState<'a, '_, T, Q>,
+        rhs_index: usize,
+        left: &[AffineExpression<&'a AlgebraicReference, T>],
+    ) -> EvalResult<'a, T> {
+        let known = left
+            .iter()
+            .map(|l| l.constant_value().is_some())
+            .collect::<Vec<_>>();
+        if !known.contains(&true) {
+            return Ok(EvalValue::incomplete(
+                IncompleteCause::NonConstantRequiredArgument("input"),
+            ));
+        }
+        self.solve_table(mutable_state)?;
+
+        let table = self.table.as_ref().unwrap();
+        let columns = self.rhs[rhs_index]
+            .expressions
+            .iter()
+            .map(|e| {
+                &table[self
+                    .fixed_data
+                    .column_name(&try_to_simple_poly(e).unwrap().poly_id)]
+            })
+            .collect::<Vec<_>>();
+        let index = self
+            .indices
+            .entry((rhs_index, known.clone()))
+            .or_insert_with(|| {
+                let mut index: HashMap<_, Vec<_>> = HashMap::new();
+                for row in 0..self.fixed_data.degree as usize {
+                    let key = columns
+                        .iter()
+                        .zip(&known)
+                        .filter(|(_, known)| **known)
+                        .map(|(column, _)| column[row])
+                        .collect::<Vec<_>>();
+                    index.entry(key).or_default().push(row);
+                }
+                index
+            });
+
+        let inputs = left
+            .iter()
+            .filter_map(|l| l.constant_value())
+            .collect::<Vec<_>>();
+        let Some(rows) = index.get(&inputs) else {
+            return Err(EvalError::from(format!(
+                "Values {} not found in the table of {}",
+                inputs
+                    .iter()
+                    .map(|v| v.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", "),
+                self.name
+            )));
+        };
+
+        // Only the values on which all matching rows agree are determined.
+        let mut updates = vec![];
+        let mut unique = true;
+        for ((l, column), known) in left.iter().zip(&columns).zip(&known) {
+            if *known {
+                continue;
+            }
+            let value = column[rows[0]];
+            if rows.iter().all(|row| column[*row] == value) {
+                updates.extend((l.clone() - value.into()).solve()?.constraints);
+            } else {
+                unique = false;
+            }
+        }
+        Ok(if unique {
+            EvalValue::complete(updates)
+        } else {
+            EvalValue::incomplete_with_constraints(updates, IncompleteCause::MultipleLookupMatches)
+        })
+    }
+}
+
+impl<'a, T: FieldElement> Machine<'a, T> for DynamicLookupMachine<'a, T> {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn process_plookup<'b, Q: QueryCallback<T>>(
+        &mut self,
+        mutable_state: &'b mut MutableState<'a, 'b, T, Q>,
+        kind: IdentityKind,
+        left: &[AffineExpression<&'a AlgebraicReference, T>],
+        right: &'a SelectedExpressions<Expression<T>>,
+    ) -> Option<EvalResult<'a, T>> {
+        if kind != IdentityKind::Plookup {
+            return None;
+        }
+        let rhs_index = self.rhs.iter().position(|rhs| *rhs == right)?;
+        Some(self.process_plookup_internal(mutable_state, rhs_index, left))
+    }
+
+    fn take_witness_col_values<'b, Q: QueryCallback<T>>(
+        &mut self,
+        fixed_lookup: &'b mut FixedLookup<T>,
+        query_callback: &'b mut Q,
+    ) -> HashMap<String, Vec<T>> {
+        // This is only needed if no lookup was made.
+        let mut call_cache = CallCache::new(false);
+        let mut mutable_state = MutableState {
+            fixed_lookup,
+            machines: [].into_iter().into(),
+            query_callback,
+            call_cache: &mut call_cache,
+            caller: None,
+        };
+        self.solve_table(&mut mutable_state)
+            .unwrap_or_else(|e| panic!("Failed to solve the table of {}:\n{e}", self.name));
+        self.table.take().unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use powdr_number::GoldilocksField;
+    use powdr_pil_analyzer::analyze_string;
+
+    use crate::constant_evaluator;
+    use crate::witgen::{unused_query_callback, Constraint, MutableStateBuilder};
+
+    use super::*;
+
+    #[test]
+    fn lookup_without_extraction() {
+        let src = r"
+namespace T(4);
+    col fixed FIRST = [1, 0, 0, 0];
+    col witness t, sq;
+    FIRST * t = 0;
+    (1 - FIRST') * (t' - t - 1) = 0;
+    sq = t * t;
+namespace main(4);
+    col witness x, y;
+    { x, y } in { T.t, T.sq };
+";
+        let analyzed = analyze_string::<GoldilocksField>(src);
+        let constants = constant_evaluator::generate(&analyzed);
+        let fixed_data = FixedData::new(&analyzed, analyzed.degree(), &constants, None);
+        let (connecting, identities): (Vec<_>, Vec<_>) = analyzed
+            .identities
+            .iter()
+            .partition(|identity| identity.kind == IdentityKind::Plookup);
+        let witnesses = fixed_data
+            .witness_cols
+            .iter()
+            .filter(|(_, column)| column.poly.name.starts_with("T."))
+            .map(|(poly_id, _)| poly_id)
+            .collect();
+        let mut machine = DynamicLookupMachine::try_new(
+            "T".to_string(),
+            &fixed_data,
+            &connecting,
+            &identities,
+            &witnesses,
+            &GlobalConstraints::unconstrained(&fixed_data),
+        )
+        .unwrap();
+
+        let mut state_builder = MutableStateBuilder::new(&fixed_data, unused_query_callback());
+        let y = try_to_simple_poly(&connecting[0].left.expressions[1]).unwrap();
+        let left = [
+            AffineExpression::from(GoldilocksField::from(3)),
+            AffineExpression::from_variable_id(y),
+        ];
+        let result = machine
+            .process_plookup(
+                &mut state_builder.state(),
+                IdentityKind::Plookup,
+                &left,
+                &connecting[0].right,
+            )
+            .unwrap()
+            .unwrap();
+        assert!(result.is_complete());
+        assert_eq!(
+            result.constraints,
+            vec![(y, Constraint::Assignment(9.into()))]
+        );
+    }
+}
@@ -0,0 +1,105 @@
+use powdr_ast::analyzed::IdentityKind;
+use powdr_number::FieldElement;
+
+use super::MachineParts;
+
+/// A summary of the machines that are extracted from a PIL file, see
+/// [crate::witgen::WitnessGenerator::extraction_report].
+/// Like [super::MachineGraph], this only describes the structure.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct ExtractionReport {
+    /// The machines, the main machine first.
+    pub machines: Vec<MachineReport>,
+    /// The identities that are processed by the main machine.
+    pub base_identities: Vec<String>,
+    /// The number of identities processed by all machines, not counting those that are
+    /// captured by global range constraints or only reference fixed columns.
+    pub identity_count: usize,
+    /// The number of lookups and permutations processed by all machines.
+    pub lookup_count: usize,
+    /// The number of machines on the longest chain of calls starting at the main machine,
+    /// including the main machine. Lookups into fixed columns are not counted.
+    pub longest_call_chain: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct MachineReport {
+    pub name: String,
+    pub witness_count: usize,
+    /// The number of identities processed by the machine.
+    pub identity_count: usize,
+    /// The number of lookups and permutations processed by the machine.
+    pub lookup_count: usize,
+}
+
+impl ExtractionReport {
+    pub(crate) fn new<'a, T: FieldElement>(
+        main: &MachineParts<'a, T>,
+        machines: &[MachineParts<'a, T>],
+    ) -> Self {
+        let all_machines = std::iter::once(main).chain(machines).collect::<Vec<_>>();
+        let reports = all_machines
+            .iter()
+            .map(|machine| MachineReport {
+                name: machine.name.clone(),
+                witness_count: machine.witnesses.len(),
+                identity_count: machine.identities.len(),
+                lookup_count: machine
+                    .identities
+                    .iter()
+                    .filter(|identity| is_lookup(identity.kind))
+                    .count(),
+            })
+            .collect::<Vec<_>>();
+        // The indices (in `all_machines`) of the machines called by each machine.
+        let callees = all_machines
+            .iter()
+            .map(|caller| {
+                machines
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, callee)| {
+                        callee.connecting_identities.iter().any(|connecting| {
+                            caller
+                                .identities
+                                .iter()
+                                .any(|identity| std::ptr::eq(*identity, *connecting))
+                        })
+                    })
+                    .map(|(index, _)| index + 1)
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        ExtractionReport {
+            identity_count: reports.iter().map(|report| report.identity_count).sum(),
+            lookup_count: reports.iter().map(|report| report.lookup_count).sum(),
+            machines: reports,
+            base_identities: main
+                .identities
+                .iter()
+                .map(|identity| identity.to_string())
+                .collect(),
+            longest_call_chain: longest_chain(&callees, 0, &mut vec![]),
+        }
+    }
+}
+
+fn is_lookup(kind: IdentityKind) -> bool {
+    matches!(kind, IdentityKind::Plookup | IdentityKind::Permutation)
+}
+
+/// Returns the number of machines on the longest chain of calls starting at `machine`,
+/// ignoring calls back into machines that are already on the chain.
+fn longest_chain(callees: &[Vec<usize>], machine: usize, chain: &mut Vec<usize>) -> usize {
+    chain.push(machine);
+    let mut longest = 0;
+    for callee in &callees[machine] {
+        if !chain.contains(callee) {
+            longest = longest.max(longest_chain(callees, *callee, chain));
+        }
+    }
+    chain.pop();
+    longest + 1
+}
@@ -1,13 +1,13 @@
-use std::collections::{BTreeMap, HashMap, HashSet};
+use std::collections::{HashMap, HashSet};
 use std::mem;
-use std::num::NonZeroUsize;
+use std::sync::Arc;
 
 use itertools::Itertools;
 use powdr_ast::analyzed::{
     AlgebraicExpression as Expression, AlgebraicReference, IdentityKind, PolyID, PolynomialType,
 };
 use powdr_ast::parsed::SelectedExpressions;
-use powdr_number::FieldElement;
+use powdr_number::{DegreeType, FieldElement};
 
 use crate::witgen::affine_expression::AffineExpression;
 use crate::witgen::global_constraints::{GlobalConstraints, RangeConstraintSet};
@@ -16,36 +16,99 @@ use crate::witgen::range_constraints::RangeConstraint;
 use crate::witgen::rows::RowPair;
 use crate::witgen::util::try_to_simple_poly_ref;
 use crate::witgen::{EvalError, EvalValue, IncompleteCause};
-use crate::witgen::{EvalResult, FixedData};
+use crate::witgen::{EvalResult, FixedColumn, FixedData};
 
 use super::record_end;
 
 type Application = (Vec<PolyID>, Vec<PolyID>);
-type Index<T> = BTreeMap<Vec<T>, IndexValue>;
+type Index<T> = HashMap<Vec<T>, IndexValue>;
+type Indices<T> = HashMap<Application, Arc<Index<T>>>;
 
-#[derive(Debug)]
-struct IndexValue(Option<NonZeroUsize>);
+/// Lookup tables that are registered at runtime, see [FixedLookup::with_table]:
+/// For each table (by name), the values of its columns (by column name).
+type Tables<T> = HashMap<String, HashMap<String, Arc<[T]>>>;
 
-impl IndexValue {
-    pub fn multiple_matches() -> Self {
-        Self(None)
+/// The values of a column on the right-hand side of a lookup into fixed columns.
+enum LookupColumn<'c, T> {
+    Fixed(&'c FixedColumn<'c, T>),
+    Table(&'c [T]),
+}
+
+impl<'c, T: FieldElement> LookupColumn<'c, T> {
+    fn new(fixed_data: &'c FixedData<'c, T>, tables: &'c Tables<T>, id: &PolyID) -> Self {
+        let name = fixed_data.column_name(id);
+        tables
+            .values()
+            .find_map(|table| table.get(name))
+            .map_or_else(
+                || LookupColumn::Fixed(&fixed_data.fixed_cols[id]),
+                |values| LookupColumn::Table(values),
+            )
     }
-    pub fn single_row(row: usize) -> Self {
-        // TODO check how expensive the cehck is
-        // We negate to make it actually nonzero.
-        Self(NonZeroUsize::new(!row))
+
+    fn value(&self, row: usize) -> T {
+        match self {
+            LookupColumn::Fixed(column) => column.value(row as DegreeType),
+            LookupColumn::Table(values) => values[row],
+        }
     }
-    fn row(&self) -> Option<usize> {
-        self.0.map(|row| (!row.get()))
+
+    fn row_count(&self, fixed_data: &FixedData<T>) -> usize {
+        match self {
+            LookupColumn::Fixed(_) => fixed_data.degree as usize,
+            LookupColumn::Table(values) => values.len(),
+        }
     }
 }
 
+/// The default number of entries in the [MatchCache].
+pub const DEFAULT_CACHE_CAPACITY: usize = 1 << 16;
+
+/// The rows matching the input values of a lookup.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+enum IndexValue {
+    /// The value of the output columns is unique, given by the first matching row.
+    SingleRow(usize),
+    /// The first row for each of the (at least two) different values of the output columns.
+    MultipleRows(Arc<[usize]>),
+}
+
 /// Indices for applications of fixed columns. For each application `(INPUT_COLS, OUTPUT_COLS)`, stores
-/// - `(V, None)` if there exists two different rows where `INPUT_COLS == V` match but `OUTPUT_COLS` differ. TODO: store bitmasks of all possible outputs instead.
-/// - `(V, Some(row)` if the value of `OUTPUT_COLS` is unique when `INPUT_COLS == V`, and `row` is the first row where `INPUT_COLS ==V`
-#[derive(Default)]
+/// - `(V, SingleRow(row))` if the value of `OUTPUT_COLS` is unique when `INPUT_COLS == V`, and `row` is the first row where `INPUT_COLS == V`
+/// - `(V, MultipleRows(rows))` if there exist rows where `INPUT_COLS == V` match but `OUTPUT_COLS` differ, with one row for each value of `OUTPUT_COLS`
 pub struct IndexedColumns<T> {
-    indices: HashMap<Application, Index<T>>,
+    indices: Indices<T>,
+    cache: MatchCache<T>,
+}
+
+impl<T> Default for IndexedColumns<T> {
+    fn default() -> Self {
+        Self {
+            indices: Default::default(),
+            cache: MatchCache::new(DEFAULT_CACHE_CAPACITY),
+        }
+    }
+}
+
+/// Memoizes the results of index lookups, keyed by the (sorted) columns and the input values.
+/// Once the capacity is reached, no new entries are added.
+struct MatchCache<T> {
+    entries: HashMap<(Application, Vec<T>), Option<IndexValue>>,
+    capacity: usize,
+    hits: u64,
+    misses: u64,
+}
+
+impl<T> MatchCache<T> {
+    fn new(capacity: usize) -> Self {
+        Self {
+            entries: Default::default(),
+            capacity,
+            hits: 0,
+            misses: 0,
+        }
+    }
 }
 
 impl<T: FieldElement> IndexedColumns<T> {
@@ -53,30 +116,48 @@ impl<T: FieldElement> IndexedColumns<T> {
     fn get_match(
         &mut self,
         fixed_data: &FixedData<T>,
+        tables: &Tables<T>,
         mut assignment: Vec<(PolyID, T)>,
         mut output_fixed_columns: Vec<PolyID>,
-    ) -> Option<&IndexValue> {
+    ) -> Option<IndexValue> {
         // sort in order to have a single index for [X, Y] and for [Y, X]
         assignment.sort_by(|(name0, _), (name1, _)| name0.cmp(name1));
         let (input_fixed_columns, values): (Vec<_>, Vec<_>) = assignment.into_iter().unzip();
         // sort the output as well
         output_fixed_columns.sort();
 
-        let fixed_columns = (input_fixed_columns, output_fixed_columns);
+        let key = ((input_fixed_columns, output_fixed_columns), values);
+        if let Some(result) = self.cache.entries.get(&key) {
+            self.cache.hits += 1;
+            return result.clone();
+        }
+        self.cache.misses += 1;
 
-        self.ensure_index(fixed_data, &fixed_columns);
+        let (fixed_columns, values) = &key;
+        self.ensure_index(fixed_data, tables, fixed_columns);
 
         // get the rows at which the input matches
-        self.indices
-            .get(&fixed_columns)
+        let result = self
+            .indices
+            .get(fixed_columns)
             .as_ref()
             .unwrap()
-            .get(&values)
+            .get(values)
+            .cloned();
+        if self.cache.entries.len() < self.cache.capacity {
+            self.cache.entries.insert(key, result.clone());
+        }
+        result
     }
 
     /// Create an index for a set of columns to be queried, if does not exist already
     /// `input_fixed_columns` is assumed to be sorted
-    fn ensure_index(&mut self, fixed_data: &FixedData<T>, sorted_fixed_columns: &Application) {
+    fn ensure_index(
+        &mut self,
+        fixed_data: &FixedData<T>,
+        tables: &Tables<T>,
+        sorted_fixed_columns: &Application,
+    ) {
         // we do not use the Entry API here because we want to clone `sorted_input_fixed_columns` only on index creation
         if self.indices.get(sorted_fixed_columns).is_some() {
             return;
@@ -84,6 +165,21 @@ impl<T: FieldElement> IndexedColumns<T> {
 
         let (sorted_input_fixed_columns, sorted_output_fixed_columns) = &sorted_fixed_columns;
 
+        if let [column] = &sorted_input_fixed_columns[..] {
+            if let Some(index) = self.bijective_index(fixed_data, tables, column) {
+                log::trace!(
+                    "Reusing the index of the bijective column {} for the outputs {}",
+                    fixed_data.column_name(column),
+                    sorted_output_fixed_columns
+                        .iter()
+                        .map(|c| fixed_data.column_name(c).to_string())
+                        .join(", ")
+                );
+                self.indices.insert(sorted_fixed_columns.clone(), index);
+                return;
+            }
+        }
+
         // create index for this lookup
         log::trace!(
             "Generating index for lookup in columns (in: {}, out: {})",
@@ -100,29 +196,38 @@ impl<T: FieldElement> IndexedColumns<T> {
         // get all values for the columns to be indexed
         let input_column_values = sorted_input_fixed_columns
             .iter()
-            .map(|id| fixed_data.fixed_cols[id].values)
+            .map(|id| LookupColumn::new(fixed_data, tables, id))
             .collect::<Vec<_>>();
 
         let output_column_values = sorted_output_fixed_columns
             .iter()
-            .map(|id| fixed_data.fixed_cols[id].values)
+            .map(|id| LookupColumn::new(fixed_data, tables, id))
             .collect::<Vec<_>>();
 
-        let index: BTreeMap<Vec<T>, IndexValue> = (0..fixed_data.degree as usize)
+        // All columns belong to the same table, see [FixedLookup::process_plookup].
+        let row_count = match input_column_values
+            .iter()
+            .chain(&output_column_values)
+            .next()
+        {
+            Some(column) => column.row_count(fixed_data),
+            None => fixed_data.degree as usize,
+        };
+        let rows: HashMap<Vec<T>, Vec<usize>> = (0..row_count)
             .fold(
                 (
-                    BTreeMap::<Vec<T>, IndexValue>::default(),
+                    HashMap::<Vec<T>, Vec<usize>>::default(),
                     HashSet::<(Vec<T>, Vec<T>)>::default(),
                 ),
                 |(mut acc, mut set), row| {
                     let input: Vec<_> = input_column_values
                         .iter()
-                        .map(|column| column[row])
+                        .map(|column| column.value(row))
                         .collect();
 
                     let output: Vec<_> = output_column_values
                         .iter()
-                        .map(|column| column[row])
+                        .map(|column| column.value(row))
                         .collect();
 
                     let input_output = (input, output);
@@ -132,17 +237,23 @@ impl<T: FieldElement> IndexedColumns<T> {
 
                         let (input, _) = input_output;
 
-                        acc.entry(input)
-                            // we have a new, different output, so we lose knowledge
-                            .and_modify(|value| {
-                                *value = IndexValue::multiple_matches();
-                            })
-                            .or_insert_with(|| IndexValue::single_row(row));
+                        // we have a new, different output
+                        acc.entry(input).or_default().push(row);
                     }
                     (acc, set)
                 },
             )
             .0;
+        let index: Index<T> = rows
+            .into_iter()
+            .map(|(input, rows)| {
+                let value = match rows[..] {
+                    [row] => IndexValue::SingleRow(row),
+                    _ => IndexValue::MultipleRows(rows.into()),
+                };
+                (input, value)
+            })
+            .collect();
 
         log::trace!(
             "Done creating index. Size (as flat list): entries * (num_inputs * input_size + row_pointer_size) = {} * ({} * {} bytes + {} bytes) = {} bytes",
@@ -157,15 +268,189 @@ impl<T: FieldElement> IndexedColumns<T> {
                 sorted_input_fixed_columns.clone(),
                 sorted_output_fixed_columns.clone(),
             ),
-            index,
+            Arc::new(index),
         );
     }
+
+    /// Returns an index (for any output columns) with `column` as the only input column,
+    /// if the values of the column are pairwise distinct, i.e. the column is a bijection
+    /// between its rows and its values. Such an index maps each value to its only row,
+    /// so it can be used for all output columns: From a value to the row and from the
+    /// row to the values of the other columns.
+    fn bijective_index(
+        &self,
+        fixed_data: &FixedData<T>,
+        tables: &Tables<T>,
+        column: &PolyID,
+    ) -> Option<Arc<Index<T>>> {
+        let row_count = LookupColumn::new(fixed_data, tables, column).row_count(fixed_data);
+        self.indices
+            .iter()
+            .find(|((input, _), index)| input[..] == [*column] && index.len() == row_count)
+            .map(|(_, index)| index.clone())
+    }
+}
+
+/// The indices of the lookups into fixed columns, which are built on the first lookup
+/// into a set of columns. They can be reused by later runs with the same fixed columns,
+/// see [crate::witgen::PreparedGenerator::fixed_lookup_index].
+#[derive(Clone)]
+pub struct FixedLookupIndex<T> {
+    /// The hash of the fixed columns and lookup tables the indices were built for,
+    /// see [fixed_columns_hash].
+    fixed_columns_hash: u64,
+    indices: Indices<T>,
+}
+
+impl<T: FieldElement> FixedLookupIndex<T> {
+    pub(crate) fn new(fixed_columns_hash: u64) -> Self {
+        Self {
+            fixed_columns_hash,
+            indices: Default::default(),
+        }
+    }
+
+    /// The number of indexed sets of input and output columns.
+    pub fn len(&self) -> usize {
+        self.indices.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.indices.is_empty()
+    }
+
+    /// The hash of the fixed columns and lookup tables the indices were built for.
+    pub fn fixed_columns_hash(&self) -> u64 {
+        self.fixed_columns_hash
+    }
+
+    /// Adds the indices of `other` that are not part of this index.
+    pub(crate) fn extend(&mut self, other: &Self) {
+        self.extend_indices(&other.indices);
+    }
+
+    fn extend_indices(&mut self, indices: &Indices<T>) {
+        for (application, index) in indices {
+            self.indices
+                .entry(application.clone())
+                .or_insert_with(|| index.clone());
+        }
+    }
 }
 
-/// Machine to perform a lookup in fixed columns only.
+#[cfg(feature = "serde")]
+impl<T: FieldElement> FixedLookupIndex<T> {
+    /// The version of the format, incremented on every incompatible change.
+    const FORMAT_VERSION: u32 = 1;
+
+    /// Writes the indices to `writer`, to be read by [FixedLookupIndex::read].
+    pub fn write(&self, writer: &mut impl std::io::Write) -> std::io::Result<()> {
+        use crate::witgen::witness_serialization::into_io_error;
+        let indices = self
+            .indices
+            .iter()
+            .map(|(application, index)| (application, &**index))
+            .collect::<Vec<_>>();
+        bincode::serialize_into(&mut *writer, &(Self::FORMAT_VERSION, modulus_bytes::<T>()))
+            .map_err(into_io_error)?;
+        bincode::serialize_into(&mut *writer, &(self.fixed_columns_hash, indices))
+            .map_err(into_io_error)
+    }
+
+    /// Reads indices written by [FixedLookupIndex::write].
+    /// Fails if they were written by a different version of the format or for a
+    /// different field. Whether they belong to the current fixed columns is only checked
+    /// when they are used.
+    pub fn read(reader: &mut impl std::io::Read) -> std::io::Result<Self> {
+        use crate::witgen::witness_serialization::into_io_error;
+        use std::io::{Error, ErrorKind};
+        let (version, modulus): (u32, Vec<u8>) =
+            bincode::deserialize_from(&mut *reader).map_err(into_io_error)?;
+        if version != Self::FORMAT_VERSION {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "Unsupported fixed lookup index format version {version} (expected {}).",
+                    Self::FORMAT_VERSION
+                ),
+            ));
+        }
+        if modulus != modulus_bytes::<T>() {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "The fixed lookup index was built for a different field.",
+            ));
+        }
+        let (fixed_columns_hash, indices): (u64, Vec<(Application, Index<T>)>) =
+            bincode::deserialize_from(&mut *reader).map_err(into_io_error)?;
+        Ok(Self {
+            fixed_columns_hash,
+            indices: indices
+                .into_iter()
+                .map(|(application, index)| (application, Arc::new(index)))
+                .collect(),
+        })
+    }
+}
+
+#[cfg(feature = "serde")]
+fn modulus_bytes<T: FieldElement>() -> Vec<u8> {
+    use powdr_number::BigInt;
+    T::modulus().to_arbitrary_integer().to_bytes_le()
+}
+
+/// A hash of the values of the fixed columns and lookup tables (by name) and the degree,
+/// which determine the indices of a [FixedLookup]. In contrast to [std::hash::Hash],
+/// it does not change between builds, so that it can be stored with the indices.
+/// Fixed columns that are computed by functions are only identified by their name.
+pub(crate) fn fixed_columns_hash<'c, T: FieldElement>(
+    degree: DegreeType,
+    columns: impl IntoIterator<Item = (&'c str, &'c [T])>,
+) -> u64 {
+    // 64-bit FNV-1a
+    let mut hash: u64 = 0xcbf29ce484222325;
+    let mut write = |bytes: &[u8]| {
+        for byte in bytes {
+            hash ^= *byte as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+    };
+    write(&degree.to_le_bytes());
+    for (name, values) in columns {
+        write(&(name.len() as u64).to_le_bytes());
+        write(name.as_bytes());
+        write(&(values.len() as u64).to_le_bytes());
+        for value in values {
+            write(&value.to_bytes_le());
+        }
+    }
+    hash
+}
+
+/// Called when a lookup into fixed columns does not match any row.
+/// Receives the right-hand side of the lookup, the values of the input columns
+/// (by name) and the names of the output columns. Returns `None` to fail the lookup
+/// or the values of the output columns (in the given order) to use instead.
+pub trait MissingLookupCallback<T>:
+    Fn(&str, &[(String, T)], &[String]) -> Option<Vec<T>> + Send + Sync
+{
+}
+impl<T, F> MissingLookupCallback<T> for F where
+    F: Fn(&str, &[(String, T)], &[String]) -> Option<Vec<T>> + Send + Sync
+{
+}
+
+/// Machine to perform a lookup (or permutation) in fixed columns only.
+/// The index of an input column whose values are pairwise distinct (e.g. a permutation
+/// of `0..degree`) is shared by all lookups with that column as the only input column.
+/// The indices and memoized results are only valid for the fixed data they were built
+/// for, so each run creates a new machine (see [FixedLookup::with_index] to reuse
+/// indices for the same fixed columns).
 pub struct FixedLookup<T: FieldElement> {
     global_constraints: GlobalConstraints<T>,
     indices: IndexedColumns<T>,
+    on_missing: Option<Arc<dyn MissingLookupCallback<T>>>,
+    tables: Tables<T>,
 }
 
 impl<T: FieldElement> FixedLookup<T> {
@@ -173,9 +458,79 @@ impl<T: FieldElement> FixedLookup<T> {
         Self {
             global_constraints,
             indices: Default::default(),
+            on_missing: None,
+            tables: Default::default(),
         }
     }
 
+    /// Sets the maximal number of lookup results that are memoized.
+    /// A capacity of zero disables the cache.
+    pub fn with_cache_capacity(mut self, capacity: usize) -> Self {
+        self.indices.cache.capacity = capacity;
+        self.indices.cache.entries.shrink_to(capacity);
+        self
+    }
+
+    /// Sets a callback that is invoked before a lookup without a matching row fails.
+    pub fn with_missing_lookup_callback(
+        mut self,
+        callback: Arc<dyn MissingLookupCallback<T>>,
+    ) -> Self {
+        self.on_missing = Some(callback);
+        self
+    }
+
+    /// Registers a lookup table whose values are given at runtime instead of being
+    /// generated from the PIL file, e.g. because the table is large and loaded from disk.
+    /// The columns (by name) have to be declared as fixed columns. Lookups into them are
+    /// answered from the given values, which all need to have the same (non-zero)
+    /// length, but not necessarily the degree. A lookup must not combine columns of the
+    /// table with other columns.
+    pub fn with_table(mut self, name: String, columns: Vec<(String, Arc<[T]>)>) -> Self {
+        assert!(
+            columns.iter().map(|(_, values)| values.len()).all_equal()
+                && columns.iter().all(|(_, values)| !values.is_empty()),
+            "The columns of lookup table {name} have to have the same non-zero length."
+        );
+        assert!(
+            self.tables
+                .insert(name.clone(), columns.into_iter().collect())
+                .is_none(),
+            "Lookup table {name} is already registered."
+        );
+        self
+    }
+
+    /// Returns the name of the registered lookup table that contains the column.
+    fn table_of(&self, column: &str) -> Option<&str> {
+        self.tables
+            .iter()
+            .find(|(_, table)| table.contains_key(column))
+            .map(|(name, _)| name.as_str())
+    }
+
+    /// Returns the number of cache hits and misses so far.
+    pub fn cache_statistics(&self) -> (u64, u64) {
+        (self.indices.cache.hits, self.indices.cache.misses)
+    }
+
+    /// Uses the given indices (built by a previous run for the same fixed columns and
+    /// lookup tables) instead of building them again.
+    pub fn with_index(mut self, index: &FixedLookupIndex<T>) -> Self {
+        self.indices.indices.extend(
+            index
+                .indices
+                .iter()
+                .map(|(application, index)| (application.clone(), index.clone())),
+        );
+        self
+    }
+
+    /// Adds the indices built so far to `index`.
+    pub fn extend_index(&self, index: &mut FixedLookupIndex<T>) {
+        index.extend_indices(&self.indices.indices);
+    }
+
     pub fn process_plookup_timed<'b>(
         &mut self,
         fixed_data: &FixedData<T>,
@@ -198,22 +553,55 @@ impl<T: FieldElement> FixedLookup<T> {
         left: &[AffineExpression<&'b AlgebraicReference, T>],
         right: &'b SelectedExpressions<Expression<T>>,
     ) -> Option<EvalResult<'b, T>> {
-        // This is a matching machine if it is a plookup and the RHS is fully constant.
-        if kind != IdentityKind::Plookup
-            || right.selector.is_some()
+        // This is a matching machine if it is a plookup or permutation and the RHS is
+        // fully constant. A call of a permutation is processed like a lookup, i.e. the
+        // match of the known values determines the unknown values, no matter how often
+        // the row is used.
+        if !matches!(kind, IdentityKind::Plookup | IdentityKind::Permutation)
             || right.expressions.iter().any(|e| e.contains_witness_ref())
         {
             return None;
         }
 
         // get the values of the fixed columns
-        let right = right
+        let columns = right
             .expressions
             .iter()
             .map(try_to_simple_poly_ref)
             .collect::<Option<Vec<_>>>()?;
+        let tables = columns
+            .iter()
+            .chain(
+                right
+                    .selector
+                    .as_ref()
+                    .and_then(try_to_simple_poly_ref)
+                    .as_ref(),
+            )
+            .map(|column| self.table_of(&column.name))
+            .collect::<HashSet<_>>();
+        if tables.len() > 1 {
+            return Some(Err(EvalError::from(format!(
+                "The lookup {right} combines columns of the lookup tables {} with other columns.",
+                tables.into_iter().flatten().join(", ")
+            ))));
+        }
 
-        Some(self.process_plookup_internal(fixed_data, rows, left, right))
+        match &right.selector {
+            None => Some(self.process_plookup_internal(fixed_data, rows, left, right, columns)),
+            Some(selector) => {
+                // A (boolean) fixed selector on the RHS restricts the lookup to the rows
+                // where it is one, i.e. `{ a, b } in SEL { X, Y }` is processed
+                // like `{ 1, a, b } in { SEL, X, Y }`.
+                let selector = try_to_simple_poly_ref(selector)
+                    .filter(|s| s.poly_id.ptype == PolynomialType::Constant)?;
+                let left = std::iter::once(T::one().into())
+                    .chain(left.iter().cloned())
+                    .collect::<Vec<_>>();
+                let columns = std::iter::once(selector).chain(columns).collect();
+                Some(self.process_plookup_internal(fixed_data, rows, &left, right, columns))
+            }
+        }
     }
 
     fn process_plookup_internal<'b>(
@@ -221,11 +609,13 @@ impl<T: FieldElement> FixedLookup<T> {
         fixed_data: &FixedData<T>,
         rows: &RowPair<'_, '_, T>,
         left: &[AffineExpression<&'b AlgebraicReference, T>],
+        lookup: &SelectedExpressions<Expression<T>>,
         right: Vec<&'b AlgebraicReference>,
     ) -> EvalResult<'b, T> {
         if left.len() == 1
             && !left.first().unwrap().is_constant()
             && right.first().unwrap().poly_id.ptype == PolynomialType::Constant
+            && self.table_of(&right.first().unwrap().name).is_none()
         {
             // Lookup of the form "c { X } in { B }". Might be a conditional range check.
             return self.process_range_check(rows, left.first().unwrap(), right.first().unwrap());
@@ -251,36 +641,73 @@ impl<T: FieldElement> FixedLookup<T> {
             .iter()
             .map(|(poly_ref, v)| (poly_ref.poly_id, *v))
             .collect();
-        let index_value = self
-            .indices
-            .get_match(
-                fixed_data,
-                input_assignment_with_ids,
-                output_columns.clone(),
-            )
-            .ok_or_else(|| {
-                let input_assignment = input_assignment
-                    .into_iter()
-                    .map(|(poly_ref, v)| (poly_ref.name.clone(), v))
-                    .collect();
-                EvalError::FixedLookupFailed(input_assignment)
-            })?;
-
-        let row = match index_value.row() {
+        let index_value = self.indices.get_match(
+            fixed_data,
+            &self.tables,
+            input_assignment_with_ids,
+            output_columns.clone(),
+        );
+        let Some(index_value) = index_value else {
+            let input_assignment = input_assignment
+                .into_iter()
+                .map(|(poly_ref, v)| (poly_ref.name.clone(), v))
+                .collect::<Vec<_>>();
+            let output_names = output_columns
+                .iter()
+                .map(|column| fixed_data.column_name(column).to_string())
+                .collect::<Vec<_>>();
+            let output = self.on_missing.as_ref().and_then(|on_missing| {
+                on_missing(&lookup.to_string(), &input_assignment, &output_names)
+            });
+            return match output {
+                Some(output) => {
+                    assert_eq!(
+                        output.len(),
+                        output_names.len(),
+                        "The callback for missing lookups has to return a value for each output column ({}).",
+                        output_names.join(", ")
+                    );
+                    Self::assign_outputs(output_expressions, output)
+                }
+                None => Err(EvalError::FixedLookupFailed(input_assignment)),
+            };
+        };
+
+        let rows = match index_value {
             // a single match, we continue
-            Some(row) => row,
-            // multiple matches, we stop and learnt nothing
-            None => {
-                return Ok(EvalValue::incomplete(
-                    IncompleteCause::MultipleLookupMatches,
-                ))
+            IndexValue::SingleRow(row) => {
+                let output = output_columns
+                    .iter()
+                    .map(|column| LookupColumn::new(fixed_data, &self.tables, column).value(row));
+                return Self::assign_outputs(output_expressions, output);
             }
+            IndexValue::MultipleRows(rows) => rows,
         };
 
-        let output = output_columns
+        // multiple matches, we can only assign the output columns on which all matches agree
+        let (agreeing_expressions, agreeing_values): (Vec<_>, Vec<_>) = output_columns
             .iter()
-            .map(|column| fixed_data.fixed_cols[column].values[row]);
+            .zip(output_expressions)
+            .filter_map(|(column, expression)| {
+                let column = LookupColumn::new(fixed_data, &self.tables, column);
+                let value = column.value(rows[0]);
+                rows.iter()
+                    .all(|row| column.value(*row) == value)
+                    .then_some((expression, value))
+            })
+            .unzip();
+        let result = Self::assign_outputs(agreeing_expressions, agreeing_values)?;
+        Ok(EvalValue::incomplete_with_constraints(
+            result.constraints,
+            IncompleteCause::MultipleLookupMatches,
+        ))
+    }
 
+    /// Solves each of the output expressions for the corresponding value.
+    fn assign_outputs<'b>(
+        output_expressions: Vec<&AffineExpression<&'b AlgebraicReference, T>>,
+        output: impl IntoIterator<Item = T>,
+    ) -> EvalResult<'b, T> {
         let mut result = EvalValue::complete(vec![]);
         for (l, r) in output_expressions.into_iter().zip(output) {
             let evaluated = l.clone() - r.into();
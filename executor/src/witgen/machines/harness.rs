@@ -0,0 +1,145 @@
+use num_bigint::BigUint;
+use powdr_ast::analyzed::{AlgebraicExpression as Expression, Identity};
+use powdr_number::{BigInt, FieldElement};
+
+use crate::witgen::range_constraints::RangeConstraint;
+use crate::witgen::{EvalError, MutableStateBuilder, QueryCallback};
+
+use super::Machine;
+
+/// Calls a machine repeatedly through one of its connecting identities with
+/// pseudo-random inputs, to fuzz its solver without running a calling machine.
+pub struct MachineHarness<'a, T: FieldElement, Q: QueryCallback<T>> {
+    state_builder: MutableStateBuilder<'a, T, Q>,
+    identity: &'a Identity<Expression<T>>,
+    /// For each argument of the identity, the range of its random values,
+    /// or `None` if the machine should determine it.
+    inputs: Vec<Option<RangeConstraint<T>>>,
+    /// The state of the xorshift generator, never zero.
+    random_state: u64,
+}
+
+/// A call made by [MachineHarness::run]: the arguments passed to the machine and
+/// the arguments completed by the machine (or the error it returned).
+pub type HarnessCall<T> = (Vec<Option<T>>, Result<Vec<Option<T>>, EvalError<T>>);
+
+impl<'a, T: FieldElement, Q: QueryCallback<T>> MachineHarness<'a, T, Q> {
+    pub fn new(
+        state_builder: MutableStateBuilder<'a, T, Q>,
+        identity: &'a Identity<Expression<T>>,
+        inputs: Vec<Option<RangeConstraint<T>>>,
+        seed: u64,
+    ) -> Self {
+        assert_eq!(inputs.len(), identity.left.expressions.len());
+        Self {
+            state_builder,
+            identity,
+            inputs,
+            random_state: seed.max(1),
+        }
+    }
+
+    /// Makes `calls` calls with new random inputs each.
+    /// Panics if the machine does not accept calls through the identity.
+    pub fn run<M: Machine<'a, T>>(&mut self, machine: &mut M, calls: usize) -> Vec<HarnessCall<T>> {
+        (0..calls)
+            .map(|_| {
+                let arguments = (0..self.inputs.len())
+                    .map(|i| {
+                        self.inputs[i]
+                            .clone()
+                            .map(|range| self.random_value(&range))
+                    })
+                    .collect::<Vec<_>>();
+                let result = machine
+                    .call(&mut self.state_builder.state(), self.identity, &arguments)
+                    .unwrap_or_else(|| {
+                        panic!(
+                            "Machine {} does not accept calls through {}",
+                            machine.name(),
+                            self.identity
+                        )
+                    });
+                (arguments, result)
+            })
+            .collect()
+    }
+
+    /// Returns a random value within the range of the constraint.
+    fn random_value(&mut self, range: &RangeConstraint<T>) -> T {
+        let (min, _) = range.range();
+        let width = range.range_width().to_arbitrary_integer();
+        min + T::from(BigUint::from(self.next_random()) % width)
+    }
+
+    fn next_random(&mut self) -> u64 {
+        self.random_state ^= self.random_state << 13;
+        self.random_state ^= self.random_state >> 7;
+        self.random_state ^= self.random_state << 17;
+        self.random_state
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use powdr_ast::analyzed::IdentityKind;
+    use powdr_number::GoldilocksField;
+    use powdr_pil_analyzer::analyze_string;
+
+    use crate::constant_evaluator;
+    use crate::witgen::global_constraints::GlobalConstraints;
+    use crate::witgen::machines::block_machine::BlockMachine;
+    use crate::witgen::{unused_query_callback, FixedData};
+
+    use super::*;
+
+    #[test]
+    fn fuzz_block_machine() {
+        let src = r"
+namespace Main(256);
+    col fixed CALL = [1, 0]*;
+    col witness a, b;
+    CALL { a, b } in Sq.LATCH { Sq.x, Sq.y };
+namespace Sq(256);
+    col fixed LATCH = [0, 1]*;
+    col witness x, y;
+    y = x * x;
+";
+        let analyzed = analyze_string::<GoldilocksField>(src);
+        let constants = constant_evaluator::generate(&analyzed);
+        let fixed_data = FixedData::new(&analyzed, analyzed.degree(), &constants, None);
+        let (connecting, identities): (Vec<_>, Vec<_>) = analyzed
+            .identities
+            .iter()
+            .partition(|identity| identity.kind == IdentityKind::Plookup);
+        let witnesses = fixed_data
+            .witness_cols
+            .iter()
+            .filter(|(_, column)| column.poly.name.starts_with("Sq."))
+            .map(|(poly_id, _)| poly_id)
+            .collect();
+        let mut machine = BlockMachine::try_new(
+            "Sq".to_string(),
+            &fixed_data,
+            &connecting,
+            &identities,
+            &witnesses,
+            &GlobalConstraints::unconstrained(&fixed_data),
+        )
+        .unwrap();
+
+        let mut harness = MachineHarness::new(
+            MutableStateBuilder::new(&fixed_data, unused_query_callback()),
+            connecting[0],
+            vec![Some(RangeConstraint::from_max_bit(15)), None],
+            42,
+        );
+        let calls = harness.run(&mut machine, 100);
+        assert_eq!(calls.len(), 100);
+        for (arguments, result) in calls {
+            let x = arguments[0].unwrap();
+            assert!(x.to_degree() < 1 << 16);
+            assert_eq!(result.unwrap(), vec![Some(x), Some(x * x)]);
+        }
+    }
+}
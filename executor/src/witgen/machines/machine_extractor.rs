@@ -1,14 +1,18 @@
-use std::collections::HashSet;
+use std::collections::{BTreeSet, HashMap, HashSet};
 
 use super::block_machine::BlockMachine;
+use super::custom_machine::{CustomMachineFactory, MachineParts};
 use super::double_sorted_witness_machine::DoubleSortedWitnesses;
+use super::dynamic_lookup_machine::DynamicLookupMachine;
 use super::fixed_lookup_machine::FixedLookup;
+use super::permutation_machine::PermutationMachine;
 use super::sorted_witness_machine::SortedWitnesses;
 use super::FixedData;
 use super::KnownMachine;
 use crate::witgen::generator::Generator;
 use crate::witgen::global_constraints::GlobalConstraints;
 use crate::witgen::machines::write_once_memory::WriteOnceMemory;
+use crate::witgen::sequence_iterator::{sort_identities, SequenceStrategy};
 use itertools::Itertools;
 use powdr_ast::analyzed::{AlgebraicExpression as Expression, Identity, IdentityKind, PolyID};
 use powdr_ast::parsed::visitor::ExpressionVisitable;
@@ -20,25 +24,334 @@ pub struct ExtractionOutput<'a, T: FieldElement> {
     pub machines: Vec<KnownMachine<'a, T>>,
     pub base_identities: Vec<&'a Identity<Expression<T>>>,
     pub base_witnesses: HashSet<PolyID>,
+    /// The parts of each extracted machine, in the order of `machines`.
+    pub machine_parts: Vec<MachineParts<'a, T>>,
 }
 
-/// Finds machines in the witness columns and identities
-/// and returns a list of machines and the identities
-/// that are not "internal" to the machines.
-pub fn split_out_machines<'a, T: FieldElement>(
-    fixed: &'a FixedData<'a, T>,
-    identities: Vec<&'a Identity<Expression<T>>>,
+/// The kind of an extracted machine, i.e. the implementation of [super::Machine] that processes
+/// its identities.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum MachineKind {
+    /// The custom machine with the given index in the custom machines.
+    Custom(usize),
+    SortedWitnesses,
+    DoubleSortedWitnesses,
+    WriteOnceMemory,
+    BlockMachine,
+    PermutationMachine,
+    DynamicLookup,
+    Vm,
+}
+
+impl MachineKind {
+    /// The kinds that are detected from the identities and witnesses of a machine, in the
+    /// order in which they are tried. A VM can process any machine.
+    const DETECTED: [MachineKind; 7] = [
+        MachineKind::SortedWitnesses,
+        MachineKind::DoubleSortedWitnesses,
+        MachineKind::WriteOnceMemory,
+        MachineKind::BlockMachine,
+        MachineKind::PermutationMachine,
+        MachineKind::DynamicLookup,
+        MachineKind::Vm,
+    ];
+
+    /// The name of the kind, which is part of the name of the machine.
+    fn type_name(&self) -> &'static str {
+        match self {
+            MachineKind::Custom(_) => "Custom",
+            MachineKind::SortedWitnesses => "SortedWitness",
+            MachineKind::DoubleSortedWitnesses => "DoubleSortedWitnesses",
+            MachineKind::WriteOnceMemory => "WriteOnceMemory",
+            MachineKind::BlockMachine => "BlockMachine",
+            MachineKind::PermutationMachine => "PermutationMachine",
+            MachineKind::DynamicLookup => "DynamicLookup",
+            MachineKind::Vm => "Vm",
+        }
+    }
+
+    fn description(&self) -> &'static str {
+        match self {
+            MachineKind::Custom(_) => "custom",
+            MachineKind::SortedWitnesses => "sorted witnesses / write-once memory",
+            MachineKind::DoubleSortedWitnesses => "memory",
+            MachineKind::WriteOnceMemory => "write-once memory",
+            MachineKind::BlockMachine => "block",
+            MachineKind::PermutationMachine => "permutation",
+            MachineKind::DynamicLookup => "dynamic lookup",
+            MachineKind::Vm => "VM.",
+        }
+    }
+}
+
+/// A machine of a [MachineLayout]. Identities are given by their index in the identities
+/// the layout was extracted from.
+struct ExtractedMachine {
+    kind: MachineKind,
+    name: String,
+    identities: Vec<usize>,
+    connecting_identities: Vec<usize>,
+    witnesses: HashSet<PolyID>,
+}
+
+/// The machines found by [extract_machines]: Their kind, witness columns and identities,
+/// and the identities and witness columns left to the main machine.
+/// Unlike the machines, the layout does not borrow the fixed data and does not depend on
+/// the external witness values or the query callback, so it can be extracted once and
+/// used to create the machines for any number of runs, see [MachineLayout::create_machines].
+pub struct MachineLayout {
+    machines: Vec<ExtractedMachine>,
+    base_identities: Vec<usize>,
+    base_witnesses: HashSet<PolyID>,
+}
+
+impl MachineLayout {
+    /// Creates the machines of the layout, e.g. for a run of the witness generator.
+    /// `fixed` and `identities` have to be the fixed data (up to the external witness
+    /// values and callbacks) and the identities the layout was extracted from.
+    pub fn create_machines<'a, T: FieldElement>(
+        &self,
+        fixed: &'a FixedData<'a, T>,
+        identities: &[&'a Identity<Expression<T>>],
+        global_range_constraints: &GlobalConstraints<T>,
+        custom_machines: &[(String, &dyn CustomMachineFactory<T>)],
+    ) -> ExtractionOutput<'a, T> {
+        let machine_parts = self.machine_parts(fixed, identities);
+        let machines = self
+            .machines
+            .iter()
+            .zip(&machine_parts)
+            .map(|(machine, parts)| {
+                try_create_machine(
+                    machine.kind,
+                    parts,
+                    global_range_constraints,
+                    custom_machines,
+                )
+                .unwrap_or_else(|| panic!("Could not create the extracted {}.", parts.name))
+            })
+            .collect();
+        ExtractionOutput {
+            fixed_lookup: FixedLookup::new(global_range_constraints.clone()),
+            machines,
+            base_identities: self.base_identities(identities),
+            base_witnesses: self.base_witnesses.clone(),
+            machine_parts,
+        }
+    }
+
+    /// Returns the parts of the machines of the layout, without creating the machines.
+    /// `fixed` and `identities` are as in [MachineLayout::create_machines].
+    pub fn machine_parts<'a, T: FieldElement>(
+        &self,
+        fixed: &'a FixedData<'a, T>,
+        identities: &[&'a Identity<Expression<T>>],
+    ) -> Vec<MachineParts<'a, T>> {
+        let select = |indices: &[usize]| indices.iter().map(|index| identities[*index]).collect();
+        self.machines
+            .iter()
+            .map(|machine| MachineParts {
+                name: machine.name.clone(),
+                fixed_data: fixed,
+                connecting_identities: select(&machine.connecting_identities),
+                identities: select(&machine.identities),
+                witnesses: machine.witnesses.clone(),
+            })
+            .collect()
+    }
+
+    /// The identities that are left to the main machine, sorted like the identities of
+    /// the other machines.
+    pub fn base_identities<'a, T>(
+        &self,
+        identities: &[&'a Identity<Expression<T>>],
+    ) -> Vec<&'a Identity<Expression<T>>> {
+        self.base_identities
+            .iter()
+            .map(|index| identities[*index])
+            .collect()
+    }
+
+    /// The witness columns that are left to the main machine.
+    pub fn base_witnesses(&self) -> &HashSet<PolyID> {
+        &self.base_witnesses
+    }
+}
+
+/// Creates a machine of the given kind, or returns `None` if the machine cannot process
+/// the identities and witnesses of `parts`.
+fn try_create_machine<'a, T: FieldElement>(
+    kind: MachineKind,
+    parts: &MachineParts<'a, T>,
     global_range_constraints: &GlobalConstraints<T>,
-) -> ExtractionOutput<'a, T> {
-    let fixed_lookup = FixedLookup::new(global_range_constraints.clone());
+    custom_machines: &[(String, &dyn CustomMachineFactory<T>)],
+) -> Option<KnownMachine<'a, T>> {
+    let MachineParts {
+        name,
+        connecting_identities,
+        identities,
+        witnesses,
+        ..
+    } = parts;
+    let fixed = parts.fixed_data;
+    let name = name.clone();
+    match kind {
+        MachineKind::Custom(index) => Some(KnownMachine::Custom(
+            custom_machines[index].1.create(parts.clone()),
+        )),
+        MachineKind::SortedWitnesses => {
+            SortedWitnesses::try_new(name, fixed, identities, witnesses)
+                .map(KnownMachine::SortedWitnesses)
+        }
+        MachineKind::DoubleSortedWitnesses => DoubleSortedWitnesses::try_new(
+            name,
+            fixed,
+            identities,
+            witnesses,
+            global_range_constraints,
+        )
+        .map(KnownMachine::DoubleSortedWitnesses),
+        MachineKind::WriteOnceMemory => {
+            WriteOnceMemory::try_new(name, fixed, connecting_identities, identities)
+                .map(KnownMachine::WriteOnceMemory)
+        }
+        MachineKind::BlockMachine => BlockMachine::try_new(
+            name,
+            fixed,
+            connecting_identities,
+            identities,
+            witnesses,
+            global_range_constraints,
+        )
+        .map(KnownMachine::BlockMachine),
+        MachineKind::PermutationMachine => {
+            PermutationMachine::try_new(name, fixed, connecting_identities, identities)
+                .map(KnownMachine::PermutationMachine)
+        }
+        MachineKind::DynamicLookup => DynamicLookupMachine::try_new(
+            name,
+            fixed,
+            connecting_identities,
+            identities,
+            witnesses,
+            global_range_constraints,
+        )
+        .map(KnownMachine::DynamicLookup),
+        MachineKind::Vm => {
+            let latch = connecting_identities
+                .iter()
+                .fold(None, |existing_latch, identity| {
+                    let current_latch = identity
+                        .right
+                        .selector
+                        .as_ref()
+                        .expect("Cannot handle lookup in this machine because it does not have a latch");
+                    if let Some(existing_latch) = existing_latch {
+                        assert_eq!(
+                            &existing_latch, current_latch,
+                            "All connecting identities must have the same selector expression on the right hand side"
+                        );
+                        Some(existing_latch)
+                    } else {
+                        Some(current_latch.clone())
+                    }
+                })
+                .unwrap();
+            Some(KnownMachine::Vm(Generator::new(
+                name,
+                fixed,
+                identities,
+                witnesses.clone(),
+                global_range_constraints,
+                Some(latch),
+                None,
+            )))
+        }
+    }
+}
 
-    let mut machines: Vec<KnownMachine<T>> = vec![];
+/// Finds machines in the witness columns and identities and returns their layout,
+/// i.e. the machines and the identities that are not "internal" to the machines.
+/// The machines themselves are created by [MachineLayout::create_machines].
+/// The identities of each machine are sorted according to the identity priorities and
+/// the sequence strategy.
+/// The witness columns of the namespaces in `custom_machines` are claimed by the
+/// respective custom machine, which are extracted first.
+///
+/// The other machines are extracted in the order of the lookups into them: The witness
+/// columns on the right-hand side of a lookup are extended by all witness columns that
+/// are row-connected to them. By default, a machine processes all identities whose
+/// left-hand side only references its witness columns (and at least one of them), the
+/// main machine processes the remaining identities.
+/// `identity_assignments` overrides this choice for identities (by ID): An identity is
+/// processed by the machine whose namespace it is assigned to, or by the main machine
+/// if no machine has that namespace. The namespace of a machine is the namespace of its
+/// first witness column in source order.
+pub fn extract_machines<'a, T: FieldElement>(
+    fixed: &'a FixedData<'a, T>,
+    identities: &[&'a Identity<Expression<T>>],
+    global_range_constraints: &GlobalConstraints<T>,
+    sequence_strategy: Option<&dyn SequenceStrategy<T>>,
+    identity_priorities: &HashMap<u64, i32>,
+    identity_assignments: &HashMap<u64, String>,
+    custom_machines: &[(String, &dyn CustomMachineFactory<T>)],
+) -> MachineLayout {
+    let index_of = identities
+        .iter()
+        .enumerate()
+        .map(|(index, identity)| (*identity as *const Identity<Expression<T>>, index))
+        .collect::<HashMap<_, _>>();
+    let indices = |identities: &[&Identity<Expression<T>>]| -> Vec<usize> {
+        identities
+            .iter()
+            .map(|identity| index_of[&(*identity as *const _)])
+            .collect()
+    };
+    let mut machines = vec![];
 
     let all_witnesses = fixed.witness_cols.keys().collect::<HashSet<_>>();
     let mut remaining_witnesses = all_witnesses.clone();
-    let mut base_identities = identities.clone();
+    let mut base_identities = identities.to_vec();
     let mut id_counter = 0;
-    for id in &identities {
+    for (index, (namespace, _)) in custom_machines.iter().enumerate() {
+        let prefix = format!("{namespace}.");
+        let machine_witnesses = remaining_witnesses
+            .iter()
+            .filter(|poly| fixed.column_name(poly).starts_with(&prefix))
+            .cloned()
+            .collect::<HashSet<_>>();
+        assert!(
+            !machine_witnesses.is_empty(),
+            "The custom machine for namespace {namespace} does not have any witness columns."
+        );
+        let (mut machine_identities, remaining_identities) = split_identities(
+            &base_identities,
+            &all_witnesses,
+            &machine_witnesses,
+            namespace,
+            identity_assignments,
+        );
+        base_identities = remaining_identities;
+        remaining_witnesses = &remaining_witnesses - &machine_witnesses;
+        sort_identities(
+            &mut machine_identities,
+            sequence_strategy,
+            identity_priorities,
+        );
+
+        let id = id_counter;
+        id_counter += 1;
+        log::debug!("Extracted custom machine for namespace {namespace}.");
+        let kind = MachineKind::Custom(index);
+        machines.push(ExtractedMachine {
+            kind,
+            name: format!("Secondary machine {id}: {namespace} ({})", kind.type_name()),
+            identities: indices(&machine_identities),
+            connecting_identities: indices(&connecting_identities(identities, &machine_witnesses)),
+            witnesses: machine_witnesses,
+        });
+    }
+    for id in identities {
         // Extract all witness columns in the RHS of the lookup.
         let lookup_witnesses = &refs_in_selected_expressions(&id.right) & (&remaining_witnesses);
         if lookup_witnesses.is_empty() {
@@ -48,35 +361,34 @@ pub fn split_out_machines<'a, T: FieldElement>(
         // Recursively extend the set to all witnesses connected through identities that preserve
         // a fixed row relation.
         let machine_witnesses =
-            all_row_connected_witnesses(lookup_witnesses, &remaining_witnesses, &identities);
-
-        // Split identities into those that only concern the machine
-        // witnesses and those that concern any other witness.
-        let (machine_identities, remaining_identities): (Vec<_>, _) =
-            base_identities.iter().cloned().partition(|i| {
-                // The identity's left side has at least one machine witness, but
-                // all referenced witnesses are machine witnesses.
-                // Note that expressions of "simple" polynomial identities are stored
-                // in i.left.selector, so those are covered as well.
-                // For lookups, any lookup calling from the current machine belongs
-                // to the machine; lookups to the machine do not.
-                let all_refs = &refs_in_selected_expressions(&i.left) & (&all_witnesses);
-                !all_refs.is_empty() && all_refs.is_subset(&machine_witnesses)
-            });
+            all_row_connected_witnesses(lookup_witnesses, &remaining_witnesses, identities);
+
+        let first_witness = machine_witnesses.iter().min().unwrap();
+        let first_witness_name = fixed.column_name(first_witness);
+        let namespace = first_witness_name
+            .rfind('.')
+            .map(|idx| &first_witness_name[..idx]);
+
+        // For machines compiled using Powdr ASM we'll always have a namespace, but as a last
+        // resort we'll use the first witness name.
+        let name = namespace.unwrap_or(first_witness_name);
+
+        let (mut machine_identities, remaining_identities) = split_identities(
+            &base_identities,
+            &all_witnesses,
+            &machine_witnesses,
+            name,
+            identity_assignments,
+        );
         base_identities = remaining_identities;
         remaining_witnesses = &remaining_witnesses - &machine_witnesses;
+        sort_identities(
+            &mut machine_identities,
+            sequence_strategy,
+            identity_priorities,
+        );
 
-        // Identities that call into the current machine
-        let connecting_identities = identities
-            .iter()
-            .cloned()
-            .filter(|i| {
-                refs_in_selected_expressions(&i.right)
-                    .intersection(&machine_witnesses)
-                    .next()
-                    .is_some()
-            })
-            .collect::<Vec<_>>();
+        let connecting_identities = connecting_identities(identities, &machine_witnesses);
         assert!(connecting_identities.contains(id));
 
         log::debug!(
@@ -99,93 +411,98 @@ pub fn split_out_machines<'a, T: FieldElement>(
                 .join("\n"),
         );
 
-        let first_witness = machine_witnesses.iter().next().unwrap();
-        let first_witness_name = fixed.column_name(first_witness);
-        let namespace = first_witness_name
-            .rfind('.')
-            .map(|idx| &first_witness_name[..idx]);
-
-        // For machines compiled using Powdr ASM we'll always have a namespace, but as a last
-        // resort we'll use the first witness name.
-        let name = namespace.unwrap_or(first_witness_name);
         let id = id_counter;
         id_counter += 1;
-        let name_with_type = |t: &str| format!("Secondary machine {id}: {name} ({t})");
-
-        if let Some(machine) = SortedWitnesses::try_new(
-            name_with_type("SortedWitness"),
-            fixed,
-            &machine_identities,
-            &machine_witnesses,
-        ) {
-            log::debug!("Detected machine: sorted witnesses / write-once memory");
-            machines.push(KnownMachine::SortedWitnesses(machine));
-        } else if let Some(machine) = DoubleSortedWitnesses::try_new(
-            name_with_type("DoubleSortedWitnesses"),
-            fixed,
-            &machine_identities,
-            &machine_witnesses,
-            global_range_constraints,
-        ) {
-            log::debug!("Detected machine: memory");
-            machines.push(KnownMachine::DoubleSortedWitnesses(machine));
-        } else if let Some(machine) = WriteOnceMemory::try_new(
-            name_with_type("WriteOnceMemory"),
-            fixed,
-            &connecting_identities,
-            &machine_identities,
-        ) {
-            log::debug!("Detected machine: write-once memory");
-            machines.push(KnownMachine::WriteOnceMemory(machine));
-        } else if let Some(machine) = BlockMachine::try_new(
-            name_with_type("BlockMachine"),
-            fixed,
-            &connecting_identities,
-            &machine_identities,
-            &machine_witnesses,
-            global_range_constraints,
-        ) {
-            log::debug!("Detected machine: block");
-            machines.push(KnownMachine::BlockMachine(machine));
-        } else {
-            log::debug!("Detected machine: VM.");
-            let latch = connecting_identities
-                .iter()
-                .fold(None, |existing_latch, identity| {
-                    let current_latch = identity
-                        .right
-                        .selector
-                        .as_ref()
-                        .expect("Cannot handle lookup in this machine because it does not have a latch");
-                    if let Some(existing_latch) = existing_latch {
-                        assert_eq!(
-                            &existing_latch, current_latch,
-                            "All connecting identities must have the same selector expression on the right hand side"
-                        );
-                        Some(existing_latch)
-                    } else {
-                        Some(current_latch.clone())
-                    }
-                })
-                .unwrap();
-            machines.push(KnownMachine::Vm(Generator::new(
-                name_with_type("Vm"),
-                fixed,
-                &machine_identities,
-                machine_witnesses,
-                global_range_constraints,
-                Some(latch),
-            )));
-        }
+        let name_with_type =
+            |kind: MachineKind| format!("Secondary machine {id}: {name} ({})", kind.type_name());
+        // The name is set once the kind of the machine is known.
+        let mut parts = MachineParts {
+            name: String::new(),
+            fixed_data: fixed,
+            connecting_identities,
+            identities: machine_identities,
+            witnesses: machine_witnesses,
+        };
+        // A VM can process any machine, so a kind is always found.
+        let kind = MachineKind::DETECTED
+            .into_iter()
+            .find(|kind| {
+                parts.name = name_with_type(*kind);
+                try_create_machine(*kind, &parts, global_range_constraints, custom_machines)
+                    .is_some()
+            })
+            .unwrap();
+        log::debug!("Detected machine: {}", kind.description());
+        machines.push(ExtractedMachine {
+            kind,
+            name: parts.name,
+            identities: indices(&parts.identities),
+            connecting_identities: indices(&parts.connecting_identities),
+            witnesses: parts.witnesses,
+        });
     }
-    ExtractionOutput {
-        fixed_lookup,
+    sort_identities(&mut base_identities, sequence_strategy, identity_priorities);
+    log::debug!(
+        "Extracted {} secondary machines ({}), leaving {} witnesses and {} identities to the main machine.",
+        machines.len(),
+        machines.iter().map(|m| &m.name).join(", "),
+        remaining_witnesses.len(),
+        base_identities.len()
+    );
+    MachineLayout {
         machines,
-        base_identities,
+        base_identities: indices(&base_identities),
         base_witnesses: remaining_witnesses,
     }
 }
 
+/// Splits identities into those that only concern the machine
+/// witnesses and those that concern any other witness.
+/// Identities in `assignments` belong to the machine if and only if they are
+/// assigned to its namespace.
+#[allow(clippy::type_complexity)]
+fn split_identities<'a, T>(
+    identities: &[&'a Identity<Expression<T>>],
+    all_witnesses: &HashSet<PolyID>,
+    machine_witnesses: &HashSet<PolyID>,
+    namespace: &str,
+    assignments: &HashMap<u64, String>,
+) -> (
+    Vec<&'a Identity<Expression<T>>>,
+    Vec<&'a Identity<Expression<T>>>,
+) {
+    identities.iter().cloned().partition(|i| {
+        if let Some(assigned) = assignments.get(&i.id) {
+            return assigned == namespace;
+        }
+        // The identity's left side has at least one machine witness, but
+        // all referenced witnesses are machine witnesses.
+        // Note that expressions of "simple" polynomial identities are stored
+        // in i.left.selector, so those are covered as well.
+        // For lookups, any lookup calling from the current machine belongs
+        // to the machine; lookups to the machine do not.
+        let all_refs = &refs_in_selected_expressions(&i.left) & all_witnesses;
+        !all_refs.is_empty() && all_refs.is_subset(machine_witnesses)
+    })
+}
+
+/// Returns the identities that call into the machine.
+fn connecting_identities<'a, T>(
+    identities: &[&'a Identity<Expression<T>>],
+    machine_witnesses: &HashSet<PolyID>,
+) -> Vec<&'a Identity<Expression<T>>> {
+    identities
+        .iter()
+        .cloned()
+        .filter(|i| {
+            refs_in_selected_expressions(&i.right)
+                .intersection(machine_witnesses)
+                .next()
+                .is_some()
+        })
+        .collect()
+}
+
 /// Extends a set of witnesses to the full set of row-connected witnesses.
 /// Two witnesses are row-connected if they are part of a polynomial identity
 /// or part of the same side of a lookup.
@@ -224,6 +541,123 @@ fn all_row_connected_witnesses<T>(
     }
 }
 
+/// Splits the identities and witnesses of the main machine into independent main
+/// machines, i.e. groups of witness columns that are not connected by any identity
+/// (they can still share fixed columns or call into the same machines).
+/// The groups are ordered by their first witness column. Identities that do not
+/// reference any witness column belong to the first group, which always exists.
+#[allow(clippy::type_complexity)]
+pub fn split_independent_main_machines<'a, T>(
+    identities: Vec<&'a Identity<Expression<T>>>,
+    witnesses: HashSet<PolyID>,
+) -> Vec<(Vec<&'a Identity<Expression<T>>>, HashSet<PolyID>)> {
+    let mut remaining = witnesses.iter().cloned().collect::<BTreeSet<_>>();
+    let mut identities = identities
+        .into_iter()
+        .map(|identity| (identity, &refs_in_identity(identity) & &witnesses))
+        .collect::<Vec<_>>();
+    let mut machines = vec![];
+    while let Some(first) = remaining.pop_first() {
+        let mut machine_witnesses = HashSet::from([first]);
+        loop {
+            let count = machine_witnesses.len();
+            for (_, refs) in &identities {
+                if !refs.is_disjoint(&machine_witnesses) {
+                    machine_witnesses.extend(refs);
+                }
+            }
+            if machine_witnesses.len() == count {
+                break;
+            }
+        }
+        let machine_identities;
+        (machine_identities, identities) = identities.into_iter().partition(|(_, refs)| {
+            !refs.is_disjoint(&machine_witnesses) || (refs.is_empty() && machines.is_empty())
+        });
+        remaining.retain(|poly_id| !machine_witnesses.contains(poly_id));
+        machines.push((
+            machine_identities
+                .into_iter()
+                .map(|(identity, _)| identity)
+                .collect(),
+            machine_witnesses,
+        ));
+    }
+    if machines.is_empty() {
+        let identities = identities.into_iter().map(|(identity, _)| identity);
+        machines.push((identities.collect(), HashSet::new()));
+    }
+    machines
+}
+
+/// Returns the indices of the machines that are called by the given identities.
+fn called_machines<T: FieldElement>(
+    identities: &[&Identity<Expression<T>>],
+    machine_parts: &[MachineParts<'_, T>],
+) -> BTreeSet<usize> {
+    machine_parts
+        .iter()
+        .positions(|machine| {
+            machine.connecting_identities.iter().any(|connecting| {
+                identities
+                    .iter()
+                    .any(|identity| std::ptr::eq(*connecting, *identity))
+            })
+        })
+        .collect()
+}
+
+/// Groups the main machines (given by their identities) together with the machines they
+/// call, directly or via other machines, such that no machine is called from two groups.
+/// The groups do not share any state while solving, so they can be solved concurrently.
+/// Returns the indices of the main machines and of the machines (in `machine_parts`) of
+/// each group, ordered by the first main machine. Machines that are not called by any
+/// main machine belong to the first group.
+pub fn independent_machine_groups<T: FieldElement>(
+    main_identities: &[&[&Identity<Expression<T>>]],
+    machine_parts: &[MachineParts<'_, T>],
+) -> Vec<(BTreeSet<usize>, BTreeSet<usize>)> {
+    let dependencies = machine_parts
+        .iter()
+        .map(|machine| called_machines(&machine.identities, machine_parts))
+        .collect::<Vec<_>>();
+    let mut groups: Vec<(BTreeSet<usize>, BTreeSet<usize>)> = vec![];
+    for (main, identities) in main_identities.iter().enumerate() {
+        let mut machines = called_machines(identities, machine_parts);
+        let mut to_visit = machines.iter().cloned().collect::<Vec<_>>();
+        while let Some(machine) = to_visit.pop() {
+            to_visit.extend(
+                dependencies[machine]
+                    .iter()
+                    .filter(|callee| machines.insert(**callee)),
+            );
+        }
+        let (connected, mut unconnected): (Vec<_>, Vec<_>) = groups
+            .into_iter()
+            .partition(|(_, group_machines)| !group_machines.is_disjoint(&machines));
+        let mut mains = BTreeSet::from([main]);
+        for (group_mains, group_machines) in connected {
+            mains.extend(group_mains);
+            machines.extend(group_machines);
+        }
+        unconnected.push((mains, machines));
+        groups = unconnected;
+    }
+    groups.sort_by_key(|(mains, _)| mains.first().cloned());
+    let uncalled = (0..machine_parts.len())
+        .filter(|machine| {
+            groups
+                .iter()
+                .all(|(_, machines)| !machines.contains(machine))
+        })
+        .collect::<Vec<_>>();
+    match groups.first_mut() {
+        Some((_, machines)) => machines.extend(uncalled),
+        None => groups.push((BTreeSet::new(), uncalled.into_iter().collect())),
+    }
+    groups
+}
+
 /// Extracts all references to names from an identity.
 pub fn refs_in_identity<T>(identity: &Identity<Expression<T>>) -> HashSet<PolyID> {
     let mut refs: HashSet<PolyID> = Default::default();
@@ -0,0 +1,196 @@
+use std::collections::{BTreeSet, HashSet};
+use std::fmt::Write;
+
+use powdr_ast::analyzed::{
+    AlgebraicExpression as Expression, Identity, IdentityKind, PolyID, PolynomialType,
+};
+use powdr_number::FieldElement;
+
+use super::machine_extractor::refs_in_selected_expressions;
+use super::MachineParts;
+use crate::witgen::FixedData;
+
+/// The name of the node that stands for all lookups into fixed columns only.
+pub const FIXED_LOOKUP_NODE: &str = "FixedLookup";
+
+/// The machines that are extracted from a PIL file and the identities connecting them,
+/// see [crate::witgen::WitnessGenerator::machine_graph].
+/// This only describes the structure, it is not used for solving.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct MachineGraph {
+    /// The machines, the main machine first. If there are lookups into fixed columns
+    /// only, they are represented by a machine named [FIXED_LOOKUP_NODE] at the end.
+    pub machines: Vec<MachineNode>,
+    /// The lookups, permutations and connections between the machines.
+    pub edges: Vec<MachineEdge>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct MachineNode {
+    pub name: String,
+    /// The witness columns of the machine, sorted by name.
+    pub witness_columns: Vec<String>,
+    /// The fixed columns referenced by the identities of the machine and by the
+    /// right-hand sides of the identities calling into it, sorted by name.
+    pub fixed_columns: Vec<String>,
+    /// The identities that are processed by the machine.
+    pub identities: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct MachineEdge {
+    /// The name of the calling machine, which owns the identity.
+    pub from: String,
+    /// The name of the machine that is called.
+    pub to: String,
+    pub identity: String,
+}
+
+impl MachineGraph {
+    pub(crate) fn new<'a, T: FieldElement>(
+        fixed: &'a FixedData<'a, T>,
+        main: MachineParts<'a, T>,
+        machines: &[MachineParts<'a, T>],
+    ) -> Self {
+        let all_machines = std::iter::once(&main).chain(machines).collect::<Vec<_>>();
+        let mut edges = vec![];
+        let mut fixed_lookup_columns = BTreeSet::new();
+        for caller in &all_machines {
+            for identity in &caller.identities {
+                if identity.kind == IdentityKind::Polynomial {
+                    continue;
+                }
+                let callee = machines.iter().find(|machine| {
+                    machine
+                        .connecting_identities
+                        .iter()
+                        .any(|i| std::ptr::eq(*i, *identity))
+                });
+                let to = match callee {
+                    Some(callee) => callee.name.clone(),
+                    None => {
+                        fixed_lookup_columns.extend(fixed_columns(
+                            fixed,
+                            refs_in_selected_expressions(&identity.right),
+                        ));
+                        FIXED_LOOKUP_NODE.to_string()
+                    }
+                };
+                edges.push(MachineEdge {
+                    from: caller.name.clone(),
+                    to,
+                    identity: identity.to_string(),
+                });
+            }
+        }
+
+        let mut nodes = all_machines
+            .iter()
+            .map(|machine| MachineNode::new(fixed, machine))
+            .collect::<Vec<_>>();
+        if edges.iter().any(|edge| edge.to == FIXED_LOOKUP_NODE) {
+            nodes.push(MachineNode {
+                name: FIXED_LOOKUP_NODE.to_string(),
+                witness_columns: vec![],
+                fixed_columns: fixed_lookup_columns.into_iter().collect(),
+                identities: vec![],
+            });
+        }
+        MachineGraph {
+            machines: nodes,
+            edges,
+        }
+    }
+
+    /// Renders the graph in the DOT format of Graphviz.
+    pub fn to_dot(&self) -> String {
+        let mut dot = "digraph machines {\n".to_string();
+        for machine in &self.machines {
+            let label = std::iter::once(&machine.name)
+                .chain(&machine.witness_columns)
+                .map(|s| escape(s))
+                .collect::<Vec<_>>()
+                .join("\\n");
+            writeln!(
+                dot,
+                "  \"{}\" [shape=box, label=\"{label}\"];",
+                escape(&machine.name)
+            )
+            .unwrap();
+        }
+        for edge in &self.edges {
+            writeln!(
+                dot,
+                "  \"{}\" -> \"{}\" [label=\"{}\"];",
+                escape(&edge.from),
+                escape(&edge.to),
+                escape(&edge.identity)
+            )
+            .unwrap();
+        }
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+impl MachineNode {
+    fn new<'a, T: FieldElement>(
+        fixed: &'a FixedData<'a, T>,
+        machine: &MachineParts<'a, T>,
+    ) -> Self {
+        let referenced = machine
+            .identities
+            .iter()
+            .flat_map(|identity| own_refs(identity))
+            .chain(
+                machine
+                    .connecting_identities
+                    .iter()
+                    .flat_map(|identity| refs_in_selected_expressions(&identity.right)),
+            )
+            .collect::<Vec<_>>();
+        MachineNode {
+            name: machine.name.clone(),
+            witness_columns: machine
+                .witnesses
+                .iter()
+                .map(|poly| fixed.column_name(poly).to_string())
+                .collect::<BTreeSet<_>>()
+                .into_iter()
+                .collect(),
+            fixed_columns: fixed_columns(fixed, referenced).into_iter().collect(),
+            identities: machine
+                .identities
+                .iter()
+                .map(|identity| identity.to_string())
+                .collect(),
+        }
+    }
+}
+
+/// The columns referenced by the identity on the side of the machine processing it,
+/// i.e. everything for polynomial identities and the left-hand side otherwise.
+fn own_refs<T>(identity: &Identity<Expression<T>>) -> HashSet<PolyID> {
+    let mut refs = refs_in_selected_expressions(&identity.left);
+    if identity.kind == IdentityKind::Polynomial {
+        refs.extend(refs_in_selected_expressions(&identity.right));
+    }
+    refs
+}
+
+fn fixed_columns<'a, T: FieldElement>(
+    fixed: &'a FixedData<'a, T>,
+    refs: impl IntoIterator<Item = PolyID>,
+) -> BTreeSet<String> {
+    refs.into_iter()
+        .filter(|poly| poly.ptype == PolynomialType::Constant)
+        .map(|poly| fixed.column_name(&poly).to_string())
+        .collect()
+}
+
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
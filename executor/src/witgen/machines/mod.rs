@@ -2,12 +2,21 @@ use std::collections::HashMap;
 
 use powdr_ast::analyzed::AlgebraicExpression as Expression;
 use powdr_ast::analyzed::AlgebraicReference;
+use powdr_ast::analyzed::Identity;
 use powdr_ast::parsed::SelectedExpressions;
 use powdr_number::FieldElement;
 
 use self::block_machine::BlockMachine;
+pub use self::custom_machine::{CustomMachine, CustomMachineFactory, MachineParts};
 use self::double_sorted_witness_machine::DoubleSortedWitnesses;
-pub use self::fixed_lookup_machine::FixedLookup;
+use self::dynamic_lookup_machine::DynamicLookupMachine;
+pub use self::extraction_report::{ExtractionReport, MachineReport};
+pub(crate) use self::fixed_lookup_machine::fixed_columns_hash;
+pub use self::fixed_lookup_machine::{
+    FixedLookup, FixedLookupIndex, MissingLookupCallback, DEFAULT_CACHE_CAPACITY,
+};
+pub use self::machine_graph::{MachineEdge, MachineGraph, MachineNode, FIXED_LOOKUP_NODE};
+use self::permutation_machine::PermutationMachine;
 use self::profiling::record_end;
 use self::profiling::record_start;
 use self::sorted_witness_machine::SortedWitnesses;
@@ -16,15 +25,25 @@ use powdr_ast::analyzed::IdentityKind;
 
 use super::affine_expression::AffineExpression;
 use super::generator::Generator;
+use super::util::try_to_simple_poly;
+use super::Constraint;
+use super::EvalError;
 use super::EvalResult;
 use super::FixedData;
 use super::MutableState;
 use super::QueryCallback;
 
 mod block_machine;
+mod custom_machine;
 mod double_sorted_witness_machine;
+mod dynamic_lookup_machine;
+mod extraction_report;
 mod fixed_lookup_machine;
+#[cfg(test)]
+mod harness;
 pub mod machine_extractor;
+mod machine_graph;
+mod permutation_machine;
 pub mod profiling;
 mod sorted_witness_machine;
 mod write_once_memory;
@@ -49,6 +68,22 @@ pub trait Machine<'a, T: FieldElement>: Send + Sync {
     /// Returns a unique name for this machine.
     fn name(&self) -> &str;
 
+    /// Returns the latch, i.e. the expression that selects the rows in which the
+    /// machine answers calls (the last row of each block), if the machine has one.
+    fn latch(&self) -> Option<&Expression<T>> {
+        None
+    }
+
+    /// Returns true if the machine is pure, i.e. the result of a lookup into it only
+    /// depends on the known values on the left-hand side and it does not have to record
+    /// each call (for example because it does not have queries or internal state that
+    /// changes between calls). The results of calls into pure machines can be reused for
+    /// later calls with the same inputs, see
+    /// [crate::witgen::WitnessGenerator::with_pure_machine_memoization].
+    fn is_pure(&self) -> bool {
+        false
+    }
+
     /// Process a plookup. Not all values on the LHS need to be available.
     /// Can update internal data.
     /// Only return an error if this machine is able to handle the query and
@@ -62,12 +97,78 @@ pub trait Machine<'a, T: FieldElement>: Send + Sync {
         right: &'a SelectedExpressions<Expression<T>>,
     ) -> Option<EvalResult<'a, T>>;
 
+    /// Processes a single call through the connecting `identity` without a calling
+    /// machine, e.g. to test the machine in isolation. `arguments` are the values of the
+    /// left-hand side of the identity, `None` for the values the machine should determine,
+    /// whose expressions have to be columns.
+    /// Returns the arguments completed by the values the machine determined, or `None`
+    /// if this is not the right machine for the call.
+    fn call<'b, Q: QueryCallback<T>>(
+        &mut self,
+        mutable_state: &'b mut MutableState<'a, 'b, T, Q>,
+        identity: &'a Identity<Expression<T>>,
+        arguments: &[Option<T>],
+    ) -> Option<Result<Vec<Option<T>>, EvalError<T>>> {
+        assert_eq!(arguments.len(), identity.left.expressions.len());
+        // The columns of the unknown arguments.
+        let variables = identity
+            .left
+            .expressions
+            .iter()
+            .zip(arguments)
+            .map(|(expr, argument)| {
+                argument.is_none().then(|| {
+                    try_to_simple_poly(expr).unwrap_or_else(|| {
+                        panic!("Unknown arguments have to be columns, but got {expr}.")
+                    })
+                })
+            })
+            .collect::<Vec<_>>();
+        let left = variables
+            .iter()
+            .zip(arguments)
+            .map(|(variable, argument)| match variable {
+                Some(variable) => AffineExpression::from_variable_id(*variable),
+                None => AffineExpression::from(argument.unwrap()),
+            })
+            .collect::<Vec<_>>();
+        let result = self.process_plookup(mutable_state, identity.kind, &left, &identity.right)?;
+        Some(result.map(|result| {
+            variables
+                .iter()
+                .zip(arguments)
+                .map(|(variable, argument)| match variable {
+                    Some(variable) => {
+                        result
+                            .constraints
+                            .iter()
+                            .find_map(|(poly, constraint)| match constraint {
+                                Constraint::Assignment(value) if poly == variable => Some(*value),
+                                _ => None,
+                            })
+                    }
+                    None => *argument,
+                })
+                .collect()
+        }))
+    }
+
     /// Returns the final values of the witness columns.
     fn take_witness_col_values<'b, Q: QueryCallback<T>>(
         &mut self,
         fixed_lookup: &'b mut FixedLookup<T>,
         query_callback: &'b mut Q,
     ) -> HashMap<String, Vec<T>>;
+
+    /// Like `take_witness_col_values`, but also returns the name of the machine.
+    fn export_witness<'b, Q: QueryCallback<T>>(
+        &mut self,
+        fixed_lookup: &'b mut FixedLookup<T>,
+        query_callback: &'b mut Q,
+    ) -> (String, HashMap<String, Vec<T>>) {
+        let columns = self.take_witness_col_values(fixed_lookup, query_callback);
+        (self.name().to_string(), columns)
+    }
 }
 
 /// All known implementations of [Machine].
@@ -78,7 +179,10 @@ pub enum KnownMachine<'a, T: FieldElement> {
     DoubleSortedWitnesses(DoubleSortedWitnesses<T>),
     WriteOnceMemory(WriteOnceMemory<'a, T>),
     BlockMachine(BlockMachine<'a, T>),
+    PermutationMachine(PermutationMachine<'a, T>),
+    DynamicLookup(DynamicLookupMachine<'a, T>),
     Vm(Generator<'a, T>),
+    Custom(Box<dyn CustomMachine<'a, T> + 'a>),
 }
 
 impl<'a, T: FieldElement> Machine<'a, T> for KnownMachine<'a, T> {
@@ -96,7 +200,12 @@ impl<'a, T: FieldElement> Machine<'a, T> for KnownMachine<'a, T> {
             }
             KnownMachine::WriteOnceMemory(m) => m.process_plookup(mutable_state, kind, left, right),
             KnownMachine::BlockMachine(m) => m.process_plookup(mutable_state, kind, left, right),
+            KnownMachine::PermutationMachine(m) => {
+                m.process_plookup(mutable_state, kind, left, right)
+            }
+            KnownMachine::DynamicLookup(m) => m.process_plookup(mutable_state, kind, left, right),
             KnownMachine::Vm(m) => m.process_plookup(mutable_state, kind, left, right),
+            KnownMachine::Custom(m) => m.process_plookup(kind, left, right),
         }
     }
 
@@ -106,7 +215,36 @@ impl<'a, T: FieldElement> Machine<'a, T> for KnownMachine<'a, T> {
             KnownMachine::DoubleSortedWitnesses(m) => m.name(),
             KnownMachine::WriteOnceMemory(m) => m.name(),
             KnownMachine::BlockMachine(m) => m.name(),
+            KnownMachine::PermutationMachine(m) => m.name(),
+            KnownMachine::DynamicLookup(m) => m.name(),
             KnownMachine::Vm(m) => m.name(),
+            KnownMachine::Custom(m) => m.name(),
+        }
+    }
+
+    fn latch(&self) -> Option<&Expression<T>> {
+        match self {
+            KnownMachine::SortedWitnesses(m) => m.latch(),
+            KnownMachine::DoubleSortedWitnesses(m) => m.latch(),
+            KnownMachine::WriteOnceMemory(m) => m.latch(),
+            KnownMachine::BlockMachine(m) => m.latch(),
+            KnownMachine::PermutationMachine(m) => m.latch(),
+            KnownMachine::DynamicLookup(m) => m.latch(),
+            KnownMachine::Vm(m) => m.latch(),
+            KnownMachine::Custom(_) => None,
+        }
+    }
+
+    fn is_pure(&self) -> bool {
+        match self {
+            KnownMachine::BlockMachine(m) => m.is_pure(),
+            KnownMachine::Custom(m) => m.is_pure(),
+            KnownMachine::SortedWitnesses(_)
+            | KnownMachine::DoubleSortedWitnesses(_)
+            | KnownMachine::WriteOnceMemory(_)
+            | KnownMachine::PermutationMachine(_)
+            | KnownMachine::DynamicLookup(_)
+            | KnownMachine::Vm(_) => false,
         }
     }
 
@@ -128,7 +266,14 @@ impl<'a, T: FieldElement> Machine<'a, T> for KnownMachine<'a, T> {
             KnownMachine::BlockMachine(m) => {
                 m.take_witness_col_values(fixed_lookup, query_callback)
             }
+            KnownMachine::PermutationMachine(m) => {
+                m.take_witness_col_values(fixed_lookup, query_callback)
+            }
+            KnownMachine::DynamicLookup(m) => {
+                m.take_witness_col_values(fixed_lookup, query_callback)
+            }
             KnownMachine::Vm(m) => m.take_witness_col_values(fixed_lookup, query_callback),
+            KnownMachine::Custom(m) => m.take_witness_col_values(),
         }
     }
 }
@@ -0,0 +1,239 @@
+use std::collections::HashMap;
+
+use powdr_ast::analyzed::{
+    AlgebraicExpression as Expression, AlgebraicReference, Identity, IdentityKind, PolyID,
+    PolynomialType,
+};
+use powdr_ast::parsed::SelectedExpressions;
+use powdr_number::{DegreeType, FieldElement};
+
+use crate::witgen::{
+    affine_expression::AffineExpression, util::try_to_simple_poly, EvalResult, EvalValue,
+    FixedData, IncompleteCause, MutableState, QueryCallback,
+};
+
+use super::{FixedLookup, Machine};
+
+/// A machine consisting of witness columns that are only constrained by a permutation
+/// argument, for example:
+/// ```pil
+/// col witness a, b;
+/// { a } is { b };
+/// ```
+/// The values of each call are stored in the next free row, so the columns contain the
+/// calls in the order they were made.
+/// The right-hand side can have a selector if it is a fixed column with values 0 and 1.
+/// In that case, the calls are stored in the rows where the selector is 1 and all other
+/// rows are set to zero.
+/// Calls are identified by the connecting identity and the row of the caller, so processing
+/// a row of the caller again does not record its call again.
+pub struct PermutationMachine<'a, T: FieldElement> {
+    name: String,
+    fixed_data: &'a FixedData<'a, T>,
+    /// The right-hand side of the connecting identity.
+    rhs: &'a SelectedExpressions<Expression<T>>,
+    /// The witness columns on the right-hand side, in order.
+    columns: Vec<PolyID>,
+    /// The rows in which the calls are stored.
+    selected_rows: Vec<DegreeType>,
+    /// The values of the calls so far.
+    data: Vec<Vec<T>>,
+    /// The index in `data` of the call made by each caller (identity and row).
+    calls: HashMap<(u64, DegreeType), usize>,
+}
+
+impl<'a, T: FieldElement> PermutationMachine<'a, T> {
+    pub fn try_new(
+        name: String,
+        fixed_data: &'a FixedData<'a, T>,
+        connecting_identities: &[&'a Identity<Expression<T>>],
+        identities: &[&Identity<Expression<T>>],
+    ) -> Option<Self> {
+        if !identities.is_empty() {
+            return None;
+        }
+
+        let [identity] = connecting_identities else {
+            return None;
+        };
+        if identity.kind != IdentityKind::Permutation {
+            return None;
+        }
+        let rhs = &identity.right;
+
+        let columns = rhs
+            .expressions
+            .iter()
+            .map(|e| {
+                try_to_simple_poly(e)
+                    .filter(|p| p.poly_id.ptype == PolynomialType::Committed)
+                    .map(|p| p.poly_id)
+            })
+            .collect::<Option<Vec<_>>>()?;
+
+        let selected_rows = match &rhs.selector {
+            None => (0..fixed_data.degree).collect(),
+            Some(selector) => {
+                let selector = try_to_simple_poly(selector)
+                    .filter(|p| p.poly_id.ptype == PolynomialType::Constant)?;
+                let column = &fixed_data.fixed_cols[&selector.poly_id];
+                if !column.values().all(|v| v.is_zero() || v.is_one()) {
+                    return None;
+                }
+                (0..fixed_data.degree)
+                    .filter(|row| column.value(*row).is_one())
+                    .collect()
+            }
+        };
+
+        Some(Self {
+            name,
+            fixed_data,
+            rhs,
+            columns,
+            selected_rows,
+            data: vec![],
+            calls: Default::default(),
+        })
+    }
+
+    fn process_plookup_internal(
+        &mut self,
+        caller: Option<(u64, DegreeType)>,
+        left: &[AffineExpression<&'a AlgebraicReference, T>],
+    ) -> EvalResult<'a, T> {
+        let Some(values) = left
+            .iter()
+            .map(|l| l.constant_value())
+            .collect::<Option<Vec<_>>>()
+        else {
+            return Ok(EvalValue::incomplete(
+                IncompleteCause::NonConstantRequiredArgument("permuted values"),
+            ));
+        };
+
+        if let Some(index) = caller.and_then(|caller| self.calls.get(&caller)) {
+            let recorded = &self.data[*index];
+            return if *recorded == values {
+                Ok(EvalValue::complete(vec![]))
+            } else {
+                Err(format!(
+                    "Permutation machine {} was called with {values:?} by a row that already called it with {recorded:?}.",
+                    self.name
+                )
+                .into())
+            };
+        }
+
+        if self.data.len() == self.selected_rows.len() {
+            return Err(format!(
+                "Permutation machine {} is full: All {} rows are already used.",
+                self.name,
+                self.selected_rows.len()
+            )
+            .into());
+        }
+
+        log::trace!("Permuted values: {:?}", values);
+        if let Some(caller) = caller {
+            self.calls.insert(caller, self.data.len());
+        }
+        self.data.push(values);
+        Ok(EvalValue::complete(vec![]))
+    }
+}
+
+impl<'a, T: FieldElement> Machine<'a, T> for PermutationMachine<'a, T> {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn process_plookup<'b, Q: QueryCallback<T>>(
+        &mut self,
+        mutable_state: &'b mut MutableState<'a, 'b, T, Q>,
+        kind: IdentityKind,
+        left: &[AffineExpression<&'a AlgebraicReference, T>],
+        right: &'a SelectedExpressions<Expression<T>>,
+    ) -> Option<EvalResult<'a, T>> {
+        (right == self.rhs && kind == IdentityKind::Permutation)
+            .then(|| self.process_plookup_internal(mutable_state.caller, left))
+    }
+
+    fn take_witness_col_values<'b, Q: QueryCallback<T>>(
+        &mut self,
+        _fixed_lookup: &'b mut FixedLookup<T>,
+        _query_callback: &'b mut Q,
+    ) -> HashMap<String, Vec<T>> {
+        assert_eq!(
+            self.data.len(),
+            self.selected_rows.len(),
+            "Permutation machine {} received {} calls, but the permutation requires exactly {}.",
+            self.name,
+            self.data.len(),
+            self.selected_rows.len()
+        );
+        let data = std::mem::take(&mut self.data);
+        self.calls.clear();
+        self.columns
+            .iter()
+            .enumerate()
+            .map(|(index, poly)| {
+                let mut column = vec![T::zero(); self.fixed_data.degree as usize];
+                for (row, values) in self.selected_rows.iter().zip(&data) {
+                    column[*row as usize] = values[index];
+                }
+                (self.fixed_data.column_name(poly).to_string(), column)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use powdr_number::GoldilocksField;
+    use powdr_pil_analyzer::analyze_string;
+
+    use crate::constant_evaluator;
+    use crate::witgen::global_constraints::GlobalConstraints;
+    use crate::witgen::{unused_query_callback, MutableStateBuilder};
+
+    use super::*;
+
+    #[test]
+    fn repeated_call_of_same_row() {
+        let src = r"
+namespace Main(4);
+    col witness a;
+    { a } is { P.x };
+namespace P(4);
+    col witness x;
+";
+        let analyzed = analyze_string::<GoldilocksField>(src);
+        let constants = constant_evaluator::generate(&analyzed);
+        let fixed_data = FixedData::new(&analyzed, analyzed.degree(), &constants, None);
+        let identity = &analyzed.identities[0];
+        let mut machine =
+            PermutationMachine::try_new("P".to_string(), &fixed_data, &[identity], &[]).unwrap();
+        let mut state_builder = MutableStateBuilder::new(&fixed_data, unused_query_callback());
+        let mut call = |machine: &mut PermutationMachine<_>, row: DegreeType, value: u64| {
+            let mut state = state_builder.state();
+            state.caller = Some((identity.id, row));
+            let left = [AffineExpression::from(GoldilocksField::from(value))];
+            machine
+                .process_plookup(&mut state, identity.kind, &left, &identity.right)
+                .unwrap()
+        };
+        for (row, value) in [(0, 5), (1, 6), (1, 6), (2, 7), (0, 5), (3, 8)] {
+            assert!(call(&mut machine, row, value).unwrap().is_complete());
+        }
+        assert!(call(&mut machine, 2, 9).is_err());
+
+        let mut fixed_lookup = FixedLookup::new(GlobalConstraints::unconstrained(&fixed_data));
+        let columns =
+            machine.take_witness_col_values(&mut fixed_lookup, &mut unused_query_callback());
+        assert_eq!(
+            columns["P.x"],
+            [5, 6, 7, 8].map(GoldilocksField::from).to_vec()
+        );
+    }
+}
@@ -42,6 +42,15 @@ pub fn record_end(name: &str) {
     EVENT_LOG.with(|s| s.borrow_mut().push((Event::End, id, Instant::now())));
 }
 
+/// Runs `f`, discarding all events it records in the current thread.
+/// This is used for work done on worker threads, whose event logs are never reported.
+pub fn without_profiling<R>(f: impl FnOnce() -> R) -> R {
+    let len = EVENT_LOG.with(|s| s.borrow().len());
+    let result = f();
+    EVENT_LOG.with(|s| s.borrow_mut().truncate(len));
+    result
+}
+
 pub fn reset_and_print_profile_summary() {
     EVENT_LOG.with(|event_log| {
         let id_to_name = NAME_TO_ID.with(|name_to_id| {
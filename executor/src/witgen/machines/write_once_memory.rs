@@ -96,7 +96,7 @@ impl<'a, T: FieldElement> WriteOnceMemory<'a, T> {
         for row in 0..fixed_data.degree {
             let key = key_polys
                 .iter()
-                .map(|k| fixed_data.fixed_cols[k].values[row as usize])
+                .map(|k| fixed_data.fixed_cols[k].value(row))
                 .collect::<Vec<_>>();
             if key_to_index.insert(key, row).is_some() {
                 // Duplicate keys, can't be a write-once memory
@@ -224,21 +224,18 @@ impl<'a, T: FieldElement> Machine<'a, T> for WriteOnceMemory<'a, T> {
             .iter()
             .enumerate()
             .map(|(value_index, poly)| {
-                let column = self.fixed_data.witness_cols[poly]
-                    .external_values
-                    .clone()
-                    .map(|mut external_values| {
-                        // External witness values might only be provided partially.
-                        external_values.resize(self.fixed_data.degree as usize, T::zero());
-                        external_values
-                    })
-                    .unwrap_or_else(|| {
-                        let mut column = vec![T::zero(); self.fixed_data.degree as usize];
-                        for (row, values) in self.data.iter() {
-                            column[*row as usize] = values[value_index].unwrap_or_default();
-                        }
-                        column
-                    });
+                let mut column = vec![T::zero(); self.fixed_data.degree as usize];
+                for (row, values) in self.data.iter() {
+                    column[*row as usize] = values[value_index].unwrap_or_default();
+                }
+                // Externally provided values take precedence over the stored ones.
+                for (row, value) in column.iter_mut().enumerate() {
+                    if let Some(external) =
+                        self.fixed_data.external_witness(row as DegreeType, poly)
+                    {
+                        *value = external;
+                    }
+                }
                 (self.fixed_data.column_name(poly).to_string(), column)
             })
             .collect()
@@ -1,42 +1,109 @@
-use std::collections::{BTreeMap, HashMap};
-use std::sync::Arc;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::sync::atomic::AtomicBool;
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
+use num_bigint::BigUint;
 use powdr_ast::analyzed::{
-    AlgebraicReference, Analyzed, Expression, FunctionValueDefinition, PolyID, PolynomialType,
-    SymbolKind,
+    AlgebraicExpression, AlgebraicReference, Analyzed, Expression, FunctionValueDefinition,
+    Identity, PolyID, PolynomialType, SymbolKind,
 };
-use powdr_number::{DegreeType, FieldElement};
+use powdr_number::{BigInt, DegreeType, FieldElement};
 
-use self::data_structures::column_map::{FixedColumnMap, WitnessColumnMap};
+pub use self::affine_expression::{solve_affine, AffineExpression};
+use self::call_cache::CallCache;
+pub use self::checkpoint::Checkpoint;
+use self::checkpoint::CheckpointWriter;
+pub use self::column_storage::{ColumnStorage, SpillConfig, SpilledColumn};
+pub use self::continuation::ContinuationContext;
+use self::data_structures::column_map::FixedColumnMap;
+pub use self::data_structures::column_map::WitnessColumnMap;
+pub use self::diagnosis::Diagnosis;
 pub use self::eval_result::{
     Constraint, Constraints, EvalError, EvalResult, EvalStatus, EvalValue, IncompleteCause,
+    PendingUnknown,
 };
-use self::generator::Generator;
-
+pub use self::export::write_witness_csv;
+pub use self::free_fill::FreeFill;
+pub use self::global_constraints::ColumnRole;
 use self::identity_processor::Machines;
-use self::machines::machine_extractor::ExtractionOutput;
-use self::machines::profiling::{record_end, record_start, reset_and_print_profile_summary};
-use self::machines::{FixedLookup, Machine};
+use self::machines::profiling::record_start;
+use self::machines::FixedLookup;
+pub use self::machines::{
+    CustomMachine, CustomMachineFactory, ExtractionReport, FixedLookupIndex, MachineEdge,
+    MachineGraph, MachineNode, MachineParts, MachineReport, MissingLookupCallback,
+    DEFAULT_CACHE_CAPACITY, FIXED_LOOKUP_NODE,
+};
+pub use self::mutable_state::MutableStateBuilder;
+pub use self::ordering::OrderingStrategy;
+pub use self::prepared::PreparedGenerator;
+use self::progress::ProgressReporter;
+pub use self::progress::{ProgressCallback, ProgressEvent};
+use self::provenance::ProvenanceLog;
+pub use self::query_processor::Query;
+pub use self::query_router::QueryRouter;
+#[cfg(feature = "serde")]
+pub use self::range_constraint_export::{
+    import_range_constraints_json, ColumnKind, ExportedRangeConstraint,
+    RANGE_CONSTRAINTS_SCHEMA_VERSION,
+};
+pub use self::range_constraints::RangeConstraint;
+#[cfg(feature = "rational-debug")]
+pub use self::rational_solver::RationalSolution;
+pub use self::sequence_iterator::SequenceStrategy;
+pub use self::statistics::GenerationStats;
+use self::symbolic_evaluator::SymbolicConstraint;
+pub use self::verification::{verify_witness, IdentityViolation};
+use self::vm_processor::{Limits, DEFAULT_WINDOW_SIZE};
+pub use self::vm_processor::{PaddingStrategy, SolvingDirection};
+use self::witness_rows::RowChannel;
+pub use self::witness_rows::{WitnessRow, WitnessRows};
+#[cfg(feature = "serde")]
+pub use self::witness_serialization::{deserialize_witness, serialize_witness};
 
 mod affine_expression;
 mod block_processor;
+mod call_cache;
+mod checkpoint;
+mod column_storage;
+mod continuation;
 mod data_structures;
+mod diagnosis;
 mod eval_result;
+mod export;
 mod expression_evaluator;
 pub mod fixed_evaluator;
+mod free_fill;
 mod generator;
 mod global_constraints;
 mod identity_processor;
 mod machines;
+mod mutable_state;
+mod ordering;
+mod prepared;
 mod processor;
+mod progress;
+mod provenance;
 mod query_processor;
+mod query_router;
+#[cfg(feature = "serde")]
+mod range_constraint_export;
 mod range_constraints;
+#[cfg(feature = "rational-debug")]
+mod rational_solver;
 mod rows;
 mod sequence_iterator;
+mod statistics;
 pub mod symbolic_evaluator;
 mod symbolic_witness_evaluator;
 mod util;
+mod verification;
 mod vm_processor;
+mod witness_rows;
+#[cfg(feature = "serde")]
+mod witness_serialization;
 
 static OUTER_CODE_NAME: &str = "witgen (outer code)";
 
@@ -50,23 +117,173 @@ pub fn chain_callbacks<T: FieldElement>(
     move |query| c1(query).or_else(|_| c2(query))
 }
 
+/// Like [QueryCallback], but receives the query in parsed form, see
+/// [WitnessGenerator::with_parsed_query_callback].
+pub trait ParsedQueryCallback<T>: Fn(&Query<T>) -> Result<Option<T>, String> + Send + Sync {}
+impl<T, F> ParsedQueryCallback<T> for F where
+    F: Fn(&Query<T>) -> Result<Option<T>, String> + Send + Sync
+{
+}
+
+/// Answers the queries of several columns of a row at once, see
+/// [WitnessGenerator::with_batch_query_callback].
+/// Receives the row and the query of one column (as a string) and returns the values of
+/// all columns of the row it can compute, by column name.
+pub trait BatchQueryCallback<T>:
+    Fn(DegreeType, &str) -> Result<HashMap<String, T>, String> + Send + Sync
+{
+}
+impl<T, F> BatchQueryCallback<T> for F where
+    F: Fn(DegreeType, &str) -> Result<HashMap<String, T>, String> + Send + Sync
+{
+}
+
+/// Adapts a callback that can only answer or decline a query (but never fail)
+/// to a [QueryCallback].
+pub fn option_query_callback<T, F>(callback: F) -> impl QueryCallback<T>
+where
+    F: Fn(&str) -> Option<T> + Send + Sync,
+{
+    move |query: &str| -> Result<Option<T>, String> { Ok(callback(query)) }
+}
+
+/// Provides externally computed witness values on demand.
+/// Receives the name of a witness column and a row and returns the value of the cell,
+/// if it is provided externally.
+pub trait ExternalWitnessProvider<T>: Fn(&str, DegreeType) -> Option<T> + Send + Sync {}
+impl<T, F> ExternalWitnessProvider<T> for F where F: Fn(&str, DegreeType) -> Option<T> + Send + Sync {}
+
+/// Computes the value of a fixed column in a given row, see
+/// [WitnessGenerator::with_fixed_column_function].
+pub trait FixedColumnFunction<T>: Fn(DegreeType) -> T + Send + Sync {}
+impl<T, F> FixedColumnFunction<T> for F where F: Fn(DegreeType) -> T + Send + Sync {}
+
+/// Computes the value of a witness column in a padding row, see [PaddingStrategy::Custom].
+/// Receives the name of the column, the row and the value of the column in the last row
+/// of the program.
+pub trait PaddingFunction<T>: Fn(&str, DegreeType, T) -> T + Send + Sync {}
+impl<T, F> PaddingFunction<T> for F where F: Fn(&str, DegreeType, T) -> T + Send + Sync {}
+
+/// Receives the values of each row of the main machine once the row is complete.
+/// The values are indexed by witness column; cells that are unknown or not part of
+/// the main machine are `None`.
+pub trait RowObserver<T>: FnMut(DegreeType, &WitnessColumnMap<Option<T>>) + Send {}
+impl<T, F> RowObserver<T> for F where F: FnMut(DegreeType, &WitnessColumnMap<Option<T>>) + Send {}
+
+/// Receives the name and the witness columns (by name) of a secondary machine once it is
+/// finalized, see [WitnessGenerator::with_machine_witness_callback].
+pub trait MachineWitnessCallback<T>: FnMut(&str, HashMap<String, Vec<T>>) + Send {}
+impl<T, F> MachineWitnessCallback<T> for F where F: FnMut(&str, HashMap<String, Vec<T>>) + Send {}
+
 /// @returns a query callback that is never expected to be used.
 pub fn unused_query_callback<T>() -> impl QueryCallback<T> {
     |_| -> _ { unreachable!() }
 }
 
-/// Everything [Generator] needs to mutate in order to compute a new row.
+/// Everything [generator::Generator] needs to mutate in order to compute a new row.
 pub struct MutableState<'a, 'b, T: FieldElement, Q: QueryCallback<T>> {
     pub fixed_lookup: &'b mut FixedLookup<T>,
     pub machines: Machines<'a, 'b, T>,
     pub query_callback: &'b mut Q,
+    pub call_cache: &'b mut CallCache<T>,
+    /// The id of the connecting identity and the row of the calling machine, if the
+    /// current call was made by another machine.
+    pub caller: Option<(u64, DegreeType)>,
+}
+
+/// The witness columns together with their degree.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WitnessResult<T> {
+    /// The private witness columns, in the order given by the [OrderingStrategy].
+    pub columns: Vec<(String, Vec<T>)>,
+    /// The witness columns that are referenced by public declarations like
+    /// `public out = x(7);`, whose values are seen by the verifier, in the same order.
+    /// They are not part of `columns`.
+    pub publics: Vec<(String, Vec<T>)>,
+    /// The values of the public declarations, by name, in source order.
+    pub public_values: Vec<(String, T)>,
+    /// The number of rows of each column.
+    pub degree: DegreeType,
+    /// The number of rows used by the program of the main machine before it was padded,
+    /// if the degree was determined via [WitnessGenerator::with_max_degree].
+    /// Otherwise, this is the degree.
+    pub used_rows: DegreeType,
+    /// The number of bits needed to represent the values of each column (in the same
+    /// order as the columns), derived from the global range constraints.
+    /// `None` if the values of a column are not known to be bounded.
+    pub bit_widths: Vec<Option<u32>>,
+    /// Whether the values of each column (in the same order as the columns) are signed,
+    /// i.e. small negative values are encoded as field elements close to the modulus.
+    /// The bit widths of signed columns include the sign bit.
+    pub signed: Vec<bool>,
 }
 
 pub struct WitnessGenerator<'a, 'b, T: FieldElement> {
     analyzed: &'a Analyzed<T>,
     fixed_col_values: &'b [(String, Vec<T>)],
     query_callback: &'b dyn QueryCallback<T>,
-    external_witness_values: Vec<(String, Vec<T>)>,
+    parsed_query_callback: Option<&'b dyn ParsedQueryCallback<T>>,
+    batch_query_callback: Option<&'b dyn BatchQueryCallback<T>>,
+    external_witness: Option<Box<dyn ExternalWitnessProvider<T> + 'b>>,
+    progress: Option<ProgressReporter<'b>>,
+    /// The degree, if it is not taken from the PIL file.
+    degree: Option<DegreeType>,
+    /// If set, the degree is determined by the number of rows the program needs,
+    /// up to this number of rows.
+    max_degree: Option<DegreeType>,
+    checkpoint: Option<Checkpoint<T>>,
+    checkpoints: Option<CheckpointWriter<'b, T>>,
+    row_observer: Option<&'b mut dyn RowObserver<T>>,
+    cell_sender: Option<Sender<(PolyID, DegreeType, T)>>,
+    machine_witness_callback: Option<&'b mut dyn MachineWitnessCallback<T>>,
+    fixed_lookup_cache_capacity: usize,
+    missing_lookup_callback: Option<Arc<dyn MissingLookupCallback<T>>>,
+    /// The lookup tables registered at runtime, by name.
+    lookup_tables: Vec<(String, Vec<(String, Arc<[T]>)>)>,
+    ordering: OrderingStrategy,
+    sequence_strategy: Option<&'b dyn SequenceStrategy<T>>,
+    identity_priorities: HashMap<u64, i32>,
+    identity_assignments: HashMap<u64, String>,
+    custom_machines: Vec<(String, &'b dyn CustomMachineFactory<T>)>,
+    timeout: Option<Duration>,
+    max_rows_without_progress: Option<DegreeType>,
+    cancellation: Option<&'b AtomicBool>,
+    direction: SolvingDirection,
+    /// The number of rows of the main machine whose identities are processed together.
+    window_size: usize,
+    padding: Option<PaddingStrategy<'b, T>>,
+    free_fill: Option<FreeFill>,
+    /// If set, the results of calls into pure machines are reused.
+    memoize_pure_machines: bool,
+    /// If set, independent groups of columns of the main machine are solved separately.
+    independent_main_machines: bool,
+    statistics: Option<&'b mut GenerationStats>,
+    fixed_cells: HashMap<(PolyID, DegreeType), T>,
+    initial_row: Vec<(PolyID, T)>,
+    /// The chunk that is generated, if the execution is split into chunks.
+    continuation: Option<ContinuationContext<T>>,
+    fixed_column_functions: Vec<(String, &'b dyn FixedColumnFunction<T>)>,
+    /// The values of fixed columns in the rows after their given values, by name.
+    fixed_column_defaults: Vec<(String, T)>,
+    /// If set, external witness values for unknown columns are ignored.
+    ignore_unknown_external_columns: bool,
+    /// The columns of the external witness values that were ignored.
+    ignored_external_columns: Vec<String>,
+    /// If set, only these rows of the main machine are solved.
+    window: Option<(DegreeType, DegreeType)>,
+    /// If set, only these columns are kept after solving.
+    selected_columns: Option<HashSet<String>>,
+    /// The columns that are interleaved into a single column after solving, by the
+    /// name of the resulting column.
+    interleavings: Vec<(String, Vec<String>)>,
+    /// If set, the derivations of all cells are recorded.
+    provenance: Option<Arc<ProvenanceLog<T>>>,
+    spill: Option<SpillConfig>,
+    /// If set, these identities are used instead of the inlined identities of the PIL file.
+    identities: Option<Vec<Identity<AlgebraicExpression<T>>>>,
+    /// Receives the rows of the main machine as they are finalized, if possible, see
+    /// [WitnessGenerator::generate_rows].
+    row_channel: Option<RowChannel<T>>,
 }
 
 impl<'a, 'b, T: FieldElement> WitnessGenerator<'a, 'b, T> {
@@ -79,268 +296,4078 @@ impl<'a, 'b, T: FieldElement> WitnessGenerator<'a, 'b, T> {
             analyzed,
             fixed_col_values,
             query_callback,
-            external_witness_values: Vec::new(),
+            parsed_query_callback: None,
+            batch_query_callback: None,
+            external_witness: None,
+            progress: None,
+            degree: None,
+            max_degree: None,
+            checkpoint: None,
+            checkpoints: None,
+            row_observer: None,
+            cell_sender: None,
+            machine_witness_callback: None,
+            fixed_lookup_cache_capacity: DEFAULT_CACHE_CAPACITY,
+            missing_lookup_callback: None,
+            lookup_tables: vec![],
+            ordering: OrderingStrategy::default(),
+            sequence_strategy: None,
+            identity_priorities: Default::default(),
+            identity_assignments: Default::default(),
+            custom_machines: vec![],
+            timeout: None,
+            max_rows_without_progress: None,
+            cancellation: None,
+            direction: SolvingDirection::Forward,
+            window_size: DEFAULT_WINDOW_SIZE,
+            padding: None,
+            free_fill: None,
+            memoize_pure_machines: false,
+            independent_main_machines: false,
+            statistics: None,
+            fixed_cells: Default::default(),
+            initial_row: vec![],
+            continuation: None,
+            fixed_column_functions: vec![],
+            fixed_column_defaults: vec![],
+            ignore_unknown_external_columns: false,
+            ignored_external_columns: vec![],
+            window: None,
+            selected_columns: None,
+            interleavings: vec![],
+            provenance: None,
+            spill: None,
+            identities: None,
+            row_channel: None,
+        }
+    }
+
+    /// Like [WitnessGenerator::new], but the degree is taken from the length of the
+    /// fixed columns instead of the degree declared in the PIL file.
+    /// Panics if the non-empty fixed columns disagree on their length.
+    pub fn with_inferred_degree(
+        analyzed: &'a Analyzed<T>,
+        fixed_col_values: &'b [(String, Vec<T>)],
+        query_callback: &'b dyn QueryCallback<T>,
+    ) -> Self {
+        WitnessGenerator {
+            degree: Some(infer_degree(fixed_col_values)),
+            ..Self::new(analyzed, fixed_col_values, query_callback)
         }
     }
 
+    fn degree(&self) -> DegreeType {
+        self.degree.unwrap_or_else(|| self.analyzed.degree())
+    }
+
+    /// The names of all witness columns, in source order.
+    fn witness_column_names(&self) -> Vec<String> {
+        witness_column_names(self.analyzed)
+    }
+
+    /// Sets values of witness columns that are provided externally.
+    /// Columns can be provided partially, i.e. with fewer values than rows (with a warning),
+    /// the remaining values are determined by the solver.
+    /// Panics if the values are invalid, see [WitnessGenerator::try_with_external_witness_values].
     pub fn with_external_witness_values(
         self,
         external_witness_values: Vec<(String, Vec<T>)>,
     ) -> Self {
-        WitnessGenerator {
-            external_witness_values,
-            ..self
-        }
+        self.try_with_external_witness_values(external_witness_values)
+            .unwrap_or_else(|e| panic!("{e}"))
     }
 
-    /// Generates the committed polynomial values
-    /// @returns the values (in source order) and the degree of the polynomials.
-    pub fn generate(self) -> Vec<(String, Vec<T>)> {
-        record_start(OUTER_CODE_NAME);
-        let fixed = FixedData::new(
-            self.analyzed,
-            self.fixed_col_values,
-            self.external_witness_values,
-        );
-        let identities = self
-            .analyzed
-            .identities_with_inlined_intermediate_polynomials();
+    /// Like [WitnessGenerator::with_external_witness_values], but returns an error if
+    /// values are given for columns that do not exist (unless they are ignored, see
+    /// [WitnessGenerator::with_unknown_external_columns_ignored]) or if a column has
+    /// more values than rows.
+    pub fn try_with_external_witness_values(
+        mut self,
+        external_witness_values: Vec<(String, Vec<T>)>,
+    ) -> Result<Self, ExternalWitnessError> {
+        let external_witness_values = self.remove_ignored_columns(external_witness_values);
+        let provider = external_witness_provider(
+            external_witness_values,
+            &self.witness_column_names(),
+            self.degree(),
+        )?;
+        Ok(self.with_external_witness_provider(provider))
+    }
 
-        let (
-            constraints,
-            // Removes identities like X * (X - 1) = 0 or { A } in { BYTES }
-            // These are already captured in the range constraints.
-            retained_identities,
-        ) = global_constraints::determine_global_constraints(&fixed, identities.iter().collect());
-        let ExtractionOutput {
-            mut fixed_lookup,
-            mut machines,
-            base_identities,
-            base_witnesses,
-        } = machines::machine_extractor::split_out_machines(
-            &fixed,
-            retained_identities,
-            &constraints,
-        );
-        let mut query_callback = self.query_callback;
-        let mut mutable_state = MutableState {
-            fixed_lookup: &mut fixed_lookup,
-            machines: Machines::from(machines.iter_mut()),
-            query_callback: &mut query_callback,
-        };
-        let mut generator = Generator::new(
-            "Main Machine".to_string(),
-            &fixed,
-            &base_identities,
-            base_witnesses,
-            &constraints,
-            // We could set the latch of the main VM here, but then we would have to detect it.
-            // Instead, the main VM will be computed in one block, directly continuing into the
-            // infinite loop after the first return.
-            None,
-        );
-
-        generator.run(&mut mutable_state);
-
-        // Get columns from machines
-        let main_columns = generator
-            .take_witness_col_values(mutable_state.fixed_lookup, mutable_state.query_callback);
-        let mut columns = mutable_state
-            .machines
-            .iter_mut()
-            .flat_map(|m| {
-                m.take_witness_col_values(mutable_state.fixed_lookup, mutable_state.query_callback)
-                    .into_iter()
+    /// Like [WitnessGenerator::try_with_external_witness_values], but the values are
+    /// given as integers, e.g. when they were computed with a different representation
+    /// of the field. Returns an error listing all values that are not canonical elements
+    /// of the field instead of reducing them.
+    pub fn try_with_external_witness_integers(
+        self,
+        external_witness_values: Vec<(String, Vec<BigUint>)>,
+    ) -> Result<Self, ExternalWitnessError> {
+        let modulus = T::modulus().to_arbitrary_integer();
+        let cells = external_witness_values
+            .iter()
+            .flat_map(|(column, values)| {
+                values
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, value)| **value >= modulus)
+                    .map(move |(row, value)| (column.clone(), row as DegreeType, value.clone()))
             })
-            .chain(main_columns)
-            .collect::<BTreeMap<_, _>>();
+            .collect::<Vec<_>>();
+        if !cells.is_empty() {
+            return Err(ExternalWitnessError::ValuesOutOfRange { cells, modulus });
+        }
+        self.try_with_external_witness_values(
+            external_witness_values
+                .into_iter()
+                .map(|(column, values)| (column, values.into_iter().map(T::from).collect()))
+                .collect(),
+        )
+    }
 
-        record_end(OUTER_CODE_NAME);
-        reset_and_print_profile_summary();
+    /// Like [WitnessGenerator::with_external_witness_values], but only the values of
+    /// some rows are given for each column, e.g. the first and last row of a segment.
+    /// The values of all other rows are determined by the solver.
+    /// Panics if the values are invalid, see
+    /// [WitnessGenerator::try_with_sparse_external_witness_values].
+    pub fn with_sparse_external_witness_values(
+        self,
+        external_witness_values: Vec<(String, BTreeMap<DegreeType, T>)>,
+    ) -> Self {
+        self.try_with_sparse_external_witness_values(external_witness_values)
+            .unwrap_or_else(|e| panic!("{e}"))
+    }
 
-        // Order columns according to the order of declaration.
-        let witness_cols = self
-            .analyzed
-            .committed_polys_in_source_order()
+    /// Like [WitnessGenerator::with_sparse_external_witness_values], but returns an error
+    /// if values are given for columns that do not exist (unless they are ignored, see
+    /// [WitnessGenerator::with_unknown_external_columns_ignored]) or for rows that are
+    /// out of range.
+    pub fn try_with_sparse_external_witness_values(
+        mut self,
+        external_witness_values: Vec<(String, BTreeMap<DegreeType, T>)>,
+    ) -> Result<Self, ExternalWitnessError> {
+        let external_witness_values = self.remove_ignored_columns(external_witness_values);
+        let provider = sparse_external_witness_provider(
+            external_witness_values,
+            &self.witness_column_names(),
+            self.degree(),
+        )?;
+        Ok(self.with_external_witness_provider(provider))
+    }
+
+    /// Removes the external witness values of columns that do not exist, if they
+    /// are ignored.
+    fn remove_ignored_columns<V>(&mut self, values: Vec<(String, V)>) -> Vec<(String, V)> {
+        if !self.ignore_unknown_external_columns {
+            return values;
+        }
+        let available_columns = self.witness_column_names();
+        let (known, unknown): (Vec<_>, Vec<_>) = values
             .into_iter()
-            .flat_map(|(p, _)| p.array_elements())
-            .map(|(name, _id)| {
-                let column = columns.remove(&name).unwrap();
-                assert!(!column.is_empty());
-                (name, column)
-            })
-            .collect::<Vec<_>>();
+            .partition(|(name, _)| available_columns.contains(name));
+        for (name, _) in unknown {
+            log::warn!("Ignoring external witness values for unknown column {name}.");
+            self.ignored_external_columns.push(name);
+        }
+        known
+    }
 
-        log::debug!("Publics:");
-        for (name, value) in extract_publics(&witness_cols, self.analyzed) {
-            log::debug!("  {name:>30}: {value}");
+    /// Ignores external witness values that are given for columns that do not exist
+    /// (with a warning) instead of failing. This has to be set before the external
+    /// witness values. The names of the ignored columns are returned by
+    /// [WitnessGenerator::ignored_external_columns].
+    pub fn with_unknown_external_columns_ignored(self) -> Self {
+        WitnessGenerator {
+            ignore_unknown_external_columns: true,
+            ..self
         }
-        witness_cols
     }
-}
 
-pub fn extract_publics<T: FieldElement>(
-    witness: &[(String, Vec<T>)],
-    pil: &Analyzed<T>,
-) -> Vec<(String, T)> {
-    let witness = witness
-        .iter()
-        .map(|(name, col)| (name.clone(), col))
-        .collect::<BTreeMap<_, _>>();
-    pil.public_declarations_in_source_order()
-        .iter()
-        .map(|(name, public_declaration)| {
-            let poly_name = &public_declaration.referenced_poly_name();
-            let poly_index = public_declaration.index;
-            let value = witness[poly_name][poly_index as usize];
-            ((*name).clone(), value)
-        })
-        .collect()
-}
+    /// The columns of the external witness values that were ignored because they
+    /// do not exist, see [WitnessGenerator::with_unknown_external_columns_ignored].
+    pub fn ignored_external_columns(&self) -> &[String] {
+        &self.ignored_external_columns
+    }
 
-/// Data that is fixed for witness generation.
-pub struct FixedData<'a, T> {
-    analyzed: &'a Analyzed<T>,
-    degree: DegreeType,
-    fixed_cols: FixedColumnMap<FixedColumn<'a, T>>,
-    witness_cols: WitnessColumnMap<WitnessColumn<'a, T>>,
-    column_by_name: HashMap<String, PolyID>,
-}
+    /// Like [WitnessGenerator::with_external_witness_values], but the values are requested
+    /// from `provider` whenever they are needed, instead of being materialized up front.
+    pub fn with_external_witness_provider(
+        self,
+        provider: impl ExternalWitnessProvider<T> + 'b,
+    ) -> Self {
+        WitnessGenerator {
+            external_witness: Some(Box::new(provider)),
+            ..self
+        }
+    }
 
-impl<'a, T: FieldElement> FixedData<'a, T> {
-    pub fn new(
-        analyzed: &'a Analyzed<T>,
-        fixed_col_values: &'a [(String, Vec<T>)],
-        external_witness_values: Vec<(String, Vec<T>)>,
+    /// Sets the values of single witness cells, given as column, row and value, before
+    /// solving. The solver treats them like external witness values (which they take
+    /// precedence over), so if an identity implies a different value for such a cell,
+    /// witness generation fails with [EvalError::ConflictingConstraints].
+    /// Panics if a column is not a witness column, a row is out of range or a cell is
+    /// given twice.
+    pub fn with_fixed_cells(mut self, cells: Vec<(PolyID, DegreeType, T)>) -> Self {
+        let degree = self.degree();
+        for (poly_id, row, value) in cells {
+            assert!(
+                poly_id.ptype == PolynomialType::Committed
+                    && poly_id.id < self.analyzed.commitment_count() as u64,
+                "Cannot fix the value of {poly_id:?}: It is not a witness column."
+            );
+            assert!(
+                row < degree,
+                "Cannot fix the value of row {row}: The degree is only {degree}."
+            );
+            let previous = self.fixed_cells.insert((poly_id, row), value);
+            assert!(
+                previous.is_none(),
+                "The value of {poly_id:?} in row {row} is fixed more than once."
+            );
+        }
+        self
+    }
+
+    /// Sets the values of cells of the first row of the main machine, e.g. to pin the
+    /// initial registers of a VM. They are used as the starting point of the solver, which
+    /// propagates them like any other known value.
+    /// Not supported when resuming from a checkpoint or solving a window of rows.
+    /// Panics if a column is not a witness column or is given twice. Witness generation
+    /// panics if a column does not belong to the main machine.
+    pub fn with_initial_row(mut self, values: Vec<(PolyID, T)>) -> Self {
+        for (poly_id, value) in values {
+            assert!(
+                poly_id.ptype == PolynomialType::Committed
+                    && poly_id.id < self.analyzed.commitment_count() as u64,
+                "Cannot set the initial value of {poly_id:?}: It is not a witness column."
+            );
+            assert!(
+                self.initial_row.iter().all(|(id, _)| *id != poly_id),
+                "The initial value of {poly_id:?} is set more than once."
+            );
+            self.initial_row.push((poly_id, value));
+        }
+        self
+    }
+
+    /// Sets an observer that receives the values of each row of the main machine
+    /// as soon as the row is complete, e.g. to trace the registers of a VM.
+    pub fn with_row_observer(self, row_observer: &'b mut dyn RowObserver<T>) -> Self {
+        WitnessGenerator {
+            row_observer: Some(row_observer),
+            ..self
+        }
+    }
+
+    /// Passes the witness columns of each secondary machine to `callback` as soon as the
+    /// machine is finalized and before the next one is, e.g. to write them to disk or
+    /// forward them to a prover, so that the columns of at most one secondary machine are
+    /// held in memory at a time.
+    /// Secondary machines are only called while the main machine is running, so once it
+    /// completes, no other machine refers to their columns anymore and each of them can
+    /// be released right after it is finalized. The machines are then finalized one
+    /// after the other instead of in parallel.
+    /// The columns passed to the callback are not part of the returned witness.
+    pub fn with_machine_witness_callback(
+        self,
+        callback: &'b mut dyn MachineWitnessCallback<T>,
     ) -> Self {
-        let mut external_witness_values = BTreeMap::from_iter(external_witness_values);
+        WitnessGenerator {
+            machine_witness_callback: Some(callback),
+            ..self
+        }
+    }
 
-        let witness_cols =
-            WitnessColumnMap::from(analyzed.committed_polys_in_source_order().iter().flat_map(
-                |(poly, value)| {
-                    poly.array_elements()
-                        .map(|(name, poly_id)| {
-                            let external_values = external_witness_values.remove(name.as_str());
-                            if let Some(external_values) = &external_values {
-                                if external_values.len() != analyzed.degree() as usize {
-                                    log::debug!(
-                                        "External witness values for column {} were only partially provided \
-                                         (length is {} but the degree is {})",
-                                        name,
-                                        external_values.len(),
-                                        analyzed.degree()
-                                    );
-                                }
-                            }
-                            WitnessColumn::new(poly_id.id as usize, &name, value, external_values)
-                        })
-                        .collect::<Vec<_>>()
-                },
-            ));
+    /// Sends the known cells of each row of the main machine as `(column, row, value)`
+    /// to `sender` as soon as the row is complete, so that e.g. another thread can start
+    /// committing to the witness before it is complete. Unlike the row observer, this
+    /// does not block the solver. Cells are not sent anymore once the receiver is dropped.
+    pub fn with_cell_sender(self, sender: Sender<(PolyID, DegreeType, T)>) -> Self {
+        WitnessGenerator {
+            cell_sender: Some(sender),
+            ..self
+        }
+    }
+
+    /// Sets the maximal number of results of lookups into fixed columns that are memoized.
+    /// A capacity of zero disables the cache.
+    pub fn with_fixed_lookup_cache_capacity(self, capacity: usize) -> Self {
+        WitnessGenerator {
+            fixed_lookup_cache_capacity: capacity,
+            ..self
+        }
+    }
+
+    /// Sets a callback that is invoked when a lookup into fixed columns does not match
+    /// any row, before generation fails with [EvalError::FixedLookupFailed].
+    /// If the callback returns values for the output columns, they are used as the
+    /// result of the lookup instead.
+    pub fn with_missing_lookup_callback(self, callback: Arc<dyn MissingLookupCallback<T>>) -> Self {
+        WitnessGenerator {
+            missing_lookup_callback: Some(callback),
+            ..self
+        }
+    }
 
-        if !external_witness_values.is_empty() {
-            let available_columns = witness_cols
+    /// Registers a lookup table whose values are given at runtime instead of being part
+    /// of the fixed column values, e.g. a large precomputed table that is loaded from
+    /// disk. The columns (by name) have to be declared as fixed columns without values
+    /// (their fixed column values are empty) and can only be used on the right-hand side
+    /// of lookups, which are answered from the given values. All columns of the table
+    /// need to have the same length, which does not have to be the degree, and a lookup
+    /// must not combine columns of the table with other columns.
+    /// Panics if a column is not a fixed column, the lengths of the columns differ or
+    /// the table is empty.
+    pub fn with_lookup_table(mut self, name: &str, columns: Vec<(String, Vec<T>)>) -> Self {
+        let fixed_columns = self
+            .analyzed
+            .constant_polys_in_source_order()
+            .iter()
+            .flat_map(|(poly, _)| poly.array_elements())
+            .map(|(name, _)| name)
+            .collect::<HashSet<_>>();
+        for (column, _) in &columns {
+            assert!(
+                fixed_columns.contains(column),
+                "Column {column} of lookup table {name} is not a fixed column."
+            );
+        }
+        assert!(
+            columns
                 .iter()
-                .map(|(_, witness)| &witness.poly.name)
-                .collect::<Vec<_>>();
-            panic!(
-                "External witness values for non-existent columns: {:?}\nAvailable columns: {:?}",
-                external_witness_values.keys(),
-                available_columns
+                .all(|(_, values)| !values.is_empty() && values.len() == columns[0].1.len()),
+            "The columns of lookup table {name} have to have the same non-zero length."
+        );
+        let columns = columns
+            .into_iter()
+            .map(|(column, values)| (column, values.into()))
+            .collect();
+        self.lookup_tables.push((name.to_string(), columns));
+        self
+    }
+
+    /// Sets priorities for identities, given by their IDs: Within each machine, identities
+    /// with a higher priority are processed first, which can reduce the number of passes
+    /// over a row if an identity is known to enable solving others.
+    /// Identities without a priority have priority zero. Among identities of the same
+    /// priority, the order given by [WitnessGenerator::with_sequence_strategy] applies.
+    /// Panics if an identity does not exist or is listed more than once.
+    pub fn with_identity_priorities(mut self, priorities: Vec<(u64, i32)>) -> Self {
+        for (id, priority) in priorities {
+            let exists = match &self.identities {
+                Some(identities) => identities.iter().any(|identity| identity.id == id),
+                None => self
+                    .analyzed
+                    .identities
+                    .iter()
+                    .any(|identity| identity.id == id),
+            };
+            assert!(exists, "Identity with ID {id} does not exist.");
+            assert!(
+                self.identity_priorities.insert(id, priority).is_none(),
+                "Duplicate priority for identity with ID {id}."
             );
         }
+        self
+    }
 
-        let fixed_cols =
-            FixedColumnMap::from(fixed_col_values.iter().map(|(n, v)| FixedColumn::new(n, v)));
-        FixedData {
-            analyzed,
-            degree: analyzed.degree(),
-            fixed_cols,
-            witness_cols,
-            column_by_name: analyzed
-                .definitions
-                .iter()
-                .filter(|(_, (symbol, _))| matches!(symbol.kind, SymbolKind::Poly(_)))
-                .map(|(name, (symbol, _))| (name.clone(), symbol.into()))
-                .collect(),
+    /// Assigns identities, given by their IDs, to the machine with the given namespace,
+    /// instead of the machine chosen automatically when the machines are extracted. An
+    /// identity assigned to a namespace without machine (e.g. the namespace of the main
+    /// machine) is processed by the main machine. This helps if an identity only
+    /// references columns of a secondary machine, but is better solved by the main
+    /// machine, or the other way around.
+    /// Panics if an identity does not exist or is listed more than once.
+    pub fn with_identity_assignments(mut self, assignments: Vec<(u64, String)>) -> Self {
+        for (id, namespace) in assignments {
+            let exists = match &self.identities {
+                Some(identities) => identities.iter().any(|identity| identity.id == id),
+                None => self
+                    .analyzed
+                    .identities
+                    .iter()
+                    .any(|identity| identity.id == id),
+            };
+            assert!(exists, "Identity with ID {id} does not exist.");
+            assert!(
+                self.identity_assignments.insert(id, namespace).is_none(),
+                "Duplicate assignment for identity with ID {id}."
+            );
         }
+        self
     }
 
-    fn witness_map_with<V: Clone>(&self, initial_value: V) -> WitnessColumnMap<V> {
-        WitnessColumnMap::new(initial_value, self.witness_cols.len())
+    /// Solves the given identities instead of the identities of the PIL file, which are
+    /// otherwise taken with intermediate polynomials inlined. This allows to test machines
+    /// on hand-written identities or to use identities that have already been transformed.
+    /// The identities have to satisfy the same invariants as the inlined identities:
+    /// - They only reference fixed and witness columns of the PIL file, i.e. intermediate
+    ///   polynomials are inlined.
+    /// - Their IDs are unique for each kind of identity (they are used for statistics and
+    ///   [WitnessGenerator::with_identity_priorities], which has to be called afterwards).
+    ///
+    /// Verification (see [verify_witness]) still uses the identities of the PIL file.
+    pub fn with_identities(self, identities: Vec<Identity<AlgebraicExpression<T>>>) -> Self {
+        WitnessGenerator {
+            identities: Some(identities),
+            ..self
+        }
     }
 
-    fn column_name(&self, poly_id: &PolyID) -> &str {
-        match poly_id.ptype {
-            PolynomialType::Committed => &self.witness_cols[poly_id].poly.name,
-            PolynomialType::Constant => &self.fixed_cols[poly_id].name,
-            PolynomialType::Intermediate => unimplemented!(),
+    /// Aborts witness generation with [EvalError::Timeout] once the given time has passed.
+    /// The time is only checked periodically while processing the main machine.
+    pub fn with_timeout(self, timeout: Duration) -> Self {
+        WitnessGenerator {
+            timeout: Some(timeout),
+            ..self
         }
     }
 
-    pub fn try_column_by_name(&self, name: &str) -> Option<PolyID> {
-        self.column_by_name.get(name).cloned()
+    /// Aborts witness generation with [EvalError::Timeout] if no cell of the main machine
+    /// can be determined in more than `rows` consecutive rows.
+    pub fn with_max_rows_without_progress(self, rows: DegreeType) -> Self {
+        WitnessGenerator {
+            max_rows_without_progress: Some(rows),
+            ..self
+        }
     }
 
-    fn external_witness(&self, row: DegreeType, column: &PolyID) -> Option<T> {
-        let row = row % self.degree;
-        self.witness_cols[column]
-            .external_values
-            .as_ref()
-            .and_then(|v| v.get(row as usize).cloned())
+    /// Aborts witness generation with [EvalError::Cancelled] once the flag is set, e.g.
+    /// from another thread. Like the timeout, the flag is only checked periodically while
+    /// processing the main machine.
+    pub fn with_cancellation(self, flag: &'b AtomicBool) -> Self {
+        WitnessGenerator {
+            cancellation: Some(flag),
+            ..self
+        }
     }
-}
 
-pub struct FixedColumn<'a, T> {
-    name: String,
-    values: &'a Vec<T>,
-}
+    /// Sets the direction in which the rows of the main machine are solved.
+    /// See [SolvingDirection::Backward] for the identities that are compatible with
+    /// solving backward.
+    pub fn with_solving_direction(self, direction: SolvingDirection) -> Self {
+        WitnessGenerator { direction, ..self }
+    }
 
-impl<'a, T> FixedColumn<'a, T> {
-    pub fn new(name: &'a str, values: &'a Vec<T>) -> FixedColumn<'a, T> {
-        let name = name.to_string();
-        FixedColumn { name, values }
+    /// Sets the number of rows of the main machine whose polynomial identities are
+    /// processed together, by default 2 (the current and the next row). If a row cannot
+    /// be completed, the polynomial identities of the next `window_size - 2` rows are
+    /// processed as well, so that cells that are only determined by later rows (e.g.
+    /// `x = y'` with `y` only known from its own row) become known.
+    /// A window that is too small leaves such cells undetermined, a larger window costs
+    /// time and memory for each incomplete row, since the rows within the window are
+    /// created and processed repeatedly. Lookups and queries of later rows are not
+    /// processed early. Only used when solving forward.
+    /// Panics if `window_size` is smaller than 2.
+    pub fn with_window_size(self, window_size: usize) -> Self {
+        assert!(
+            window_size >= 2,
+            "The window has to contain at least the current and the next row."
+        );
+        WitnessGenerator {
+            window_size,
+            ..self
+        }
     }
-}
 
-#[derive(Debug)]
-pub struct WitnessColumn<'a, T> {
-    /// A polynomial reference that points to this column in the "current" row
-    /// (i.e., the "next" flag is set to false).
-    /// This is needed in situations where we want to update a cell when the
-    /// update does not come from an identity (which also has an AlgebraicReference).
-    poly: AlgebraicReference,
-    /// The prover query expression, if any.
-    query: Option<&'a Expression<T>>,
-    /// A list of externally computed witness values, if any.
-    /// The length of this list must be equal to the degree.
-    external_values: Option<Vec<T>>,
-}
+    /// Fills the rows of the main machine after the end of the program with padding rows
+    /// instead of solving them. The program is considered to have ended in the first row
+    /// that is equal to the row before it, as in the infinite loop the main machine enters
+    /// after returning. This row and all rows after it are replaced by padding rows,
+    /// which are checked against the identities. See [PaddingStrategy] for the
+    /// requirements on the program.
+    /// Only supported when generating all rows of the main machine forward, without
+    /// checkpoints.
+    pub fn with_padding_strategy(self, padding: PaddingStrategy<'b, T>) -> Self {
+        WitnessGenerator {
+            padding: Some(padding),
+            ..self
+        }
+    }
 
-impl<'a, T> WitnessColumn<'a, T> {
-    pub fn new(
-        id: usize,
+    /// Solves the groups of witness columns of the main machine that are not connected
+    /// by any identity (e.g. several state machines that run side by side and only share
+    /// fixed columns) as separate main machines. Each of them detects the end of its
+    /// program (see [WitnessGenerator::with_padding_strategy]) on its own, instead of
+    /// waiting for all of them to loop.
+    /// Main machines that call into the same machines (directly or via other machines)
+    /// are solved one after the other, the resulting groups are solved concurrently.
+    /// With a row observer or a [WitnessGenerator::with_machine_witness_callback], all
+    /// main machines are solved one after the other.
+    /// The first main machine is the one containing the first witness column. Initial
+    /// rows and row observers only apply to it. [GenerationStats::program_end] is the
+    /// latest end of the programs of all main machines, so that a degree determined via
+    /// [WitnessGenerator::with_max_degree] contains all of them.
+    /// Only supported when generating the complete witness, without checkpoints or
+    /// windows of rows.
+    pub fn with_independent_main_machines(self) -> Self {
+        WitnessGenerator {
+            independent_main_machines: true,
+            ..self
+        }
+    }
+
+    /// Determines the degree from the number of rows the program of the main machine
+    /// needs instead of using a fixed degree: The main machine is first run with
+    /// `max_degree` rows until the program ends (as detected by the padding strategy,
+    /// which is required, see [WitnessGenerator::with_padding_strategy]), and then again
+    /// with the smallest power of two rows that contains the program and at least one
+    /// padding row. The number of rows used by the program is returned in
+    /// [WitnessResult::used_rows].
+    /// Since the main machine is run twice, the query callback has to answer the same
+    /// queries repeatedly. The fixed columns have to be periodic (see [FixedData]) or
+    /// at most as long as the resulting degree, and external witness values have to be
+    /// given after calling this function. Fails with [EvalError::RowsExhausted] if the
+    /// program does not end within `max_degree` rows.
+    /// Not supported when generating partial witnesses.
+    pub fn with_max_degree(self, max_degree: DegreeType) -> Self {
+        WitnessGenerator {
+            degree: Some(max_degree),
+            max_degree: Some(max_degree),
+            ..self
+        }
+    }
+
+    /// Computes the values of the fixed column `name` using `function` whenever they
+    /// are accessed, instead of storing them. This saves memory for columns like
+    /// `id` or `one` that are easy to compute.
+    /// The column still has to be part of the fixed column values, but its values
+    /// are ignored and can be empty.
+    /// Panics if a function or a default (see
+    /// [WitnessGenerator::with_fixed_column_default]) is already registered for the column.
+    pub fn with_fixed_column_function(
+        mut self,
         name: &str,
-        value: &'a Option<FunctionValueDefinition<T>>,
-        external_values: Option<Vec<T>>,
-    ) -> WitnessColumn<'a, T> {
-        let query = if let Some(FunctionValueDefinition::Query(query)) = value {
-            Some(query)
-        } else {
-            None
-        };
-        let poly = AlgebraicReference {
-            poly_id: PolyID {
-                id: id as u64,
-                ptype: PolynomialType::Committed,
-            },
-            name: name.to_string(),
-            next: false,
+        function: &'b dyn FixedColumnFunction<T>,
+    ) -> Self {
+        assert!(
+            self.fixed_column_functions.iter().all(|(n, _)| n != name),
+            "A function for fixed column {name} is already registered."
+        );
+        assert!(
+            self.fixed_column_defaults.iter().all(|(n, _)| n != name),
+            "Fixed column {name} has a default, so it cannot be computed by a function."
+        );
+        self.fixed_column_functions
+            .push((name.to_string(), function));
+        self
+    }
+
+    /// Fills the rows of the fixed column `name` after its given values with `default`
+    /// (e.g. zero for sparse tables), instead of repeating its values. The column can
+    /// have at most as many values as rows.
+    /// Panics if the default cannot be used, see
+    /// [WitnessGenerator::try_with_fixed_column_default].
+    pub fn with_fixed_column_default(self, name: &str, default: T) -> Self {
+        self.try_with_fixed_column_default(name, default)
+            .unwrap_or_else(|e| panic!("{e}"))
+    }
+
+    /// Like [WitnessGenerator::with_fixed_column_default], but returns an error if the
+    /// fixed column does not exist, has no values (e.g. because it is computed by a
+    /// function, see [WitnessGenerator::with_fixed_column_function]), has more values
+    /// than rows or already has a default.
+    pub fn try_with_fixed_column_default(mut self, name: &str, default: T) -> Result<Self, String> {
+        let Some((_, values)) = self.fixed_col_values.iter().find(|(n, _)| n == name) else {
+            return Err(format!("Fixed column {name} does not exist."));
         };
-        WitnessColumn {
-            poly,
-            query,
-            external_values,
+        if values.is_empty() || self.fixed_column_functions.iter().any(|(n, _)| n == name) {
+            return Err(format!(
+                "Fixed column {name} has no values that could be padded."
+            ));
+        }
+        let degree = self.degree();
+        if values.len() as DegreeType > degree {
+            return Err(format!(
+                "Fixed column {name} has {} values, but only {degree} rows.",
+                values.len()
+            ));
+        }
+        if self.fixed_column_defaults.iter().any(|(n, _)| n == name) {
+            return Err(format!(
+                "A default for fixed column {name} is already registered."
+            ));
         }
+        self.fixed_column_defaults.push((name.to_string(), default));
+        Ok(self)
+    }
+
+    /// Registers a custom machine for the given namespace. The machine claims all witness
+    /// columns of the namespace and all identities that only reference those columns,
+    /// so they are not processed by the built-in machines.
+    /// Calls into the namespace are processed by the machine created by `factory`.
+    /// Panics if a custom machine is already registered for the namespace.
+    pub fn with_custom_machine(
+        mut self,
+        namespace: &str,
+        factory: &'b dyn CustomMachineFactory<T>,
+    ) -> Self {
+        assert!(
+            self.custom_machines.iter().all(|(n, _)| n != namespace),
+            "A custom machine for namespace {namespace} is already registered."
+        );
+        self.custom_machines.push((namespace.to_string(), factory));
+        self
+    }
+
+    /// Replaces the given columns in the output of [WitnessGenerator::generate] (and of
+    /// [PreparedGenerator::generate]) by a single column `name` (at the position of the
+    /// first of them) that interleaves them, i.e. row `i` of the `j`-th of `n` columns is
+    /// stored at index `i * n + j`, which is the layout of backends that pack several
+    /// logical columns into one physical column.
+    /// Panics if the columns cannot be interleaved, see
+    /// [WitnessGenerator::try_with_interleaved_columns].
+    pub fn with_interleaved_columns(self, name: &str, columns: &[&str]) -> Self {
+        self.try_with_interleaved_columns(name, columns)
+            .unwrap_or_else(|e| panic!("{e}"))
+    }
+
+    /// Like [WitnessGenerator::with_interleaved_columns], but returns an error if no
+    /// columns are given, a column does not exist or is already interleaved, or if the
+    /// name is taken by another column.
+    pub fn try_with_interleaved_columns(
+        mut self,
+        name: &str,
+        columns: &[&str],
+    ) -> Result<Self, String> {
+        if columns.is_empty() {
+            return Err(format!("No columns to interleave into {name}."));
+        }
+        let available = self.witness_column_names();
+        let mut interleaved = self
+            .interleavings
+            .iter()
+            .flat_map(|(_, columns)| columns.clone())
+            .collect::<HashSet<_>>();
+        for column in columns {
+            if !available.iter().any(|c| c == column) {
+                return Err(format!("Witness column {column} does not exist."));
+            }
+            if !interleaved.insert(column.to_string()) {
+                return Err(format!("Witness column {column} is already interleaved."));
+            }
+        }
+        if !columns.contains(&name)
+            && (available.iter().any(|c| c == name)
+                || self.interleavings.iter().any(|(n, _)| n == name))
+        {
+            return Err(format!("Column {name} already exists."));
+        }
+        self.interleavings.push((
+            name.to_string(),
+            columns.iter().map(|c| c.to_string()).collect(),
+        ));
+        Ok(self)
+    }
+
+    /// Estimates the memory (in bytes) needed to generate the witness, without running
+    /// any analysis: the values of the witness columns (`degree` values for each witness
+    /// column), the fixed column values and the solver overhead of one bit per witness
+    /// cell, which records whether the cell is known.
+    /// The indices of lookups and rows that are only kept temporarily are not included.
+    pub fn estimate_memory(&self) -> usize {
+        let cells = self.degree() as usize * self.analyzed.commitment_count();
+        let fixed_values = self
+            .fixed_col_values
+            .iter()
+            .map(|(_, values)| values.len())
+            .sum::<usize>();
+        (cells + fixed_values) * std::mem::size_of::<T>() + cells.div_ceil(8)
+    }
+
+    /// Solves the polynomial identities over the rationals instead of the field, to debug
+    /// underdetermined cells in small examples: The values are shown as fractions (e.g.
+    /// `1/2` instead of a large field element), and the identities that still contain
+    /// undetermined cells show which constraint is missing. Field elements are interpreted
+    /// as signed integers. Lookups, permutations and queries are ignored, so this is only
+    /// meant for debugging and not for generating witnesses.
+    #[cfg(feature = "rational-debug")]
+    pub fn solve_over_rationals(&self) -> RationalSolution {
+        self.prepare().solve_over_rationals()
+    }
+
+    /// Generates the committed polynomial values
+    /// @returns the values (in the order given by the [OrderingStrategy]).
+    pub fn generate(self) -> Vec<(String, Vec<T>)> {
+        self.generate_rows(WitnessRows::into_columns)
+    }
+
+    /// Like [WitnessGenerator::generate], but returns [EvalError::Timeout] if a limit set
+    /// by [WitnessGenerator::with_timeout] or
+    /// [WitnessGenerator::with_max_rows_without_progress] is exceeded, and
+    /// [EvalError::Cancelled] if the flag set by [WitnessGenerator::with_cancellation]
+    /// is set. Identities that can never hold (e.g. `1 = 0`) are reported as
+    /// [EvalError::UnsatisfiablePil] and identities on fixed columns that do not hold as
+    /// [EvalError::FixedIdentityViolated] before any row is solved. A checkpoint given to
+    /// [WitnessGenerator::resume_from] that cannot be restored is reported as
+    /// [EvalError::Generic].
+    /// Other failures still lead to a panic.
+    pub fn try_generate(mut self) -> Result<Vec<(String, Vec<T>)>, EvalError<T>> {
+        let analyzed = self.analyzed;
+        let ordering = self.ordering.clone();
+        let interleavings = std::mem::take(&mut self.interleavings);
+        let machine_witnesses = self.run_machines(RunMode::Generate)?.complete();
+        Ok(interleave_columns(
+            merge_machine_witnesses(analyzed, &ordering, machine_witnesses),
+            &interleavings,
+        ))
+    }
+
+    /// Like [WitnessGenerator::generate], but returns the columns as they are stored
+    /// after generation, i.e. the columns spilled to disk (see
+    /// [WitnessGenerator::with_spill_to_disk]) are not read back into memory.
+    pub fn generate_to_storage(self) -> Vec<(String, ColumnStorage<T>)> {
+        let analyzed = self.analyzed;
+        let ordering = self.ordering.clone();
+        let machine_witnesses = self
+            .run_machines(RunMode::Generate)
+            .unwrap_or_else(|e| panic!("Witness generation failed:\n{e}"))
+            .complete_storage();
+        order_machine_witnesses(analyzed, &ordering, machine_witnesses)
+    }
+
+    /// Like [WitnessGenerator::generate], but only solves the rows `start..end`, which
+    /// allows splitting the generation of a large witness into independent parts.
+    /// The returned columns have `end - start` rows.
+    /// The rows at the boundary have to be provided externally (e.g. via
+    /// [WitnessGenerator::with_fixed_cells]): Row `start` is not derived from row
+    /// `start - 1`, so all of its values that do not follow from the identities within
+    /// the row have to be given. Row `end` is not returned, but the identities between
+    /// rows `end - 1` and `end` are checked, so the values of row `end` that are needed
+    /// for them have to be given as well.
+    /// Only supported if the main machine does not call into other machines.
+    /// Panics if the range of rows is empty or larger than the degree.
+    pub fn generate_window(self, start: DegreeType, end: DegreeType) -> Vec<(String, Vec<T>)> {
+        let degree = self.degree();
+        assert!(
+            start < end && end <= degree,
+            "Invalid window of rows {start}..{end} for degree {degree}."
+        );
+        assert!(
+            self.checkpoint.is_none() && self.direction == SolvingDirection::Forward,
+            "Checkpoints and solving backward are not supported for windows of rows."
+        );
+        WitnessGenerator {
+            window: Some((start, end)),
+            ..self
+        }
+        .generate()
+    }
+
+    /// Like [WitnessGenerator::generate], but only returns the given columns, in the given
+    /// order. All columns are still solved, but the other columns are dropped as soon as
+    /// their machine is finalized.
+    /// Panics if a column does not exist or is listed more than once.
+    pub fn generate_columns(self, names: &[&str]) -> Vec<(String, Vec<T>)> {
+        let available = self.witness_column_names();
+        let mut selected = HashSet::new();
+        for name in names {
+            assert!(
+                available.iter().any(|column| column == name),
+                "Witness column {name} does not exist."
+            );
+            assert!(
+                selected.insert(name.to_string()),
+                "Witness column {name} is listed more than once."
+            );
+        }
+        let mut columns = WitnessGenerator {
+            selected_columns: Some(selected),
+            ..self
+        }
+        .run_machines(RunMode::Generate)
+        .unwrap_or_else(|e| panic!("Witness generation failed:\n{e}"))
+        .complete()
+        .into_iter()
+        .flat_map(|(_, columns)| columns)
+        .collect::<HashMap<_, _>>();
+        names
+            .iter()
+            .map(|name| columns.remove_entry(*name).unwrap())
+            .collect()
+    }
+
+    /// Like [WitnessGenerator::generate], but converts the values into the field `U`
+    /// after solving. Each value is converted via its canonical integer representative
+    /// in the range `0..T::modulus()`, so this is lossy: negative values like `-1` are
+    /// not mapped to `-1` in `U`, and an error is returned if a representative does not
+    /// fit into `U`.
+    pub fn generate_into<U: FieldElement>(self) -> Result<Vec<(String, Vec<U>)>, String> {
+        convert_witness(self.generate())
+    }
+
+    /// Like [WitnessGenerator::generate], but returns the columns of each machine
+    /// separately, keyed by the name of the machine.
+    /// The columns of each machine are in source order.
+    pub fn generate_per_machine(self) -> BTreeMap<String, Vec<(String, Vec<T>)>> {
+        let column_names = self.witness_column_names();
+        self.run_machines(RunMode::Generate)
+            .unwrap_or_else(|e| panic!("Witness generation failed:\n{e}"))
+            .complete()
+            .into_iter()
+            .map(|(machine, mut columns)| {
+                let columns = column_names
+                    .iter()
+                    .filter_map(|name| columns.remove_entry(name))
+                    .collect();
+                (machine, columns)
+            })
+            .collect()
+    }
+
+    /// Like [WitnessGenerator::generate], but also returns the degree and the bit widths
+    /// of the columns, and returns the columns referenced by public declarations
+    /// separately from the other columns.
+    /// Panics if a column does not have exactly `degree` rows.
+    pub fn generate_with_degree(self) -> WitnessResult<T> {
+        let (generator, used_rows) = self
+            .resolve_max_degree()
+            .unwrap_or_else(|e| panic!("Witness generation failed:\n{e}"));
+        let degree = generator.degree();
+        let analyzed = generator.analyzed;
+        let ordering = generator.ordering.clone();
+        let prepared = generator.prepare();
+        let machine_witnesses = generator
+            .run_prepared(&prepared, RunMode::Generate)
+            .unwrap_or_else(|e| panic!("Witness generation failed:\n{e}"))
+            .complete();
+        let columns = merge_machine_witnesses(analyzed, &ordering, machine_witnesses);
+        for (name, values) in &columns {
+            assert_eq!(
+                values.len() as DegreeType,
+                degree,
+                "Witness column {name} has {} rows, but the degree is {degree}.",
+                values.len()
+            );
+        }
+        let public_values = analyzed
+            .public_declarations_in_source_order()
+            .into_iter()
+            .map(|(name, public)| {
+                let column = public.referenced_poly_name();
+                let (_, values) = columns
+                    .iter()
+                    .find(|(name, _)| *name == column)
+                    .unwrap_or_else(|| panic!("Public {name} refers to unknown column {column}."));
+                (name.clone(), values[public.index as usize])
+            })
+            .collect();
+        let public_columns = analyzed
+            .public_declarations
+            .values()
+            .map(|public| public.referenced_poly_name())
+            .collect::<HashSet<_>>();
+        let (publics, columns): (Vec<_>, Vec<_>) = columns
+            .into_iter()
+            .partition(|(name, _)| public_columns.contains(name));
+        let bit_widths = prepared.bit_widths();
+        let bit_widths = columns.iter().map(|(name, _)| bit_widths[name]).collect();
+        let signed_columns = prepared.signed_columns();
+        let signed = columns
+            .iter()
+            .map(|(name, _)| signed_columns.contains(name))
+            .collect();
+        WitnessResult {
+            columns,
+            publics,
+            public_values,
+            degree,
+            used_rows: used_rows.unwrap_or(degree),
+            bit_widths,
+            signed,
+        }
+    }
+
+    /// Checks that a witness can be generated, without materializing the witness columns:
+    /// Rows of the main machine are discarded as soon as they are not needed anymore and
+    /// solving stops at the first row that cannot be solved.
+    /// Secondary machines are run as far as the main machine calls into them, but they
+    /// are not finalized.
+    pub fn validate_only(self) -> Result<(), EvalError<T>> {
+        self.run_machines(RunMode::Validate).map(|_| ())
+    }
+
+    /// Like [WitnessGenerator::generate], but stops at the first row of the main machine
+    /// that cannot be solved instead of panicking, and returns the columns computed so far
+    /// (in source order) together with the error.
+    /// Cells that are not known (e.g. because they are after the failing row) are `None`.
+    /// Secondary machines are only finalized if the main machine completes, otherwise
+    /// their columns are `None` as well.
+    pub fn generate_partial(self) -> (Vec<(String, Vec<Option<T>>)>, Option<EvalError<T>>) {
+        let column_names = self.witness_column_names();
+        let degree = self.degree() as usize;
+        let RunOutput::Partial(mut columns, error) = self
+            .run_machines(RunMode::Partial)
+            .expect("Errors are returned as part of the partial output.")
+        else {
+            unreachable!()
+        };
+        let columns = column_names
+            .into_iter()
+            .map(|name| {
+                let column = columns.remove(&name).unwrap_or_else(|| vec![None; degree]);
+                (name, column)
+            })
+            .collect();
+        (columns, error)
+    }
+
+    /// Generates the witness columns of each machine in the given mode.
+    fn run_machines(self, mode: RunMode) -> Result<RunOutput<T>, EvalError<T>> {
+        assert!(
+            mode != RunMode::Partial || self.max_degree.is_none(),
+            "A maximum degree is not supported when generating partial witnesses."
+        );
+        if self.analyzed.commitment_count() == 0 {
+            // There is nothing to solve, but the identities on fixed columns are still
+            // checked if there are any rows.
+            let degree = self.degree.or(self.analyzed.degree).unwrap_or_default();
+            let error = (degree > 0).then(|| self.prepare().unsatisfiable).flatten();
+            return match (mode, error) {
+                (RunMode::Partial, error) => Ok(RunOutput::Partial(Default::default(), error)),
+                (_, Some(e)) => Err(e),
+                (_, None) => Ok(RunOutput::Complete(vec![])),
+            };
+        }
+        let (generator, _) = self.resolve_max_degree()?;
+        let prepared = generator.prepare();
+        generator.run_prepared(&prepared, mode)
+    }
+
+    /// If a maximum degree is set (see [WitnessGenerator::with_max_degree]), runs the
+    /// main machine to find the end of the program and returns the generator with the
+    /// resulting degree, together with the number of rows used by the program.
+    fn resolve_max_degree(mut self) -> Result<(Self, Option<DegreeType>), EvalError<T>> {
+        let Some(max_degree) = self.max_degree.take() else {
+            return Ok((self, None));
+        };
+        assert!(
+            self.padding.is_some(),
+            "A maximum degree requires a padding strategy to detect the end of the program."
+        );
+        let mut stats = GenerationStats::default();
+        record_start(OUTER_CODE_NAME);
+        self.prepare().run(
+            &answer_bootloader_inputs(&self.continuation, self.query_callback),
+            self.external_witness.as_deref(),
+            RunOptions {
+                progress: None,
+                checkpoint: None,
+                checkpoints: None,
+                row_observer: None,
+                cell_sender: None,
+                machine_witness_callback: None,
+                mode: RunMode::Generate,
+                limits: Limits {
+                    deadline: self.timeout.map(|timeout| Instant::now() + timeout),
+                    max_rows_without_progress: self.max_rows_without_progress,
+                    cancellation: self.cancellation,
+                },
+                statistics: Some(&mut stats),
+                fixed_cells: self.fixed_cells.clone(),
+                initial_row: self.initial_row.clone(),
+                window: None,
+                selected_columns: Some(Default::default()),
+                provenance: None,
+                row_channel: None,
+            },
+        )?;
+        let used_rows = stats.program_end.ok_or(EvalError::RowsExhausted)?;
+        let longest_fixed_column = self
+            .fixed_col_values
+            .iter()
+            .map(|(_, values)| values.len() as DegreeType)
+            .max()
+            .unwrap_or_default();
+        let degree = (used_rows + 1)
+            .next_power_of_two()
+            .max(longest_fixed_column)
+            .min(max_degree);
+        log::info!("The program uses {used_rows} rows, continuing with degree {degree}.");
+        self.degree = Some(degree);
+        Ok((self, Some(used_rows)))
+    }
+
+    fn run_prepared(
+        self,
+        prepared: &PreparedGenerator<'a, 'b, T>,
+        mode: RunMode,
+    ) -> Result<RunOutput<T>, EvalError<T>> {
+        assert!(
+            mode == RunMode::Generate || (self.checkpoint.is_none() && self.checkpoints.is_none()),
+            "Checkpoints are only supported when generating the complete witness."
+        );
+        let limits = Limits {
+            deadline: self.timeout.map(|timeout| Instant::now() + timeout),
+            max_rows_without_progress: self.max_rows_without_progress,
+            cancellation: self.cancellation,
+        };
+        record_start(OUTER_CODE_NAME);
+        prepared.run(
+            &answer_bootloader_inputs(&self.continuation, self.query_callback),
+            self.external_witness.as_deref(),
+            RunOptions {
+                progress: self.progress,
+                checkpoint: self.checkpoint,
+                checkpoints: self.checkpoints,
+                row_observer: self.row_observer,
+                cell_sender: self.cell_sender,
+                machine_witness_callback: self.machine_witness_callback,
+                mode,
+                limits,
+                statistics: self.statistics,
+                fixed_cells: self.fixed_cells,
+                initial_row: self.initial_row,
+                window: self.window,
+                selected_columns: self.selected_columns,
+                provenance: self.provenance,
+                row_channel: self.row_channel,
+            },
+        )
+    }
+}
+
+/// Accessors for the results of the analysis of the PIL file that is done before solving.
+/// Each of them runs the analysis again, while a [PreparedGenerator] (see
+/// [WitnessGenerator::prepared]) runs it only once and provides some of them as well.
+impl<'a, 'b, T: FieldElement> WitnessGenerator<'a, 'b, T> {
+    /// Returns the range constraint of a column that holds in every row, as determined
+    /// by the global analysis of the fixed columns and identities before witness
+    /// generation starts. Returns `None` if nothing is known about the column.
+    pub fn range_constraint(&self, poly_id: PolyID) -> Option<RangeConstraint<T>> {
+        self.prepare().constraints.column_range_constraint(&poly_id)
+    }
+
+    /// Returns the role of a column (e.g. whether it is a boolean selector), as
+    /// determined by the static analysis of the identities, see [ColumnRole].
+    pub fn column_role(&self, poly_id: PolyID) -> ColumnRole {
+        self.prepare().constraints.column_role(&poly_id)
+    }
+
+    /// Returns the range constraints of all columns (see
+    /// [WitnessGenerator::range_constraint]) as JSON in a versioned schema that does not
+    /// depend on powdr, to be imported by other tools, see
+    /// [RANGE_CONSTRAINTS_SCHEMA_VERSION] and [import_range_constraints_json].
+    /// The witness columns come first, both witness and fixed columns in source order.
+    #[cfg(feature = "serde")]
+    pub fn export_range_constraints_json(&self) -> String {
+        let prepared = self.prepare();
+        let witness_columns = self
+            .analyzed
+            .committed_polys_in_source_order()
+            .into_iter()
+            .map(|(poly, _)| (poly, ColumnKind::Witness));
+        let fixed_columns = self
+            .analyzed
+            .constant_polys_in_source_order()
+            .into_iter()
+            .map(|(poly, _)| (poly, ColumnKind::Fixed));
+        let columns = witness_columns
+            .chain(fixed_columns)
+            .flat_map(|(poly, kind)| {
+                poly.array_elements()
+                    .map(move |(name, poly_id)| (name, poly_id, kind))
+            })
+            .map(|(name, poly_id, kind)| ExportedRangeConstraint {
+                name,
+                kind,
+                constraint: prepared.constraints.column_range_constraint(&poly_id),
+            });
+        range_constraint_export::export_range_constraints_json(columns)
+    }
+
+    /// Returns the identities that are processed by the solver: all identities with
+    /// intermediate polynomials inlined, except those that are already captured by
+    /// global range constraints (see [WitnessGenerator::range_constraint]).
+    /// The identities are in source order.
+    pub fn symbolic_constraints(&self) -> Vec<SymbolicConstraint<T>> {
+        self.prepare().symbolic_constraints()
+    }
+
+    /// Returns the machines that are extracted from the PIL file, with the identities
+    /// they process and the identities connecting them.
+    pub fn machine_graph(&self) -> MachineGraph {
+        self.prepare().machine_graph()
+    }
+
+    /// Returns a summary of the machines that are extracted from the PIL file: the
+    /// number of identities and lookups per machine, the identities that stay in the
+    /// main machine and the longest chain of calls between the machines.
+    pub fn extraction_report(&self) -> ExtractionReport {
+        self.prepare().extraction_report()
+    }
+}
+
+/// Answers the bootloader input queries of the chunk (if any) and passes all other
+/// queries to `query_callback`.
+fn answer_bootloader_inputs<'c, T: FieldElement>(
+    continuation: &'c Option<ContinuationContext<T>>,
+    query_callback: &'c dyn QueryCallback<T>,
+) -> impl QueryCallback<T> + 'c {
+    move |query: &str| match continuation.as_ref().and_then(|c| c.query(query)) {
+        Some(answer) => answer,
+        None => query_callback(query),
+    }
+}
+
+/// Hooks and settings that only apply to a single run.
+struct RunOptions<'b, T: FieldElement> {
+    progress: Option<ProgressReporter<'b>>,
+    checkpoint: Option<Checkpoint<T>>,
+    checkpoints: Option<CheckpointWriter<'b, T>>,
+    row_observer: Option<&'b mut dyn RowObserver<T>>,
+    cell_sender: Option<Sender<(PolyID, DegreeType, T)>>,
+    machine_witness_callback: Option<&'b mut dyn MachineWitnessCallback<T>>,
+    mode: RunMode,
+    limits: Limits<'b>,
+    statistics: Option<&'b mut GenerationStats>,
+    fixed_cells: HashMap<(PolyID, DegreeType), T>,
+    initial_row: Vec<(PolyID, T)>,
+    window: Option<(DegreeType, DegreeType)>,
+    selected_columns: Option<HashSet<String>>,
+    provenance: Option<Arc<ProvenanceLog<T>>>,
+    /// If set and the main machine is the only machine, its rows are sent to the channel
+    /// as they are finalized instead of being returned, see [RunOutput::Sent].
+    row_channel: Option<RowChannel<T>>,
+}
+
+/// How failures are handled and which data is kept.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum RunMode {
+    /// Generate the complete witness, panicking on failures.
+    Generate,
+    /// Only check that all rows of the main machine can be solved, without keeping them.
+    /// The first failure is returned.
+    Validate,
+    /// Keep the rows solved so far, stopping at the first failure of the main machine.
+    Partial,
+}
+
+enum RunOutput<T> {
+    /// The witness columns of each machine, the main machine first
+    /// (or no columns at all in validate-only mode).
+    Complete(Vec<(String, HashMap<String, ColumnStorage<T>>)>),
+    /// The columns computed so far and the error that stopped the main machine (if any).
+    Partial(HashMap<String, Vec<Option<T>>>, Option<EvalError<T>>),
+    /// The rows of the witness were sent to the row channel of the [RunOptions].
+    Sent,
+}
+
+impl<T: FieldElement> RunOutput<T> {
+    fn complete(self) -> Vec<(String, HashMap<String, Vec<T>>)> {
+        self.complete_storage()
+            .into_iter()
+            .map(|(machine, columns)| {
+                let columns = columns
+                    .into_iter()
+                    .map(|(name, column)| (name, column.into_vec()))
+                    .collect();
+                (machine, columns)
+            })
+            .collect()
+    }
+
+    fn complete_storage(self) -> Vec<(String, HashMap<String, ColumnStorage<T>>)> {
+        match self {
+            RunOutput::Complete(witness) => witness,
+            RunOutput::Partial(..) => unreachable!("Only partial runs return partial columns."),
+            RunOutput::Sent => unreachable!("Only runs with a row channel send the rows."),
+        }
+    }
+}
+
+/// Converts witness columns into the field `U` using the canonical integer representatives
+/// of the values. Returns an error if a value does not fit into `U`.
+pub fn convert_witness<T: FieldElement, U: FieldElement>(
+    witness: Vec<(String, Vec<T>)>,
+) -> Result<Vec<(String, Vec<U>)>, String> {
+    let modulus = U::modulus().to_arbitrary_integer();
+    witness
+        .into_iter()
+        .map(|(name, values)| {
+            let values = values
+                .into_iter()
+                .enumerate()
+                .map(|(row, value)| {
+                    let value = value.to_arbitrary_integer();
+                    if value < modulus {
+                        Ok(U::from(value))
+                    } else {
+                        Err(format!(
+                            "Value {value} of column {name} in row {row} does not fit into \
+                             the target field with modulus {modulus}."
+                        ))
+                    }
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok((name, values))
+        })
+        .collect()
+}
+
+/// Merges the witness columns of all machines (the main machine first) into a single list
+/// in the order given by `ordering`.
+fn merge_machine_witnesses<T: FieldElement>(
+    analyzed: &Analyzed<T>,
+    ordering: &OrderingStrategy,
+    machine_witnesses: Vec<(String, HashMap<String, Vec<T>>)>,
+) -> Vec<(String, Vec<T>)> {
+    let witness_cols = order_machine_witnesses(analyzed, ordering, machine_witnesses);
+    for (name, column) in &witness_cols {
+        assert!(!column.is_empty(), "Witness column {name} is empty.");
+    }
+
+    // Publics cannot be extracted if columns were passed to a machine witness callback.
+    if witness_cols.len() == analyzed.commitment_count() {
+        log::debug!("Publics:");
+        for (name, value) in extract_publics(&witness_cols, analyzed) {
+            log::debug!("  {name:>30}: {value}");
+        }
+    }
+    witness_cols
+}
+
+/// Like [merge_machine_witnesses], for any representation of the columns.
+fn order_machine_witnesses<T, C>(
+    analyzed: &Analyzed<T>,
+    ordering: &OrderingStrategy,
+    machine_witnesses: Vec<(String, HashMap<String, C>)>,
+) -> Vec<(String, C)> {
+    // The index of the machine (0 being the main machine) each column belongs to.
+    let machine_of = machine_witnesses
+        .iter()
+        .enumerate()
+        .flat_map(|(index, (_, columns))| columns.keys().map(move |name| (name.clone(), index)))
+        .collect::<HashMap<_, _>>();
+    let mut columns = machine_witnesses
+        .into_iter()
+        .flat_map(|(_, columns)| columns)
+        .collect::<BTreeMap<_, _>>();
+
+    ordering
+        .order(witness_column_names(analyzed), &machine_of)
+        .into_iter()
+        // Columns passed to a machine witness callback are missing.
+        .filter_map(|name| columns.remove_entry(&name))
+        .collect()
+}
+
+/// Replaces the columns of each interleaving by a single column,
+/// see [WitnessGenerator::with_interleaved_columns].
+fn interleave_columns<T: FieldElement>(
+    mut witness: Vec<(String, Vec<T>)>,
+    interleavings: &[(String, Vec<String>)],
+) -> Vec<(String, Vec<T>)> {
+    for (name, columns) in interleavings {
+        let parts = columns
+            .iter()
+            .map(|column| {
+                let (_, values) = witness.iter_mut().find(|(n, _)| n == column).unwrap();
+                std::mem::take(values)
+            })
+            .collect::<Vec<_>>();
+        let values = (0..parts[0].len())
+            .flat_map(|row| parts.iter().map(move |values| values[row]))
+            .collect();
+        witness.retain(|(n, _)| !columns[1..].contains(n));
+        let position = witness.iter().position(|(n, _)| n == &columns[0]).unwrap();
+        witness[position] = (name.clone(), values);
+    }
+    witness
+}
+
+/// The names of all witness columns, in source order.
+fn witness_column_names<T>(analyzed: &Analyzed<T>) -> Vec<String> {
+    analyzed
+        .committed_polys_in_source_order()
+        .into_iter()
+        .flat_map(|(poly, _)| poly.array_elements())
+        .map(|(name, _)| name)
+        .collect()
+}
+
+/// An error in the external witness values.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExternalWitnessError {
+    /// Values were given for columns that do not exist.
+    UnknownColumns {
+        columns: Vec<String>,
+        available_columns: Vec<String>,
+    },
+    /// More values than rows were given for a column.
+    TooManyValues {
+        column: String,
+        degree: DegreeType,
+        length: usize,
+    },
+    /// A value was given for a row that does not exist.
+    RowOutOfRange {
+        column: String,
+        row: DegreeType,
+        degree: DegreeType,
+    },
+    /// Values that are not canonical elements of the field, i.e. not smaller than the
+    /// modulus, by column and row.
+    ValuesOutOfRange {
+        cells: Vec<(String, DegreeType, BigUint)>,
+        modulus: BigUint,
+    },
+}
+
+impl fmt::Display for ExternalWitnessError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExternalWitnessError::UnknownColumns {
+                columns,
+                available_columns,
+            } => write!(
+                f,
+                "External witness values for non-existent columns: {columns:?}\nAvailable columns: {available_columns:?}"
+            ),
+            ExternalWitnessError::TooManyValues {
+                column,
+                degree,
+                length,
+            } => write!(
+                f,
+                "External witness values for column {column} have length {length}, \
+                 but the degree is only {degree}."
+            ),
+            ExternalWitnessError::RowOutOfRange {
+                column,
+                row,
+                degree,
+            } => write!(
+                f,
+                "External witness value for column {column} given for row {row}, \
+                 but the degree is only {degree}."
+            ),
+            ExternalWitnessError::ValuesOutOfRange { cells, modulus } => {
+                write!(
+                    f,
+                    "External witness values are not smaller than the modulus {modulus}:"
+                )?;
+                for (column, row, value) in cells {
+                    write!(f, "\n    {column} in row {row}: {value}")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Turns the given external witness values into a provider.
+fn external_witness_provider<T: FieldElement>(
+    external_witness_values: Vec<(String, Vec<T>)>,
+    available_columns: &[String],
+    degree: DegreeType,
+) -> Result<impl ExternalWitnessProvider<T>, ExternalWitnessError> {
+    let mut external_witness_values = HashMap::<_, _>::from_iter(external_witness_values);
+    check_external_columns(&external_witness_values, available_columns)?;
+    if let Some((column, values)) = external_witness_values
+        .iter()
+        .filter(|(_, values)| values.len() > degree as usize)
+        .min_by_key(|(column, _)| *column)
+    {
+        return Err(ExternalWitnessError::TooManyValues {
+            column: column.clone(),
+            degree,
+            length: values.len(),
+        });
+    }
+    // Shorter columns are allowed, their remaining values are determined by the solver.
+    for (name, external_values) in &external_witness_values {
+        if external_values.len() != degree as usize {
+            log::warn!(
+                "External witness values for column {} were only partially provided \
+                 (length is {} but the degree is {})",
+                name,
+                external_values.len(),
+                degree
+            );
+        }
+    }
+    external_witness_values.retain(|_, values| !values.is_empty());
+
+    Ok(move |name: &str, row: DegreeType| {
+        external_witness_values
+            .get(name)
+            .and_then(|values| values.get(row as usize).cloned())
+    })
+}
+
+/// Turns the given external witness values of single rows into a provider.
+fn sparse_external_witness_provider<T: FieldElement>(
+    external_witness_values: Vec<(String, BTreeMap<DegreeType, T>)>,
+    available_columns: &[String],
+    degree: DegreeType,
+) -> Result<impl ExternalWitnessProvider<T>, ExternalWitnessError> {
+    let external_witness_values = HashMap::<_, _>::from_iter(external_witness_values);
+    check_external_columns(&external_witness_values, available_columns)?;
+    if let Some((column, row)) = external_witness_values
+        .iter()
+        .filter_map(|(column, values)| {
+            let (row, _) = values.last_key_value()?;
+            (*row >= degree).then_some((column, *row))
+        })
+        .min_by_key(|(column, _)| *column)
+    {
+        return Err(ExternalWitnessError::RowOutOfRange {
+            column: column.clone(),
+            row,
+            degree,
+        });
+    }
+
+    Ok(move |name: &str, row: DegreeType| {
+        external_witness_values
+            .get(name)
+            .and_then(|values| values.get(&row).cloned())
+    })
+}
+
+/// Returns an error if external witness values are given for columns that do not exist.
+fn check_external_columns<V>(
+    external_witness_values: &HashMap<String, V>,
+    available_columns: &[String],
+) -> Result<(), ExternalWitnessError> {
+    let mut unknown_columns = external_witness_values
+        .keys()
+        .filter(|name| !available_columns.contains(name))
+        .cloned()
+        .collect::<Vec<_>>();
+    if unknown_columns.is_empty() {
+        Ok(())
+    } else {
+        unknown_columns.sort();
+        Err(ExternalWitnessError::UnknownColumns {
+            columns: unknown_columns,
+            available_columns: available_columns.to_vec(),
+        })
+    }
+}
+
+/// Determines the degree from the lengths of the fixed columns, ignoring empty columns.
+/// The degree is the length of the longest column; shorter (periodic) columns have to
+/// divide it.
+fn infer_degree<T>(fixed_col_values: &[(String, Vec<T>)]) -> DegreeType {
+    let lengths = fixed_col_values
+        .iter()
+        .filter(|(_, values)| !values.is_empty())
+        .map(|(name, values)| (name, values.len()))
+        .collect::<Vec<_>>();
+    let Some((longest_name, degree)) = lengths.iter().rev().max_by_key(|(_, len)| *len) else {
+        panic!("Cannot infer the degree: There are no non-empty fixed columns.");
+    };
+    if let Some((name, len)) = lengths.iter().find(|(_, len)| degree % len != 0) {
+        panic!(
+            "Cannot infer the degree: Fixed column {longest_name} has length {degree}, \
+             but fixed column {name} has length {len}."
+        );
+    }
+    *degree as DegreeType
+}
+
+pub fn extract_publics<T: FieldElement>(
+    witness: &[(String, Vec<T>)],
+    pil: &Analyzed<T>,
+) -> Vec<(String, T)> {
+    let witness = witness
+        .iter()
+        .map(|(name, col)| (name.clone(), col))
+        .collect::<BTreeMap<_, _>>();
+    pil.public_declarations_in_source_order()
+        .iter()
+        .map(|(name, public_declaration)| {
+            let poly_name = &public_declaration.referenced_poly_name();
+            let poly_index = public_declaration.index;
+            let value = witness[poly_name][poly_index as usize];
+            ((*name).clone(), value)
+        })
+        .collect()
+}
+
+/// Data that is fixed for witness generation.
+pub struct FixedData<'a, T> {
+    analyzed: &'a Analyzed<T>,
+    degree: DegreeType,
+    fixed_cols: FixedColumnMap<FixedColumn<'a, T>>,
+    witness_cols: WitnessColumnMap<WitnessColumn<'a, T>>,
+    column_by_name: HashMap<String, PolyID>,
+    /// The names of the intermediate columns, which are only needed for diagnostics
+    /// since intermediate columns are inlined.
+    intermediate_names: HashMap<PolyID, String>,
+    external_witness: Option<&'a dyn ExternalWitnessProvider<T>>,
+    /// Values of single witness cells that are known before solving, by column and row.
+    fixed_cells: HashMap<(PolyID, DegreeType), T>,
+    parsed_query_callback: Option<&'a dyn ParsedQueryCallback<T>>,
+    batch_query_callback: Option<&'a dyn BatchQueryCallback<T>>,
+    /// The values returned by the last call to the batch query callback, by column name,
+    /// together with the row of the call.
+    batch_query_answers: Mutex<Option<(DegreeType, HashMap<String, T>)>>,
+    /// If set, the solver records how the values of the cells were derived.
+    provenance: Option<Arc<ProvenanceLog<T>>>,
+    /// If set, the main machine sends the known cells of each completed row.
+    cell_sender: Option<Sender<(PolyID, DegreeType, T)>>,
+}
+
+impl<'a, T: FieldElement> FixedData<'a, T> {
+    pub fn new(
+        analyzed: &'a Analyzed<T>,
+        degree: DegreeType,
+        fixed_col_values: &'a [(String, Vec<T>)],
+        external_witness: Option<&'a dyn ExternalWitnessProvider<T>>,
+    ) -> Self {
+        Self::new_with_defaults(analyzed, degree, fixed_col_values, &[], external_witness)
+    }
+
+    /// Like [FixedData::new], but the given fixed columns (by name) are filled up to the
+    /// degree with the given default values instead of being repeated, so their length
+    /// does not have to divide the degree.
+    /// Panics if such a column has more values than rows.
+    pub fn new_with_defaults(
+        analyzed: &'a Analyzed<T>,
+        degree: DegreeType,
+        fixed_col_values: &'a [(String, Vec<T>)],
+        fixed_column_defaults: &[(String, T)],
+        external_witness: Option<&'a dyn ExternalWitnessProvider<T>>,
+    ) -> Self {
+        assert!(degree > 0, "Resulting degree is zero.");
+
+        let witness_cols =
+            WitnessColumnMap::from(analyzed.committed_polys_in_source_order().iter().flat_map(
+                |(poly, value)| {
+                    poly.array_elements()
+                        .map(|(name, poly_id)| {
+                            WitnessColumn::new(poly_id.id as usize, &name, value)
+                        })
+                        .collect::<Vec<_>>()
+                },
+            ));
+
+        // Columns that are shorter than the degree are repeated to fill all rows, unless
+        // they have a default value for the remaining rows.
+        // Columns without values have to be replaced (e.g. by functions) before they
+        // are used, see [PreparedGenerator].
+        let fixed_cols = FixedColumnMap::from(fixed_col_values.iter().map(|(n, v)| {
+            let len = v.len() as DegreeType;
+            if let Some((_, default)) = fixed_column_defaults.iter().find(|(name, _)| name == n) {
+                FixedColumn::new_with_default(n, v, degree, *default)
+            } else if len == 0 || len >= degree {
+                FixedColumn::new(n, v)
+            } else {
+                assert!(
+                    degree % len == 0,
+                    "Fixed column {n} has length {len}, which does not divide the degree {degree}."
+                );
+                FixedColumn::new_periodic(n, v, degree)
+            }
+        }));
+        FixedData {
+            analyzed,
+            degree,
+            fixed_cols,
+            witness_cols,
+            column_by_name: analyzed
+                .definitions
+                .iter()
+                .filter(|(_, (symbol, _))| matches!(symbol.kind, SymbolKind::Poly(_)))
+                .map(|(name, (symbol, _))| (name.clone(), symbol.into()))
+                .collect(),
+            intermediate_names: analyzed
+                .intermediate_polys_in_source_order()
+                .into_iter()
+                .flat_map(|(symbol, _)| symbol.array_elements())
+                .map(|(name, poly_id)| (poly_id, name))
+                .collect(),
+            external_witness,
+            fixed_cells: Default::default(),
+            parsed_query_callback: None,
+            batch_query_callback: None,
+            batch_query_answers: Default::default(),
+            provenance: None,
+            cell_sender: None,
+        }
+    }
+
+    /// Like [FixedData::new], but the values of the fixed columns are computed by the
+    /// given functions (by column name) whenever they are accessed, so they never have
+    /// to be materialized.
+    /// Panics unless there is exactly one function for each fixed column.
+    pub fn from_fixed_column_functions(
+        analyzed: &'a Analyzed<T>,
+        degree: DegreeType,
+        functions: impl IntoIterator<Item = (String, Box<dyn FixedColumnFunction<T> + 'a>)>,
+        external_witness: Option<&'a dyn ExternalWitnessProvider<T>>,
+    ) -> Self {
+        let mut functions = functions.into_iter().collect::<HashMap<_, _>>();
+        let fixed_cols = FixedColumnMap::from(
+            analyzed
+                .constant_polys_in_source_order()
+                .iter()
+                .flat_map(|(poly, _)| poly.array_elements())
+                .map(|(name, _)| {
+                    let function = functions
+                        .remove(&name)
+                        .unwrap_or_else(|| panic!("No function for fixed column {name}."));
+                    FixedColumn::new_boxed_function(&name, function, degree)
+                }),
+        );
+        let mut unknown = functions.into_keys().collect::<Vec<_>>();
+        unknown.sort();
+        assert!(
+            unknown.is_empty(),
+            "Functions for unknown fixed columns: {}",
+            unknown.join(", ")
+        );
+        FixedData {
+            fixed_cols,
+            ..Self::new(analyzed, degree, &[], external_witness)
+        }
+    }
+
+    /// Sets the values of single witness cells, which take precedence over the
+    /// external witness values.
+    pub fn with_fixed_cells(self, fixed_cells: HashMap<(PolyID, DegreeType), T>) -> Self {
+        FixedData {
+            fixed_cells,
+            ..self
+        }
+    }
+
+    /// Sets the callback that answers queries in parsed form, if they can be parsed.
+    pub fn with_parsed_query_callback(
+        self,
+        parsed_query_callback: Option<&'a dyn ParsedQueryCallback<T>>,
+    ) -> Self {
+        FixedData {
+            parsed_query_callback,
+            ..self
+        }
+    }
+
+    /// Sets the callback that answers the queries of several columns of a row at once.
+    pub fn with_batch_query_callback(
+        self,
+        batch_query_callback: Option<&'a dyn BatchQueryCallback<T>>,
+    ) -> Self {
+        FixedData {
+            batch_query_callback,
+            ..self
+        }
+    }
+
+    /// Sets the log into which the solver records the derivation of each cell.
+    fn with_provenance(self, provenance: Option<Arc<ProvenanceLog<T>>>) -> Self {
+        FixedData { provenance, ..self }
+    }
+
+    /// Sets the channel into which the main machine sends the cells of completed rows.
+    fn with_cell_sender(self, cell_sender: Option<Sender<(PolyID, DegreeType, T)>>) -> Self {
+        FixedData {
+            cell_sender,
+            ..self
+        }
+    }
+
+    /// Replaces the given fixed columns by columns whose values are computed by the
+    /// given functions.
+    pub fn with_fixed_column_functions(
+        mut self,
+        functions: &'a [(String, &'a dyn FixedColumnFunction<T>)],
+    ) -> Self {
+        for (name, function) in functions {
+            let poly_id = self
+                .try_column_by_name(name)
+                .filter(|poly_id| poly_id.ptype == PolynomialType::Constant)
+                .unwrap_or_else(|| panic!("{name} is not a fixed column."));
+            self.fixed_cols[&poly_id] = FixedColumn::new_function(name, *function, self.degree);
+        }
+        self
+    }
+
+    fn witness_map_with<V: Clone>(&self, initial_value: V) -> WitnessColumnMap<V> {
+        WitnessColumnMap::new(initial_value, self.witness_cols.len())
+    }
+
+    /// The number of rows.
+    pub fn degree(&self) -> DegreeType {
+        self.degree
+    }
+
+    pub fn column_name(&self, poly_id: &PolyID) -> &str {
+        match poly_id.ptype {
+            PolynomialType::Committed => &self.witness_cols[poly_id].poly.name,
+            PolynomialType::Constant => &self.fixed_cols[poly_id].name,
+            PolynomialType::Intermediate => &self.intermediate_names[poly_id],
+        }
+    }
+
+    pub fn try_column_by_name(&self, name: &str) -> Option<PolyID> {
+        self.column_by_name.get(name).cloned()
+    }
+
+    /// Returns the value of the cell if it is fixed or provided as external witness.
+    fn external_witness(&self, row: DegreeType, column: &PolyID) -> Option<T> {
+        let row = row % self.degree;
+        if let Some(value) = self.fixed_cells.get(&(*column, row)) {
+            return Some(*value);
+        }
+        let external_witness = self.external_witness?;
+        external_witness(&self.witness_cols[column].poly.name, row)
+    }
+
+    fn is_fixed_cell(&self, row: DegreeType, column: &PolyID) -> bool {
+        self.fixed_cells.contains_key(&(*column, row % self.degree))
+    }
+}
+
+pub struct FixedColumn<'a, T> {
+    name: String,
+    values: FixedColumnValues<'a, T>,
+    /// The number of rows.
+    len: DegreeType,
+}
+
+enum FixedColumnValues<'a, T> {
+    /// The stored values, which are repeated to fill all rows.
+    Stored(&'a [T]),
+    /// The values are computed from the row index when they are accessed.
+    Function(&'a dyn FixedColumnFunction<T>),
+    /// Like `Function`, but the column owns the function.
+    BoxedFunction(Box<dyn FixedColumnFunction<T> + 'a>),
+    /// The stored values, followed by `default` in all remaining rows.
+    Padded { values: &'a [T], default: T },
+}
+
+impl<'a, T: Copy> FixedColumn<'a, T> {
+    pub fn new(name: &'a str, values: &'a [T]) -> FixedColumn<'a, T> {
+        let name = name.to_string();
+        let len = values.len() as DegreeType;
+        FixedColumn {
+            name,
+            values: FixedColumnValues::Stored(values),
+            len,
+        }
+    }
+
+    /// Creates a column with `degree` rows that repeats `pattern`, without expanding it.
+    /// The length of the pattern has to divide the degree.
+    pub fn new_periodic(name: &'a str, pattern: &'a [T], degree: DegreeType) -> FixedColumn<'a, T> {
+        assert!(
+            !pattern.is_empty() && degree % pattern.len() as DegreeType == 0,
+            "The length of the pattern of fixed column {name} ({}) does not divide the degree ({degree}).",
+            pattern.len()
+        );
+        FixedColumn {
+            name: name.to_string(),
+            values: FixedColumnValues::Stored(pattern),
+            len: degree,
+        }
+    }
+
+    /// Creates a column with `degree` rows that starts with `values` and has the value
+    /// `default` in all other rows.
+    /// Panics if there are more values than rows.
+    pub fn new_with_default(
+        name: &str,
+        values: &'a [T],
+        degree: DegreeType,
+        default: T,
+    ) -> FixedColumn<'a, T> {
+        assert!(
+            values.len() as DegreeType <= degree,
+            "Fixed column {name} has {} values, but only {degree} rows.",
+            values.len()
+        );
+        FixedColumn {
+            name: name.to_string(),
+            values: FixedColumnValues::Padded { values, default },
+            len: degree,
+        }
+    }
+
+    /// Creates a column with `degree` rows whose values are computed by `function`.
+    pub fn new_function(
+        name: &str,
+        function: &'a dyn FixedColumnFunction<T>,
+        degree: DegreeType,
+    ) -> FixedColumn<'a, T> {
+        FixedColumn {
+            name: name.to_string(),
+            values: FixedColumnValues::Function(function),
+            len: degree,
+        }
+    }
+
+    /// Like [FixedColumn::new_function], but the column owns the function.
+    pub fn new_boxed_function(
+        name: &str,
+        function: Box<dyn FixedColumnFunction<T> + 'a>,
+        degree: DegreeType,
+    ) -> FixedColumn<'a, T> {
+        FixedColumn {
+            name: name.to_string(),
+            values: FixedColumnValues::BoxedFunction(function),
+            len: degree,
+        }
+    }
+
+    /// The number of rows.
+    pub fn len(&self) -> DegreeType {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The value in the given row, wrapping around after the last row.
+    #[inline]
+    pub fn value(&self, row: DegreeType) -> T {
+        match &self.values {
+            FixedColumnValues::Stored(values) => {
+                values[(row % values.len() as DegreeType) as usize]
+            }
+            FixedColumnValues::Function(function) => function(row % self.len),
+            FixedColumnValues::BoxedFunction(function) => function(row % self.len),
+            FixedColumnValues::Padded { values, default } => values
+                .get((row % self.len) as usize)
+                .copied()
+                .unwrap_or(*default),
+        }
+    }
+
+    /// The values of all rows.
+    pub fn values(&self) -> impl Iterator<Item = T> + '_ {
+        (0..self.len).map(|row| self.value(row))
+    }
+
+    /// The stored values, if any. These are only all values if the column is not periodic.
+    pub fn pattern(&self) -> Option<&'a [T]> {
+        match self.values {
+            FixedColumnValues::Stored(values) => Some(values),
+            FixedColumnValues::Function(_)
+            | FixedColumnValues::BoxedFunction(_)
+            | FixedColumnValues::Padded { .. } => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct WitnessColumn<'a, T> {
+    /// A polynomial reference that points to this column in the "current" row
+    /// (i.e., the "next" flag is set to false).
+    /// This is needed in situations where we want to update a cell when the
+    /// update does not come from an identity (which also has an AlgebraicReference).
+    poly: AlgebraicReference,
+    /// The prover query expression, if any.
+    query: Option<&'a Expression<T>>,
+}
+
+impl<'a, T> WitnessColumn<'a, T> {
+    pub fn new(
+        id: usize,
+        name: &str,
+        value: &'a Option<FunctionValueDefinition<T>>,
+    ) -> WitnessColumn<'a, T> {
+        let query = if let Some(FunctionValueDefinition::Query(query)) = value {
+            Some(query)
+        } else {
+            None
+        };
+        let poly = AlgebraicReference {
+            poly_id: PolyID {
+                id: id as u64,
+                ptype: PolynomialType::Committed,
+            },
+            name: name.to_string(),
+            next: false,
+        };
+        WitnessColumn { poly, query }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use powdr_number::{Bn254Field, GoldilocksField};
+    use powdr_pil_analyzer::analyze_string;
+    use pretty_assertions::assert_eq;
+    use test_log::test;
+
+    use powdr_ast::analyzed::IdentityKind;
+    use powdr_ast::parsed::SelectedExpressions;
+
+    use crate::constant_evaluator;
+
+    use super::*;
+
+    pub(super) fn generate_witness(src: &str) -> Vec<(String, Vec<GoldilocksField>)> {
+        let analyzed = analyze_string::<GoldilocksField>(src);
+        let fixed_cols = constant_evaluator::generate(&analyzed);
+        let query_callback = unused_query_callback();
+        WitnessGenerator::new(&analyzed, &fixed_cols, &query_callback).generate()
+    }
+
+    pub(super) fn values(values: impl IntoIterator<Item = u64>) -> Vec<GoldilocksField> {
+        values.into_iter().map(GoldilocksField::from).collect()
+    }
+
+    pub(super) const ARRAY_SRC: &str = r"
+let N = 8;
+namespace Arr(N);
+    col fixed ISLAST(i) { match i {
+        N - 1 => 1,
+        _ => 0,
+    } };
+    col witness mem[2];
+
+    ISLAST * (mem[0]' - 1) = 0;
+    ISLAST * (mem[1]' - 2) = 0;
+    (1 - ISLAST) * (mem[0]' - mem[0]) = 0;
+    (1 - ISLAST) * (mem[1]' - (mem[1] + mem[0])) = 0;
+";
+
+    #[test]
+    fn committed_array() {
+        let witness = generate_witness(ARRAY_SRC);
+        assert_eq!(
+            witness,
+            vec![
+                ("Arr.mem[0]".to_string(), values([1; 8])),
+                ("Arr.mem[1]".to_string(), values(2..10)),
+            ]
+        );
+    }
+
+    #[test]
+    fn fixed_array() {
+        let src = r"
+namespace Arr(4);
+    let multiple: int -> col = |k| |i| i * k;
+    let P: col[4] = [multiple(0), multiple(1), multiple(2), multiple(3)];
+    let index = 3;
+    col witness a, b;
+    a = P[2];
+    b = P[index];
+";
+        let witness = generate_witness(src);
+        assert_eq!(
+            witness,
+            vec![
+                ("Arr.a".to_string(), values([0, 2, 4, 6])),
+                ("Arr.b".to_string(), values([0, 3, 6, 9])),
+            ]
+        );
+    }
+
+    #[test]
+    fn permutation_between_columns() {
+        let src = r"
+namespace Perm(8);
+    col fixed SQUARES(i) { i * i };
+    col witness a, b;
+    a = SQUARES;
+    { a } is { b };
+";
+        let witness = generate_witness(src);
+        let squares = values((0..8).map(|i| i * i));
+        assert_eq!(
+            witness,
+            vec![
+                ("Perm.a".to_string(), squares.clone()),
+                ("Perm.b".to_string(), squares),
+            ]
+        );
+    }
+
+    #[test]
+    fn permutation_into_bijective_fixed_columns() {
+        // `y` is known, so `x` can only be determined from the row of `y` in `PERM`.
+        let src = r"
+namespace Perm(8);
+    col fixed ID(i) { i };
+    col fixed SQUARES(i) { i * i };
+    col fixed PERM = [3, 6, 1, 4, 7, 2, 5, 0];
+    col witness x, y, z;
+    y = ID;
+    { x, y } is { ID, PERM };
+    { y, z } is { PERM, SQUARES };
+";
+        let witness = generate_witness(src);
+        let inverse = [7, 2, 5, 0, 3, 6, 1, 4];
+        assert_eq!(
+            witness,
+            vec![
+                ("Perm.x".to_string(), values(inverse)),
+                ("Perm.y".to_string(), values(0..8)),
+                ("Perm.z".to_string(), values(inverse.map(|i| i * i))),
+            ]
+        );
+    }
+
+    #[test]
+    fn validate_only() {
+        let analyzed = analyze_string::<GoldilocksField>(ARRAY_SRC);
+        let fixed_cols = constant_evaluator::generate(&analyzed);
+        let query_callback = unused_query_callback();
+        WitnessGenerator::new(&analyzed, &fixed_cols, &query_callback)
+            .validate_only()
+            .unwrap();
+    }
+
+    #[test]
+    fn validate_only_reports_failing_row() {
+        let src = r"
+namespace N(4);
+    col fixed ISTWO(i) { match i {
+        2 => 1,
+        _ => 0,
+    } };
+    col witness x;
+    x = 1;
+    ISTWO * (x - 2) = 0;
+";
+        let analyzed = analyze_string::<GoldilocksField>(src);
+        let fixed_cols = constant_evaluator::generate(&analyzed);
+        let query_callback = unused_query_callback();
+        let err = WitnessGenerator::new(&analyzed, &fixed_cols, &query_callback)
+            .validate_only()
+            .unwrap_err();
+        assert!(matches!(err, EvalError::RowFailed { row: 2, .. }), "{err}");
+    }
+
+    #[test]
+    fn failing_query() {
+        let src = r#"
+namespace N(4);
+    col witness x(i) query ("hint", i);
+    col witness y;
+    y = x + 1;
+"#;
+        let analyzed = analyze_string::<GoldilocksField>(src);
+        let fixed_cols = constant_evaluator::generate(&analyzed);
+        let queries = std::sync::Mutex::new(vec![]);
+        let query_callback = |query: &str| -> Result<Option<GoldilocksField>, String> {
+            queries.lock().unwrap().push(query.to_string());
+            Err("no input file".to_string())
+        };
+        let err = WitnessGenerator::new(&analyzed, &fixed_cols, &query_callback)
+            .validate_only()
+            .unwrap_err();
+        let query = queries.lock().unwrap()[0].clone();
+        assert!(
+            err.to_string().contains(&format!(
+                "Query \"{query}\" for column N.x failed on row 0: no input file"
+            )),
+            "{err}"
+        );
+
+        let query_callback = option_query_callback(|_| Some(GoldilocksField::from(3)));
+        let witness = WitnessGenerator::new(&analyzed, &fixed_cols, &query_callback).generate();
+        assert_eq!(witness[1], ("N.y".to_string(), values([4; 4])));
+    }
+
+    #[test]
+    fn unanswered_query() {
+        let src = r#"
+namespace N(4);
+    col witness x(i) query ("hint", i);
+    col witness y;
+    y = x + 1;
+"#;
+        let analyzed = analyze_string::<GoldilocksField>(src);
+        let fixed_cols = constant_evaluator::generate(&analyzed);
+        let query_callback = |_: &str| -> Result<Option<GoldilocksField>, String> { Ok(None) };
+        let err = WitnessGenerator::new(&analyzed, &fixed_cols, &query_callback)
+            .validate_only()
+            .unwrap_err();
+        let EvalError::RowFailed { row: 0, errors } = &err else {
+            panic!("{err}");
+        };
+        assert!(
+            matches!(
+                &errors[0],
+                EvalError::UnansweredQuery { column, row: 0, .. } if column == "N.x"
+            ),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn unsatisfiable_pil() {
+        let src = r"
+namespace N(4);
+    col witness x;
+    x = 1;
+    2 * 3 = 5;
+";
+        let analyzed = analyze_string::<GoldilocksField>(src);
+        let fixed_cols = constant_evaluator::generate(&analyzed);
+        let query_callback = unused_query_callback();
+        let err = WitnessGenerator::new(&analyzed, &fixed_cols, &query_callback)
+            .try_generate()
+            .unwrap_err();
+        assert!(
+            matches!(&err, EvalError::UnsatisfiablePil { value, .. } if *value == 1.into()),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn fixed_only_identities() {
+        let src = |factor: u64| {
+            format!(
+                r"
+namespace N(4);
+    col fixed A = [1, 2, 3, 4];
+    col fixed B = [2, 4, 6, 8];
+    col witness x;
+    B = {factor} * A;
+    x = A;
+"
+            )
+        };
+        let analyzed = analyze_string::<GoldilocksField>(&src(2));
+        let fixed_cols = constant_evaluator::generate(&analyzed);
+        let query_callback = unused_query_callback();
+        let generator = WitnessGenerator::new(&analyzed, &fixed_cols, &query_callback);
+        assert_eq!(generator.extraction_report().identity_count, 1);
+        assert_eq!(
+            generator.generate(),
+            vec![("N.x".to_string(), values([1, 2, 3, 4]))]
+        );
+
+        let analyzed = analyze_string::<GoldilocksField>(&src(3));
+        let fixed_cols = constant_evaluator::generate(&analyzed);
+        let err = WitnessGenerator::new(&analyzed, &fixed_cols, &query_callback)
+            .try_generate()
+            .unwrap_err();
+        assert!(
+            matches!(&err, EvalError::FixedIdentityViolated { row: 0, value, .. }
+                if *value == GoldilocksField::from(-1)),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn generate_with_degree() {
+        let analyzed = analyze_string::<GoldilocksField>(ARRAY_SRC);
+        let fixed_cols = constant_evaluator::generate(&analyzed);
+        let query_callback = unused_query_callback();
+        let result =
+            WitnessGenerator::with_inferred_degree(&analyzed, &fixed_cols, &query_callback)
+                .generate_with_degree();
+        assert_eq!(result.degree, 8);
+        assert_eq!(result.columns, generate_witness(ARRAY_SRC));
+    }
+
+    #[test]
+    fn conflicting_constraints() {
+        let src = r"
+namespace N(4);
+    col witness x;
+    x = 1;
+    x = 2;
+";
+        let analyzed = analyze_string::<GoldilocksField>(src);
+        let fixed_cols = constant_evaluator::generate(&analyzed);
+        let query_callback = unused_query_callback();
+        let err = WitnessGenerator::new(&analyzed, &fixed_cols, &query_callback)
+            .validate_only()
+            .unwrap_err();
+        let EvalError::RowFailed { row: 0, errors } = &err else {
+            panic!("Unexpected error: {err}");
+        };
+        assert!(
+            errors.iter().any(|e| matches!(
+                e,
+                EvalError::ConflictingConstraints { cell, row: 0, values, .. }
+                    if cell == "N.x" && *values == (1.into(), 2.into())
+            )),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn periodic_fixed_column() {
+        let src = r"
+namespace N(8);
+    col fixed P(i) { i % 4 };
+    col witness w;
+    w = P;
+";
+        let analyzed = analyze_string::<GoldilocksField>(src);
+        let fixed_cols = constant_evaluator::generate(&analyzed);
+        let query_callback = unused_query_callback();
+        let expanded = WitnessGenerator::new(&analyzed, &fixed_cols, &query_callback).generate();
+        let pattern = vec![("N.P".to_string(), fixed_cols[0].1[..4].to_vec())];
+        let periodic = WitnessGenerator::new(&analyzed, &pattern, &query_callback).generate();
+        assert_eq!(periodic, expanded);
+        assert_eq!(periodic[0].1, values([0, 1, 2, 3, 0, 1, 2, 3]));
+    }
+
+    #[test]
+    fn fixed_column_function() {
+        let src = r"
+namespace N(16);
+    col fixed ID(i) { i };
+    col fixed BYTE(i) { i & 0xf };
+    col witness w, x;
+    w = ID + 1;
+    { x } in { BYTE };
+";
+        let analyzed = analyze_string::<GoldilocksField>(src);
+        let fixed_cols = constant_evaluator::generate(&analyzed);
+        let query_callback = unused_query_callback();
+        let expanded = WitnessGenerator::new(&analyzed, &fixed_cols, &query_callback).generate();
+        let empty = vec![("N.ID".to_string(), vec![]), ("N.BYTE".to_string(), vec![])];
+        let id = |row: DegreeType| GoldilocksField::from(row);
+        let byte = |row: DegreeType| GoldilocksField::from(row & 0xf);
+        let computed = WitnessGenerator::new(&analyzed, &empty, &query_callback)
+            .with_fixed_column_function("N.ID", &id)
+            .with_fixed_column_function("N.BYTE", &byte)
+            .generate();
+        assert_eq!(computed, expanded);
+        assert_eq!(computed[0].1, values(1..=16));
+    }
+
+    #[test]
+    fn fixed_column_default() {
+        let src = r"
+namespace N(8);
+    col fixed T;
+    col witness w;
+    w = T + 1;
+";
+        let analyzed = analyze_string::<GoldilocksField>(src);
+        let fixed_cols = vec![("N.T".to_string(), values([5, 6, 7]))];
+        let query_callback = unused_query_callback();
+        let witness = WitnessGenerator::new(&analyzed, &fixed_cols, &query_callback)
+            .with_fixed_column_default("N.T", 0.into())
+            .generate();
+        assert_eq!(witness[0].1, values([6, 7, 8, 1, 1, 1, 1, 1]));
+
+        let column = FixedColumn::new_with_default("N.T", &fixed_cols[0].1, 8, 9.into());
+        assert_eq!(column.len(), 8);
+        assert_eq!(column.value(2), 7.into());
+        assert_eq!(column.value(7), 9.into());
+        assert_eq!(column.value(8), 5.into());
+        assert!(column.pattern().is_none());
+    }
+
+    #[test]
+    fn fixed_column_default_errors() {
+        let src = r"
+namespace N(4);
+    col fixed T;
+    col fixed ID(i) { i };
+    col witness w;
+    w = T + ID;
+";
+        let analyzed = analyze_string::<GoldilocksField>(src);
+        let fixed_cols = vec![
+            ("N.T".to_string(), values([5, 6, 7, 8, 9])),
+            ("N.ID".to_string(), vec![]),
+        ];
+        let query_callback = unused_query_callback();
+        let id = |row: DegreeType| GoldilocksField::from(row);
+        let generator = || {
+            WitnessGenerator::new(&analyzed, &fixed_cols, &query_callback)
+                .with_fixed_column_function("N.ID", &id)
+        };
+        let error = |name: &str| {
+            generator()
+                .try_with_fixed_column_default(name, 0.into())
+                .err()
+                .unwrap()
+        };
+        assert_eq!(error("N.X"), "Fixed column N.X does not exist.");
+        assert_eq!(error("N.w"), "Fixed column N.w does not exist.");
+        assert_eq!(
+            error("N.ID"),
+            "Fixed column N.ID has no values that could be padded."
+        );
+        assert_eq!(
+            error("N.T"),
+            "Fixed column N.T has 5 values, but only 4 rows."
+        );
+    }
+
+    #[test]
+    fn fixed_data_from_functions() {
+        let src = r"
+namespace N(16);
+    col fixed ID(i) { i };
+    col fixed BYTE(i) { i & 0xf };
+    col witness w;
+    w = ID + BYTE;
+";
+        let analyzed = analyze_string::<GoldilocksField>(src);
+        let fixed_cols = constant_evaluator::generate(&analyzed);
+        let expanded = FixedData::new(&analyzed, 16, &fixed_cols, None);
+        // The columns are not given in source order.
+        let functions: [(String, Box<dyn FixedColumnFunction<GoldilocksField>>); 2] = [
+            (
+                "N.BYTE".to_string(),
+                Box::new(|row: DegreeType| GoldilocksField::from(row & 0xf)),
+            ),
+            (
+                "N.ID".to_string(),
+                Box::new(|row: DegreeType| GoldilocksField::from(row)),
+            ),
+        ];
+        let computed = FixedData::from_fixed_column_functions(&analyzed, 16, functions, None);
+        assert_eq!(computed.fixed_cols.len(), 2);
+        for (poly_id, column) in computed.fixed_cols.iter() {
+            assert_eq!(column.name, expanded.column_name(&poly_id));
+            assert!(column.pattern().is_none());
+            assert!(column.values().eq(expanded.fixed_cols[&poly_id].values()));
+        }
+    }
+
+    #[test]
+    fn bit_widths() {
+        let src = r"
+namespace N(256);
+    col fixed BYTE(i) { i & 0xff };
+    col witness b, x, y;
+    b * (1 - b) = 0;
+    { x } in { BYTE };
+";
+        let analyzed = analyze_string::<GoldilocksField>(src);
+        let fixed_cols = constant_evaluator::generate(&analyzed);
+        let query_callback = unused_query_callback();
+        let result = WitnessGenerator::new(&analyzed, &fixed_cols, &query_callback)
+            .with_external_witness_values(vec![("N.x".to_string(), values([7]))])
+            .generate_with_degree();
+        assert_eq!(
+            result
+                .columns
+                .iter()
+                .map(|(name, _)| name.as_str())
+                .collect::<Vec<_>>(),
+            ["N.b", "N.x", "N.y"]
+        );
+        assert_eq!(result.bit_widths, [Some(1), Some(8), None]);
+        assert_eq!(result.signed, [false, false, false]);
+    }
+
+    #[test]
+    fn public_columns() {
+        let src = r"
+namespace N(4);
+    col fixed LINE(i) { i };
+    col witness x, y;
+    x = LINE * 2;
+    y = x + 1;
+    public out = y(2);
+";
+        let analyzed = analyze_string::<GoldilocksField>(src);
+        let fixed_cols = constant_evaluator::generate(&analyzed);
+        let query_callback = unused_query_callback();
+        let result =
+            WitnessGenerator::new(&analyzed, &fixed_cols, &query_callback).generate_with_degree();
+        assert_eq!(result.columns, [("N.x".to_string(), values([0, 2, 4, 6]))]);
+        assert_eq!(result.publics, [("N.y".to_string(), values([1, 3, 5, 7]))]);
+        assert_eq!(result.public_values, [("out".to_string(), 5.into())]);
+        assert_eq!(result.bit_widths.len(), 1);
+    }
+
+    #[test]
+    fn signed_bit_widths() {
+        let src = r"
+namespace N(256);
+    col fixed SBYTE(i) { (i & 0xff) - 128 };
+    col witness s, t;
+    { s } in { SBYTE };
+    t = s;
+";
+        let analyzed = analyze_string::<GoldilocksField>(src);
+        let fixed_cols = constant_evaluator::generate(&analyzed);
+        let query_callback = unused_query_callback();
+        let s = vec![GoldilocksField::from(-5); 256];
+        let result = WitnessGenerator::new(&analyzed, &fixed_cols, &query_callback)
+            .with_external_witness_values(vec![("N.s".to_string(), s.clone())])
+            .generate_with_degree();
+        assert_eq!(result.columns[1].1, s);
+        assert_eq!(result.bit_widths, [Some(8), Some(8)]);
+        assert_eq!(result.signed, [true, true]);
+    }
+
+    #[test]
+    fn range_constraint() {
+        let src = r"
+namespace N(256);
+    col fixed BYTE(i) { i & 0xff };
+    col witness x, y, z;
+    x * (1 - x) = 0;
+    { y } in { BYTE };
+";
+        let analyzed = analyze_string::<GoldilocksField>(src);
+        let fixed_cols = constant_evaluator::generate(&analyzed);
+        let query_callback = unused_query_callback();
+        let generator = WitnessGenerator::new(&analyzed, &fixed_cols, &query_callback);
+        let poly_id = |name: &str| -> PolyID { (&analyzed.definitions[name].0).into() };
+        assert_eq!(
+            generator.range_constraint(poly_id("N.x")),
+            Some(RangeConstraint::from_max_bit(0))
+        );
+        assert_eq!(
+            generator.range_constraint(poly_id("N.y")),
+            Some(RangeConstraint::from_mask(0xffu64))
+        );
+        assert_eq!(generator.range_constraint(poly_id("N.z")), None);
+    }
+
+    #[test]
+    fn column_role() {
+        let src = r"
+namespace N(256);
+    col fixed BIT(i) { i & 1 };
+    col witness sel, x, y;
+    sel * (1 - sel) = 0;
+    { x } in { BIT };
+    y = sel * x;
+";
+        let analyzed = analyze_string::<GoldilocksField>(src);
+        let fixed_cols = constant_evaluator::generate(&analyzed);
+        let query_callback = unused_query_callback();
+        let generator = WitnessGenerator::new(&analyzed, &fixed_cols, &query_callback);
+        let poly_id = |name: &str| -> PolyID { (&analyzed.definitions[name].0).into() };
+        assert_eq!(
+            generator.column_role(poly_id("N.sel")),
+            ColumnRole::Selector
+        );
+        // Boolean, but not through a polynomial identity.
+        assert_eq!(generator.column_role(poly_id("N.x")), ColumnRole::Plain);
+        assert_eq!(generator.column_role(poly_id("N.y")), ColumnRole::Plain);
+        assert_eq!(generator.column_role(poly_id("N.BIT")), ColumnRole::Plain);
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn export_range_constraints_json() {
+        let src = r"
+namespace N(256);
+    col fixed BYTE(i) { i & 0xff };
+    col witness x, y, z;
+    x * (1 - x) = 0;
+    { y } in { BYTE };
+";
+        let analyzed = analyze_string::<GoldilocksField>(src);
+        let fixed_cols = constant_evaluator::generate(&analyzed);
+        let query_callback = unused_query_callback();
+        let generator = WitnessGenerator::new(&analyzed, &fixed_cols, &query_callback);
+        let json = generator.export_range_constraints_json();
+        let imported = import_range_constraints_json::<GoldilocksField>(&json).unwrap();
+        let names = imported.iter().map(|c| c.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, ["N.x", "N.y", "N.z", "N.BYTE"]);
+        for column in &imported {
+            let poly_id = (&analyzed.definitions[&column.name].0).into();
+            assert_eq!(column.constraint, generator.range_constraint(poly_id));
+        }
+        assert_eq!(imported[3].kind, ColumnKind::Fixed);
+    }
+
+    #[test]
+    fn range_constraint_from_comparison() {
+        // a < b is enforced by range checking b - a - 1.
+        let src = r"
+namespace N(256);
+    col fixed BYTE(i) { i & 0xff };
+    col fixed NIBBLE(i) { i & 0xf };
+    col witness a, b;
+    { a } in { BYTE };
+    { b } in { NIBBLE };
+    { b - a - 1 } in { BYTE };
+";
+        let analyzed = analyze_string::<GoldilocksField>(src);
+        let fixed_cols = constant_evaluator::generate(&analyzed);
+        let query_callback = unused_query_callback();
+        let generator = WitnessGenerator::new(&analyzed, &fixed_cols, &query_callback);
+        let poly_id = |name: &str| -> PolyID { (&analyzed.definitions[name].0).into() };
+        let a = generator.range_constraint(poly_id("N.a")).unwrap();
+        assert_eq!(a.range(), (0.into(), 14.into()));
+        assert_eq!(a.bit_width(), Some(4));
+        let b = generator.range_constraint(poly_id("N.b")).unwrap();
+        assert_eq!(b.range(), (1.into(), 15.into()));
+    }
+
+    #[test]
+    fn inferred_degree() {
+        let analyzed = analyze_string::<GoldilocksField>(ARRAY_SRC);
+        let fixed_cols = constant_evaluator::generate(&analyzed);
+        let query_callback = unused_query_callback();
+        let witness =
+            WitnessGenerator::with_inferred_degree(&analyzed, &fixed_cols, &query_callback)
+                .generate();
+        assert_eq!(witness, generate_witness(ARRAY_SRC));
+
+        // The degree declared in the PIL file is ignored.
+        let src = r"
+namespace N(16);
+    col fixed ID;
+    col witness x;
+    x = 2 * ID;
+";
+        let analyzed = analyze_string::<GoldilocksField>(src);
+        let fixed_cols = vec![("N.ID".to_string(), values(0..4))];
+        let generator =
+            WitnessGenerator::with_inferred_degree(&analyzed, &fixed_cols, &query_callback);
+        assert_eq!(analyzed.degree(), 16);
+        assert_eq!(generator.degree(), 4);
+        assert_eq!(
+            generator.generate(),
+            vec![("N.x".to_string(), values([0, 2, 4, 6]))]
+        );
+    }
+
+    #[test]
+    #[should_panic = "Fixed column N.T has length 3, which does not divide the degree 8."]
+    fn fixed_column_length_not_dividing_degree() {
+        let src = r"
+namespace N(8);
+    col fixed T;
+    col witness x;
+    x = T;
+";
+        let analyzed = analyze_string::<GoldilocksField>(src);
+        let fixed_cols = vec![("N.T".to_string(), values([1, 2, 3]))];
+        let query_callback = unused_query_callback();
+        WitnessGenerator::new(&analyzed, &fixed_cols, &query_callback).generate();
+    }
+
+    #[test]
+    #[should_panic = "Fixed column N.T has no values and is neither computed by a function nor part of a lookup table."]
+    fn fixed_column_without_values() {
+        let src = r"
+namespace N(8);
+    col fixed T;
+    col witness x;
+    x = T;
+";
+        let analyzed = analyze_string::<GoldilocksField>(src);
+        let fixed_cols = vec![("N.T".to_string(), vec![])];
+        let query_callback = unused_query_callback();
+        WitnessGenerator::new(&analyzed, &fixed_cols, &query_callback).generate();
+    }
+
+    #[test]
+    #[should_panic = "Fixed column A has length 4, but fixed column C has length 3."]
+    fn inferred_degree_mismatch() {
+        let fixed_cols = vec![
+            ("A".to_string(), values(0..4)),
+            ("B".to_string(), vec![]),
+            ("C".to_string(), values(0..3)),
+        ];
+        infer_degree(&fixed_cols);
+    }
+
+    #[test]
+    fn external_witness_integers_out_of_range() {
+        let analyzed = analyze_string::<GoldilocksField>(ARRAY_SRC);
+        let fixed_cols = constant_evaluator::generate(&analyzed);
+        let query_callback = unused_query_callback();
+        let modulus = GoldilocksField::modulus().to_arbitrary_integer();
+        let integers = vec![
+            BigUint::from(7u32),
+            modulus.clone() + 1u32,
+            BigUint::from(1u32),
+        ];
+        let err = WitnessGenerator::new(&analyzed, &fixed_cols, &query_callback)
+            .try_with_external_witness_integers(vec![("Arr.mem[0]".to_string(), integers)])
+            .err()
+            .unwrap();
+        assert_eq!(
+            err,
+            ExternalWitnessError::ValuesOutOfRange {
+                cells: vec![("Arr.mem[0]".to_string(), 1, modulus.clone() + 1u32)],
+                modulus,
+            }
+        );
+        assert!(err.to_string().contains("Arr.mem[0] in row 1"));
+    }
+
+    #[test]
+    fn external_witness_provider() {
+        let analyzed = analyze_string::<GoldilocksField>(ARRAY_SRC);
+        let fixed_cols = constant_evaluator::generate(&analyzed);
+        let query_callback = unused_query_callback();
+        let witness = WitnessGenerator::new(&analyzed, &fixed_cols, &query_callback)
+            .with_external_witness_provider(|name: &str, row| {
+                (name == "Arr.mem[1]").then(|| GoldilocksField::from(row + 2))
+            })
+            .generate();
+        assert_eq!(witness, generate_witness(ARRAY_SRC));
+    }
+
+    #[test]
+    fn independent_main_machines() {
+        let src = r"
+namespace main(8);
+    col fixed FIRST = [1] + [0]*;
+    col witness a, b;
+    FIRST * a = 0;
+    (1 - FIRST') * (a' - a - 1) = 0;
+    FIRST * (b - 10) = 0;
+    (1 - FIRST') * (b' - b - 2) = 0;
+";
+        let analyzed = analyze_string::<GoldilocksField>(src);
+        let fixed_cols = constant_evaluator::generate(&analyzed);
+        let query_callback = unused_query_callback();
+        let mut stats = GenerationStats::default();
+        let witness = WitnessGenerator::new(&analyzed, &fixed_cols, &query_callback)
+            .with_independent_main_machines()
+            .with_statistics(&mut stats)
+            .generate();
+        assert_eq!(
+            witness,
+            vec![
+                ("main.a".to_string(), values(0..8)),
+                ("main.b".to_string(), values((10..26).step_by(2))),
+            ]
+        );
+        assert_eq!(witness, generate_witness(src));
+        assert_eq!(
+            stats.rows_per_machine.keys().collect::<Vec<_>>(),
+            vec!["Main Machine", "Main Machine 2"]
+        );
+    }
+
+    #[test]
+    fn independent_main_machines_max_degree() {
+        // The second main machine runs longer than the first one and determines the degree.
+        let src = r"
+namespace N(1024);
+    col fixed FIRST, X, NEXT, Z, ZNEXT;
+    col witness x, y, z, w;
+    FIRST * x = 0;
+    { x, y } in { X, NEXT };
+    x' = (1 - FIRST') * y;
+    FIRST * z = 0;
+    { z, w } in { Z, ZNEXT };
+    z' = (1 - FIRST') * w;
+";
+        let analyzed = analyze_string::<GoldilocksField>(src);
+        let fixed_cols = vec![
+            ("N.FIRST".to_string(), vec![]),
+            ("N.X".to_string(), values([0, 1, 2, 3, 4, 5, 5, 5])),
+            ("N.NEXT".to_string(), values([1, 2, 3, 4, 5, 5, 5, 5])),
+            ("N.Z".to_string(), values(0..8)),
+            ("N.ZNEXT".to_string(), values([1, 2, 3, 4, 5, 6, 7, 7])),
+        ];
+        let first = |row: DegreeType| GoldilocksField::from(row == 0);
+        let query_callback = unused_query_callback();
+        let result = WitnessGenerator::new(&analyzed, &fixed_cols, &query_callback)
+            .with_fixed_column_function("N.FIRST", &first)
+            .with_padding_strategy(PaddingStrategy::RepeatLastRow)
+            .with_max_degree(1024)
+            .with_independent_main_machines()
+            .generate_with_degree();
+        assert_eq!(result.used_rows, 8);
+        assert_eq!(result.degree, 16);
+        let column = |name: &str| {
+            result
+                .columns
+                .iter()
+                .find(|(n, _)| n == name)
+                .map(|(_, values)| values.clone())
+                .unwrap()
+        };
+        assert_eq!(
+            column("N.x"),
+            values([0, 1, 2, 3, 4, 5].into_iter().chain([5; 10]))
+        );
+        assert_eq!(column("N.z"), values((0..8).chain([7; 8])));
+    }
+
+    #[test]
+    fn concurrent_main_machines() {
+        // The main machines call into different machines, so they are solved concurrently.
+        let src = r"
+namespace main(8);
+    col fixed FIRST = [1] + [0]*;
+    col fixed CALL = [1, 0]*;
+    col witness a, b, c, d;
+    FIRST * a = 0;
+    (1 - FIRST') * (a' - a - 1) = 0;
+    CALL { a, b } in SqA.LATCH { SqA.x, SqA.y };
+    FIRST * (c - 10) = 0;
+    (1 - FIRST') * (c' - c - 2) = 0;
+    CALL { c, d } in SqB.LATCH { SqB.x, SqB.y };
+namespace SqA(8);
+    col fixed LATCH = [0, 1]*;
+    col witness x, y;
+    y = x * x;
+namespace SqB(8);
+    col fixed LATCH = [0, 1]*;
+    col witness x, y;
+    y = x * x;
+";
+        let analyzed = analyze_string::<GoldilocksField>(src);
+        let fixed_cols = constant_evaluator::generate(&analyzed);
+        let query_callback = unused_query_callback();
+        let mut stats = GenerationStats::default();
+        let concurrent = WitnessGenerator::new(&analyzed, &fixed_cols, &query_callback)
+            .with_independent_main_machines()
+            .with_statistics(&mut stats)
+            .generate();
+        assert_eq!(concurrent, generate_witness(src));
+
+        // With a machine witness callback, the main machines are solved one after the other.
+        let mut released = vec![];
+        let mut callback = |_: &str, columns: HashMap<String, Vec<GoldilocksField>>| {
+            released.extend(columns);
+        };
+        let mut sequential_stats = GenerationStats::default();
+        let mut sequential = WitnessGenerator::new(&analyzed, &fixed_cols, &query_callback)
+            .with_independent_main_machines()
+            .with_machine_witness_callback(&mut callback)
+            .with_statistics(&mut sequential_stats)
+            .generate();
+        sequential.extend(released);
+        sequential.sort();
+        let mut concurrent = concurrent;
+        concurrent.sort();
+        assert_eq!(concurrent, sequential);
+        assert_eq!(stats.rows_per_machine, sequential_stats.rows_per_machine);
+        assert_eq!(
+            stats.rows_per_machine.keys().collect::<Vec<_>>(),
+            vec!["Main Machine", "Main Machine 2"]
+        );
+    }
+
+    #[test]
+    fn single_row() {
+        // The next row is the row itself, so x = y.
+        let witness = generate_witness(
+            r"
+namespace N(1);
+    col witness x, y;
+    y = 5;
+    x = y';
+",
+        );
+        assert_eq!(
+            witness,
+            [
+                ("N.x".to_string(), values([5])),
+                ("N.y".to_string(), values([5]))
+            ]
+        );
+    }
+
+    #[test]
+    fn no_witness_columns() {
+        let analyzed = analyze_string::<GoldilocksField>("");
+        let query_callback = unused_query_callback();
+        let witness = WitnessGenerator::new(&analyzed, &[], &query_callback).generate();
+        assert!(witness.is_empty());
+
+        let analyzed = analyze_string::<GoldilocksField>(
+            r"
+namespace N(4);
+    col fixed A(i) { i + 1 };
+    col fixed B = [1, 2, 3, 4];
+    A = B;
+",
+        );
+        let fixed_cols = constant_evaluator::generate(&analyzed);
+        let witness = WitnessGenerator::new(&analyzed, &fixed_cols, &query_callback).generate();
+        assert!(witness.is_empty());
+    }
+
+    #[test]
+    fn row_observer() {
+        let analyzed = analyze_string::<GoldilocksField>(ARRAY_SRC);
+        let fixed_cols = constant_evaluator::generate(&analyzed);
+        let query_callback = unused_query_callback();
+        let mut rows = vec![];
+        let mut observer = |row: DegreeType, values: &WitnessColumnMap<Option<GoldilocksField>>| {
+            rows.push((row, values.values().map(|v| v.unwrap()).collect::<Vec<_>>()));
+        };
+        let witness = WitnessGenerator::new(&analyzed, &fixed_cols, &query_callback)
+            .with_row_observer(&mut observer)
+            .generate();
+        let expected = (0..8)
+            .map(|row| {
+                (
+                    row as DegreeType,
+                    vec![witness[0].1[row], witness[1].1[row]],
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(rows, expected);
+    }
+
+    #[test]
+    fn cell_sender() {
+        let analyzed = analyze_string::<GoldilocksField>(ARRAY_SRC);
+        let fixed_cols = constant_evaluator::generate(&analyzed);
+        let query_callback = unused_query_callback();
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let consumer = std::thread::spawn(move || receiver.iter().collect::<Vec<_>>());
+        let witness = WitnessGenerator::new(&analyzed, &fixed_cols, &query_callback)
+            .with_cell_sender(sender)
+            .generate();
+        let cells = consumer.join().unwrap();
+        assert_eq!(cells.len(), 2 * 8);
+        for (poly_id, row, value) in cells {
+            assert_eq!(witness[poly_id.id as usize].1[row as usize], value);
+        }
+    }
+
+    #[test]
+    fn intermediate_column_name() {
+        let src = r"
+namespace N(4);
+    col witness x;
+    col inter = x + 1;
+    let inters: expr[2] = [x, inter];
+    inter = 1;
+";
+        let analyzed = analyze_string::<GoldilocksField>(src);
+        let fixed = FixedData::new(&analyzed, 4, &[], None);
+        let names = analyzed
+            .intermediate_polys_in_source_order()
+            .into_iter()
+            .flat_map(|(symbol, _)| symbol.array_elements())
+            .map(|(_, poly_id)| fixed.column_name(&poly_id))
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["N.inter", "N.inters[0]", "N.inters[1]"]);
+    }
+
+    #[test]
+    fn generate_per_machine() {
+        let src = r"
+namespace Perm(8);
+    col fixed SQUARES(i) { i * i };
+    col witness a, b;
+    a = SQUARES;
+    { a } is { b };
+";
+        let analyzed = analyze_string::<GoldilocksField>(src);
+        let fixed_cols = constant_evaluator::generate(&analyzed);
+        let query_callback = unused_query_callback();
+        let per_machine =
+            WitnessGenerator::new(&analyzed, &fixed_cols, &query_callback).generate_per_machine();
+        let squares = values((0..8).map(|i| i * i));
+        assert_eq!(
+            per_machine,
+            BTreeMap::from([
+                (
+                    "Main Machine".to_string(),
+                    vec![("Perm.a".to_string(), squares.clone())]
+                ),
+                (
+                    "Secondary machine 0: Perm (PermutationMachine)".to_string(),
+                    vec![("Perm.b".to_string(), squares)]
+                ),
+            ])
+        );
+    }
+
+    #[test]
+    fn generate_columns() {
+        let src = r"
+namespace Perm(8);
+    col fixed SQUARES(i) { i * i };
+    col witness a, b, c;
+    a = SQUARES;
+    c = a + 1;
+    { a } is { b };
+";
+        let analyzed = analyze_string::<GoldilocksField>(src);
+        let fixed_cols = constant_evaluator::generate(&analyzed);
+        let query_callback = unused_query_callback();
+        let full = generate_witness(src);
+        let columns = WitnessGenerator::new(&analyzed, &fixed_cols, &query_callback)
+            .generate_columns(&["Perm.c", "Perm.b"]);
+        assert_eq!(columns, vec![full[2].clone(), full[1].clone()]);
+    }
+
+    #[test]
+    fn machine_graph() {
+        let src = r"
+namespace Perm(8);
+    col fixed SQUARES(i) { i * i };
+    col fixed X(i) { i };
+    col witness a, b, c;
+    a = SQUARES;
+    { a } is { b };
+    { c, a } in { X, SQUARES };
+";
+        let analyzed = analyze_string::<GoldilocksField>(src);
+        let fixed_cols = constant_evaluator::generate(&analyzed);
+        let query_callback = unused_query_callback();
+        let graph = WitnessGenerator::new(&analyzed, &fixed_cols, &query_callback).machine_graph();
+        let secondary = "Secondary machine 0: Perm (PermutationMachine)";
+        let nodes = graph
+            .machines
+            .iter()
+            .map(|m| {
+                (
+                    m.name.as_str(),
+                    m.witness_columns.clone(),
+                    m.fixed_columns.clone(),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            nodes,
+            vec![
+                (
+                    "Main Machine",
+                    vec!["Perm.a".to_string(), "Perm.c".to_string()],
+                    vec!["Perm.SQUARES".to_string()]
+                ),
+                (secondary, vec!["Perm.b".to_string()], vec![]),
+                (
+                    FIXED_LOOKUP_NODE,
+                    vec![],
+                    vec!["Perm.SQUARES".to_string(), "Perm.X".to_string()]
+                ),
+            ]
+        );
+        assert_eq!(graph.machines[0].identities.len(), 3);
+        assert!(graph.machines[1].identities.is_empty());
+        let mut edges = graph
+            .edges
+            .iter()
+            .map(|e| (e.from.as_str(), e.to.as_str(), e.identity.as_str()))
+            .collect::<Vec<_>>();
+        edges.sort();
+        assert_eq!(
+            edges,
+            vec![
+                (
+                    "Main Machine",
+                    FIXED_LOOKUP_NODE,
+                    "{ Perm.c, Perm.a } in { Perm.X, Perm.SQUARES };"
+                ),
+                ("Main Machine", secondary, "{ Perm.a } is { Perm.b };"),
+            ]
+        );
+        assert!(graph
+            .to_dot()
+            .contains(&format!("\"Main Machine\" -> \"{secondary}\"")));
+    }
+
+    #[test]
+    fn interleaved_columns() {
+        let src = r"
+namespace N(4);
+    col fixed X(i) { i };
+    col witness a, b, c;
+    a = X;
+    b = X + 10;
+    c = 7;
+";
+        let analyzed = analyze_string::<GoldilocksField>(src);
+        let fixed_cols = constant_evaluator::generate(&analyzed);
+        let query_callback = unused_query_callback();
+        assert_eq!(
+            WitnessGenerator::new(&analyzed, &fixed_cols, &query_callback)
+                .with_interleaved_columns("N.ab", &["N.a", "N.b"])
+                .generate(),
+            vec![
+                ("N.ab".to_string(), values([0, 10, 1, 11, 2, 12, 3, 13])),
+                ("N.c".to_string(), values([7; 4])),
+            ]
+        );
+        let error = |name: &str, columns: &[&str]| {
+            WitnessGenerator::new(&analyzed, &fixed_cols, &query_callback)
+                .try_with_interleaved_columns(name, columns)
+                .err()
+                .unwrap()
+        };
+        assert_eq!(
+            error("N.ab", &["N.a", "N.d"]),
+            "Witness column N.d does not exist."
+        );
+        assert_eq!(error("N.c", &["N.a", "N.b"]), "Column N.c already exists.");
+        assert_eq!(error("N.ab", &[]), "No columns to interleave into N.ab.");
+    }
+
+    #[test]
+    fn machine_path_of_nested_error() {
+        let src = r"
+namespace Main(8);
+    col fixed CALL = [1, 0]*;
+    col fixed A(i) { 3 };
+    col fixed B(i) { 10 };
+    col witness a, b;
+    a = A;
+    b = B;
+    CALL { a, b } in Mem.LATCH { Mem.x, Mem.y };
+namespace Mem(8);
+    col fixed LATCH = [0, 1]*;
+    col witness x, y;
+    LATCH { x, y } in Sq.LATCH { Sq.p, Sq.q };
+namespace Sq(8);
+    col fixed LATCH = [0, 1]*;
+    col witness p, q;
+    q = p * p;
+";
+        let analyzed = analyze_string::<GoldilocksField>(src);
+        let fixed_cols = constant_evaluator::generate(&analyzed);
+        let query_callback = unused_query_callback();
+        let err = WitnessGenerator::new(&analyzed, &fixed_cols, &query_callback)
+            .try_generate()
+            .unwrap_err();
+        let path = err.machine_path();
+        assert_eq!(path.len(), 2, "{err}");
+        assert!(path[0].contains(": Mem ("), "{err}");
+        assert!(path[1].contains(": Sq ("), "{err}");
+        assert!(err.to_string().contains(&path.join(" -> ")), "{err}");
+    }
+
+    #[test]
+    fn extraction_report() {
+        let src = r"
+namespace Main(8);
+    col fixed CALL = [1, 0]*;
+    col witness a, b;
+    CALL { a, b } in Sq.LATCH { Sq.x, Sq.y };
+namespace Sq(8);
+    col fixed LATCH = [0, 1]*;
+    col witness x, y, z;
+    y = x * z;
+    LATCH { x, z } in Id.LATCH { Id.p, Id.q };
+namespace Id(8);
+    col fixed LATCH = [0, 1]*;
+    col witness p, q;
+    q = p;
+";
+        let analyzed = analyze_string::<GoldilocksField>(src);
+        let fixed_cols = constant_evaluator::generate(&analyzed);
+        let query_callback = unused_query_callback();
+        let report =
+            WitnessGenerator::new(&analyzed, &fixed_cols, &query_callback).extraction_report();
+        let counts = report
+            .machines
+            .iter()
+            .map(|m| (m.witness_count, m.identity_count, m.lookup_count))
+            .collect::<Vec<_>>();
+        assert_eq!(counts, vec![(2, 1, 1), (3, 2, 1), (2, 1, 0)]);
+        assert_eq!(report.machines[0].name, "Main Machine");
+        assert_eq!(report.identity_count, 4);
+        assert_eq!(report.lookup_count, 2);
+        assert_eq!(report.longest_call_chain, 3);
+        assert_eq!(report.base_identities.len(), 1);
+        assert!(report.base_identities[0].contains("Sq.LATCH"));
+    }
+
+    #[test]
+    fn machine_witness_callback() {
+        let src = r"
+namespace Main(8);
+    col fixed CALL = [1, 0]*;
+    col witness a, b, c;
+    a = 3;
+    CALL { a, b } in Sq.LATCH { Sq.x, Sq.y };
+    CALL { a, c } in Cube.LATCH { Cube.x, Cube.y };
+namespace Sq(8);
+    col fixed LATCH = [0, 1]*;
+    col witness x, y;
+    y = x * x;
+namespace Cube(8);
+    col fixed LATCH = [0, 1]*;
+    col witness x, y, z;
+    z = x * x;
+    y = z * x;
+";
+        let analyzed = analyze_string::<GoldilocksField>(src);
+        let fixed_cols = constant_evaluator::generate(&analyzed);
+        let query_callback = unused_query_callback();
+        let mut full_stats = GenerationStats::default();
+        let full = WitnessGenerator::new(&analyzed, &fixed_cols, &query_callback)
+            .with_statistics(&mut full_stats)
+            .generate();
+
+        let mut released = vec![];
+        let mut callback = |_: &str, columns: HashMap<String, Vec<GoldilocksField>>| {
+            let mut columns = columns.into_iter().collect::<Vec<_>>();
+            columns.sort();
+            released.push(columns);
+        };
+        let mut stats = GenerationStats::default();
+        let witness = WitnessGenerator::new(&analyzed, &fixed_cols, &query_callback)
+            .with_machine_witness_callback(&mut callback)
+            .with_statistics(&mut stats)
+            .generate();
+        released.sort();
+
+        assert_eq!(witness, full[..3]);
+        let full_columns = |names: &[&str]| {
+            names
+                .iter()
+                .map(|name| full.iter().find(|(n, _)| n == name).unwrap().clone())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            released,
+            [
+                full_columns(&["Cube.x", "Cube.y", "Cube.z"]),
+                full_columns(&["Sq.x", "Sq.y"])
+            ]
+        );
+        // Only the columns of one machine are held at a time.
+        assert_eq!(full_stats.peak_machine_cells, 5 * 8);
+        assert_eq!(stats.peak_machine_cells, 3 * 8);
+    }
+
+    #[test]
+    fn generate_into() {
+        let analyzed = analyze_string::<GoldilocksField>(ARRAY_SRC);
+        let fixed_cols = constant_evaluator::generate(&analyzed);
+        let query_callback = unused_query_callback();
+        let witness = WitnessGenerator::new(&analyzed, &fixed_cols, &query_callback)
+            .generate_into::<Bn254Field>()
+            .unwrap();
+        assert_eq!(witness[1].1[3], Bn254Field::from(5));
+        let round_trip = convert_witness::<_, GoldilocksField>(witness).unwrap();
+        assert_eq!(round_trip, generate_witness(ARRAY_SRC));
+    }
+
+    #[test]
+    fn convert_witness_out_of_range() {
+        let witness = vec![("x".to_string(), vec![Bn254Field::from(-1)])];
+        let err = convert_witness::<_, GoldilocksField>(witness).unwrap_err();
+        assert!(err.contains("of column x in row 0 does not fit"), "{err}");
+    }
+
+    #[test]
+    fn identity_priorities() {
+        // Each identity can only be solved once the one after it is solved.
+        let src = r"
+namespace N(8);
+    col witness a, b, c;
+    c = b + 1;
+    b = a + 1;
+    a = 1;
+";
+        let analyzed = analyze_string::<GoldilocksField>(src);
+        let fixed_cols = constant_evaluator::generate(&analyzed);
+        let query_callback = unused_query_callback();
+        let evaluations = |priorities: Vec<(u64, i32)>| {
+            let mut stats = GenerationStats::default();
+            let witness = WitnessGenerator::new(&analyzed, &fixed_cols, &query_callback)
+                .with_identity_priorities(priorities)
+                .with_statistics(&mut stats)
+                .generate();
+            assert_eq!(
+                witness,
+                vec![
+                    ("N.a".to_string(), values([1; 8])),
+                    ("N.b".to_string(), values([2; 8])),
+                    ("N.c".to_string(), values([3; 8])),
+                ]
+            );
+            stats.identity_evaluations
+        };
+        let before = evaluations(vec![]);
+        let after = evaluations(vec![(2, 2), (1, 1)]);
+        assert!(
+            after < before,
+            "{after} evaluations with priorities, {before} without"
+        );
+    }
+
+    #[test]
+    fn estimate_memory() {
+        let analyzed = analyze_string::<GoldilocksField>(ARRAY_SRC);
+        let fixed_cols = constant_evaluator::generate(&analyzed);
+        let query_callback = unused_query_callback();
+        let generator = WitnessGenerator::new(&analyzed, &fixed_cols, &query_callback);
+        let estimate = generator.estimate_memory();
+        let witness = generator.generate();
+        let size = std::mem::size_of::<GoldilocksField>();
+        let actual = witness
+            .iter()
+            .chain(&fixed_cols)
+            .map(|(_, values)| values.len() * size)
+            .sum::<usize>();
+        assert!(
+            actual <= estimate && estimate <= 2 * actual,
+            "estimated {estimate} bytes, actual {actual} bytes"
+        );
+    }
+
+    #[test]
+    fn identity_assignments() {
+        let src = r"
+namespace Main(8);
+    col fixed CALL = [1, 0]*;
+    col witness a, b;
+    CALL { a, b } in Sq.LATCH { Sq.x, Sq.y };
+namespace Sq(8);
+    col fixed LATCH = [0, 1]*;
+    col witness x, y;
+    y = x * x;
+";
+        let analyzed = analyze_string::<GoldilocksField>(src);
+        let fixed_cols = constant_evaluator::generate(&analyzed);
+        let query_callback = unused_query_callback();
+        let square = analyzed
+            .identities
+            .iter()
+            .find(|identity| identity.kind == IdentityKind::Polynomial)
+            .unwrap()
+            .id;
+        let identities = |assignments: Vec<(u64, String)>| {
+            let graph = WitnessGenerator::new(&analyzed, &fixed_cols, &query_callback)
+                .with_identity_assignments(assignments)
+                .machine_graph();
+            let identities_of = |prefix: &str| {
+                graph
+                    .machines
+                    .iter()
+                    .find(|m| m.name.starts_with(prefix))
+                    .unwrap()
+                    .identities
+                    .len()
+            };
+            (
+                identities_of("Main Machine"),
+                identities_of("Secondary machine 0: Sq"),
+            )
+        };
+        assert_eq!(identities(vec![]), (1, 1));
+        assert_eq!(identities(vec![(square, "Main".to_string())]), (2, 0));
+    }
+
+    #[test]
+    fn generate_partial() {
+        let src = r"
+namespace N(4);
+    col fixed ISTWO(i) { match i {
+        2 => 1,
+        _ => 0,
+    } };
+    col witness x;
+    x = 1;
+    ISTWO * (x - 2) = 0;
+";
+        let analyzed = analyze_string::<GoldilocksField>(src);
+        let fixed_cols = constant_evaluator::generate(&analyzed);
+        let query_callback = unused_query_callback();
+        let (columns, error) =
+            WitnessGenerator::new(&analyzed, &fixed_cols, &query_callback).generate_partial();
+        assert!(matches!(error, Some(EvalError::RowFailed { row: 2, .. })));
+        let one = Some(GoldilocksField::from(1));
+        assert_eq!(
+            columns,
+            vec![("N.x".to_string(), vec![one, one, None, None])]
+        );
+    }
+
+    /// Computes `y = x * x` for calls `{ x, y } in { Sq.x, Sq.y }`.
+    struct SquareMachine<'a> {
+        parts: MachineParts<'a, GoldilocksField>,
+        calls: Vec<GoldilocksField>,
+    }
+
+    impl<'a> CustomMachine<'a, GoldilocksField> for SquareMachine<'a> {
+        fn name(&self) -> &str {
+            &self.parts.name
+        }
+
+        fn process_plookup(
+            &mut self,
+            kind: IdentityKind,
+            left: &[AffineExpression<&'a AlgebraicReference, GoldilocksField>],
+            right: &'a SelectedExpressions<AlgebraicExpression<GoldilocksField>>,
+        ) -> Option<EvalResult<'a, GoldilocksField>> {
+            if kind != IdentityKind::Plookup
+                || !self
+                    .parts
+                    .connecting_identities
+                    .iter()
+                    .any(|i| &i.right == right)
+            {
+                return None;
+            }
+            let [x, y] = left else {
+                return Some(Err("Expected two arguments.".to_string().into()));
+            };
+            let Some(x) = x.constant_value() else {
+                return Some(Ok(EvalValue::incomplete(
+                    IncompleteCause::NonConstantRequiredArgument("x"),
+                )));
+            };
+            self.calls.push(x);
+            Some((y.clone() - (x * x).into()).solve())
+        }
+
+        fn take_witness_col_values(&mut self) -> HashMap<String, Vec<GoldilocksField>> {
+            let mut x = std::mem::take(&mut self.calls);
+            x.resize(self.parts.fixed_data.degree() as usize, 0.into());
+            let y = x.iter().map(|x| *x * *x).collect();
+            [("Sq.x".to_string(), x), ("Sq.y".to_string(), y)]
+                .into_iter()
+                .collect()
+        }
+    }
+
+    struct SquareMachineFactory;
+
+    impl CustomMachineFactory<GoldilocksField> for SquareMachineFactory {
+        fn create<'a>(
+            &self,
+            parts: MachineParts<'a, GoldilocksField>,
+        ) -> Box<dyn CustomMachine<'a, GoldilocksField> + 'a> {
+            Box::new(SquareMachine {
+                parts,
+                calls: vec![],
+            })
+        }
+    }
+
+    #[test]
+    fn custom_machine() {
+        let src = r"
+namespace Sq(4);
+    col witness x, y;
+    y = x * x;
+namespace main(4);
+    col fixed ROW(i) { i };
+    col witness a, b;
+    a = ROW + 1;
+    { a, b } in { Sq.x, Sq.y };
+";
+        let analyzed = analyze_string::<GoldilocksField>(src);
+        let fixed_cols = constant_evaluator::generate(&analyzed);
+        let query_callback = unused_query_callback();
+        let witness = WitnessGenerator::new(&analyzed, &fixed_cols, &query_callback)
+            .with_custom_machine("Sq", &SquareMachineFactory)
+            .generate();
+        let column = |values: [u64; 4]| values.into_iter().map(GoldilocksField::from).collect();
+        assert_eq!(
+            witness,
+            vec![
+                ("Sq.x".to_string(), column([1, 2, 3, 4])),
+                ("Sq.y".to_string(), column([1, 4, 9, 16])),
+                ("main.a".to_string(), column([1, 2, 3, 4])),
+                ("main.b".to_string(), column([1, 4, 9, 16])),
+            ]
+        );
+    }
+
+    #[test]
+    fn max_rows_without_progress() {
+        let src = r"
+namespace N(8);
+    col witness x, y;
+    x * y = 0;
+";
+        let analyzed = analyze_string::<GoldilocksField>(src);
+        let fixed_cols = constant_evaluator::generate(&analyzed);
+        let query_callback = unused_query_callback();
+        let err = WitnessGenerator::new(&analyzed, &fixed_cols, &query_callback)
+            .with_max_rows_without_progress(2)
+            .try_generate()
+            .unwrap_err();
+        assert!(matches!(err, EvalError::Timeout { row: 2, .. }), "{err}");
+    }
+
+    #[test]
+    fn timeout() {
+        let src = r"
+namespace N(8);
+    col witness x, y;
+    x * y = 0;
+";
+        let analyzed = analyze_string::<GoldilocksField>(src);
+        let fixed_cols = constant_evaluator::generate(&analyzed);
+        let query_callback = unused_query_callback();
+        let err = WitnessGenerator::new(&analyzed, &fixed_cols, &query_callback)
+            .with_timeout(Duration::ZERO)
+            .validate_only()
+            .unwrap_err();
+        assert!(matches!(err, EvalError::Timeout { row: 0, .. }), "{err}");
+    }
+
+    #[test]
+    fn cancellation() {
+        let src = r"
+namespace N(1024);
+    col fixed FIRST = [1] + [0]*;
+    col witness x;
+    FIRST * x = 0;
+    (1 - FIRST') * (x' - x - 1) = 0;
+";
+        let analyzed = analyze_string::<GoldilocksField>(src);
+        let fixed_cols = constant_evaluator::generate(&analyzed);
+        let query_callback = unused_query_callback();
+        let cancelled = AtomicBool::new(false);
+        // Cancel while the main machine is running.
+        let mut observer = |row: DegreeType, _: &WitnessColumnMap<Option<GoldilocksField>>| {
+            if row == 10 {
+                cancelled.store(true, std::sync::atomic::Ordering::Relaxed);
+            }
+        };
+        let (witness, err) = WitnessGenerator::new(&analyzed, &fixed_cols, &query_callback)
+            .with_cancellation(&cancelled)
+            .with_row_observer(&mut observer)
+            .generate_partial();
+        let err = err.unwrap();
+        assert!(matches!(err, EvalError::Cancelled { row: 256 }), "{err}");
+        let x = &witness[0].1;
+        assert_eq!(x[255], Some(255.into()));
+        assert!(x[300].is_none());
+
+        // The generator can be used again once the flag is reset.
+        cancelled.store(false, std::sync::atomic::Ordering::Relaxed);
+        let witness = WitnessGenerator::new(&analyzed, &fixed_cols, &query_callback)
+            .with_cancellation(&cancelled)
+            .try_generate()
+            .unwrap();
+        assert_eq!(witness[0].1[1023], 1023.into());
+    }
+
+    #[test]
+    fn selected_fixed_lookup() {
+        // Without the selector on the right, the lookup would be ambiguous for all inputs.
+        let src = r"
+namespace N(8);
+    col fixed X = [0, 1, 2, 3, 0, 1, 2, 3];
+    col fixed Y = [0, 1, 4, 9, 7, 7, 7, 7];
+    col fixed SEL = [1, 1, 1, 1, 0, 0, 0, 0];
+    col fixed ODD = [0, 1, 0, 1, 0, 1, 0, 1];
+    col fixed INPUT = [0, 1, 2, 3, 0, 1, 2, 3];
+    col witness s, a, b;
+    s = ODD;
+    a = INPUT;
+    s { a, b } in SEL { X, Y };
+";
+        let witness = generate_witness(src);
+        let b = &witness.iter().find(|(name, _)| name == "N.b").unwrap().1;
+        assert_eq!(b, &values([0, 1, 0, 9, 0, 1, 0, 9]));
+    }
+
+    #[test]
+    fn sparse_external_witness() {
+        let src = r"
+namespace N(8);
+    col fixed LAST = [0, 0, 0, 0, 0, 0, 0, 1];
+    col witness x, y;
+    (1 - LAST) * (x' - x - 1) = 0;
+    y = 2 * x;
+";
+        let analyzed = analyze_string::<GoldilocksField>(src);
+        let fixed_cols = constant_evaluator::generate(&analyzed);
+        let query_callback = unused_query_callback();
+        let pinned = |values: &[(DegreeType, u64)]| {
+            values
+                .iter()
+                .map(|(row, value)| (*row, GoldilocksField::from(*value)))
+                .collect::<BTreeMap<_, _>>()
+        };
+        let witness = WitnessGenerator::new(&analyzed, &fixed_cols, &query_callback)
+            .with_sparse_external_witness_values(vec![
+                ("N.x".to_string(), pinned(&[(0, 3), (7, 10)])),
+                ("N.y".to_string(), pinned(&[(4, 14)])),
+            ])
+            .generate();
+        assert_eq!(
+            witness[0],
+            ("N.x".to_string(), values([3, 4, 5, 6, 7, 8, 9, 10]))
+        );
+        assert_eq!(
+            witness[1],
+            ("N.y".to_string(), values([6, 8, 10, 12, 14, 16, 18, 20]))
+        );
+
+        let err = WitnessGenerator::new(&analyzed, &fixed_cols, &query_callback)
+            .try_with_sparse_external_witness_values(vec![("N.x".to_string(), pinned(&[(8, 0)]))])
+            .err()
+            .unwrap();
+        assert_eq!(
+            err,
+            ExternalWitnessError::RowOutOfRange {
+                column: "N.x".to_string(),
+                row: 8,
+                degree: 8
+            }
+        );
+    }
+
+    #[test]
+    fn prebuilt_identities() {
+        let src = r"
+namespace N(4);
+    col fixed X = [1, 2, 3, 4];
+    col witness x, y;
+    x = X;
+    y = x + 1;
+";
+        // Declares the same columns, so the identities reference the same columns.
+        let other_src = r"
+namespace N(4);
+    col fixed X = [1, 2, 3, 4];
+    col witness x, y;
+    x = X;
+    y = 2 * x;
+";
+        let analyzed = analyze_string::<GoldilocksField>(src);
+        let identities = analyze_string::<GoldilocksField>(other_src)
+            .identities_with_inlined_intermediate_polynomials();
+        let fixed_cols = constant_evaluator::generate(&analyzed);
+        let query_callback = unused_query_callback();
+        let witness = WitnessGenerator::new(&analyzed, &fixed_cols, &query_callback)
+            .with_identities(identities)
+            .generate();
+        assert_eq!(
+            witness,
+            vec![
+                ("N.x".to_string(), values([1, 2, 3, 4])),
+                ("N.y".to_string(), values([2, 4, 6, 8])),
+            ]
+        );
+    }
+
+    #[test]
+    fn conditional_constraints() {
+        let src = r"
+namespace N(4);
+    col fixed SEL = [1, 0, 1, 0];
+    col fixed B = [7, 8, 9, 10];
+    col witness a, b;
+    b = B;
+    SEL * (a - b) = 0;
+    (1 - SEL) * (a - 2 * b) = 0;
+";
+        let witness = generate_witness(src);
+        assert_eq!(
+            witness,
+            vec![
+                ("N.a".to_string(), values([7, 16, 9, 20])),
+                ("N.b".to_string(), values([7, 8, 9, 10])),
+            ]
+        );
+    }
+
+    #[test]
+    fn padding_strategy() {
+        // `x` counts up to 5 and then stays there, so the rows from row 6 on are padding.
+        let src = r"
+namespace N(16);
+    col fixed FIRST = [1] + [0]*;
+    col fixed X = [0, 1, 2, 3, 4] + [5]*;
+    col fixed NEXT = [1, 2, 3, 4, 5] + [5]*;
+    col witness x, y;
+    FIRST * x = 0;
+    { x, y } in { X, NEXT };
+    x' = (1 - FIRST') * y;
+";
+        let analyzed = analyze_string::<GoldilocksField>(src);
+        let fixed_cols = constant_evaluator::generate(&analyzed);
+        let query_callback = unused_query_callback();
+        let witness = WitnessGenerator::new(&analyzed, &fixed_cols, &query_callback)
+            .with_padding_strategy(PaddingStrategy::RepeatLastRow)
+            .generate();
+        assert_eq!(
+            witness,
+            WitnessGenerator::new(&analyzed, &fixed_cols, &query_callback).generate()
+        );
+        let columns = fixed_cols
+            .iter()
+            .cloned()
+            .chain(witness)
+            .collect::<Vec<_>>();
+        assert_eq!(verify_witness(&analyzed, &columns, 16), Ok(()));
+
+        // Zero rows do not satisfy the identities after the last row of the program.
+        let result = WitnessGenerator::new(&analyzed, &fixed_cols, &query_callback)
+            .with_padding_strategy(PaddingStrategy::Zero)
+            .try_generate();
+        let Err(EvalError::RowFailed { row, .. }) = result else {
+            panic!("Expected the padding to fail.");
+        };
+        assert_eq!(row, 6);
+    }
+
+    #[test]
+    fn max_degree() {
+        // The program of `padding_strategy`, which ends after 6 rows of at most 1024.
+        let src = r"
+namespace N(1024);
+    col fixed FIRST, X, NEXT;
+    col witness x, y;
+    FIRST * x = 0;
+    { x, y } in { X, NEXT };
+    x' = (1 - FIRST') * y;
+";
+        let analyzed = analyze_string::<GoldilocksField>(src);
+        let fixed_cols = vec![
+            ("N.FIRST".to_string(), vec![]),
+            ("N.X".to_string(), values([0, 1, 2, 3, 4, 5, 5, 5])),
+            ("N.NEXT".to_string(), values([1, 2, 3, 4, 5, 5, 5, 5])),
+        ];
+        let first = |row: DegreeType| GoldilocksField::from(row == 0);
+        let query_callback = unused_query_callback();
+        let result = WitnessGenerator::new(&analyzed, &fixed_cols, &query_callback)
+            .with_fixed_column_function("N.FIRST", &first)
+            .with_padding_strategy(PaddingStrategy::RepeatLastRow)
+            .with_max_degree(1024)
+            .generate_with_degree();
+        assert_eq!(result.used_rows, 6);
+        assert_eq!(result.degree, 8);
+        assert_eq!(result.columns[0].1, values([0, 1, 2, 3, 4, 5, 5, 5]));
+
+        // The program does not end within 4 rows.
+        let result = WitnessGenerator::new(&analyzed, &fixed_cols, &query_callback)
+            .with_fixed_column_function("N.FIRST", &first)
+            .with_padding_strategy(PaddingStrategy::RepeatLastRow)
+            .with_max_degree(4)
+            .validate_only();
+        assert_eq!(result, Err(EvalError::RowsExhausted));
+    }
+
+    #[test]
+    fn queries_depending_on_witness_cells() {
+        // `z` depends on `x` and `y`, which are only known after their queries are answered.
+        let src = r#"
+namespace N(4);
+    col witness z(i) query ("add", x(i), y(i));
+    col witness y(i) query ("square", x(i));
+    col witness x(i) query ("input", i);
+"#;
+        let analyzed = analyze_string::<GoldilocksField>(src);
+        let fixed_cols = constant_evaluator::generate(&analyzed);
+        let query_callback = unused_query_callback();
+        let parsed_query_callback =
+            |query: &Query<GoldilocksField>| -> Result<Option<GoldilocksField>, String> {
+                let args = &query.arguments;
+                Ok(Some(match query.name.as_str() {
+                    "input" => args[0] + GoldilocksField::from(1),
+                    "square" => args[0] * args[0],
+                    "add" => args[0] + args[1],
+                    name => unreachable!("Unexpected query {name}"),
+                }))
+            };
+        let witness = WitnessGenerator::new(&analyzed, &fixed_cols, &query_callback)
+            .with_parsed_query_callback(&parsed_query_callback)
+            .generate();
+        assert_eq!(
+            witness,
+            vec![
+                ("N.z".to_string(), values([2, 6, 12, 20])),
+                ("N.y".to_string(), values([1, 4, 9, 16])),
+                ("N.x".to_string(), values([1, 2, 3, 4])),
+            ]
+        );
+    }
+
+    #[test]
+    fn dynamic_lookup() {
+        // The table of squares is built by its own identities, not by the lookups.
+        let src = r"
+namespace Table(8);
+    col fixed FIRST = [1] + [0]*;
+    col witness t, sq;
+    FIRST * t = 0;
+    (1 - FIRST') * (t' - t - 1) = 0;
+    sq = t * t;
+namespace Main(8);
+    col fixed X = [3, 1, 4, 1, 5, 2, 6, 0];
+    col witness x, y;
+    x = X;
+    { x, y } in { Table.t, Table.sq };
+";
+        let witness = generate_witness(src);
+        let column = |name: &str| witness.iter().find(|(n, _)| n == name).unwrap().1.clone();
+        assert_eq!(column("Table.t"), values(0..8));
+        assert_eq!(column("Table.sq"), values([0, 1, 4, 9, 16, 25, 36, 49]));
+        assert_eq!(column("Main.y"), values([9, 1, 16, 1, 25, 4, 36, 0]));
+    }
+
+    #[test]
+    fn fixed_lookup_with_duplicate_keys() {
+        // For key 0, all matching rows agree on `A`, for key 2 on `B`,
+        // so these can be determined from the lookup and the other from `b = a - 4`.
+        let src = r"
+namespace N(8);
+    col fixed K(i) { 2 * (i % 2) };
+    col fixed KEY = [0, 0, 1, 1, 2, 2, 3, 3];
+    col fixed A = [5, 5, 6, 6, 7, 8, 9, 9];
+    col fixed B = [1, 2, 3, 3, 4, 4, 5, 6];
+    col witness k, a, b;
+    k = K;
+    b = a - 4;
+    { k, a, b } in { KEY, A, B };
+";
+        let witness = generate_witness(src);
+        assert_eq!(
+            witness,
+            vec![
+                ("N.k".to_string(), values([0, 2, 0, 2, 0, 2, 0, 2])),
+                ("N.a".to_string(), values([5, 8, 5, 8, 5, 8, 5, 8])),
+                ("N.b".to_string(), values([1, 4, 1, 4, 1, 4, 1, 4])),
+            ]
+        );
+    }
+
+    #[test]
+    fn lookup_table() {
+        // The table has more rows than the degree.
+        let src = r"
+namespace N(4);
+    col fixed INPUT, X, CUBE;
+    col witness x, y;
+    x = INPUT;
+    { x, y } in { X, CUBE };
+";
+        let analyzed = analyze_string::<GoldilocksField>(src);
+        let fixed_cols = vec![
+            ("N.INPUT".to_string(), values([0, 3, 9, 14])),
+            ("N.X".to_string(), vec![]),
+            ("N.CUBE".to_string(), vec![]),
+        ];
+        let query_callback = unused_query_callback();
+        let table = vec![
+            ("N.X".to_string(), values(0..16)),
+            ("N.CUBE".to_string(), values((0..16).map(|x| x * x * x))),
+        ];
+        let witness = WitnessGenerator::new(&analyzed, &fixed_cols, &query_callback)
+            .with_lookup_table("cubes", table)
+            .generate();
+        assert_eq!(witness[1], ("N.y".to_string(), values([0, 27, 729, 2744])));
+    }
+
+    #[test]
+    fn missing_lookup_callback() {
+        let src = r"
+namespace N(4);
+    col fixed X = [0, 1, 2, 3];
+    col fixed SQUARE = [0, 1, 4, 9];
+    col fixed INPUT = [0, 1, 2, 5];
+    col witness x, y;
+    x = INPUT;
+    { x, y } in { X, SQUARE };
+";
+        let analyzed = analyze_string::<GoldilocksField>(src);
+        let fixed_cols = constant_evaluator::generate(&analyzed);
+        let query_callback = unused_query_callback();
+
+        let misses = Arc::new(std::sync::Mutex::new(vec![]));
+        let recorded = misses.clone();
+        let callback = move |lookup: &str,
+                             inputs: &[(String, GoldilocksField)],
+                             outputs: &[String]| {
+            recorded
+                .lock()
+                .unwrap()
+                .push((lookup.to_string(), inputs.to_vec(), outputs.to_vec()));
+            let value = inputs[0].1;
+            Some(vec![value * value])
+        };
+        let witness = WitnessGenerator::new(&analyzed, &fixed_cols, &query_callback)
+            .with_missing_lookup_callback(Arc::new(callback))
+            .generate();
+        assert_eq!(witness[1], ("N.y".to_string(), values([0, 1, 4, 25])));
+        assert_eq!(
+            misses.lock().unwrap()[0],
+            (
+                "{ N.X, N.SQUARE }".to_string(),
+                vec![("N.X".to_string(), 5.into())],
+                vec!["N.SQUARE".to_string()]
+            )
+        );
+
+        // Declining to provide a value preserves the error.
+        let err = WitnessGenerator::new(&analyzed, &fixed_cols, &query_callback)
+            .with_missing_lookup_callback(Arc::new(|_: &str, _: &[_], _: &[_]| None::<Vec<_>>))
+            .validate_only()
+            .unwrap_err();
+        assert!(
+            err.to_string().contains("no match for query: N.X = 5"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn fixed_cells() {
+        let src = r"
+namespace N(4);
+    col fixed LAST = [0, 0, 0, 1];
+    col witness x, y;
+    (1 - LAST) * (x' - x - 1) = 0;
+    y = 2 * x;
+";
+        let analyzed = analyze_string::<GoldilocksField>(src);
+        let fixed_cols = constant_evaluator::generate(&analyzed);
+        let query_callback = unused_query_callback();
+        let x: PolyID = (&analyzed.definitions["N.x"].0).into();
+        let witness = WitnessGenerator::new(&analyzed, &fixed_cols, &query_callback)
+            .with_fixed_cells(vec![(x, 0, 5.into())])
+            .generate();
+        assert_eq!(witness[0], ("N.x".to_string(), values([5, 6, 7, 8])));
+        assert_eq!(witness[1], ("N.y".to_string(), values([10, 12, 14, 16])));
+
+        let err = WitnessGenerator::new(&analyzed, &fixed_cols, &query_callback)
+            .with_fixed_cells(vec![(x, 0, 5.into()), (x, 2, 5.into())])
+            .validate_only()
+            .unwrap_err();
+        let EvalError::RowFailed { row: 1, errors } = &err else {
+            panic!("Unexpected error: {err}");
+        };
+        assert!(
+            errors.iter().any(|e| matches!(
+                e,
+                EvalError::ConflictingConstraints { cell, row: 2, values, identities }
+                    if cell == "N.x" && *values == (5.into(), 7.into())
+                        && identities.0 == "fixed cell"
+            )),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn initial_row() {
+        let src = r"
+namespace main(4);
+    col fixed LAST = [0, 0, 0, 1];
+    col witness pc, reg;
+    (1 - LAST) * (pc' - pc - 1) = 0;
+    (1 - LAST) * (reg' - reg - 2) = 0;
+";
+        let analyzed = analyze_string::<GoldilocksField>(src);
+        let fixed_cols = constant_evaluator::generate(&analyzed);
+        let query_callback = unused_query_callback();
+        let pc: PolyID = (&analyzed.definitions["main.pc"].0).into();
+        let reg: PolyID = (&analyzed.definitions["main.reg"].0).into();
+        let witness = WitnessGenerator::new(&analyzed, &fixed_cols, &query_callback)
+            .with_initial_row(vec![(pc, 0.into()), (reg, 7.into())])
+            .generate();
+        assert_eq!(witness[0], ("main.pc".to_string(), values([0, 1, 2, 3])));
+        assert_eq!(witness[1], ("main.reg".to_string(), values([7, 9, 11, 13])));
+    }
+
+    #[test]
+    fn window_size() {
+        // `a` is only determined by `b` in the next row, which is only known once the
+        // identities of the next row are processed.
+        let src = r"
+namespace N(4);
+    col fixed LAST = [0, 0, 0, 1];
+    col fixed F = [1, 2, 3, 4];
+    col witness a, b;
+    (1 - LAST) * (a - b') = 0;
+    b = F;
+";
+        let analyzed = analyze_string::<GoldilocksField>(src);
+        let fixed_cols = constant_evaluator::generate(&analyzed);
+        let query_callback = unused_query_callback();
+        let generate = |window_size: usize| {
+            let witness = WitnessGenerator::new(&analyzed, &fixed_cols, &query_callback)
+                .with_window_size(window_size)
+                .generate();
+            let columns = fixed_cols
+                .iter()
+                .cloned()
+                .chain(witness.clone())
+                .collect::<Vec<_>>();
+            (witness, verify_witness(&analyzed, &columns, 4))
+        };
+        assert!(generate(2).1.is_err());
+        let (witness, verification) = generate(3);
+        assert_eq!(
+            witness,
+            vec![
+                ("N.a".to_string(), values([2, 3, 4, 0])),
+                ("N.b".to_string(), values([1, 2, 3, 4])),
+            ]
+        );
+        assert_eq!(verification, Ok(()));
+    }
+
+    #[test]
+    fn next_reference_wraps_around() {
+        let src = r"
+namespace N(8);
+    col fixed LAST = [0]* + [1];
+    col witness x;
+    x' = (1 - LAST) * (x + 1);
+";
+        let analyzed = analyze_string::<GoldilocksField>(src);
+        let fixed_cols = constant_evaluator::generate(&analyzed);
+        let query_callback = unused_query_callback();
+        let witness = WitnessGenerator::new(&analyzed, &fixed_cols, &query_callback).generate();
+        // Row 0 is derived from the last row.
+        assert_eq!(witness, vec![("N.x".to_string(), values(0..8))]);
+        let columns = fixed_cols
+            .iter()
+            .cloned()
+            .chain(witness)
+            .collect::<Vec<_>>();
+        assert_eq!(verify_witness(&analyzed, &columns, 8), Ok(()));
+    }
+
+    #[test]
+    fn next_reference_wraps_around_inconsistently() {
+        let src = r"
+namespace N(8);
+    col witness x;
+    x' = x + 1;
+";
+        let analyzed = analyze_string::<GoldilocksField>(src);
+        let fixed_cols = constant_evaluator::generate(&analyzed);
+        let query_callback = unused_query_callback();
+        let x: PolyID = (&analyzed.definitions["N.x"].0).into();
+        let err = WitnessGenerator::new(&analyzed, &fixed_cols, &query_callback)
+            .with_initial_row(vec![(x, 0.into())])
+            .try_generate()
+            .unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("Column N.x is 0 in the first row, but 8 after wrapping around."),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn generate_window() {
+        let src = r"
+namespace N(8);
+    col fixed FIRST = [1] + [0]*;
+    col witness x, y;
+    FIRST * x = 0;
+    (1 - FIRST') * (x' - x - 1) = 0;
+    y = 2 * x;
+";
+        let analyzed = analyze_string::<GoldilocksField>(src);
+        let fixed_cols = constant_evaluator::generate(&analyzed);
+        let query_callback = unused_query_callback();
+        let x: PolyID = (&analyzed.definitions["N.x"].0).into();
+        let full = WitnessGenerator::new(&analyzed, &fixed_cols, &query_callback).generate();
+        let window = |start, end| {
+            // Row 4 is the boundary between the two windows.
+            WitnessGenerator::new(&analyzed, &fixed_cols, &query_callback)
+                .with_fixed_cells(vec![(x, 4, 4.into())])
+                .generate_window(start, end)
+        };
+        let (first, second) = (window(0, 4), window(4, 8));
+        assert_eq!(first[0], ("N.x".to_string(), values([0, 1, 2, 3])));
+        let stitched = first
+            .into_iter()
+            .zip(second)
+            .map(|((name, mut values), (_, second))| {
+                values.extend(second);
+                (name, values)
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(stitched, full);
+    }
+
+    #[test]
+    fn solve_backward() {
+        let src = r"
+namespace N(4);
+    col fixed LAST(i) { match i {
+        3 => 1,
+        _ => 0,
+    } };
+    col witness x;
+    LAST * (x - 7) = 0;
+    (1 - LAST) * (x' - x - 1) = 0;
+";
+        let analyzed = analyze_string::<GoldilocksField>(src);
+        let fixed_cols = constant_evaluator::generate(&analyzed);
+        let query_callback = unused_query_callback();
+        // Solving forward, the first row is not determined.
+        assert!(
+            WitnessGenerator::new(&analyzed, &fixed_cols, &query_callback)
+                .validate_only()
+                .is_err()
+        );
+        let witness = WitnessGenerator::new(&analyzed, &fixed_cols, &query_callback)
+            .with_solving_direction(SolvingDirection::Backward)
+            .generate();
+        assert_eq!(
+            witness,
+            vec![(
+                "N.x".to_string(),
+                [4, 5, 6, 7]
+                    .into_iter()
+                    .map(GoldilocksField::from)
+                    .collect()
+            )]
+        );
+    }
+
+    #[test]
+    fn symbolic_constraints() {
+        let src = r"
+namespace N(4);
+    col witness x, y;
+    col inter = x + 1;
+    x * (1 - x) = 0;
+    y' = 2 * inter;
+    x * y = 0;
+";
+        let analyzed = analyze_string::<GoldilocksField>(src);
+        let fixed_cols = constant_evaluator::generate(&analyzed);
+        let query_callback = unused_query_callback();
+        let constraints =
+            WitnessGenerator::new(&analyzed, &fixed_cols, &query_callback).symbolic_constraints();
+        // The bit constraint on x is captured by the range constraints.
+        assert_eq!(constraints.len(), 2);
+        let poly_id = |name: &str| -> PolyID { (&analyzed.definitions[name].0).into() };
+        let affine = constraints[0].affine.clone().unwrap();
+        let mut coefficients = affine.nonzero_coefficients();
+        coefficients.sort();
+        assert_eq!(
+            coefficients,
+            vec![
+                ((poly_id("N.x"), 0), -GoldilocksField::from(2)),
+                ((poly_id("N.y"), 1), 1.into())
+            ]
+        );
+        assert_eq!(affine.offset, -GoldilocksField::from(2));
+        assert!(constraints[1].affine.is_none());
+    }
+
+    #[test]
+    fn too_many_external_witness_values() {
+        let src = r"
+namespace N(4);
+    col witness x, y;
+    y = 2 * x;
+";
+        let analyzed = analyze_string::<GoldilocksField>(src);
+        let query_callback = unused_query_callback();
+        let result = WitnessGenerator::new(&analyzed, &[], &query_callback)
+            .try_with_external_witness_values(vec![("N.x".to_string(), values(0..5))]);
+        assert_eq!(
+            result.err(),
+            Some(ExternalWitnessError::TooManyValues {
+                column: "N.x".to_string(),
+                degree: 4,
+                length: 5
+            })
+        );
+    }
+
+    #[test]
+    fn ignore_unknown_external_columns() {
+        let src = r"
+namespace N(4);
+    col witness x, y;
+    y = 2 * x;
+";
+        let analyzed = analyze_string::<GoldilocksField>(src);
+        let query_callback = unused_query_callback();
+        let generator = WitnessGenerator::new(&analyzed, &[], &query_callback)
+            .with_unknown_external_columns_ignored()
+            .with_external_witness_values(vec![
+                ("N.x".to_string(), values([1, 2, 3, 4])),
+                ("N.z".to_string(), values([5, 6, 7, 8])),
+                ("M.x".to_string(), vec![]),
+            ]);
+        assert_eq!(generator.ignored_external_columns(), ["N.z", "M.x"]);
+        let witness = generator.generate();
+        assert_eq!(witness[1], ("N.y".to_string(), values([2, 4, 6, 8])));
     }
 }
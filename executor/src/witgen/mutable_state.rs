@@ -0,0 +1,64 @@
+use powdr_number::FieldElement;
+
+use super::call_cache::CallCache;
+use super::global_constraints::GlobalConstraints;
+use super::identity_processor::Machines;
+use super::machines::{FixedLookup, KnownMachine};
+use super::{FixedData, MutableState, QueryCallback};
+
+/// Owns everything a [MutableState] borrows, so that machines can be tested in
+/// isolation, without extracting them from a PIL file:
+/// ```ignore
+/// let mut builder = MutableStateBuilder::new(&fixed_data, query_callback)
+///     .with_machine(KnownMachine::BlockMachine(callee));
+/// let result = machine.process_plookup(&mut builder.state(), kind, &left, right);
+/// ```
+pub struct MutableStateBuilder<'a, T: FieldElement, Q: QueryCallback<T>> {
+    fixed_lookup: FixedLookup<T>,
+    machines: Vec<KnownMachine<'a, T>>,
+    query_callback: Q,
+    call_cache: CallCache<T>,
+}
+
+impl<'a, T: FieldElement, Q: QueryCallback<T>> MutableStateBuilder<'a, T, Q> {
+    /// Creates a state without machines, whose lookups into fixed columns do not use
+    /// any global range constraints.
+    pub fn new(fixed_data: &FixedData<'_, T>, query_callback: Q) -> Self {
+        Self {
+            fixed_lookup: FixedLookup::new(GlobalConstraints::unconstrained(fixed_data)),
+            machines: vec![],
+            query_callback,
+            call_cache: CallCache::new(false),
+        }
+    }
+
+    pub fn with_fixed_lookup(self, fixed_lookup: FixedLookup<T>) -> Self {
+        Self {
+            fixed_lookup,
+            ..self
+        }
+    }
+
+    /// Adds a machine that can be called by the machine under test.
+    pub fn with_machine(mut self, machine: KnownMachine<'a, T>) -> Self {
+        self.machines.push(machine);
+        self
+    }
+
+    /// Returns the state, borrowing the parts from the builder.
+    pub fn state(&mut self) -> MutableState<'a, '_, T, Q> {
+        MutableState {
+            fixed_lookup: &mut self.fixed_lookup,
+            machines: Machines::from(self.machines.iter_mut()),
+            query_callback: &mut self.query_callback,
+            call_cache: &mut self.call_cache,
+            caller: None,
+        }
+    }
+
+    /// Returns the machines added by [MutableStateBuilder::with_machine], e.g. to
+    /// inspect their witness columns after the calls.
+    pub fn machines_mut(&mut self) -> &mut [KnownMachine<'a, T>] {
+        &mut self.machines
+    }
+}
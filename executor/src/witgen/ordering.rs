@@ -0,0 +1,124 @@
+use std::collections::{BTreeSet, HashMap};
+
+use itertools::Itertools;
+use powdr_number::FieldElement;
+
+use super::WitnessGenerator;
+
+/// The order in which [WitnessGenerator::generate] returns the witness columns.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum OrderingStrategy {
+    /// The order in which the columns are declared.
+    #[default]
+    SourceOrder,
+    /// The columns of the main machine first, followed by the columns of each secondary
+    /// machine (in the order in which the machines were extracted).
+    /// Within a machine, columns are in source order.
+    ByMachine,
+    /// The given order. Every witness column has to be listed exactly once.
+    Custom(Vec<String>),
+}
+
+impl OrderingStrategy {
+    /// Panics if this is a custom order that is not a permutation of `columns`.
+    pub(crate) fn validate(&self, columns: &[String]) {
+        let OrderingStrategy::Custom(order) = self else {
+            return;
+        };
+        let duplicates = order.iter().duplicates().collect::<Vec<_>>();
+        let listed = order.iter().collect::<BTreeSet<_>>();
+        let available = columns.iter().collect::<BTreeSet<_>>();
+        let unknown = listed.difference(&available).collect::<Vec<_>>();
+        let missing = available.difference(&listed).collect::<Vec<_>>();
+        if !(duplicates.is_empty() && unknown.is_empty() && missing.is_empty()) {
+            panic!(
+                "Invalid custom column order: Every witness column has to be listed exactly once.\n\
+                 Duplicate columns: {duplicates:?}\n\
+                 Unknown columns: {unknown:?}\n\
+                 Missing columns: {missing:?}"
+            );
+        }
+    }
+
+    /// Orders the column names, given in source order.
+    /// `machine_of` maps each column to the index of the machine it belongs to.
+    pub(crate) fn order(
+        &self,
+        source_order: Vec<String>,
+        machine_of: &HashMap<String, usize>,
+    ) -> Vec<String> {
+        match self {
+            OrderingStrategy::SourceOrder => source_order,
+            OrderingStrategy::ByMachine => source_order
+                .into_iter()
+                .sorted_by_key(|name| machine_of.get(name))
+                .collect(),
+            OrderingStrategy::Custom(order) => {
+                self.validate(&source_order);
+                order.clone()
+            }
+        }
+    }
+}
+
+impl<'a, 'b, T: FieldElement> WitnessGenerator<'a, 'b, T> {
+    /// Sets the order in which [WitnessGenerator::generate] returns the columns.
+    /// Panics if a custom order does not list every witness column exactly once.
+    pub fn with_ordering_strategy(self, ordering: OrderingStrategy) -> Self {
+        ordering.validate(&self.witness_column_names());
+        WitnessGenerator { ordering, ..self }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use powdr_number::GoldilocksField;
+    use powdr_pil_analyzer::analyze_string;
+    use pretty_assertions::assert_eq;
+    use test_log::test;
+
+    use crate::constant_evaluator;
+    use crate::witgen::tests::ARRAY_SRC;
+    use crate::witgen::unused_query_callback;
+
+    use super::*;
+
+    #[test]
+    fn ordering_strategies() {
+        let src = r"
+namespace Perm(4);
+    col fixed SQUARES(i) { i * i };
+    col witness b, a;
+    a = SQUARES;
+    { a } is { b };
+";
+        let analyzed = analyze_string::<GoldilocksField>(src);
+        let fixed_cols = constant_evaluator::generate(&analyzed);
+        let query_callback = unused_query_callback();
+        let names = |ordering| {
+            WitnessGenerator::new(&analyzed, &fixed_cols, &query_callback)
+                .with_ordering_strategy(ordering)
+                .generate()
+                .into_iter()
+                .map(|(name, _)| name)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(names(OrderingStrategy::SourceOrder), ["Perm.b", "Perm.a"]);
+        assert_eq!(names(OrderingStrategy::ByMachine), ["Perm.a", "Perm.b"]);
+        let custom = vec!["Perm.a".to_string(), "Perm.b".to_string()];
+        assert_eq!(
+            names(OrderingStrategy::Custom(custom)),
+            ["Perm.a", "Perm.b"]
+        );
+    }
+
+    #[test]
+    #[should_panic = "Missing columns: [\"Arr.mem[1]\"]"]
+    fn custom_ordering_missing_column() {
+        let analyzed = analyze_string::<GoldilocksField>(ARRAY_SRC);
+        let fixed_cols = constant_evaluator::generate(&analyzed);
+        let query_callback = unused_query_callback();
+        let _ = WitnessGenerator::new(&analyzed, &fixed_cols, &query_callback)
+            .with_ordering_strategy(OrderingStrategy::Custom(vec!["Arr.mem[0]".to_string()]));
+    }
+}
@@ -0,0 +1,1219 @@
+use std::cmp::max;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use powdr_ast::analyzed::{AlgebraicExpression, Analyzed, Identity, PolyID};
+use powdr_ast::parsed::visitor::ExpressionVisitable;
+use powdr_number::{DegreeType, FieldElement};
+use rayon::prelude::*;
+
+use super::call_cache::CallCache;
+use super::free_fill::free_columns;
+use super::generator::Generator;
+use super::global_constraints::{self, GlobalConstraints};
+use super::identity_processor::Machines;
+use super::machines::machine_extractor::{self, ExtractionOutput, MachineLayout};
+use super::machines::profiling::{
+    record_end, record_start, reset_and_print_profile_summary, without_profiling,
+};
+use super::machines::{self, FixedLookup, Machine};
+use super::progress::ProgressReporter;
+#[cfg(feature = "rational-debug")]
+use super::rational_solver::{self, RationalSolution};
+use super::rows::{CellValue, RowFactory};
+use super::statistics;
+use super::symbolic_evaluator::SymbolicConstraint;
+use super::vm_processor::Limits;
+use super::witness_rows::{RowChannel, RowSender};
+use super::{
+    external_witness_provider, interleave_columns, merge_machine_witnesses, witness_column_names,
+    BatchQueryCallback, ColumnStorage, CustomMachineFactory, EvalError, ExternalWitnessProvider,
+    ExtractionReport, FixedColumnFunction, FixedData, FixedLookupIndex, FreeFill, GenerationStats,
+    MachineGraph, MachineParts, MissingLookupCallback, MutableState, OrderingStrategy,
+    PaddingStrategy, ParsedQueryCallback, ProgressEvent, QueryCallback, RangeConstraint, RunMode,
+    RunOptions, RunOutput, SequenceStrategy, SolvingDirection, SpillConfig, WitnessGenerator,
+    OUTER_CODE_NAME,
+};
+
+impl<'a, 'b, T: FieldElement> WitnessGenerator<'a, 'b, T> {
+    /// Runs the analysis of the PIL file that does not depend on the external witness
+    /// values or the query callback, so that witnesses for different inputs can be
+    /// generated repeatedly without repeating it.
+    /// Panics if external witness values, fixed cells, a progress callback, checkpoints,
+    /// limits or statistics are set, since they only apply to a single run.
+    pub fn prepared(self) -> PreparedGenerator<'a, 'b, T> {
+        assert!(
+            self.external_witness.is_none()
+                && self.progress.is_none()
+                && self.row_observer.is_none()
+                && self.cell_sender.is_none()
+                && self.machine_witness_callback.is_none()
+                && self.checkpoint.is_none()
+                && self.checkpoints.is_none()
+                && self.max_degree.is_none()
+                && self.timeout.is_none()
+                && self.max_rows_without_progress.is_none()
+                && self.cancellation.is_none()
+                && self.statistics.is_none()
+                && self.fixed_cells.is_empty()
+                && self.initial_row.is_empty()
+                && self.continuation.is_none(),
+            "External witness values, fixed cells, initial rows, continuations, progress \
+             callbacks, row observers, cell senders, machine witness callbacks, checkpoints, \
+             maximum degrees, limits and statistics are not supported by prepared generators."
+        );
+        self.prepare()
+    }
+
+    pub(crate) fn prepare(&self) -> PreparedGenerator<'a, 'b, T> {
+        PreparedGenerator::new(self)
+    }
+}
+
+/// The result of the analysis of a PIL file (inlining of intermediate polynomials
+/// and determining global range constraints), which can be used to generate
+/// witnesses repeatedly, e.g. for different external witness values.
+/// Created by [WitnessGenerator::prepared].
+pub struct PreparedGenerator<'a, 'b, T: FieldElement> {
+    analyzed: &'a Analyzed<T>,
+    fixed_col_values: &'b [(String, Vec<T>)],
+    parsed_query_callback: Option<&'b dyn ParsedQueryCallback<T>>,
+    batch_query_callback: Option<&'b dyn BatchQueryCallback<T>>,
+    degree: DegreeType,
+    fixed_lookup_cache_capacity: usize,
+    missing_lookup_callback: Option<Arc<dyn MissingLookupCallback<T>>>,
+    lookup_tables: Vec<(String, Vec<(String, Arc<[T]>)>)>,
+    ordering: OrderingStrategy,
+    sequence_strategy: Option<&'b dyn SequenceStrategy<T>>,
+    identity_priorities: HashMap<u64, i32>,
+    identity_assignments: HashMap<u64, String>,
+    custom_machines: Vec<(String, &'b dyn CustomMachineFactory<T>)>,
+    fixed_column_functions: Vec<(String, &'b dyn FixedColumnFunction<T>)>,
+    fixed_column_defaults: Vec<(String, T)>,
+    direction: SolvingDirection,
+    window_size: usize,
+    padding: Option<PaddingStrategy<'b, T>>,
+    free_fill: Option<FreeFill>,
+    memoize_pure_machines: bool,
+    independent_main_machines: bool,
+    interleavings: Vec<(String, Vec<String>)>,
+    /// The witness columns that are not referenced by any retained identity, by name,
+    /// with their range constraints. Only computed if `free_fill` is set.
+    free_columns: HashMap<String, (PolyID, Option<RangeConstraint<T>>)>,
+    spill: Option<SpillConfig>,
+    identities: Vec<Identity<AlgebraicExpression<T>>>,
+    pub(crate) constraints: GlobalConstraints<T>,
+    /// The indices of the identities that are not implied by the range constraints.
+    retained_identities: Vec<usize>,
+    /// Set if an identity can never be satisfied, see [EvalError::UnsatisfiablePil].
+    pub(crate) unsatisfiable: Option<EvalError<T>>,
+    global_constraints_time: Duration,
+    /// The machines extracted from the retained identities, which are created again by
+    /// every run.
+    machine_layout: MachineLayout,
+    extraction_time: Duration,
+    /// The indices of lookups into fixed columns built by the runs so far, which are
+    /// reused by later runs.
+    fixed_lookup_index: Mutex<FixedLookupIndex<T>>,
+}
+
+impl<'a, 'b, T: FieldElement> PreparedGenerator<'a, 'b, T> {
+    fn new(generator: &WitnessGenerator<'a, 'b, T>) -> Self {
+        let analyzed = generator.analyzed;
+        let fixed_col_values = generator.fixed_col_values;
+        let degree = generator.degree();
+        let start = Instant::now();
+        let mut identities = match &generator.identities {
+            Some(identities) => identities.clone(),
+            None => {
+                let identities = analyzed.identities_with_inlined_intermediate_polynomials();
+                log::debug!(
+                    "Inlined intermediate polynomials in {} identities in {:?}.",
+                    identities.len(),
+                    start.elapsed()
+                );
+                identities
+            }
+        };
+        if degree == 1 {
+            // In a trace with a single row, the next row is the row itself.
+            for identity in &mut identities {
+                identity.pre_visit_expressions_mut(&mut |e| {
+                    if let AlgebraicExpression::Reference(reference) = e {
+                        reference.next = false;
+                    }
+                });
+            }
+        }
+        let fixed = FixedData::new_with_defaults(
+            analyzed,
+            degree,
+            fixed_col_values,
+            &generator.fixed_column_defaults,
+            None,
+        )
+        .with_fixed_column_functions(&generator.fixed_column_functions);
+        // Only the columns of lookup tables are never accessed by row.
+        let table_columns = generator
+            .lookup_tables
+            .iter()
+            .flat_map(|(_, columns)| columns.iter().map(|(name, _)| name.as_str()))
+            .collect::<HashSet<_>>();
+        if let Some((_, column)) = fixed
+            .fixed_cols
+            .iter()
+            .find(|(_, column)| column.is_empty() && !table_columns.contains(column.name.as_str()))
+        {
+            panic!(
+                "Fixed column {} has no values and is neither computed by a function nor part of a lookup table.",
+                column.name
+            );
+        }
+        let (
+            constraints,
+            // Removes identities like X * (X - 1) = 0 or { A } in { BYTES }
+            // These are already captured in the range constraints.
+            retained_identities,
+            unsatisfiable,
+        ) = match global_constraints::determine_global_constraints(
+            &fixed,
+            identities.iter().collect(),
+        ) {
+            Ok((constraints, retained_identities)) => (constraints, retained_identities, None),
+            // The error is returned by the first run.
+            Err(e) => (GlobalConstraints::unconstrained(&fixed), vec![], Some(e)),
+        };
+        let retained_identities = retained_identities
+            .into_iter()
+            .map(|retained| {
+                identities
+                    .iter()
+                    .position(|identity| std::ptr::eq(identity, retained))
+                    .unwrap()
+            })
+            .collect::<Vec<_>>();
+        let free_columns = match generator.free_fill {
+            Some(_) => free_columns(
+                analyzed,
+                &constraints,
+                retained_identities.iter().map(|index| &identities[*index]),
+            ),
+            None => Default::default(),
+        };
+        let global_constraints_time = start.elapsed();
+        let extraction_start = Instant::now();
+        let machine_layout = machine_extractor::extract_machines(
+            &fixed,
+            &retained_identities
+                .iter()
+                .map(|index| &identities[*index])
+                .collect::<Vec<_>>(),
+            &constraints,
+            generator.sequence_strategy,
+            &generator.identity_priorities,
+            &generator.identity_assignments,
+            &generator.custom_machines,
+        );
+        let extraction_time = extraction_start.elapsed();
+        PreparedGenerator {
+            analyzed,
+            fixed_col_values,
+            parsed_query_callback: generator.parsed_query_callback,
+            batch_query_callback: generator.batch_query_callback,
+            degree,
+            fixed_lookup_cache_capacity: generator.fixed_lookup_cache_capacity,
+            missing_lookup_callback: generator.missing_lookup_callback.clone(),
+            lookup_tables: generator.lookup_tables.clone(),
+            ordering: generator.ordering.clone(),
+            sequence_strategy: generator.sequence_strategy,
+            identity_priorities: generator.identity_priorities.clone(),
+            identity_assignments: generator.identity_assignments.clone(),
+            custom_machines: generator.custom_machines.clone(),
+            fixed_column_functions: generator.fixed_column_functions.clone(),
+            fixed_column_defaults: generator.fixed_column_defaults.clone(),
+            direction: generator.direction,
+            window_size: generator.window_size,
+            padding: generator.padding,
+            free_fill: generator.free_fill,
+            memoize_pure_machines: generator.memoize_pure_machines,
+            independent_main_machines: generator.independent_main_machines,
+            interleavings: generator.interleavings.clone(),
+            free_columns,
+            spill: generator.spill.clone(),
+            identities,
+            constraints,
+            retained_identities,
+            unsatisfiable,
+            global_constraints_time,
+            machine_layout,
+            extraction_time,
+            // The hash is only computed when the index is exported.
+            fixed_lookup_index: Mutex::new(FixedLookupIndex::new(0)),
+        }
+    }
+
+    /// Adds the indices built by `fixed_lookup` to the indices shared by all machines and
+    /// runs, and its cache statistics to `stats`.
+    fn release_fixed_lookup(&self, fixed_lookup: &FixedLookup<T>, stats: &mut GenerationStats) {
+        let (hits, misses) = fixed_lookup.cache_statistics();
+        stats.fixed_lookup_cache_hits += hits;
+        stats.fixed_lookup_cache_misses += misses;
+        fixed_lookup.extend_index(&mut self.fixed_lookup_index.lock().unwrap());
+    }
+
+    fn configure_fixed_lookup(&self, fixed_lookup: FixedLookup<T>) -> FixedLookup<T> {
+        let fixed_lookup = self.lookup_tables.iter().fold(
+            fixed_lookup
+                .with_cache_capacity(self.fixed_lookup_cache_capacity)
+                .with_index(&self.fixed_lookup_index.lock().unwrap()),
+            |fixed_lookup, (name, columns)| fixed_lookup.with_table(name.clone(), columns.clone()),
+        );
+        match &self.missing_lookup_callback {
+            Some(callback) => fixed_lookup.with_missing_lookup_callback(callback.clone()),
+            None => fixed_lookup,
+        }
+    }
+
+    /// Returns the indices of the lookups into fixed columns that were built by the runs
+    /// so far, e.g. to store them (see [FixedLookupIndex::write]) and use them for
+    /// later generations with the same fixed columns, see
+    /// [PreparedGenerator::try_with_fixed_lookup_index].
+    pub fn fixed_lookup_index(&self) -> FixedLookupIndex<T> {
+        let mut exported = FixedLookupIndex::new(self.fixed_columns_hash());
+        exported.extend(&self.fixed_lookup_index.lock().unwrap());
+        exported
+    }
+
+    /// Uses the indices of lookups into fixed columns built by another prepared
+    /// generator (see [PreparedGenerator::fixed_lookup_index]) instead of building them
+    /// again. Fails if the indices were built for different fixed columns, lookup
+    /// tables or a different degree.
+    /// Fixed columns that are computed by functions (see
+    /// [WitnessGenerator::with_fixed_column_function]) are only compared by name.
+    pub fn try_with_fixed_lookup_index(
+        mut self,
+        index: FixedLookupIndex<T>,
+    ) -> Result<Self, String> {
+        if index.fixed_columns_hash() != self.fixed_columns_hash() {
+            return Err(
+                "The fixed lookup index was built for different fixed columns.".to_string(),
+            );
+        }
+        self.fixed_lookup_index.get_mut().unwrap().extend(&index);
+        Ok(self)
+    }
+
+    /// The hash of the fixed columns and lookup tables, see [FixedLookupIndex].
+    fn fixed_columns_hash(&self) -> u64 {
+        let fixed_columns = self
+            .fixed_col_values
+            .iter()
+            .map(|(name, values)| (name.as_str(), values.as_slice()));
+        let tables = self.lookup_tables.iter().flat_map(|(_, columns)| {
+            columns
+                .iter()
+                .map(|(name, values)| (name.as_str(), &values[..]))
+        });
+        let functions = self
+            .fixed_column_functions
+            .iter()
+            .map(|(name, _)| (name.as_str(), &[][..]));
+        let defaults = self
+            .fixed_column_defaults
+            .iter()
+            .map(|(name, value)| (name.as_str(), std::slice::from_ref(value)));
+        machines::fixed_columns_hash(
+            self.degree,
+            fixed_columns.chain(tables).chain(functions).chain(defaults),
+        )
+    }
+
+    /// Starts sending the rows of the main machine with the given witness columns to
+    /// `channel`, with the columns in the order of [WitnessGenerator::generate].
+    fn row_sender(
+        &self,
+        channel: &RowChannel<T>,
+        fixed: &FixedData<'_, T>,
+        witnesses: &HashSet<PolyID>,
+    ) -> RowSender<T> {
+        // The rows of the machine are stored in the order of the column IDs.
+        let mut ids = witnesses.iter().collect::<Vec<_>>();
+        ids.sort();
+        let positions = ids
+            .into_iter()
+            .enumerate()
+            .map(|(position, id)| (fixed.column_name(id), position))
+            .collect::<HashMap<_, _>>();
+        // All columns belong to the same machine.
+        let names = self
+            .ordering
+            .order(witness_column_names(self.analyzed), &Default::default());
+        let positions = names.iter().map(|name| positions[name.as_str()]).collect();
+        channel.sender(names, positions, self.degree as usize)
+    }
+
+    /// Stores the witness columns of a machine, see [WitnessGenerator::with_spill_to_disk].
+    /// If `selected` is given, all other columns are dropped.
+    fn store_columns(
+        &self,
+        columns: HashMap<String, Vec<T>>,
+        selected: Option<&HashSet<String>>,
+    ) -> HashMap<String, ColumnStorage<T>> {
+        select_columns(columns, selected)
+            .into_iter()
+            .map(|(name, values)| (name, ColumnStorage::new(values, self.spill.as_ref())))
+            .collect()
+    }
+
+    /// Returns all identities of the PIL file with intermediate polynomials inlined
+    /// (or the identities given by [WitnessGenerator::with_identities]).
+    /// They are computed once when the generator is prepared and reused by every run.
+    pub fn inlined_identities(&self) -> &[Identity<AlgebraicExpression<T>>] {
+        &self.identities
+    }
+
+    /// Returns the number of bits needed to represent the values of each witness column,
+    /// by name, as far as they are bounded by the global range constraints.
+    /// The widths of signed columns include the sign bit, see
+    /// [PreparedGenerator::signed_columns].
+    pub fn bit_widths(&self) -> HashMap<String, Option<u32>> {
+        self.witness_range_constraints()
+            .map(|(name, constraint)| (name, constraint.and_then(|c| c.bit_width())))
+            .collect()
+    }
+
+    /// Returns the names of the witness columns whose values are known to be in a
+    /// signed interval like `[-128, 127]`, i.e. small negative values are encoded as
+    /// field elements close to the modulus.
+    pub fn signed_columns(&self) -> HashSet<String> {
+        self.witness_range_constraints()
+            .filter(|(_, constraint)| constraint.as_ref().map_or(false, |c| c.is_signed()))
+            .map(|(name, _)| name)
+            .collect()
+    }
+
+    /// The global range constraints of the witness columns, by name.
+    fn witness_range_constraints(
+        &self,
+    ) -> impl Iterator<Item = (String, Option<RangeConstraint<T>>)> + '_ {
+        self.analyzed
+            .committed_polys_in_source_order()
+            .iter()
+            .flat_map(|(poly, _)| poly.array_elements())
+            .map(|(name, poly_id)| (name, self.constraints.column_range_constraint(&poly_id)))
+    }
+
+    /// Returns the identities that are processed by the solver,
+    /// see [WitnessGenerator::symbolic_constraints].
+    pub fn symbolic_constraints(&self) -> Vec<SymbolicConstraint<T>> {
+        self.retained_identities
+            .iter()
+            .map(|index| SymbolicConstraint::new(self.identities[*index].clone()))
+            .collect()
+    }
+
+    /// Solves the polynomial identities over the rationals,
+    /// see [WitnessGenerator::solve_over_rationals].
+    #[cfg(feature = "rational-debug")]
+    pub fn solve_over_rationals(&self) -> RationalSolution {
+        let fixed = FixedData::new_with_defaults(
+            self.analyzed,
+            self.degree,
+            self.fixed_col_values,
+            &self.fixed_column_defaults,
+            None,
+        )
+        .with_fixed_column_functions(&self.fixed_column_functions);
+        rational_solver::solve(&fixed, &self.identities.iter().collect::<Vec<_>>())
+    }
+
+    /// The identities that are not implied by the range constraints.
+    fn retained_identities(&self) -> Vec<&Identity<AlgebraicExpression<T>>> {
+        self.retained_identities
+            .iter()
+            .map(|index| &self.identities[*index])
+            .collect()
+    }
+
+    /// Returns the machines that are extracted from the PIL file,
+    /// see [WitnessGenerator::machine_graph].
+    pub fn machine_graph(&self) -> MachineGraph {
+        self.with_machine_parts(|fixed, main, machines| MachineGraph::new(fixed, main, machines))
+    }
+
+    /// Returns a summary of the machines that are extracted from the PIL file,
+    /// see [WitnessGenerator::extraction_report].
+    pub fn extraction_report(&self) -> ExtractionReport {
+        self.with_machine_parts(|_, main, machines| ExtractionReport::new(&main, machines))
+    }
+
+    /// Calls `f` with the parts of the main machine and of the other machines (without
+    /// creating them for solving).
+    fn with_machine_parts<R>(
+        &self,
+        f: impl for<'c> FnOnce(&'c FixedData<'c, T>, MachineParts<'c, T>, &[MachineParts<'c, T>]) -> R,
+    ) -> R {
+        let fixed = FixedData::new_with_defaults(
+            self.analyzed,
+            self.degree,
+            self.fixed_col_values,
+            &self.fixed_column_defaults,
+            None,
+        )
+        .with_fixed_column_functions(&self.fixed_column_functions);
+        let retained_identities = self.retained_identities();
+        let machine_parts = self
+            .machine_layout
+            .machine_parts(&fixed, &retained_identities);
+        let main = MachineParts {
+            name: "Main Machine".to_string(),
+            fixed_data: &fixed,
+            connecting_identities: vec![],
+            identities: self.machine_layout.base_identities(&retained_identities),
+            witnesses: self.machine_layout.base_witnesses().clone(),
+        };
+        f(&fixed, main, &machine_parts)
+    }
+
+    /// Generates the committed polynomial values for the given external witness values.
+    /// @returns the values (in the order given by the [OrderingStrategy]).
+    pub fn generate(
+        &self,
+        external_witness_values: Vec<(String, Vec<T>)>,
+        query_callback: &dyn QueryCallback<T>,
+    ) -> Vec<(String, Vec<T>)> {
+        let external_witness = external_witness_provider(
+            external_witness_values,
+            &witness_column_names(self.analyzed),
+            self.degree,
+        )
+        .unwrap_or_else(|e| panic!("{e}"));
+        record_start(OUTER_CODE_NAME);
+        let machine_witnesses = self
+            .run(
+                query_callback,
+                Some(&external_witness as &dyn ExternalWitnessProvider<T>),
+                RunOptions {
+                    progress: None,
+                    checkpoint: None,
+                    checkpoints: None,
+                    row_observer: None,
+                    cell_sender: None,
+                    machine_witness_callback: None,
+                    mode: RunMode::Generate,
+                    limits: Limits::default(),
+                    statistics: None,
+                    fixed_cells: Default::default(),
+                    initial_row: vec![],
+                    window: None,
+                    selected_columns: None,
+                    provenance: None,
+                    row_channel: None,
+                },
+            )
+            .unwrap_or_else(|e| panic!("Witness generation failed:\n{e}"))
+            .complete();
+        interleave_columns(
+            merge_machine_witnesses(self.analyzed, &self.ordering, machine_witnesses),
+            &self.interleavings,
+        )
+    }
+
+    /// Runs witness generation. The caller has to start profiling the outer code.
+    pub(crate) fn run(
+        &self,
+        query_callback: &dyn QueryCallback<T>,
+        external_witness: Option<&dyn ExternalWitnessProvider<T>>,
+        mut options: RunOptions<'_, T>,
+    ) -> Result<RunOutput<T>, EvalError<T>> {
+        let target = options.statistics.take();
+        let mut stats = GenerationStats {
+            global_constraints_time: self.global_constraints_time,
+            ..Default::default()
+        };
+        let (result, counters) = statistics::collect(target.is_some(), || {
+            self.run_inner(query_callback, external_witness, options, &mut stats)
+        });
+        if let (Some(target), Some(counters)) = (target, counters) {
+            stats.add_counters(counters);
+            *target = stats;
+        }
+        result
+    }
+
+    fn run_inner(
+        &self,
+        query_callback: &dyn QueryCallback<T>,
+        external_witness: Option<&dyn ExternalWitnessProvider<T>>,
+        options: RunOptions<'_, T>,
+        stats: &mut GenerationStats,
+    ) -> Result<RunOutput<T>, EvalError<T>> {
+        let RunOptions {
+            mut progress,
+            checkpoint,
+            checkpoints,
+            row_observer,
+            cell_sender,
+            machine_witness_callback,
+            mode,
+            limits,
+            statistics: _,
+            fixed_cells,
+            initial_row,
+            window,
+            selected_columns,
+            provenance,
+            row_channel,
+        } = options;
+        if let Some(e) = &self.unsatisfiable {
+            return match mode {
+                RunMode::Partial => Ok(RunOutput::Partial(Default::default(), Some(e.clone()))),
+                _ => Err(e.clone()),
+            };
+        }
+        let fill_free_columns = self.free_fill.map(|fill| {
+            let free_columns = &self.free_columns;
+            move |name: &str, row: DegreeType| {
+                external_witness
+                    .and_then(|external_witness| external_witness(name, row))
+                    .or_else(|| {
+                        let (poly_id, range) = free_columns.get(name)?;
+                        Some(fill.value(poly_id.id, row, range.as_ref()))
+                    })
+            }
+        });
+        let fixed = FixedData::new_with_defaults(
+            self.analyzed,
+            self.degree,
+            self.fixed_col_values,
+            &self.fixed_column_defaults,
+            match &fill_free_columns {
+                Some(fill_free_columns) => {
+                    Some(fill_free_columns as &dyn ExternalWitnessProvider<T>)
+                }
+                None => external_witness,
+            },
+        )
+        .with_fixed_cells(fixed_cells)
+        .with_fixed_column_functions(&self.fixed_column_functions)
+        .with_parsed_query_callback(self.parsed_query_callback)
+        .with_batch_query_callback(self.batch_query_callback)
+        .with_provenance(provenance)
+        .with_cell_sender(cell_sender);
+        let constraints = &self.constraints;
+        let extraction_start = Instant::now();
+        let ExtractionOutput {
+            fixed_lookup,
+            mut machines,
+            base_identities,
+            base_witnesses,
+            machine_parts,
+        } = self.machine_layout.create_machines(
+            &fixed,
+            &self.retained_identities(),
+            constraints,
+            &self.custom_machines,
+        );
+        stats.extraction_time = self.extraction_time + extraction_start.elapsed();
+        assert!(
+            machines.is_empty() || window.is_none(),
+            "Windows of rows are only supported if the main machine does not call into other machines."
+        );
+        assert!(
+            initial_row.is_empty() || (checkpoint.is_none() && window.is_none()),
+            "An initial row cannot be combined with checkpoints or windows of rows."
+        );
+        assert!(
+            self.padding.is_none()
+                || (mode == RunMode::Generate
+                    && window.is_none()
+                    && checkpoint.is_none()
+                    && checkpoints.is_none()
+                    && self.direction == SolvingDirection::Forward),
+            "Padding is only supported when generating all rows of the main machine forward, \
+             without checkpoints."
+        );
+        let mut main_machines = if self.independent_main_machines {
+            assert!(
+                mode == RunMode::Generate
+                    && window.is_none()
+                    && checkpoint.is_none()
+                    && checkpoints.is_none(),
+                "Independent main machines are only supported when generating the complete \
+                 witness, without checkpoints or windows of rows."
+            );
+            machines::machine_extractor::split_independent_main_machines(
+                base_identities,
+                base_witnesses,
+            )
+        } else {
+            vec![(base_identities, base_witnesses)]
+        };
+        let (base_identities, base_witnesses) = main_machines.remove(0);
+        // Only the rows of a main machine with all columns are complete once they are
+        // finalized.
+        let row_sender = row_channel
+            .filter(|_| {
+                mode == RunMode::Generate
+                    && window.is_none()
+                    && selected_columns.is_none()
+                    && machines.is_empty()
+                    && main_machines.is_empty()
+            })
+            .map(|channel| self.row_sender(&channel, &fixed, &base_witnesses));
+        let send_rows = row_sender.is_some();
+        if !main_machines.is_empty() {
+            log::info!(
+                "Solving {} independent main machines.",
+                main_machines.len() + 1
+            );
+        }
+        let initial_row = (!initial_row.is_empty()).then(|| {
+            let mut row = RowFactory::new(&fixed, constraints.clone()).fresh_row(0);
+            for (poly_id, value) in initial_row {
+                assert!(
+                    base_witnesses.contains(&poly_id),
+                    "Cannot set the initial value of {}: It is not a column of the main machine.",
+                    fixed.column_name(&poly_id)
+                );
+                row[&poly_id].value = CellValue::Known(value);
+            }
+            row
+        });
+        let mut fixed_lookup = self.configure_fixed_lookup(fixed_lookup);
+        let mut query_callback = query_callback;
+        let mut generator = Generator::new(
+            "Main Machine".to_string(),
+            &fixed,
+            &base_identities,
+            base_witnesses,
+            constraints,
+            // We could set the latch of the main VM here, but then we would have to detect it.
+            // Instead, the main VM will be computed in one block, directly continuing into the
+            // infinite loop after the first return.
+            None,
+            initial_row,
+        )
+        .with_direction(self.direction)
+        .with_window_size(self.window_size)
+        .with_padding(self.padding)
+        .with_spill(self.spill.clone())
+        .with_row_sender(row_sender);
+        let mut further_generators = main_machines
+            .iter()
+            .enumerate()
+            .map(|(index, (identities, witnesses))| {
+                Generator::new(
+                    format!("Main Machine {}", index + 2),
+                    &fixed,
+                    identities,
+                    witnesses.clone(),
+                    constraints,
+                    None,
+                    None,
+                )
+                .with_direction(self.direction)
+                .with_window_size(self.window_size)
+                .with_padding(self.padding)
+                .with_spill(self.spill.clone())
+            })
+            .collect::<Vec<_>>();
+
+        // Main machines that do not call into the same machines (directly or via other
+        // machines) are solved concurrently, unless the machines are finalized one by one
+        // to save memory.
+        let groups = if further_generators.is_empty()
+            || machine_witness_callback.is_some()
+            || row_observer.is_some()
+        {
+            vec![]
+        } else {
+            let main_identities = std::iter::once(&base_identities)
+                .chain(main_machines.iter().map(|(identities, _)| identities))
+                .map(|identities| identities.as_slice())
+                .collect::<Vec<_>>();
+            machines::machine_extractor::independent_machine_groups(
+                &main_identities,
+                &machine_parts,
+            )
+        };
+
+        let solving_start = Instant::now();
+        let result = if groups.len() > 1 {
+            log::info!(
+                "Solving {} independent groups of machines concurrently.",
+                groups.len()
+            );
+            let mut group_of_main = vec![0; further_generators.len() + 1];
+            let mut group_of_machine = vec![0; machines.len()];
+            for (group, (mains, group_machines)) in groups.iter().enumerate() {
+                mains.iter().for_each(|main| group_of_main[*main] = group);
+                group_machines
+                    .iter()
+                    .for_each(|machine| group_of_machine[*machine] = group);
+            }
+            let mut group_parts = groups.iter().map(|_| (vec![], vec![])).collect::<Vec<_>>();
+            for (generator, group) in std::iter::once(&mut generator)
+                .chain(&mut further_generators)
+                .zip(group_of_main)
+            {
+                group_parts[group].0.push(generator);
+            }
+            for (machine, group) in machines.iter_mut().zip(group_of_machine) {
+                group_parts[group].1.push(machine);
+            }
+            let collect_statistics = statistics::is_enabled();
+            let frequency = progress.as_ref().map(ProgressReporter::frequency);
+            // The progress callback is not `Sync`, so the events of all groups are
+            // forwarded to it one at a time.
+            let shared_progress = Mutex::new(progress.as_mut().map(ProgressReporter::reborrow));
+            let results = group_parts
+                .into_par_iter()
+                .map(|(generators, group_machines)| {
+                    // All groups share the indices of the lookups into fixed columns.
+                    let mut fixed_lookup =
+                        self.configure_fixed_lookup(FixedLookup::new(constraints.clone()));
+                    let mut query_callback = query_callback;
+                    let mut call_cache = CallCache::new(self.memoize_pure_machines);
+                    let mut forward = |event: ProgressEvent<'_>| {
+                        if let Some(progress) = shared_progress.lock().unwrap().as_mut() {
+                            progress.report(event);
+                        }
+                    };
+                    let mut progress =
+                        frequency.map(|frequency| ProgressReporter::new(&mut forward, frequency));
+                    let (result, counters) = statistics::collect(collect_statistics, || {
+                        without_profiling(|| {
+                            let mut mutable_state = MutableState {
+                                fixed_lookup: &mut fixed_lookup,
+                                machines: Machines::from(group_machines.into_iter()),
+                                query_callback: &mut query_callback,
+                                call_cache: &mut call_cache,
+                                caller: None,
+                            };
+                            generators.into_iter().try_for_each(|generator| {
+                                generator.run(
+                                    &mut mutable_state,
+                                    None,
+                                    progress.as_mut().map(ProgressReporter::reborrow),
+                                    None,
+                                    None,
+                                    limits,
+                                )
+                            })
+                        })
+                    });
+                    fixed_lookup.extend_index(&mut self.fixed_lookup_index.lock().unwrap());
+                    (
+                        result,
+                        counters,
+                        fixed_lookup.cache_statistics(),
+                        call_cache.hits(),
+                    )
+                })
+                .collect::<Vec<_>>();
+            // The main machines are finalized using all indices built while solving.
+            fixed_lookup = self.configure_fixed_lookup(FixedLookup::new(constraints.clone()));
+            results.into_iter().fold(
+                Ok(()),
+                |result, (group_result, counters, (hits, misses), call_cache_hits)| {
+                    if let Some(counters) = counters {
+                        statistics::add(counters);
+                    }
+                    stats.fixed_lookup_cache_hits += hits;
+                    stats.fixed_lookup_cache_misses += misses;
+                    stats.pure_call_cache_hits += call_cache_hits;
+                    result.and(group_result)
+                },
+            )
+        } else {
+            let mut call_cache = CallCache::new(self.memoize_pure_machines);
+            let mut mutable_state = MutableState {
+                fixed_lookup: &mut fixed_lookup,
+                machines: Machines::from(machines.iter_mut()),
+                query_callback: &mut query_callback,
+                call_cache: &mut call_cache,
+                caller: None,
+            };
+            let result = match mode {
+                RunMode::Generate => match window {
+                    Some((start, end)) => {
+                        generator.run_window(&mut mutable_state, start, end, limits)
+                    }
+                    None => generator.run(
+                        &mut mutable_state,
+                        checkpoint,
+                        progress.as_mut().map(ProgressReporter::reborrow),
+                        checkpoints,
+                        row_observer,
+                        limits,
+                    ),
+                },
+                RunMode::Validate => generator.validate(
+                    &mut mutable_state,
+                    progress.as_mut().map(ProgressReporter::reborrow),
+                    row_observer,
+                    limits,
+                ),
+                RunMode::Partial => generator.run_partial(
+                    &mut mutable_state,
+                    progress.as_mut().map(ProgressReporter::reborrow),
+                    row_observer,
+                    limits,
+                ),
+            };
+            let result = further_generators
+                .iter_mut()
+                .fold(result, |result, generator| {
+                    result.and_then(|()| {
+                        generator.run(
+                            &mut mutable_state,
+                            None,
+                            progress.as_mut().map(ProgressReporter::reborrow),
+                            None,
+                            None,
+                            limits,
+                        )
+                    })
+                });
+            stats.pure_call_cache_hits = call_cache.hits();
+            result
+        };
+        stats.solving_time = solving_start.elapsed();
+        // The main machines share the degree, so the program ends once all of them ended.
+        stats.program_end = std::iter::once(&generator)
+            .chain(&further_generators)
+            .map(Generator::program_end)
+            .collect::<Option<Vec<_>>>()
+            .and_then(|ends| ends.into_iter().max());
+
+        let finalization_start = Instant::now();
+        match mode {
+            RunMode::Generate => {}
+            RunMode::Validate => {
+                self.release_fixed_lookup(&fixed_lookup, stats);
+                record_end(OUTER_CODE_NAME);
+                reset_and_print_profile_summary();
+                return result.map(|()| RunOutput::Complete(vec![]));
+            }
+            RunMode::Partial => {
+                let error = result.err();
+                let mut columns = generator.take_partial_witness();
+                if error.is_none() {
+                    for machine in &mut machines {
+                        let machine_columns =
+                            machine.take_witness_col_values(&mut fixed_lookup, &mut query_callback);
+                        columns.extend(
+                            machine_columns.into_iter().map(|(name, values)| {
+                                (name, values.into_iter().map(Some).collect())
+                            }),
+                        );
+                    }
+                }
+                self.release_fixed_lookup(&fixed_lookup, stats);
+                stats.finalization_time = finalization_start.elapsed();
+                record_end(OUTER_CODE_NAME);
+                reset_and_print_profile_summary();
+                return Ok(RunOutput::Partial(columns, error));
+            }
+        }
+        if let Err(e) = result {
+            self.release_fixed_lookup(&fixed_lookup, stats);
+            record_end(OUTER_CODE_NAME);
+            reset_and_print_profile_summary();
+            return Err(e);
+        }
+        if window.is_some() {
+            let main_witness = (
+                generator.name().to_string(),
+                self.store_columns(generator.take_window_witness(), selected_columns.as_ref()),
+            );
+            self.release_fixed_lookup(&fixed_lookup, stats);
+            stats.finalization_time = finalization_start.elapsed();
+            record_end(OUTER_CODE_NAME);
+            reset_and_print_profile_summary();
+            return Ok(RunOutput::Complete(vec![main_witness]));
+        }
+        // Get columns from machines
+        let main_witnesses = std::iter::once(&mut generator)
+            .chain(&mut further_generators)
+            .map(|generator| {
+                let columns =
+                    generator.take_witness_columns(&mut fixed_lookup, &mut query_callback);
+                stats.peak_main_machine_rows = max(
+                    stats.peak_main_machine_rows,
+                    generator.peak_resident_rows() as u64,
+                );
+                (
+                    generator.name().to_string(),
+                    select_columns(columns, selected_columns.as_ref()),
+                )
+            })
+            .collect::<Vec<_>>();
+        // Machines only call into each other while the main machine is running.
+        // Finalizing a machine does not access any other machine, so it can be done in parallel.
+        let main_witnesses = main_witnesses.into_iter();
+        let machine_witnesses = if let Some(callback) = machine_witness_callback {
+            // Each machine is released before the next one is finalized.
+            for machine in &mut machines {
+                if let Some(progress) = &mut progress {
+                    progress.machine_started(machine.name());
+                }
+                let (name, columns) =
+                    machine.export_witness(&mut fixed_lookup, &mut query_callback);
+                if let Some(progress) = &mut progress {
+                    progress.machine_finished(&name);
+                }
+                let cells = columns.values().map(|column| column.len() as u64).sum();
+                stats.peak_machine_cells = max(stats.peak_machine_cells, cells);
+                callback(&name, columns);
+            }
+            self.release_fixed_lookup(&fixed_lookup, stats);
+            main_witnesses.collect::<Vec<_>>()
+        } else {
+            self.release_fixed_lookup(&fixed_lookup, stats);
+            let collect_statistics = statistics::is_enabled();
+            // The machines are finalized in parallel, but the callback is not `Sync`.
+            let progress = Mutex::new(progress);
+            let machine_witnesses = machines
+                .par_iter_mut()
+                .map(|m| {
+                    // Each machine starts with the indices built so far by all machines.
+                    let mut fixed_lookup =
+                        self.configure_fixed_lookup(FixedLookup::new(constraints.clone()));
+                    let mut query_callback = query_callback;
+                    // Counters are collected per thread, so they are sent back
+                    // to this thread together with the witness.
+                    let (witness, counters) = statistics::collect(collect_statistics, || {
+                        without_profiling(|| {
+                            if let Some(progress) = progress.lock().unwrap().as_mut() {
+                                progress.machine_started(m.name());
+                            }
+                            let (name, columns) =
+                                m.export_witness(&mut fixed_lookup, &mut query_callback);
+                            if let Some(progress) = progress.lock().unwrap().as_mut() {
+                                progress.machine_finished(&name);
+                            }
+                            (name, self.store_columns(columns, selected_columns.as_ref()))
+                        })
+                    });
+                    fixed_lookup.extend_index(&mut self.fixed_lookup_index.lock().unwrap());
+                    (witness, counters, fixed_lookup.cache_statistics())
+                })
+                .collect::<Vec<_>>()
+                .into_iter()
+                .map(|(witness, counters, (hits, misses))| {
+                    if let Some(counters) = counters {
+                        statistics::add(counters);
+                    }
+                    stats.fixed_lookup_cache_hits += hits;
+                    stats.fixed_lookup_cache_misses += misses;
+                    witness
+                })
+                .collect::<Vec<_>>();
+            // All columns are held until the end.
+            stats.peak_machine_cells = machine_witnesses
+                .iter()
+                .flat_map(|(_, columns)| columns.values())
+                .filter(|column| !column.is_spilled())
+                .map(|column| column.len() as u64)
+                .sum();
+            main_witnesses.chain(machine_witnesses).collect::<Vec<_>>()
+        };
+        stats.finalization_time = finalization_start.elapsed();
+        let (hits, misses) = (
+            stats.fixed_lookup_cache_hits,
+            stats.fixed_lookup_cache_misses,
+        );
+        log::debug!(
+            "Fixed lookup cache: {hits} hits, {misses} misses ({:.1}% hit rate)",
+            hits as f64 * 100.0 / max(hits + misses, 1) as f64
+        );
+
+        record_end(OUTER_CODE_NAME);
+        reset_and_print_profile_summary();
+
+        if send_rows {
+            Ok(RunOutput::Sent)
+        } else {
+            Ok(RunOutput::Complete(machine_witnesses))
+        }
+    }
+}
+
+/// Returns the columns whose names are selected, or all columns.
+fn select_columns<C>(
+    columns: HashMap<String, C>,
+    selected: Option<&HashSet<String>>,
+) -> HashMap<String, C> {
+    columns
+        .into_iter()
+        .filter(|(name, _)| selected.map_or(true, |selected| selected.contains(name)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use powdr_number::GoldilocksField;
+    use powdr_pil_analyzer::analyze_string;
+    use pretty_assertions::assert_eq;
+    use test_log::test;
+
+    use crate::constant_evaluator;
+    use crate::witgen::tests::values;
+    use crate::witgen::unused_query_callback;
+
+    use super::*;
+
+    #[test]
+    fn prepared_generator() {
+        let src = r"
+namespace N(4);
+    col witness x, y;
+    y = 2 * x;
+";
+        let analyzed = analyze_string::<GoldilocksField>(src);
+        let query_callback = unused_query_callback();
+        let prepared = WitnessGenerator::new(&analyzed, &[], &query_callback).prepared();
+        for inputs in [[1, 2, 3, 4], [5, 0, 7, 1]] {
+            let witness =
+                prepared.generate(vec![("N.x".to_string(), values(inputs))], &query_callback);
+            assert_eq!(
+                witness,
+                vec![
+                    ("N.x".to_string(), values(inputs)),
+                    ("N.y".to_string(), values(inputs.map(|x| 2 * x))),
+                ]
+            );
+        }
+    }
+
+    #[test]
+    fn prepared_generator_with_machines() {
+        // The machines are extracted once, but each run creates them again, so the
+        // blocks of the first run do not end up in the witness of the second one.
+        let src = r"
+namespace main(8);
+    col fixed CALL = [1, 0]*;
+    col witness a, b;
+    CALL { a, b } in Sq.LATCH { Sq.x, Sq.y };
+namespace Sq(8);
+    col fixed LATCH = [0, 1]*;
+    col witness x, y;
+    y = x * x;
+";
+        let analyzed = analyze_string::<GoldilocksField>(src);
+        let fixed_cols = constant_evaluator::generate(&analyzed);
+        let query_callback = unused_query_callback();
+        let prepared = WitnessGenerator::new(&analyzed, &fixed_cols, &query_callback).prepared();
+        assert_eq!(prepared.extraction_report().machines.len(), 2);
+        for inputs in [[1, 0, 2, 0, 3, 0, 4, 0], [5, 0, 6, 0, 7, 0, 8, 0]] {
+            let external_witness = vec![("main.a".to_string(), values(inputs))];
+            let witness = prepared.generate(external_witness.clone(), &query_callback);
+            assert_eq!(
+                witness,
+                WitnessGenerator::new(&analyzed, &fixed_cols, &query_callback)
+                    .with_external_witness_values(external_witness)
+                    .generate()
+            );
+            let b = &witness.iter().find(|(name, _)| name == "main.b").unwrap().1;
+            assert_eq!(b[0], values(inputs)[0] * values(inputs)[0]);
+        }
+    }
+
+    #[test]
+    fn prepared_generator_fixed_lookup_index() {
+        let src = r"
+namespace N(4);
+    col fixed X(i) { i };
+    col fixed SQUARE(i) { i * i };
+    col witness x, y;
+    { x, y } in { X, SQUARE };
+";
+        let analyzed = analyze_string::<GoldilocksField>(src);
+        let fixed_cols = constant_evaluator::generate(&analyzed);
+        let query_callback = unused_query_callback();
+        let inputs = || vec![("N.x".to_string(), values([1, 2, 3, 0]))];
+        let expected = vec![
+            ("N.x".to_string(), values([1, 2, 3, 0])),
+            ("N.y".to_string(), values([1, 4, 9, 0])),
+        ];
+        let prepared = WitnessGenerator::new(&analyzed, &fixed_cols, &query_callback).prepared();
+        assert!(prepared.fixed_lookup_index().is_empty());
+        assert_eq!(prepared.generate(inputs(), &query_callback), expected);
+        let index = prepared.fixed_lookup_index();
+        assert_eq!(index.len(), 1);
+        #[cfg(feature = "serde")]
+        let index = {
+            let mut buf = vec![];
+            index.write(&mut buf).unwrap();
+            FixedLookupIndex::read(&mut buf.as_slice()).unwrap()
+        };
+
+        let reused = WitnessGenerator::new(&analyzed, &fixed_cols, &query_callback)
+            .prepared()
+            .try_with_fixed_lookup_index(index.clone())
+            .unwrap();
+        assert_eq!(reused.generate(inputs(), &query_callback), expected);
+
+        let mut other_fixed_cols = fixed_cols.clone();
+        other_fixed_cols[1].1[3] = 10.into();
+        assert!(
+            WitnessGenerator::new(&analyzed, &other_fixed_cols, &query_callback)
+                .prepared()
+                .try_with_fixed_lookup_index(index)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn prepared_generator_inlined_identities() {
+        let src = r"
+namespace N(4);
+    col witness x, y;
+    col inter = x + 1;
+    y = 2 * inter;
+";
+        let analyzed = analyze_string::<GoldilocksField>(src);
+        let query_callback = unused_query_callback();
+        let prepared = WitnessGenerator::new(&analyzed, &[], &query_callback).prepared();
+        assert_eq!(
+            prepared.inlined_identities(),
+            analyzed.identities_with_inlined_intermediate_polynomials()
+        );
+        let witness = prepared.generate(
+            vec![("N.x".to_string(), values([1, 2, 3, 4]))],
+            &query_callback,
+        );
+        assert_eq!(witness[1], ("N.y".to_string(), values([4, 6, 8, 10])));
+    }
+
+    #[test]
+    fn interleaved_columns_of_prepared_generator() {
+        let src = r"
+namespace N(4);
+    col witness a, b, c;
+    b = a + 10;
+    c = 7;
+";
+        let analyzed = analyze_string::<GoldilocksField>(src);
+        let query_callback = unused_query_callback();
+        let prepared = WitnessGenerator::new(&analyzed, &[], &query_callback)
+            .with_interleaved_columns("N.ab", &["N.a", "N.b"])
+            .prepared();
+        for offset in [0, 5] {
+            let inputs = vec![("N.a".to_string(), values((0..4).map(|i| i + offset)))];
+            assert_eq!(
+                prepared.generate(inputs, &query_callback),
+                vec![
+                    (
+                        "N.ab".to_string(),
+                        values((0..4).flat_map(|i| [i + offset, i + offset + 10]))
+                    ),
+                    ("N.c".to_string(), values([7; 4])),
+                ]
+            );
+        }
+    }
+}
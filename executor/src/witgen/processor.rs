@@ -1,8 +1,13 @@
-use std::collections::{BTreeMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt::{self, Display};
+
+use bit_vec::BitVec;
 
 use powdr_ast::{
-    analyzed::{AlgebraicExpression as Expression, AlgebraicReference, Identity, PolyID},
-    parsed::SelectedExpressions,
+    analyzed::{
+        AlgebraicExpression as Expression, AlgebraicReference, Identity, IdentityKind, PolyID,
+    },
+    parsed::{visitor::ExpressionVisitable, SelectedExpressions},
 };
 use powdr_number::{DegreeType, FieldElement};
 use powdr_parser_util::lines::indent;
@@ -11,10 +16,15 @@ use crate::witgen::{query_processor::QueryProcessor, util::try_to_simple_poly, C
 
 use super::{
     affine_expression::AffineExpression,
+    column_storage::SpillConfig,
     data_structures::{column_map::WitnessColumnMap, finalizable_data::FinalizableData},
     identity_processor::IdentityProcessor,
+    provenance::{CellDerivation, ProvenanceLog},
+    range_constraints::RangeConstraint,
     rows::{CellValue, Row, RowPair, RowUpdater, UnknownStrategy},
-    Constraints, EvalError, EvalValue, FixedData, MutableState, QueryCallback,
+    witness_rows::RowSender,
+    Constraints, EvalError, EvalStatus, EvalValue, FixedData, IncompleteCause, MutableState,
+    QueryCallback,
 };
 
 type Left<'a, T> = Vec<AffineExpression<&'a AlgebraicReference, T>>;
@@ -38,6 +48,29 @@ impl<'a, T: FieldElement> OuterQuery<'a, T> {
     }
 }
 
+/// Where the value of a cell came from.
+#[derive(Clone, Copy)]
+enum AssignmentSource<'a, T> {
+    Identity(&'a Identity<Expression<T>>),
+    Queries,
+    Inputs,
+    OuterQuery,
+    /// The cell was fixed before solving, see [crate::witgen::WitnessGenerator::with_fixed_cells].
+    FixedCell,
+}
+
+impl<T: Display> Display for AssignmentSource<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AssignmentSource::Identity(identity) => write!(f, "{identity}"),
+            AssignmentSource::Queries => write!(f, "queries"),
+            AssignmentSource::Inputs => write!(f, "inputs"),
+            AssignmentSource::OuterQuery => write!(f, "outer query"),
+            AssignmentSource::FixedCell => write!(f, "fixed cell"),
+        }
+    }
+}
+
 pub struct IdentityResult {
     /// Whether any progress was made by processing the identity
     pub progress: bool,
@@ -68,6 +101,14 @@ pub struct Processor<'a, 'b, 'c, T: FieldElement, Q: QueryCallback<T>> {
     outer_query: Option<OuterQuery<'a, T>>,
     inputs: BTreeMap<PolyID, T>,
     previously_set_inputs: BTreeMap<PolyID, usize>,
+    /// The sources of the values of the cells in the current row pair, used
+    /// to report conflicting constraints.
+    assignment_sources: HashMap<(usize, PolyID), AssignmentSource<'a, T>>,
+    /// The row index of the last update; sources of earlier rows are forgotten.
+    assignment_sources_row: usize,
+    /// The row of the last call to [Processor::process_queries] and the columns whose
+    /// queries were not answered in that call, with the query strings.
+    unanswered_queries: (usize, Vec<(PolyID, String)>),
 }
 
 impl<'a, 'b, 'c, T: FieldElement, Q: QueryCallback<T>> Processor<'a, 'b, 'c, T, Q> {
@@ -94,6 +135,9 @@ impl<'a, 'b, 'c, T: FieldElement, Q: QueryCallback<T>> Processor<'a, 'b, 'c, T,
             outer_query: None,
             inputs: BTreeMap::new(),
             previously_set_inputs: BTreeMap::new(),
+            assignment_sources: HashMap::new(),
+            assignment_sources_row: 0,
+            unanswered_queries: (0, vec![]),
         }
     }
 
@@ -153,12 +197,38 @@ impl<'a, 'b, 'c, T: FieldElement, Q: QueryCallback<T>> Processor<'a, 'b, 'c, T,
             UnknownStrategy::Unknown,
         );
         let mut updates = EvalValue::complete(vec![]);
+        let mut unanswered = vec![];
         for poly_id in self.fixed_data.witness_cols.keys() {
             if self.is_relevant_witness[&poly_id] {
-                updates.combine(query_processor.process_query(&row_pair, &poly_id)?);
+                let result = query_processor.process_query(&row_pair, &poly_id)?;
+                if let EvalStatus::Incomplete(IncompleteCause::NoQueryAnswer(query, _)) =
+                    &result.status
+                {
+                    unanswered.push((poly_id, query.clone()));
+                }
+                updates.combine(result);
             }
         }
-        Ok(self.apply_updates(row_index, &updates, || "queries".to_string()))
+        self.unanswered_queries = (row_index, unanswered);
+        Ok(self.apply_updates(row_index, &updates, AssignmentSource::Queries))
+    }
+
+    /// Returns an [EvalError::UnansweredQuery] for each cell of the given row whose query
+    /// was not answered by the query callback and whose value is still unknown.
+    pub fn unanswered_queries(&self, row_index: usize) -> Vec<EvalError<T>> {
+        let (row, unanswered) = &self.unanswered_queries;
+        if *row != row_index {
+            return vec![];
+        }
+        unanswered
+            .iter()
+            .filter(|(poly_id, _)| !self.data[row_index][poly_id].value.is_known())
+            .map(|(poly_id, query)| EvalError::UnansweredQuery {
+                column: self.fixed_data.column_name(poly_id).to_string(),
+                query_string: query.clone(),
+                row: self.row_offset + row_index as DegreeType,
+            })
+            .collect()
     }
 
     /// Given a row and identity index, computes any updates and applies them.
@@ -183,21 +253,28 @@ impl<'a, 'b, 'c, T: FieldElement, Q: QueryCallback<T>> Processor<'a, 'b, 'c, T,
         let mut identity_processor = IdentityProcessor::new(self.fixed_data, self.mutable_state);
         let updates = identity_processor
             .process_identity(identity, &row_pair)
-            .map_err(|e| -> EvalError<T> {
-                log::warn!("Error in identity: {identity}");
-                log::warn!(
-                    "Known values in current row (local: {row_index}, global {global_row_index}):\n{}",
-                    self.data[row_index].render_values(false, Some(self.witness_cols)),
-                );
-                if identity.contains_next_ref() {
+            .map_err(|e| {
+                // Keep the path of the machines the error passed through at the outside,
+                // so that it is extended when the error is returned to the calling machine.
+                e.map_keeping_machine_path(|e| -> EvalError<T> {
+                    if let Some(conflict) = self.find_conflict(row_index, identity) {
+                        return conflict;
+                    }
+                    log::warn!("Error in identity: {identity}");
                     log::warn!(
-                        "Known values in next row (local: {}, global {}):\n{}",
-                        row_index + 1,
-                        global_row_index + 1,
-                        self.data[row_index + 1].render_values(false, Some(self.witness_cols)),
+                        "Known values in current row (local: {row_index}, global {global_row_index}):\n{}",
+                        self.data[row_index].render_values(false, Some(self.witness_cols)),
                     );
-                }
-                format!("{identity}:\n{}", indent(&format!("{e}"), "    ")).into()
+                    if identity.contains_next_ref() {
+                        log::warn!(
+                            "Known values in next row (local: {}, global {}):\n{}",
+                            row_index + 1,
+                            global_row_index + 1,
+                            self.data[row_index + 1].render_values(false, Some(self.witness_cols)),
+                        );
+                    }
+                    format!("{identity}:\n{}", indent(&format!("{e}"), "    ")).into()
+                })
             })?;
 
         if unknown_strategy == UnknownStrategy::Zero {
@@ -209,7 +286,7 @@ impl<'a, 'b, 'c, T: FieldElement, Q: QueryCallback<T>> Processor<'a, 'b, 'c, T,
         }
 
         Ok(IdentityResult {
-            progress: self.apply_updates(row_index, &updates, || identity.to_string()),
+            progress: self.apply_updates(row_index, &updates, AssignmentSource::Identity(identity)),
             is_complete: updates.is_complete(),
         })
     }
@@ -245,7 +322,7 @@ impl<'a, 'b, 'c, T: FieldElement, Q: QueryCallback<T>> Processor<'a, 'b, 'c, T,
                 e
             })?;
 
-        let progress = self.apply_updates(row_index, &updates, || "outer query".to_string());
+        let progress = self.apply_updates(row_index, &updates, AssignmentSource::OuterQuery);
 
         let outer_assignments = updates
             .constraints
@@ -294,20 +371,30 @@ impl<'a, 'b, 'c, T: FieldElement, Q: QueryCallback<T>> Processor<'a, 'b, 'c, T,
         for (poly, _) in &input_updates.constraints {
             self.previously_set_inputs.insert(poly.poly_id, row_index);
         }
-        self.apply_updates(row_index, &input_updates, || "inputs".to_string())
+        self.apply_updates(row_index, &input_updates, AssignmentSource::Inputs)
     }
 
     fn apply_updates(
         &mut self,
         row_index: usize,
         updates: &EvalValue<&'a AlgebraicReference, T>,
-        source_name: impl Fn() -> String,
+        source: AssignmentSource<'a, T>,
     ) -> bool {
         if updates.constraints.is_empty() {
             return false;
         }
 
-        log::trace!("    Updates from: {}", source_name());
+        log::trace!("    Updates from: {source}");
+
+        if let Some(log) = &self.fixed_data.provenance {
+            self.record_derivations(log, row_index, updates, source);
+        }
+
+        if row_index != self.assignment_sources_row {
+            self.assignment_sources
+                .retain(|(row, _), _| *row >= row_index);
+            self.assignment_sources_row = row_index;
+        }
 
         // Build RowUpdater
         // (a bit complicated, because we need two mutable
@@ -319,6 +406,10 @@ impl<'a, 'b, 'c, T: FieldElement, Q: QueryCallback<T>> Processor<'a, 'b, 'c, T,
         for (poly, c) in &updates.constraints {
             if self.witness_cols.contains(&poly.poly_id) {
                 row_updater.apply_update(poly, c);
+                if let Constraint::Assignment(_) = c {
+                    self.assignment_sources
+                        .insert((row_index + poly.next as usize, poly.poly_id), source);
+                }
                 progress = true;
             } else if let Constraint::Assignment(v) = c {
                 let left = &mut self.outer_query.as_mut().unwrap().left;
@@ -333,6 +424,116 @@ impl<'a, 'b, 'c, T: FieldElement, Q: QueryCallback<T>> Processor<'a, 'b, 'c, T,
         progress
     }
 
+    /// Records the source of each assignment and the known cells it was derived from.
+    fn record_derivations(
+        &self,
+        log: &ProvenanceLog<T>,
+        row_index: usize,
+        updates: &EvalValue<&'a AlgebraicReference, T>,
+        source: AssignmentSource<'a, T>,
+    ) {
+        let degree = self.fixed_data.degree;
+        let global_row = |row: usize| (self.row_offset + row as DegreeType) % degree;
+        let mut inputs = vec![];
+        if let AssignmentSource::Identity(identity) = source {
+            identity.pre_visit_expressions(&mut |e| {
+                if let Expression::Reference(poly) = e {
+                    let row = row_index + poly.next as usize;
+                    if let (true, CellValue::Known(value)) =
+                        (poly.is_witness(), &self.data[row][&poly.poly_id].value)
+                    {
+                        let input = (poly.poly_id, global_row(row), *value);
+                        if !inputs.contains(&input) {
+                            inputs.push(input);
+                        }
+                    }
+                }
+            });
+        }
+        for (poly, c) in &updates.constraints {
+            if let (true, Constraint::Assignment(value)) =
+                (self.witness_cols.contains(&poly.poly_id), c)
+            {
+                log.record(
+                    poly.poly_id,
+                    global_row(row_index + poly.next as usize),
+                    CellDerivation {
+                        value: *value,
+                        source: source.to_string(),
+                        inputs: inputs.clone(),
+                    },
+                );
+            }
+        }
+    }
+
+    /// Checks if the (failing) polynomial identity would determine a value for a cell
+    /// that was already set to a different value and if so, returns an error
+    /// describing the conflict.
+    fn find_conflict(
+        &self,
+        row_index: usize,
+        identity: &'a Identity<Expression<T>>,
+    ) -> Option<EvalError<T>> {
+        if identity.kind != IdentityKind::Polynomial {
+            return None;
+        }
+        let expression = identity.expression_for_poly_id();
+        let mut cells: Vec<AlgebraicReference> = vec![];
+        expression.pre_visit_expressions(&mut |e| {
+            if let Expression::Reference(poly) = e {
+                if poly.is_witness() && !cells.contains(poly) {
+                    cells.push(poly.clone());
+                }
+            }
+        });
+        cells.into_iter().find_map(|poly| {
+            let row = row_index + poly.next as usize;
+            let first_source = self
+                .assignment_sources
+                .get(&(row, poly.poly_id))
+                .copied()
+                .or_else(|| {
+                    self.fixed_data
+                        .is_fixed_cell(self.row_offset + row as DegreeType, &poly.poly_id)
+                        .then_some(AssignmentSource::FixedCell)
+                })?;
+            let CellValue::Known(first_value) = self.data[row][&poly.poly_id].value else {
+                return None;
+            };
+
+            // Evaluate the identity again, pretending that the cell is not known yet.
+            let mut current = self.data[row_index].clone();
+            let mut next = self.data[row_index + 1].clone();
+            let cell_row = if poly.next { &mut next } else { &mut current };
+            cell_row[&poly.poly_id].value = CellValue::Unknown;
+            let row_pair = RowPair::new(
+                &current,
+                &next,
+                self.row_offset + row_index as u64,
+                self.fixed_data,
+                UnknownStrategy::Unknown,
+            );
+            let second_value = row_pair
+                .evaluate(expression)
+                .ok()?
+                .solve()
+                .ok()?
+                .constraints
+                .into_iter()
+                .find_map(|(p, c)| match c {
+                    Constraint::Assignment(v) if *p == poly => Some(v),
+                    _ => None,
+                })?;
+            (second_value != first_value).then(|| EvalError::ConflictingConstraints {
+                cell: poly.name,
+                row: self.row_offset + row as DegreeType,
+                values: (first_value, second_value),
+                identities: (first_source.to_string(), identity.to_string()),
+            })
+        })
+    }
+
     pub fn len(&self) -> usize {
         self.data.len()
     }
@@ -341,10 +542,34 @@ impl<'a, 'b, 'c, T: FieldElement, Q: QueryCallback<T>> Processor<'a, 'b, 'c, T,
         self.data.finalize_range(range)
     }
 
+    pub fn discard_until(&mut self, end: usize) {
+        self.data.discard_until(end)
+    }
+
+    pub fn spill_until(&mut self, end: usize, spill: &SpillConfig) {
+        self.data.spill_until(end, spill)
+    }
+
+    pub fn send_until(&mut self, end: usize, row_sender: &RowSender<T>) {
+        self.data.send_until(end, row_sender)
+    }
+
     pub fn row(&self, i: usize) -> &Row<'a, T> {
         &self.data[i]
     }
 
+    pub fn column_ids(&self) -> &[PolyID] {
+        self.data.column_ids()
+    }
+
+    pub fn known_values(&self, i: usize) -> (Vec<T>, BitVec) {
+        self.data.known_values(i)
+    }
+
+    pub fn range_constraints(&self, i: usize) -> Vec<(usize, RangeConstraint<T>)> {
+        self.data.range_constraints(i)
+    }
+
     pub fn has_outer_query(&self) -> bool {
         self.outer_query.is_some()
     }
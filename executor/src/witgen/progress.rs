@@ -0,0 +1,165 @@
+use powdr_number::{DegreeType, FieldElement};
+
+use super::WitnessGenerator;
+
+/// An event reported to a [ProgressCallback] during witness generation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProgressEvent<'a> {
+    /// The machine started running.
+    MachineStarted { machine: &'a str },
+    /// The machine is about to process the given row, out of `degree` rows.
+    Row {
+        machine: &'a str,
+        row: DegreeType,
+        degree: DegreeType,
+    },
+    /// The machine finished running.
+    MachineFinished { machine: &'a str },
+}
+
+/// A callback that is informed about the progress of witness generation.
+pub trait ProgressCallback: FnMut(ProgressEvent<'_>) + Send {}
+impl<F> ProgressCallback for F where F: FnMut(ProgressEvent<'_>) + Send {}
+
+/// Forwards events to a [ProgressCallback], reporting only every `frequency`-th row.
+pub struct ProgressReporter<'p> {
+    callback: &'p mut dyn ProgressCallback,
+    frequency: DegreeType,
+}
+
+impl<'p> ProgressReporter<'p> {
+    pub fn new(callback: &'p mut dyn ProgressCallback, frequency: DegreeType) -> Self {
+        assert!(
+            frequency > 0,
+            "Progress reporting frequency must be positive."
+        );
+        Self {
+            callback,
+            frequency,
+        }
+    }
+
+    /// Returns a reporter to the same callback that borrows from this one.
+    pub fn reborrow(&mut self) -> ProgressReporter<'_> {
+        ProgressReporter {
+            callback: &mut *self.callback,
+            frequency: self.frequency,
+        }
+    }
+
+    pub fn frequency(&self) -> DegreeType {
+        self.frequency
+    }
+
+    /// Reports the event, regardless of the frequency.
+    pub fn report(&mut self, event: ProgressEvent<'_>) {
+        (self.callback)(event);
+    }
+
+    pub fn machine_started(&mut self, machine: &str) {
+        (self.callback)(ProgressEvent::MachineStarted { machine });
+    }
+
+    pub fn machine_finished(&mut self, machine: &str) {
+        (self.callback)(ProgressEvent::MachineFinished { machine });
+    }
+
+    /// Reports the row if it is a multiple of the frequency.
+    #[inline]
+    pub fn row(&mut self, machine: &str, row: DegreeType, degree: DegreeType) {
+        if row % self.frequency == 0 {
+            (self.callback)(ProgressEvent::Row {
+                machine,
+                row,
+                degree,
+            });
+        }
+    }
+}
+
+impl<'a, 'b, T: FieldElement> WitnessGenerator<'a, 'b, T> {
+    /// Sets a callback that is informed when the main machine starts and finishes
+    /// and every `frequency` rows in between, and when each other machine starts and
+    /// finishes being finalized. Since other machines are finalized in parallel, their
+    /// events can be interleaved.
+    pub fn with_progress_callback(
+        self,
+        progress_callback: &'b mut dyn ProgressCallback,
+        frequency: DegreeType,
+    ) -> Self {
+        WitnessGenerator {
+            progress: Some(ProgressReporter::new(progress_callback, frequency)),
+            ..self
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use powdr_number::GoldilocksField;
+    use powdr_pil_analyzer::analyze_string;
+    use pretty_assertions::assert_eq;
+    use test_log::test;
+
+    use crate::constant_evaluator;
+    use crate::witgen::tests::ARRAY_SRC;
+    use crate::witgen::unused_query_callback;
+
+    use super::*;
+
+    #[test]
+    fn progress_events() {
+        let analyzed = analyze_string::<GoldilocksField>(ARRAY_SRC);
+        let fixed_cols = constant_evaluator::generate(&analyzed);
+        let query_callback = unused_query_callback();
+        let mut events = vec![];
+        let mut callback = |event: ProgressEvent<'_>| events.push(format!("{event:?}"));
+        WitnessGenerator::new(&analyzed, &fixed_cols, &query_callback)
+            .with_progress_callback(&mut callback, 4)
+            .generate();
+        let row = |row| format!("Row {{ machine: \"Main Machine\", row: {row}, degree: 8 }}");
+        assert_eq!(
+            events,
+            vec![
+                "MachineStarted { machine: \"Main Machine\" }".to_string(),
+                row(0),
+                row(4),
+                row(8),
+                "MachineFinished { machine: \"Main Machine\" }".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn progress_events_of_secondary_machines() {
+        let src = r"
+namespace Main(8);
+    col fixed CALL = [1, 0]*;
+    col witness a, b;
+    a = 3;
+    CALL { a, b } in Sq.LATCH { Sq.x, Sq.y };
+namespace Sq(8);
+    col fixed LATCH = [0, 1]*;
+    col witness x, y;
+    y = x * x;
+";
+        let analyzed = analyze_string::<GoldilocksField>(src);
+        let fixed_cols = constant_evaluator::generate(&analyzed);
+        let query_callback = unused_query_callback();
+        let mut events = vec![];
+        let mut callback = |event: ProgressEvent<'_>| match event {
+            ProgressEvent::MachineStarted { machine } => events.push((true, machine.to_string())),
+            ProgressEvent::MachineFinished { machine } => events.push((false, machine.to_string())),
+            ProgressEvent::Row { .. } => {}
+        };
+        WitnessGenerator::new(&analyzed, &fixed_cols, &query_callback)
+            .with_progress_callback(&mut callback, 4)
+            .generate();
+        let main = "Main Machine".to_string();
+        assert_eq!(events.len(), 4);
+        assert_eq!(events[..2], [(true, main.clone()), (false, main.clone())]);
+        let secondary = events[2].1.clone();
+        assert_ne!(secondary, main);
+        assert_eq!(events[2..], [(true, secondary.clone()), (false, secondary)]);
+    }
+}
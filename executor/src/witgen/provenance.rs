@@ -0,0 +1,176 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+
+use powdr_ast::analyzed::{PolyID, PolynomialType};
+use powdr_number::{DegreeType, FieldElement};
+
+use super::WitnessGenerator;
+
+/// How the value of a witness cell was determined by the solver.
+#[derive(Clone, Debug)]
+pub(crate) struct CellDerivation<T> {
+    pub value: T,
+    /// The identity (or other source like "queries") that determined the value.
+    pub source: String,
+    /// The witness cells that were known when the value was determined and that are
+    /// referenced by the identity, by column and row.
+    pub inputs: Vec<(PolyID, DegreeType, T)>,
+}
+
+/// The derivations of the cells of a run, see [crate::witgen::WitnessGenerator::explain_cell].
+/// Only the first derivation of each cell is kept.
+#[derive(Default)]
+pub(crate) struct ProvenanceLog<T> {
+    derivations: Mutex<HashMap<(PolyID, DegreeType), CellDerivation<T>>>,
+}
+
+impl<T> ProvenanceLog<T> {
+    pub fn record(&self, poly_id: PolyID, row: DegreeType, derivation: CellDerivation<T>) {
+        self.derivations
+            .lock()
+            .unwrap()
+            .entry((poly_id, row))
+            .or_insert(derivation);
+    }
+
+    pub fn take(&self) -> HashMap<(PolyID, DegreeType), CellDerivation<T>> {
+        std::mem::take(&mut self.derivations.lock().unwrap())
+    }
+}
+
+/// Renders the derivation of the cell and, indented below it, of its inputs.
+pub(crate) fn explain<T: FieldElement>(
+    derivations: &HashMap<(PolyID, DegreeType), CellDerivation<T>>,
+    column_name: &impl Fn(&PolyID) -> String,
+    poly_id: PolyID,
+    row: DegreeType,
+    value: T,
+) -> String {
+    let mut out = String::new();
+    explain_into(
+        &mut out,
+        derivations,
+        column_name,
+        (poly_id, row, value),
+        0,
+        &mut HashSet::new(),
+    );
+    out
+}
+
+fn explain_into<T: FieldElement>(
+    out: &mut String,
+    derivations: &HashMap<(PolyID, DegreeType), CellDerivation<T>>,
+    column_name: &impl Fn(&PolyID) -> String,
+    (poly_id, row, value): (PolyID, DegreeType, T),
+    depth: usize,
+    explained: &mut HashSet<(PolyID, DegreeType)>,
+) {
+    let indent = "  ".repeat(depth);
+    let cell = format!("{} in row {row} = {value}", column_name(&poly_id));
+    let Some(derivation) = derivations.get(&(poly_id, row)) else {
+        writeln!(
+            out,
+            "{indent}{cell}: not derived by the solver (e.g. an external witness value, \
+             a value copied from a repeating row or a default value)"
+        )
+        .unwrap();
+        return;
+    };
+    if !explained.insert((poly_id, row)) {
+        writeln!(out, "{indent}{cell}: see above").unwrap();
+        return;
+    }
+    writeln!(out, "{indent}{cell}: from {}", derivation.source).unwrap();
+    for input in &derivation.inputs {
+        explain_into(out, derivations, column_name, *input, depth + 1, explained);
+    }
+}
+
+impl<'a, 'b, T: FieldElement> WitnessGenerator<'a, 'b, T> {
+    /// Generates the witness while recording how the value of each cell was derived and
+    /// returns a human-readable explanation of the value of the given cell: The identity
+    /// that determined it and, recursively, the explanations of the cells that were
+    /// known at that point and are referenced by the identity.
+    /// Cells whose values were not determined by the solver (e.g. external witness
+    /// values) end the recursion.
+    /// Recording makes generation considerably slower, so this is only meant for
+    /// debugging.
+    /// Panics if the column is not a witness column or the row is out of range.
+    pub fn explain_cell(self, poly_id: PolyID, row: DegreeType) -> String {
+        let degree = self.degree();
+        assert!(
+            poly_id.ptype == PolynomialType::Committed,
+            "Only witness cells can be explained."
+        );
+        assert!(
+            row < degree,
+            "Row {row} is out of range for degree {degree}."
+        );
+        let names = self
+            .analyzed
+            .committed_polys_in_source_order()
+            .into_iter()
+            .flat_map(|(poly, _)| poly.array_elements())
+            .map(|(name, poly_id)| (poly_id, name))
+            .collect::<HashMap<_, _>>();
+        let log = Arc::new(ProvenanceLog::default());
+        let witness = WitnessGenerator {
+            provenance: Some(log.clone()),
+            ..self
+        }
+        .generate();
+        let name = &names[&poly_id];
+        let (_, values) = witness.iter().find(|(n, _)| n == name).unwrap();
+        explain(
+            &log.take(),
+            &|poly_id| names[poly_id].clone(),
+            poly_id,
+            row,
+            values[row as usize],
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use powdr_number::GoldilocksField;
+    use powdr_pil_analyzer::analyze_string;
+    use pretty_assertions::assert_eq;
+    use test_log::test;
+
+    use crate::constant_evaluator;
+    use crate::witgen::unused_query_callback;
+
+    use super::*;
+
+    #[test]
+    fn explain_cell() {
+        let src = r"
+namespace N(4);
+    col fixed A(i) { i };
+    col witness a, b, c;
+    a = A + 1;
+    b = 2 * a;
+    c = a + b;
+";
+        let analyzed = analyze_string::<GoldilocksField>(src);
+        let fixed_cols = constant_evaluator::generate(&analyzed);
+        let query_callback = unused_query_callback();
+        let c: PolyID = (&analyzed.definitions["N.c"].0).into();
+        let explanation =
+            WitnessGenerator::new(&analyzed, &fixed_cols, &query_callback).explain_cell(c, 2);
+        let lines = explanation.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 4, "{explanation}");
+        let expected = [
+            "N.c in row 2 = 9: from N.c = ",
+            "  N.a in row 2 = 3: from N.a = ",
+            "  N.b in row 2 = 6: from N.b = ",
+            "    N.a in row 2 = 3: see above",
+        ];
+        for (line, expected) in lines.iter().zip(expected) {
+            assert!(line.starts_with(expected), "{explanation}");
+        }
+    }
+}
@@ -1,6 +1,5 @@
 use std::{fmt::Display, rc::Rc};
 
-use num_traits::ToPrimitive;
 use powdr_ast::analyzed::{
     types::TypedExpression, AlgebraicReference, Expression, FunctionValueDefinition, PolyID,
     PolynomialType,
@@ -8,7 +7,41 @@ use powdr_ast::analyzed::{
 use powdr_number::{DegreeType, FieldElement};
 use powdr_pil_analyzer::evaluator::{self, Custom, EvalError, SymbolLookup, Value};
 
-use super::{rows::RowPair, Constraint, EvalResult, EvalValue, FixedData, IncompleteCause};
+use super::{
+    rows::RowPair, BatchQueryCallback, Constraint, EvalResult, EvalValue, FixedData,
+    IncompleteCause,
+};
+
+/// A query whose arguments are already evaluated, e.g. `("input", 5)` has the name
+/// `input` and the single argument `5`.
+/// Queries are only available in this form if they are a tuple of a string and
+/// values that can be converted to field elements, or just a string.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Query<T> {
+    pub name: String,
+    pub arguments: Vec<T>,
+}
+
+impl<T: FieldElement> Query<T> {
+    fn try_from_value<C: Custom>(value: &Value<'_, T, C>) -> Option<Self> {
+        let (name, arguments) = match value {
+            Value::String(name) => (name, &[][..]),
+            Value::Tuple(items) => match items.split_first()? {
+                (Value::String(name), arguments) => (name, arguments),
+                _ => return None,
+            },
+            _ => return None,
+        };
+        let arguments = arguments
+            .iter()
+            .map(|argument| argument.clone().try_to_field_element().ok())
+            .collect::<Option<Vec<_>>>()?;
+        Some(Self {
+            name: name.clone(),
+            arguments,
+        })
+    }
+}
 
 /// Computes value updates that result from a query.
 pub struct QueryProcessor<'a, 'b, T: FieldElement, QueryCallback: Send + Sync> {
@@ -38,13 +71,17 @@ impl<'a, 'b, T: FieldElement, QueryCallback: super::QueryCallback<T>>
         Ok(EvalValue::complete(vec![]))
     }
 
+    /// Evaluates the query and passes it to the query callback.
+    /// Queries can reference the witness cells of the current and the next row. If one of
+    /// them is not known yet, the callback is not invoked and the query is retried once more
+    /// cells are known, so queries can depend on the answers to other queries.
     fn process_witness_query(
         &mut self,
         query: &'a Expression<T>,
         poly: &'a AlgebraicReference,
         rows: &RowPair<T>,
     ) -> EvalResult<'a, T> {
-        let query_str = match self.interpolate_query(query, rows) {
+        let (query_str, parsed_query) = match self.interpolate_query(query, rows) {
             Ok(query) => query,
             Err(e) => {
                 return match e {
@@ -61,10 +98,21 @@ impl<'a, 'b, T: FieldElement, QueryCallback: super::QueryCallback<T>>
                 };
             }
         };
+        let answer = if let Some(callback) = self.fixed_data.batch_query_callback {
+            self.batch_query(callback, rows.current_row_index, &poly.name, &query_str)
+        } else {
+            match self.fixed_data.parsed_query_callback.zip(parsed_query) {
+                Some((callback, parsed_query)) => callback(&parsed_query),
+                None => (self.query_callback)(&query_str),
+            }
+        };
         Ok(
-            if let Some(value) =
-                (self.query_callback)(&query_str).map_err(super::EvalError::ProverQueryError)?
-            {
+            if let Some(value) = answer.map_err(|e| {
+                super::EvalError::ProverQueryError(format!(
+                    "Query \"{query_str}\" for column {} failed on row {}: {e}",
+                    poly.name, rows.current_row_index
+                ))
+            })? {
                 EvalValue::complete(vec![(poly, Constraint::Assignment(value))])
             } else {
                 EvalValue::incomplete(IncompleteCause::NoQueryAnswer(
@@ -75,11 +123,37 @@ impl<'a, 'b, T: FieldElement, QueryCallback: super::QueryCallback<T>>
         )
     }
 
+    /// Returns the value of the column in the given row from the answers of the batch
+    /// query callback, which is only invoked if it has not returned the value yet.
+    fn batch_query(
+        &self,
+        callback: &dyn BatchQueryCallback<T>,
+        row: DegreeType,
+        column: &str,
+        query: &str,
+    ) -> Result<Option<T>, String> {
+        let mut answers = self.fixed_data.batch_query_answers.lock().unwrap();
+        match &*answers {
+            Some((answers_row, values)) if *answers_row == row && values.contains_key(column) => {
+                return Ok(Some(values[column]));
+            }
+            _ => {}
+        }
+        let values = callback(row, query)?;
+        let value = values.get(column).copied();
+        match &mut *answers {
+            Some((answers_row, existing)) if *answers_row == row => existing.extend(values),
+            _ => *answers = Some((row, values)),
+        }
+        Ok(value)
+    }
+
+    /// Evaluates the query, returning it as a string and, if possible, in parsed form.
     fn interpolate_query(
         &self,
         query: &'a Expression<T>,
         rows: &RowPair<T>,
-    ) -> Result<String, EvalError> {
+    ) -> Result<(String, Option<Query<T>>), EvalError> {
         let arguments = vec![Rc::new(Value::Integer(num_bigint::BigInt::from(
             rows.current_row_index,
         )))];
@@ -88,7 +162,8 @@ impl<'a, 'b, T: FieldElement, QueryCallback: super::QueryCallback<T>>
             rows,
         };
         let fun = evaluator::evaluate(query, &symbols)?;
-        evaluator::evaluate_function_call(fun, arguments, &symbols).map(|v| v.to_string())
+        let value = evaluator::evaluate_function_call(fun, arguments, &symbols)?;
+        Ok((value.to_string(), Query::try_from_value(&value)))
     }
 }
 
@@ -157,12 +232,8 @@ impl<'a, T: FieldElement> SymbolLookup<'a, T, Reference<'a>> for Symbols<'a, T>
                     .get_value(&poly_ref)
                     .ok_or(EvalError::DataNotAvailable)?
             }
-            PolynomialType::Constant => {
-                let values = self.fixed_data.fixed_cols[&function.poly_id].values;
-                values[(usize::try_from(row).unwrap() % values.len())
-                    .to_u64()
-                    .unwrap() as usize]
-            }
+            PolynomialType::Constant => self.fixed_data.fixed_cols[&function.poly_id]
+                .value(DegreeType::try_from(row).unwrap()),
         }))
     }
 }
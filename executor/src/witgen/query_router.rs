@@ -0,0 +1,213 @@
+use std::collections::BTreeMap;
+
+use powdr_number::FieldElement;
+
+use super::{BatchQueryCallback, ParsedQueryCallback, QueryCallback, WitnessGenerator};
+
+/// Dispatches queries to callbacks registered for a prefix of the query name.
+///
+/// The query name is the first element of the query tuple without quotes,
+/// e.g. `input` for the query `("input", 3)`. If the prefixes of several callbacks
+/// match, the callback with the longest prefix is used. Queries that do not match
+/// any prefix are passed to the fallback callback, if there is one.
+pub struct QueryRouter<T> {
+    routes: BTreeMap<String, Box<dyn QueryCallback<T>>>,
+    fallback: Option<Box<dyn QueryCallback<T>>>,
+}
+
+impl<T> Default for QueryRouter<T> {
+    fn default() -> Self {
+        Self {
+            routes: Default::default(),
+            fallback: None,
+        }
+    }
+}
+
+impl<T: 'static> QueryRouter<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers a callback for all queries whose name starts with `prefix`.
+    /// Panics if a callback is already registered for the prefix.
+    pub fn route(mut self, prefix: &str, callback: impl QueryCallback<T> + 'static) -> Self {
+        let previous = self.routes.insert(prefix.to_string(), Box::new(callback));
+        assert!(
+            previous.is_none(),
+            "A callback for the query prefix \"{prefix}\" is already registered."
+        );
+        self
+    }
+
+    /// Sets the callback for queries that do not match any prefix.
+    pub fn fallback(self, callback: impl QueryCallback<T> + 'static) -> Self {
+        Self {
+            fallback: Some(Box::new(callback)),
+            ..self
+        }
+    }
+
+    /// Answers the query using the callback of the longest matching prefix.
+    pub fn query(&self, query: &str) -> Result<Option<T>, String> {
+        let name = query_name(query);
+        let callback = self
+            .routes
+            .iter()
+            .filter(|(prefix, _)| name.starts_with(prefix.as_str()))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map(|(_, callback)| callback)
+            .or(self.fallback.as_ref())
+            .ok_or_else(|| format!("No callback registered for query: {query}"))?;
+        callback(query)
+    }
+
+    /// Turns the router into a [QueryCallback].
+    pub fn into_query_callback(self) -> impl QueryCallback<T> {
+        move |query: &str| self.query(query)
+    }
+}
+
+/// Returns the first element of the query tuple without quotes,
+/// or the whole query if it is not a tuple.
+pub(super) fn query_name(query: &str) -> &str {
+    let Some(elements) = query.trim().strip_prefix('(') else {
+        return query;
+    };
+    let end = elements.find([',', ')']).unwrap_or(elements.len());
+    elements[..end].trim().trim_matches('"')
+}
+
+impl<'a, 'b, T: FieldElement> WitnessGenerator<'a, 'b, T> {
+    /// Answers all queries that consist of a string and arguments that evaluate to field
+    /// elements (e.g. `("input", 5)`) using `callback`, which receives them in parsed
+    /// form, so that they do not have to be parsed from the query string.
+    /// All other queries are still answered by the query callback.
+    pub fn with_parsed_query_callback(self, callback: &'b dyn ParsedQueryCallback<T>) -> Self {
+        WitnessGenerator {
+            parsed_query_callback: Some(callback),
+            ..self
+        }
+    }
+
+    /// Answers all queries using `callback`, which can return the values of several
+    /// columns of the row at once (e.g. the quotient and the remainder of a division).
+    /// The values are kept until a query of another row is processed, so the queries
+    /// of the other columns of the row are answered without invoking the callback again.
+    /// Takes precedence over the query callback and the parsed query callback.
+    pub fn with_batch_query_callback(self, callback: &'b dyn BatchQueryCallback<T>) -> Self {
+        WitnessGenerator {
+            batch_query_callback: Some(callback),
+            ..self
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use powdr_number::{DegreeType, GoldilocksField};
+    use powdr_pil_analyzer::analyze_string;
+    use pretty_assertions::assert_eq;
+    use test_log::test;
+
+    use crate::constant_evaluator;
+    use crate::witgen::tests::values;
+    use crate::witgen::{unused_query_callback, Query};
+
+    use super::*;
+
+    fn answer(value: u64) -> impl QueryCallback<GoldilocksField> {
+        move |_: &str| Ok(Some(value.into()))
+    }
+
+    #[test]
+    fn query_names() {
+        assert_eq!(query_name("(\"input\", 3)"), "input");
+        assert_eq!(query_name("( \"print_char\" )"), "print_char");
+        assert_eq!(query_name("\"hint\""), "\"hint\"");
+    }
+
+    #[test]
+    fn dispatch_precedence() {
+        let callback = QueryRouter::new()
+            .route("input", answer(1))
+            .route("bootloader", answer(2))
+            .route("bootloader_input", answer(3))
+            .fallback(answer(4))
+            .into_query_callback();
+        assert_eq!(callback("(\"input\", 0)"), Ok(Some(1.into())));
+        assert_eq!(callback("(\"bootloader_init\", 0)"), Ok(Some(2.into())));
+        assert_eq!(callback("(\"bootloader_input\", 0)"), Ok(Some(3.into())));
+        assert_eq!(callback("(\"print_char\", 65)"), Ok(Some(4.into())));
+    }
+
+    #[test]
+    fn no_fallback() {
+        let router = QueryRouter::new().route("input", answer(1));
+        assert!(router.query("(\"hint\", 0)").is_err());
+    }
+
+    #[test]
+    fn parsed_query_callback() {
+        let src = r#"
+namespace N(4);
+    col witness x(i) query ("input", 2 * i);
+    col witness y(i) query ("hint", "text");
+"#;
+        let analyzed = analyze_string::<GoldilocksField>(src);
+        let fixed_cols = constant_evaluator::generate(&analyzed);
+        let query_callback = |query: &str| -> Result<Option<GoldilocksField>, String> {
+            assert_eq!(query, r#"("hint", "text")"#);
+            Ok(Some(7.into()))
+        };
+        let parsed_query_callback =
+            |query: &Query<GoldilocksField>| -> Result<Option<GoldilocksField>, String> {
+                assert_eq!(query.name, "input");
+                Ok(Some(query.arguments[0] + GoldilocksField::from(1)))
+            };
+        let witness = WitnessGenerator::new(&analyzed, &fixed_cols, &query_callback)
+            .with_parsed_query_callback(&parsed_query_callback)
+            .generate();
+        assert_eq!(witness[0], ("N.x".to_string(), values([1, 3, 5, 7])));
+        assert_eq!(witness[1], ("N.y".to_string(), values([7, 7, 7, 7])));
+    }
+
+    #[test]
+    fn batch_query_callback() {
+        let src = r#"
+namespace N(4);
+    col fixed X(i) { i + 10 };
+    col witness q(i) query ("div", X(i));
+    col witness r(i) query ("div", X(i));
+    X = 3 * q + r;
+"#;
+        let analyzed = analyze_string::<GoldilocksField>(src);
+        let fixed_cols = constant_evaluator::generate(&analyzed);
+        let query_callback = unused_query_callback();
+        let calls = std::sync::atomic::AtomicUsize::new(0);
+        let batch_query_callback =
+            |row: DegreeType, _: &str| -> Result<HashMap<String, GoldilocksField>, String> {
+                calls.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                let x = row + 10;
+                Ok([
+                    ("N.q".to_string(), (x / 3).into()),
+                    ("N.r".to_string(), (x % 3).into()),
+                ]
+                .into_iter()
+                .collect())
+            };
+        let witness = WitnessGenerator::new(&analyzed, &fixed_cols, &query_callback)
+            .with_batch_query_callback(&batch_query_callback)
+            .generate();
+        assert_eq!(
+            witness,
+            vec![
+                ("N.q".to_string(), values([3, 3, 4, 4])),
+                ("N.r".to_string(), values([1, 2, 0, 1])),
+            ]
+        );
+        assert_eq!(calls.into_inner(), 4);
+    }
+}
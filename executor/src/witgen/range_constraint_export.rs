@@ -0,0 +1,222 @@
+//! An export of the global range constraints of all columns as JSON, so that tools
+//! outside of powdr (e.g. constraint compilers of other proving stacks) can import them.
+//!
+//! The document contains the version of the schema, the modulus of the field and one
+//! entry per column (in source order, witness columns first):
+//!
+//! ```json
+//! {
+//!   "version": 1,
+//!   "modulus": "18446744069414584321",
+//!   "columns": [
+//!     { "name": "main.x", "kind": "witness", "constraint": { "type": "mask", "mask": "0x1" } },
+//!     { "name": "main.y", "kind": "witness",
+//!       "constraint": { "type": "interval", "min": "18446744069414584193", "max": "127", "mask": "0xffffffffffffffff" } },
+//!     { "name": "main.z", "kind": "witness", "constraint": null }
+//!   ]
+//! }
+//! ```
+//!
+//! A `mask` constraint is exactly "x & mask == x". An `interval` constraint is the
+//! conjunction of the inclusive range from `min` to `max` (which wraps around if `min`
+//! is larger than `max`) and the bit mask. Field elements are decimal strings, masks
+//! are hexadecimal strings, so that no precision is lost in JSON numbers.
+
+use powdr_number::{BigInt, FieldElement};
+use serde::{Deserialize, Serialize};
+
+use super::range_constraints::RangeConstraint;
+
+/// The version of the schema, incremented on every incompatible change.
+pub const RANGE_CONSTRAINTS_SCHEMA_VERSION: u32 = 1;
+
+/// The kind of a column in the export.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ColumnKind {
+    Witness,
+    Fixed,
+}
+
+/// The range constraint of a column, see [import_range_constraints_json].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExportedRangeConstraint<T: FieldElement> {
+    pub name: String,
+    pub kind: ColumnKind,
+    /// The constraint, `None` if nothing is known about the column.
+    pub constraint: Option<RangeConstraint<T>>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Document {
+    version: u32,
+    modulus: String,
+    columns: Vec<Column>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Column {
+    name: String,
+    kind: ColumnKind,
+    constraint: Option<Constraint>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum Constraint {
+    Mask {
+        mask: String,
+    },
+    Interval {
+        min: String,
+        max: String,
+        mask: String,
+    },
+}
+
+impl Constraint {
+    fn new<T: FieldElement>(constraint: &RangeConstraint<T>) -> Self {
+        let mask = format!("0x{:x}", constraint.mask());
+        if *constraint == RangeConstraint::from_mask(*constraint.mask()) {
+            Constraint::Mask { mask }
+        } else {
+            let (min, max) = constraint.range();
+            Constraint::Interval {
+                min: min.to_integer().to_string(),
+                max: max.to_integer().to_string(),
+                mask,
+            }
+        }
+    }
+
+    fn to_range_constraint<T: FieldElement>(&self) -> Result<RangeConstraint<T>, String> {
+        Ok(match self {
+            Constraint::Mask { mask } => RangeConstraint::from_mask(parse_mask::<T>(mask)?),
+            Constraint::Interval { min, max, mask } => RangeConstraint::from_parts(
+                parse_mask::<T>(mask)?,
+                parse_value(min)?,
+                parse_value(max)?,
+            ),
+        })
+    }
+}
+
+/// Serializes the range constraints of the columns to JSON, see the module documentation
+/// for the schema.
+pub fn export_range_constraints_json<T: FieldElement>(
+    columns: impl IntoIterator<Item = ExportedRangeConstraint<T>>,
+) -> String {
+    let document = Document {
+        version: RANGE_CONSTRAINTS_SCHEMA_VERSION,
+        modulus: T::modulus().to_string(),
+        columns: columns
+            .into_iter()
+            .map(|column| Column {
+                name: column.name,
+                kind: column.kind,
+                constraint: column.constraint.as_ref().map(Constraint::new),
+            })
+            .collect(),
+    };
+    serde_json::to_string_pretty(&document).unwrap()
+}
+
+/// Reads range constraints written by [export_range_constraints_json].
+/// Fails if the document has a different version of the schema or was written for a
+/// different field.
+pub fn import_range_constraints_json<T: FieldElement>(
+    json: &str,
+) -> Result<Vec<ExportedRangeConstraint<T>>, String> {
+    let document: Document = serde_json::from_str(json).map_err(|e| e.to_string())?;
+    if document.version != RANGE_CONSTRAINTS_SCHEMA_VERSION {
+        return Err(format!(
+            "Unsupported range constraint schema version {}, expected {RANGE_CONSTRAINTS_SCHEMA_VERSION}.",
+            document.version
+        ));
+    }
+    if document.modulus != T::modulus().to_string() {
+        return Err(format!(
+            "The range constraints were exported for a field with modulus {}, but the field has modulus {}.",
+            document.modulus,
+            T::modulus()
+        ));
+    }
+    document
+        .columns
+        .into_iter()
+        .map(|column| {
+            Ok(ExportedRangeConstraint {
+                constraint: column
+                    .constraint
+                    .map(|c| c.to_range_constraint())
+                    .transpose()
+                    .map_err(|e| format!("Invalid constraint of column {}: {e}", column.name))?,
+                name: column.name,
+                kind: column.kind,
+            })
+        })
+        .collect()
+}
+
+fn parse_mask<T: FieldElement>(mask: &str) -> Result<T::Integer, String> {
+    mask.strip_prefix("0x")
+        .and_then(|digits| num_bigint::BigUint::parse_bytes(digits.as_bytes(), 16))
+        .and_then(|mask| T::Integer::try_from(mask).ok())
+        .ok_or_else(|| format!("Invalid mask: {mask}"))
+}
+
+fn parse_value<T: FieldElement>(value: &str) -> Result<T, String> {
+    let parsed = T::from_str_radix(value, 10)?;
+    // Reject values that are reduced when parsing, they are not field elements.
+    if parsed.to_integer().to_string() != value {
+        return Err(format!("Invalid field element: {value}"));
+    }
+    Ok(parsed)
+}
+
+#[cfg(test)]
+mod tests {
+    use powdr_number::{Bn254Field, GoldilocksField};
+
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let columns = vec![
+            ExportedRangeConstraint {
+                name: "main.x".to_string(),
+                kind: ColumnKind::Witness,
+                constraint: Some(RangeConstraint::from_max_bit(7)),
+            },
+            ExportedRangeConstraint {
+                name: "main.y".to_string(),
+                kind: ColumnKind::Witness,
+                constraint: Some(RangeConstraint::from_range((-128).into(), 127.into())),
+            },
+            ExportedRangeConstraint {
+                name: "main.z".to_string(),
+                kind: ColumnKind::Witness,
+                constraint: None,
+            },
+            ExportedRangeConstraint {
+                name: "main.BYTE".to_string(),
+                kind: ColumnKind::Fixed,
+                constraint: Some(
+                    RangeConstraint::from_range(3.into(), 200.into())
+                        .conjunction(&RangeConstraint::from_mask(0xfeu64)),
+                ),
+            },
+        ];
+        let json = export_range_constraints_json::<GoldilocksField>(columns.clone());
+        assert!(json.contains(r#""type": "mask""#));
+        assert!(json.contains(r#""type": "interval""#));
+        assert_eq!(import_range_constraints_json(&json), Ok(columns));
+    }
+
+    #[test]
+    fn mismatched_modulus() {
+        let json = export_range_constraints_json::<GoldilocksField>(vec![]);
+        let err = import_range_constraints_json::<Bn254Field>(&json).unwrap_err();
+        assert!(err.contains("modulus"), "{err}");
+    }
+}
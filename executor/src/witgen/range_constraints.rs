@@ -62,6 +62,13 @@ impl<T: FieldElement> RangeConstraint<T> {
         Self { mask, min, max }
     }
 
+    /// Constraint that is the conjunction of the bit mask and the min-max range,
+    /// taken as they are, i.e. the inverse of [RangeConstraint::mask] and
+    /// [RangeConstraint::range].
+    pub(crate) fn from_parts(mask: T::Integer, min: T, max: T) -> Self {
+        Self { mask, min, max }
+    }
+
     /// Returns a bit mask. This might be drastically underfitted in case
     /// the constraint is more resembling an interval.
     /// Semantics: X & mask == X holds for all possible values of X.
@@ -77,6 +84,30 @@ impl<T: FieldElement> RangeConstraint<T> {
         (self.min, self.max)
     }
 
+    /// Returns true if the constraint is a signed interval, i.e. a wrapping range from a
+    /// "negative" value (in the upper half of the field) to a non-negative value
+    /// (in the lower half), like `[-128, 127]`.
+    pub fn is_signed(&self) -> bool {
+        self.min > self.max && !self.min.is_in_lower_half() && self.max.is_in_lower_half()
+    }
+
+    /// Returns the number of bits needed to represent all allowed values, or `None` if
+    /// the constraint does not restrict the number of bits.
+    /// For signed intervals (see [RangeConstraint::is_signed]), this is the number of
+    /// bits of the two's complement representation, including the sign bit.
+    pub fn bit_width(&self) -> Option<u32> {
+        let bits = if self.is_signed() {
+            // A signed interval contains -2^(bits - 1) to 2^(bits - 1) - 1.
+            let negative_bits = (-self.min - T::one()).to_integer().num_bits();
+            negative_bits.max(self.max.to_integer().num_bits()) + 1
+        } else if self.min <= self.max {
+            self.mask.num_bits().min(self.max.to_integer().num_bits())
+        } else {
+            self.mask.num_bits()
+        };
+        (bits < T::modulus().num_bits()).then_some(bits)
+    }
+
     /// Returns (an upper bound for) the number of field elements included in the constraint.
     pub fn range_width(&self) -> T::Integer {
         range_width(self.min, self.max)
@@ -272,6 +303,33 @@ mod test {
         );
     }
 
+    #[test]
+    fn bit_width() {
+        assert_eq!(RCg::from_max_bit(0).bit_width(), Some(1));
+        assert_eq!(RCg::from_mask(0xffu32).bit_width(), Some(8));
+        assert_eq!(RCg::from_range(3.into(), 9.into()).bit_width(), Some(4));
+        assert_eq!(RCg::from_value(0.into()).bit_width(), Some(0));
+        assert_eq!(RCg::from_range(9.into(), 3.into()).bit_width(), None);
+    }
+
+    #[test]
+    fn signed_bit_width() {
+        let signed_byte = RCg::from_range((-128).into(), 127.into());
+        assert!(signed_byte.is_signed());
+        assert_eq!(signed_byte.bit_width(), Some(8));
+        assert_eq!(RCg::from_range((-1).into(), 0.into()).bit_width(), Some(1));
+        assert_eq!(
+            RCg::from_range((-3).into(), 100.into()).bit_width(),
+            Some(8)
+        );
+        assert_eq!(
+            RCg::from_range((-129).into(), 0.into()).bit_width(),
+            Some(9)
+        );
+        assert!(!RCg::from_range(9.into(), 3.into()).is_signed());
+        assert!(!RCg::from_range(3.into(), 9.into()).is_signed());
+    }
+
     #[test]
     fn from_range() {
         assert_eq!(
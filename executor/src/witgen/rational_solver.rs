@@ -0,0 +1,269 @@
+use std::collections::BTreeMap;
+
+use itertools::Itertools;
+use num_bigint::BigInt;
+use num_rational::BigRational;
+use num_traits::{One, Zero};
+use powdr_ast::analyzed::{
+    AlgebraicBinaryOperator, AlgebraicExpression as Expression, AlgebraicUnaryOperator, Identity,
+    IdentityKind, PolyID, PolynomialType,
+};
+use powdr_number::{DegreeType, FieldElement};
+
+use super::FixedData;
+
+/// The values of the witness cells of a PIL file over the rationals, see
+/// [super::WitnessGenerator::solve_over_rationals].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RationalSolution {
+    /// The values of the cells that could be determined, by column name and row.
+    pub values: BTreeMap<(String, DegreeType), BigRational>,
+    /// The cells that could not be determined, by column name and row.
+    pub undetermined: Vec<(String, DegreeType)>,
+    /// The identities that still contain undetermined cells, in the row they are
+    /// evaluated on, with the known cells substituted, e.g. `N.x(2) + 3 * N.y(2) = 1/2`.
+    /// Identities that are not affine in the undetermined cells are given as they are,
+    /// together with the row.
+    pub remaining_constraints: Vec<String>,
+    /// The identities (and rows) that are violated by the determined values.
+    pub violations: Vec<(String, DegreeType)>,
+}
+
+/// A cell of a witness column, by column and row.
+type Cell = (PolyID, DegreeType);
+
+/// An affine expression in the unknown cells with rational coefficients.
+#[derive(Clone)]
+struct RationalAffine {
+    coefficients: BTreeMap<Cell, BigRational>,
+    offset: BigRational,
+}
+
+impl RationalAffine {
+    fn constant(offset: BigRational) -> Self {
+        Self {
+            coefficients: Default::default(),
+            offset,
+        }
+    }
+
+    fn constant_value(&self) -> Option<&BigRational> {
+        self.coefficients.is_empty().then_some(&self.offset)
+    }
+
+    fn add(mut self, other: Self) -> Self {
+        for (cell, c) in other.coefficients {
+            let coefficient = self.coefficients.entry(cell).or_insert_with(Zero::zero);
+            *coefficient += c;
+            if coefficient.is_zero() {
+                self.coefficients.remove(&cell);
+            }
+        }
+        self.offset += other.offset;
+        self
+    }
+
+    fn scale(mut self, factor: &BigRational) -> Self {
+        if factor.is_zero() {
+            return Self::constant(Zero::zero());
+        }
+        for c in self.coefficients.values_mut() {
+            *c *= factor;
+        }
+        self.offset *= factor;
+        self
+    }
+}
+
+/// Solves the polynomial identities over the rationals instead of the field: Field
+/// elements are interpreted as signed integers and each identity that is affine in a
+/// single unknown cell is solved for it, until no more cells can be determined.
+/// Lookups, permutations and queries are ignored.
+pub fn solve<T: FieldElement>(
+    fixed: &FixedData<'_, T>,
+    identities: &[&Identity<Expression<T>>],
+) -> RationalSolution {
+    let identities = identities
+        .iter()
+        .filter(|identity| identity.kind == IdentityKind::Polynomial)
+        .collect::<Vec<_>>();
+    let mut values = BTreeMap::new();
+    loop {
+        let mut progress = false;
+        for identity in &identities {
+            for row in 0..fixed.degree {
+                let Some(affine) = evaluate(fixed, &values, identity.expression_for_poly_id(), row)
+                else {
+                    continue;
+                };
+                if let Ok((cell, c)) = affine.coefficients.iter().exactly_one() {
+                    values.insert(*cell, -&affine.offset / c);
+                    progress = true;
+                }
+            }
+        }
+        if !progress {
+            break;
+        }
+    }
+
+    let cell_name = |(poly_id, row): &Cell| (fixed.column_name(poly_id).to_string(), *row);
+    let mut remaining_constraints = vec![];
+    let mut violations = vec![];
+    for identity in &identities {
+        for row in 0..fixed.degree {
+            match evaluate(fixed, &values, identity.expression_for_poly_id(), row) {
+                Some(affine) => match affine.constant_value() {
+                    Some(value) if !value.is_zero() => violations.push((identity.to_string(), row)),
+                    Some(_) => {}
+                    None => remaining_constraints.push(format!(
+                        "{} = {}",
+                        affine
+                            .coefficients
+                            .iter()
+                            .map(|(cell, c)| {
+                                let (name, row) = cell_name(cell);
+                                if c.is_one() {
+                                    format!("{name}({row})")
+                                } else {
+                                    format!("{c} * {name}({row})")
+                                }
+                            })
+                            .join(" + "),
+                        -affine.offset
+                    )),
+                },
+                None => remaining_constraints.push(format!("{identity} in row {row}")),
+            }
+        }
+    }
+    let undetermined = fixed
+        .witness_cols
+        .keys()
+        .flat_map(|poly_id| (0..fixed.degree).map(move |row| (poly_id, row)))
+        .filter(|cell| !values.contains_key(cell))
+        .map(|cell| cell_name(&cell))
+        .collect();
+    RationalSolution {
+        values: values
+            .iter()
+            .map(|(cell, value)| (cell_name(cell), value.clone()))
+            .collect(),
+        undetermined,
+        remaining_constraints,
+        violations,
+    }
+}
+
+/// Evaluates the expression in the given row, with the cells in `values` known.
+/// Returns `None` if the result is not affine in the unknown cells.
+fn evaluate<T: FieldElement>(
+    fixed: &FixedData<'_, T>,
+    values: &BTreeMap<Cell, BigRational>,
+    expression: &Expression<T>,
+    row: DegreeType,
+) -> Option<RationalAffine> {
+    Some(match expression {
+        Expression::Reference(reference) => {
+            let row = (row + reference.next as DegreeType) % fixed.degree;
+            match reference.poly_id.ptype {
+                PolynomialType::Committed => match values.get(&(reference.poly_id, row)) {
+                    Some(value) => RationalAffine::constant(value.clone()),
+                    None => RationalAffine {
+                        coefficients: [((reference.poly_id, row), One::one())].into(),
+                        offset: Zero::zero(),
+                    },
+                },
+                PolynomialType::Constant => RationalAffine::constant(to_rational(
+                    fixed.fixed_cols[&reference.poly_id].value(row),
+                )),
+                PolynomialType::Intermediate => return None,
+            }
+        }
+        Expression::PublicReference(_) => return None,
+        Expression::Number(n) => RationalAffine::constant(to_rational(*n)),
+        Expression::BinaryOperation(left, op, right) => {
+            let left = evaluate(fixed, values, left, row)?;
+            let right = evaluate(fixed, values, right, row)?;
+            match op {
+                AlgebraicBinaryOperator::Add => left.add(right),
+                AlgebraicBinaryOperator::Sub => left.add(right.scale(&-BigRational::one())),
+                AlgebraicBinaryOperator::Mul => {
+                    match (left.constant_value(), right.constant_value()) {
+                        (Some(l), _) => right.scale(l),
+                        (_, Some(r)) => left.scale(r),
+                        _ => return None,
+                    }
+                }
+                AlgebraicBinaryOperator::Pow => {
+                    let exponent = right.constant_value()?.to_integer().try_into().ok()?;
+                    match (left.constant_value(), exponent) {
+                        (Some(base), _) => {
+                            RationalAffine::constant(num_traits::pow(base.clone(), exponent))
+                        }
+                        (None, 0) => RationalAffine::constant(One::one()),
+                        (None, 1) => left,
+                        _ => return None,
+                    }
+                }
+            }
+        }
+        Expression::UnaryOperation(AlgebraicUnaryOperator::Minus, inner) => {
+            evaluate(fixed, values, inner, row)?.scale(&-BigRational::one())
+        }
+    })
+}
+
+/// Interprets a field element as a signed integer, i.e. values in the upper half of
+/// the field are negative.
+fn to_rational<T: FieldElement>(value: T) -> BigRational {
+    let integer = if value.is_in_lower_half() {
+        BigInt::from(value.to_arbitrary_integer())
+    } else {
+        -BigInt::from((-value).to_arbitrary_integer())
+    };
+    BigRational::from_integer(integer)
+}
+
+#[cfg(test)]
+mod tests {
+    use powdr_number::GoldilocksField;
+    use powdr_pil_analyzer::analyze_string;
+
+    use crate::constant_evaluator;
+
+    use super::*;
+
+    #[test]
+    fn underdetermined() {
+        let src = r"
+namespace N(2);
+    col witness x, y, z, w;
+    2 * x = 1;
+    y = x + 1;
+    z + w = y;
+";
+        let analyzed = analyze_string::<GoldilocksField>(src);
+        let constants = constant_evaluator::generate(&analyzed);
+        let fixed = FixedData::new(&analyzed, analyzed.degree(), &constants, None);
+        let identities = analyzed.identities.iter().collect::<Vec<_>>();
+        let solution = solve(&fixed, &identities);
+        let rational = |n: i64, d: i64| BigRational::new(n.into(), d.into());
+        assert_eq!(solution.values[&("N.x".to_string(), 1)], rational(1, 2));
+        assert_eq!(solution.values[&("N.y".to_string(), 1)], rational(3, 2));
+        assert_eq!(
+            solution.undetermined,
+            vec![
+                ("N.z".to_string(), 0),
+                ("N.z".to_string(), 1),
+                ("N.w".to_string(), 0),
+                ("N.w".to_string(), 1),
+            ]
+        );
+        assert_eq!(
+            solution.remaining_constraints,
+            vec!["N.z(0) + N.w(0) = 3/2", "N.z(1) + N.w(1) = 3/2"]
+        );
+        assert!(solution.violations.is_empty());
+    }
+}
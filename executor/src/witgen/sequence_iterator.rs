@@ -1,8 +1,38 @@
-use std::collections::BTreeMap;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap};
 
+use powdr_ast::analyzed::{AlgebraicExpression as Expression, Identity};
 use powdr_number::FieldElement;
 
 use super::affine_expression::AffineExpression;
+use super::WitnessGenerator;
+
+/// Determines the order in which the identities of a machine are processed:
+/// Identities are processed in increasing order of the returned priority.
+/// Identities with the same priority keep the order in which they appear in the PIL file,
+/// so the order is deterministic as long as the priorities are.
+/// Without a strategy, all identities are processed in the order of the PIL file.
+pub trait SequenceStrategy<T>: Fn(&Identity<Expression<T>>) -> i64 + Send + Sync {}
+impl<T, F> SequenceStrategy<T> for F where F: Fn(&Identity<Expression<T>>) -> i64 + Send + Sync {}
+
+/// Sorts the identities by the priorities given by their IDs (higher first, identities
+/// without a priority have priority zero) and then by the priority given by the
+/// strategy (if any).
+pub fn sort_identities<T>(
+    identities: &mut [&Identity<Expression<T>>],
+    strategy: Option<&dyn SequenceStrategy<T>>,
+    priorities: &HashMap<u64, i32>,
+) {
+    if strategy.is_none() && priorities.is_empty() {
+        return;
+    }
+    identities.sort_by_cached_key(|identity| {
+        (
+            Reverse(priorities.get(&identity.id).copied().unwrap_or_default()),
+            strategy.map_or(0, |strategy| strategy(identity)),
+        )
+    });
+}
 
 #[derive(Clone, Debug)]
 pub struct SequenceStep {
@@ -13,6 +43,9 @@ pub struct SequenceStep {
 /// Goes through all rows of the block machine (plus the ones before and after)
 /// forward, backward, and forward again.
 /// In each row, iterates over all identities until no further progress is made.
+/// The sequence is deterministic: Each round processes the identities in the order
+/// of their indices (see [SequenceStrategy]), followed by the prover queries and,
+/// on the row of the outer query, the outer query.
 pub struct DefaultSequenceIterator {
     identities_count: usize,
     row_deltas: Vec<i64>,
@@ -273,3 +306,104 @@ impl ProcessingSequenceCache {
         }
     }
 }
+
+impl<'a, 'b, T: FieldElement> WitnessGenerator<'a, 'b, T> {
+    /// Sets the order in which the identities of each machine are processed.
+    pub fn with_sequence_strategy(self, sequence_strategy: &'b dyn SequenceStrategy<T>) -> Self {
+        WitnessGenerator {
+            sequence_strategy: Some(sequence_strategy),
+            ..self
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use powdr_ast::parsed::visitor::ExpressionVisitable;
+    use powdr_number::GoldilocksField;
+    use powdr_pil_analyzer::analyze_string;
+    use pretty_assertions::assert_eq;
+    use test_log::test;
+
+    use crate::constant_evaluator;
+    use crate::witgen::tests::{generate_witness, ARRAY_SRC};
+    use crate::witgen::unused_query_callback;
+
+    use super::*;
+
+    fn all_steps(mut iterator: DefaultSequenceIterator) -> Vec<(i64, String)> {
+        let mut steps = vec![];
+        while let Some(step) = iterator.next() {
+            // Make progress in the first round of each row, to also cover repeated rounds.
+            let progress = !steps
+                .iter()
+                .any(|(row_delta, _)| *row_delta == step.row_delta);
+            steps.push((step.row_delta, format!("{:?}", step.action)));
+            iterator.report_progress(progress);
+        }
+        steps
+    }
+
+    #[test]
+    fn deterministic_order() {
+        let steps = all_steps(DefaultSequenceIterator::new(2, 2, Some(1)));
+        assert_eq!(
+            steps,
+            all_steps(DefaultSequenceIterator::new(2, 2, Some(1)))
+        );
+        assert_eq!(
+            steps[..7],
+            [
+                (-1, "InternalIdentity(0)".to_string()),
+                (-1, "InternalIdentity(1)".to_string()),
+                (-1, "ProverQueries".to_string()),
+                (-1, "InternalIdentity(0)".to_string()),
+                (-1, "InternalIdentity(1)".to_string()),
+                (-1, "ProverQueries".to_string()),
+                (0, "InternalIdentity(0)".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn sort_by_strategy() {
+        let src = r"
+namespace N(4);
+    col witness x, y, z;
+    x = 1;
+    y * y = x;
+    z = y + x;
+";
+        let analyzed = analyze_string::<GoldilocksField>(src);
+        let mut identities = analyzed.identities.iter().collect::<Vec<_>>();
+        // Identities with more references first. The last two identities have the same
+        // number of references, so they keep their order.
+        let strategy = |identity: &Identity<Expression<GoldilocksField>>| {
+            let mut count = 0;
+            identity.pre_visit_expressions(&mut |e| {
+                if matches!(e, Expression::Reference(_)) {
+                    count += 1;
+                }
+            });
+            -count
+        };
+        sort_identities(&mut identities, Some(&strategy), &HashMap::new());
+        assert_eq!(
+            identities.iter().map(|i| i.id).collect::<Vec<_>>(),
+            vec![1, 2, 0]
+        );
+    }
+
+    #[test]
+    fn sequence_strategy() {
+        let analyzed = analyze_string::<GoldilocksField>(ARRAY_SRC);
+        let fixed_cols = constant_evaluator::generate(&analyzed);
+        let query_callback = unused_query_callback();
+        // Process the identities in reverse order.
+        let reverse = |identity: &Identity<Expression<GoldilocksField>>| -(identity.id as i64);
+        let witness = WitnessGenerator::new(&analyzed, &fixed_cols, &query_callback)
+            .with_sequence_strategy(&reverse)
+            .generate();
+        assert_eq!(witness, generate_witness(ARRAY_SRC));
+    }
+}
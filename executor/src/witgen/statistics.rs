@@ -0,0 +1,202 @@
+use std::{cell::RefCell, collections::BTreeMap, time::Duration};
+
+use powdr_number::{DegreeType, FieldElement};
+
+use super::WitnessGenerator;
+
+/// Statistics about a run of witness generation, collected if requested via
+/// [super::WitnessGenerator::with_statistics].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GenerationStats {
+    /// The number of rows solved by each machine, by name.
+    /// Only machines that solve rows using the generic solver are included.
+    pub rows_per_machine: BTreeMap<String, DegreeType>,
+    /// The number of times an identity was processed on a row (pair).
+    pub identity_evaluations: u64,
+    /// The number of lookups into fixed columns whose result was cached.
+    pub fixed_lookup_cache_hits: u64,
+    /// The number of lookups into fixed columns whose result was not cached.
+    pub fixed_lookup_cache_misses: u64,
+    /// The number of calls into pure machines that were answered with the result of an
+    /// earlier call, see [super::WitnessGenerator::with_pure_machine_memoization].
+    pub pure_call_cache_hits: u64,
+    /// The time spent inlining intermediate polynomials and determining global range
+    /// constraints. For prepared generators, this is only done once.
+    pub global_constraints_time: Duration,
+    /// The time spent extracting and creating the machines. For prepared generators, the
+    /// machines are only extracted once, but created again by every run.
+    pub extraction_time: Duration,
+    /// The time spent running the main machine, including calls into other machines.
+    pub solving_time: Duration,
+    /// The time spent finalizing the machines and collecting their columns.
+    pub finalization_time: Duration,
+    /// The largest number of cells of secondary machines that were held in memory at
+    /// the same time while finalizing the machines, see
+    /// [super::WitnessGenerator::with_machine_witness_callback].
+    pub peak_machine_cells: u64,
+    /// The largest number of rows of a main machine that were held in memory at the
+    /// same time. Finalized rows are moved out of memory while solving if
+    /// [super::WitnessGenerator::with_spill_to_disk] is used.
+    pub peak_main_machine_rows: u64,
+    /// The number of rows used by the program of the main machine before it was padded,
+    /// if a padding strategy is set and the end of the program was detected.
+    pub program_end: Option<DegreeType>,
+}
+
+impl GenerationStats {
+    /// The share of lookups into fixed columns that were answered from the cache.
+    pub fn fixed_lookup_cache_hit_rate(&self) -> f64 {
+        let total = self.fixed_lookup_cache_hits + self.fixed_lookup_cache_misses;
+        self.fixed_lookup_cache_hits as f64 / total.max(1) as f64
+    }
+
+    pub(crate) fn add_counters(&mut self, counters: Counters) {
+        self.identity_evaluations += counters.identity_evaluations;
+        for (machine, rows) in counters.rows {
+            *self.rows_per_machine.entry(machine).or_default() += rows;
+        }
+    }
+}
+
+/// Counters that are updated during solving, if collecting them is enabled
+/// in the current thread.
+#[derive(Default)]
+pub struct Counters {
+    identity_evaluations: u64,
+    rows: BTreeMap<String, DegreeType>,
+}
+
+impl Counters {
+    fn merge(&mut self, other: Counters) {
+        self.identity_evaluations += other.identity_evaluations;
+        for (machine, rows) in other.rows {
+            *self.rows.entry(machine).or_default() += rows;
+        }
+    }
+}
+
+thread_local! {
+    static COUNTERS: RefCell<Option<Counters>> = RefCell::new(None);
+}
+
+/// Runs `f`, collecting the counters of the current thread if `enabled`.
+pub fn collect<R>(enabled: bool, f: impl FnOnce() -> R) -> (R, Option<Counters>) {
+    if !enabled {
+        return (f(), None);
+    }
+    let outer = COUNTERS.with(|counters| counters.replace(Some(Counters::default())));
+    let result = f();
+    let counters = COUNTERS.with(|counters| counters.replace(outer));
+    (result, counters)
+}
+
+/// Returns whether counters are collected in the current thread.
+pub fn is_enabled() -> bool {
+    COUNTERS.with(|counters| counters.borrow().is_some())
+}
+
+/// Adds the counters collected on another thread to those of the current thread.
+pub fn add(other: Counters) {
+    COUNTERS.with(|counters| {
+        if let Some(counters) = counters.borrow_mut().as_mut() {
+            counters.merge(other);
+        }
+    });
+}
+
+pub fn count_identity_evaluation() {
+    COUNTERS.with(|counters| {
+        if let Some(counters) = counters.borrow_mut().as_mut() {
+            counters.identity_evaluations += 1;
+        }
+    });
+}
+
+pub fn count_rows(machine: &str, rows: DegreeType) {
+    COUNTERS.with(|counters| {
+        if let Some(counters) = counters.borrow_mut().as_mut() {
+            *counters.rows.entry(machine.to_string()).or_default() += rows;
+        }
+    });
+}
+
+impl<'a, 'b, T: FieldElement> WitnessGenerator<'a, 'b, T> {
+    /// Collects statistics about the run (rows per machine, identity evaluations,
+    /// fixed lookup cache hits and the time spent in each phase) into `statistics`.
+    /// They are written once the run is finished, also if it fails with an error.
+    pub fn with_statistics(self, statistics: &'b mut GenerationStats) -> Self {
+        WitnessGenerator {
+            statistics: Some(statistics),
+            ..self
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use powdr_number::GoldilocksField;
+    use powdr_pil_analyzer::analyze_string;
+    use pretty_assertions::assert_eq;
+    use test_log::test;
+
+    use crate::constant_evaluator;
+    use crate::witgen::tests::values;
+    use crate::witgen::unused_query_callback;
+
+    use super::*;
+
+    #[test]
+    fn generation_stats() {
+        let src = r"
+namespace N(4);
+    col fixed FIRST = [1] + [0]*;
+    col witness x;
+    FIRST * (x - 1) = 0;
+    (1 - FIRST') * (x' - x - 1) = 0;
+";
+        let analyzed = analyze_string::<GoldilocksField>(src);
+        let fixed_cols = constant_evaluator::generate(&analyzed);
+        let query_callback = unused_query_callback();
+        let mut stats = GenerationStats::default();
+        WitnessGenerator::new(&analyzed, &fixed_cols, &query_callback)
+            .with_statistics(&mut stats)
+            .generate();
+        assert!(stats.rows_per_machine["Main Machine"] >= 4);
+        assert!(stats.identity_evaluations > 0);
+    }
+
+    #[test]
+    fn fixed_lookup_cache_statistics() {
+        let src = r"
+namespace N(8);
+    col fixed X(i) { i };
+    col fixed Y(i) { i * i };
+    col witness x, y;
+    x = 3;
+    { x, y } in { X, Y };
+";
+        let analyzed = analyze_string::<GoldilocksField>(src);
+        let fixed_cols = constant_evaluator::generate(&analyzed);
+        let query_callback = unused_query_callback();
+        let mut stats = GenerationStats::default();
+        let witness = WitnessGenerator::new(&analyzed, &fixed_cols, &query_callback)
+            .with_statistics(&mut stats)
+            .generate();
+        assert_eq!(witness[1], ("N.y".to_string(), values([9; 8])));
+        // All rows look up the same value, so only the first lookup misses the cache.
+        assert_eq!(stats.fixed_lookup_cache_misses, 1);
+        assert!(stats.fixed_lookup_cache_hits >= 7);
+
+        let mut uncached_stats = GenerationStats::default();
+        let uncached = WitnessGenerator::new(&analyzed, &fixed_cols, &query_callback)
+            .with_fixed_lookup_cache_capacity(0)
+            .with_statistics(&mut uncached_stats)
+            .generate();
+        assert_eq!(uncached, witness);
+        assert_eq!(uncached_stats.fixed_lookup_cache_hits, 0);
+        assert_eq!(
+            uncached_stats.fixed_lookup_cache_misses,
+            stats.fixed_lookup_cache_hits + stats.fixed_lookup_cache_misses
+        );
+    }
+}
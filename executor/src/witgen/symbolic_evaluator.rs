@@ -1,7 +1,9 @@
 use super::affine_expression::{AffineExpression, AffineResult};
-use super::expression_evaluator::SymbolicVariables;
+use super::expression_evaluator::{ExpressionEvaluator, SymbolicVariables};
 
-use powdr_ast::analyzed::AlgebraicReference;
+use powdr_ast::analyzed::{
+    AlgebraicExpression as Expression, AlgebraicReference, Identity, IdentityKind, PolyID,
+};
 use powdr_number::FieldElement;
 
 /// A purely symbolic evaluator, uses AlgebraicReference as keys
@@ -16,3 +18,43 @@ impl<T: FieldElement> SymbolicVariables<T> for SymbolicEvaluator {
         Ok(AffineExpression::from_variable_id(poly))
     }
 }
+
+/// A column together with the row it refers to, relative to the current row
+/// (0 for the current row, 1 for the next row).
+pub type ColumnRotation = (PolyID, u8);
+
+/// An identity in the form in which it is processed by the solver, i.e. with all
+/// intermediate polynomials inlined.
+#[derive(Debug, Clone)]
+pub struct SymbolicConstraint<T> {
+    pub identity: Identity<Expression<T>>,
+    /// For polynomial identities, the expression that has to be zero, if it is affine
+    /// in the (fixed and witness) columns.
+    pub affine: Option<AffineExpression<ColumnRotation, T>>,
+}
+
+impl<T: FieldElement> SymbolicConstraint<T> {
+    pub fn new(identity: Identity<Expression<T>>) -> Self {
+        let affine = match identity.kind {
+            IdentityKind::Polynomial => affine_form(identity.expression_for_poly_id()),
+            _ => None,
+        };
+        SymbolicConstraint { identity, affine }
+    }
+}
+
+/// Returns the expression as an affine expression in the columns it references,
+/// or `None` if it is not affine.
+pub fn affine_form<T: FieldElement>(
+    expr: &Expression<T>,
+) -> Option<AffineExpression<ColumnRotation, T>> {
+    let affine = ExpressionEvaluator::new(SymbolicEvaluator)
+        .evaluate(expr)
+        .ok()?;
+    Some(affine.nonzero_coefficients().into_iter().fold(
+        affine.offset.into(),
+        |acc, (poly, coefficient)| {
+            acc + AffineExpression::from_variable_id((poly.poly_id, poly.next as u8)) * coefficient
+        },
+    ))
+}
@@ -45,10 +45,9 @@ where
             self.witness_access.value(poly)
         } else {
             // Constant polynomial (or something else)
-            let values = self.fixed_data.fixed_cols[&poly.poly_id].values;
-            let row =
-                if poly.next { self.row + 1 } else { self.row } % (values.len() as DegreeType);
-            Ok(values[row as usize].into())
+            let column = &self.fixed_data.fixed_cols[&poly.poly_id];
+            let row = if poly.next { self.row + 1 } else { self.row };
+            Ok(column.value(row).into())
         }
     }
 }
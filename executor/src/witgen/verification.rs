@@ -0,0 +1,245 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt::{self, Display, Formatter};
+
+use powdr_ast::analyzed::{
+    AlgebraicExpression as Expression, AlgebraicReference, Analyzed, Identity, IdentityKind, PolyID,
+};
+use powdr_ast::parsed::SelectedExpressions;
+use powdr_number::{DegreeType, FieldElement};
+
+use super::affine_expression::AffineResult;
+use super::expression_evaluator::{ExpressionEvaluator, SymbolicVariables};
+
+/// An identity that does not hold on a witness, see [verify_witness].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IdentityViolation {
+    /// The row in which the identity is violated. For lookups and permutations, this is
+    /// the row of a tuple that is not contained in the other side.
+    pub row: DegreeType,
+    /// The identity, as it is displayed in the PIL file (with intermediate polynomials
+    /// inlined).
+    pub identity: String,
+}
+
+impl Display for IdentityViolation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Identity violated in row {}: {}",
+            self.row, self.identity
+        )
+    }
+}
+
+/// Checks that all polynomial identities, lookups and permutations hold on the given
+/// columns, which have to contain all fixed and witness columns by name, each with
+/// `degree` rows. References to the next row wrap around to the first row.
+/// Connection identities are not checked.
+/// This is independent of the witness generator, so it can be used to detect invalid
+/// witnesses, e.g. those produced by a faulty custom machine.
+pub fn verify_witness<T: FieldElement>(
+    analyzed: &Analyzed<T>,
+    columns: &[(String, Vec<T>)],
+    degree: DegreeType,
+) -> Result<(), Vec<IdentityViolation>> {
+    let poly_ids = analyzed
+        .constant_polys_in_source_order()
+        .into_iter()
+        .chain(analyzed.committed_polys_in_source_order())
+        .flat_map(|(poly, _)| poly.array_elements())
+        .collect::<HashMap<_, _>>();
+    let columns = columns
+        .iter()
+        .filter_map(|(name, values)| {
+            assert_eq!(
+                values.len() as DegreeType,
+                degree,
+                "Column {name} does not have {degree} rows."
+            );
+            poly_ids
+                .get(name)
+                .map(|poly_id| (*poly_id, values.as_slice()))
+        })
+        .collect::<HashMap<_, _>>();
+    let verifier = Verifier { columns, degree };
+
+    let violations = analyzed
+        .identities_with_inlined_intermediate_polynomials()
+        .iter()
+        .flat_map(|identity| {
+            verifier
+                .violating_rows(identity)
+                .into_iter()
+                .map(|row| IdentityViolation {
+                    row,
+                    identity: identity.to_string(),
+                })
+        })
+        .collect::<Vec<_>>();
+    if violations.is_empty() {
+        Ok(())
+    } else {
+        Err(violations)
+    }
+}
+
+struct Verifier<'a, T> {
+    columns: HashMap<PolyID, &'a [T]>,
+    degree: DegreeType,
+}
+
+impl<'a, T: FieldElement> Verifier<'a, T> {
+    /// Returns the rows in which the identity is violated.
+    fn violating_rows(&self, identity: &Identity<Expression<T>>) -> Vec<DegreeType> {
+        match identity.kind {
+            IdentityKind::Polynomial => (0..self.degree)
+                .filter(|row| {
+                    !self
+                        .evaluate(identity.expression_for_poly_id(), *row)
+                        .is_zero()
+                })
+                .collect(),
+            IdentityKind::Plookup => {
+                let right = self
+                    .selected_tuples(&identity.right)
+                    .map(|(_, tuple)| tuple)
+                    .collect::<HashSet<_>>();
+                self.selected_tuples(&identity.left)
+                    .filter(|(_, tuple)| !right.contains(tuple))
+                    .map(|(row, _)| row)
+                    .collect()
+            }
+            IdentityKind::Permutation => {
+                let mut right = HashMap::<_, Vec<DegreeType>>::new();
+                for (row, tuple) in self.selected_tuples(&identity.right) {
+                    right.entry(tuple).or_default().push(row);
+                }
+                let mut rows = self
+                    .selected_tuples(&identity.left)
+                    .filter(|(_, tuple)| {
+                        right
+                            .get_mut(tuple)
+                            .and_then(|right_rows| right_rows.pop())
+                            .is_none()
+                    })
+                    .map(|(row, _)| row)
+                    .collect::<Vec<_>>();
+                // Tuples on the right-hand side that were not matched.
+                rows.extend(right.into_values().flatten());
+                rows.sort();
+                rows
+            }
+            IdentityKind::Connect => vec![],
+        }
+    }
+
+    /// Returns the tuples of the rows where the selector is non-zero, with their rows.
+    fn selected_tuples<'b>(
+        &'b self,
+        selected: &'b SelectedExpressions<Expression<T>>,
+    ) -> impl Iterator<Item = (DegreeType, Vec<T>)> + 'b {
+        (0..self.degree)
+            .filter(move |row| {
+                selected
+                    .selector
+                    .as_ref()
+                    .map_or(true, |selector| !self.evaluate(selector, *row).is_zero())
+            })
+            .map(move |row| {
+                let tuple = selected
+                    .expressions
+                    .iter()
+                    .map(|e| self.evaluate(e, row))
+                    .collect();
+                (row, tuple)
+            })
+    }
+
+    fn evaluate(&self, expression: &Expression<T>, row: DegreeType) -> T {
+        ExpressionEvaluator::new(RowValues {
+            verifier: self,
+            row,
+        })
+        .evaluate(expression)
+        .ok()
+        .and_then(|value| value.constant_value())
+        .unwrap_or_else(|| panic!("Could not evaluate {expression} in row {row}."))
+    }
+}
+
+/// The values of all columns in a row and the next row.
+struct RowValues<'a, 'b, T> {
+    verifier: &'b Verifier<'a, T>,
+    row: DegreeType,
+}
+
+impl<'a, 'b, T: FieldElement> SymbolicVariables<T> for RowValues<'a, 'b, T> {
+    fn value<'c>(&self, poly: &'c AlgebraicReference) -> AffineResult<&'c AlgebraicReference, T> {
+        let column = self
+            .verifier
+            .columns
+            .get(&poly.poly_id)
+            .unwrap_or_else(|| panic!("The values of column {} are missing.", poly.name));
+        let row = (self.row + poly.next as DegreeType) % self.verifier.degree;
+        Ok(column[row as usize].into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use powdr_number::GoldilocksField;
+    use powdr_pil_analyzer::analyze_string;
+    use pretty_assertions::assert_eq;
+    use test_log::test;
+
+    use crate::constant_evaluator;
+    use crate::witgen::{unused_query_callback, WitnessGenerator};
+
+    use super::*;
+
+    #[test]
+    fn verify_witness_reports_violations() {
+        let src = r"
+namespace N(4);
+    col fixed LAST = [0, 0, 0, 1];
+    col fixed BYTE = [0, 1, 2, 3];
+    col witness x, y;
+    (1 - LAST) * (x' - x - 1) = 0;
+    y' = y;
+    { x } in { BYTE };
+";
+        let analyzed = analyze_string::<GoldilocksField>(src);
+        let fixed_cols = constant_evaluator::generate(&analyzed);
+        let query_callback = unused_query_callback();
+        let x: PolyID = (&analyzed.definitions["N.x"].0).into();
+        let y: PolyID = (&analyzed.definitions["N.y"].0).into();
+        let witness = WitnessGenerator::new(&analyzed, &fixed_cols, &query_callback)
+            .with_fixed_cells(vec![(x, 0, 0.into()), (y, 0, 5.into())])
+            .generate();
+        let mut columns = fixed_cols
+            .iter()
+            .cloned()
+            .chain(witness)
+            .collect::<Vec<_>>();
+        assert_eq!(verify_witness(&analyzed, &columns, 4), Ok(()));
+
+        // Changing the first row of `y` violates `y' = y` in the first row and, since
+        // the next row of the last row is the first row, also in the last row.
+        columns[2].1[0] = 6.into();
+        columns[3].1[0] = 7.into();
+        let violations = verify_witness(&analyzed, &columns, 4).unwrap_err();
+        let violations = violations
+            .iter()
+            .map(|v| (v.row, v.identity.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            violations,
+            vec![
+                (0, "(1 - N.LAST) * (N.x' - N.x - 1) = 0;"),
+                (0, "N.y' = N.y;"),
+                (3, "N.y' = N.y;"),
+                (0, "{ N.x } in { N.BYTE };"),
+            ]
+        );
+    }
+}
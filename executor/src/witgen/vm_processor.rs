@@ -1,28 +1,91 @@
+use bit_vec::BitVec;
 use indicatif::{ProgressBar, ProgressStyle};
-use itertools::Itertools;
+use itertools::{izip, Itertools};
 use powdr_ast::analyzed::{
     AlgebraicExpression as Expression, AlgebraicReference, Identity, IdentityKind, PolyID,
 };
+use powdr_ast::parsed::visitor::ExpressionVisitable;
 use powdr_number::{DegreeType, FieldElement};
 use powdr_parser_util::lines::indent;
 use std::cmp::max;
 use std::collections::HashSet;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Sender;
 use std::time::Instant;
 
 use crate::witgen::identity_processor::{self};
 use crate::witgen::IncompleteCause;
 
+use super::checkpoint::{CheckpointRow, CheckpointWriter};
+use super::column_storage::SpillConfig;
+use super::data_structures::column_map::WitnessColumnMap;
 use super::data_structures::finalizable_data::FinalizableData;
 use super::processor::{OuterQuery, Processor};
+use super::progress::ProgressReporter;
 
-use super::rows::{Row, RowFactory, UnknownStrategy};
-use super::{Constraints, EvalError, EvalValue, FixedData, MutableState, QueryCallback};
+use super::rows::{CellValue, Row, RowFactory, UnknownStrategy};
+use super::witness_rows::RowSender;
+use super::{
+    Constraints, EvalError, EvalValue, FixedData, MutableState, PaddingFunction, PendingUnknown,
+    QueryCallback, RowObserver,
+};
 
 /// Maximal period checked during loop detection.
 const MAX_PERIOD: usize = 4;
 
 const REPORT_FREQUENCY: u64 = 1_000;
 
+/// The number of rows after which the deadline and the cancellation flag are checked.
+const DEADLINE_CHECK_FREQUENCY: u64 = 256;
+
+/// The default number of rows whose identities are processed together, i.e. the current
+/// and the next row.
+pub const DEFAULT_WINDOW_SIZE: usize = 2;
+
+/// Limits after which processing is aborted with [EvalError::Timeout], and the flag
+/// after which it is aborted with [EvalError::Cancelled].
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Limits<'c> {
+    /// The point in time after which processing is aborted.
+    pub deadline: Option<Instant>,
+    /// The maximal number of consecutive rows in which no cell could be determined.
+    pub max_rows_without_progress: Option<DegreeType>,
+    /// Processing is aborted once this is set (from any thread).
+    pub cancellation: Option<&'c AtomicBool>,
+}
+
+/// The order in which the rows of a machine are solved.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SolvingDirection {
+    /// From the first to the last row. Identities that reference the next row are
+    /// mostly used to determine the next row from the current row.
+    #[default]
+    Forward,
+    /// From the last to the first row. The row pair of each row still consists of the
+    /// current and the next row, but the next row is already solved, so identities that
+    /// reference the next row are used to determine the current row from the next row
+    /// (e.g. `x = x' - 1`).
+    /// Since calls into other machines are made in reverse order, this is only compatible
+    /// with machines that do not depend on the order of calls (e.g. lookups into fixed
+    /// columns or block machines), but not for example with memory machines.
+    /// Rows are not finalized before all rows are solved, so this needs more memory.
+    Backward,
+}
+
+/// How the rows of the main machine after the end of the program are filled,
+/// see [crate::witgen::WitnessGenerator::with_padding_strategy].
+/// Padding rows are not solved, so the program has to be in a state in which it does
+/// not call into other machines (e.g. memory) when it ends.
+#[derive(Clone, Copy)]
+pub enum PaddingStrategy<'a, T> {
+    /// Repeats the last row of the program, e.g. a "halt" state that loops on itself.
+    RepeatLastRow,
+    /// Sets all witness cells to zero.
+    Zero,
+    /// Computes each witness cell from the value in the last row of the program.
+    Custom(&'a dyn PaddingFunction<T>),
+}
+
 /// A list of identities with a flag whether it is complete.
 struct CompletableIdentities<'a, T: FieldElement> {
     identities_with_complete: Vec<(&'a Identity<Expression<T>>, bool)>,
@@ -35,6 +98,14 @@ impl<'a, T: FieldElement> CompletableIdentities<'a, T> {
         }
     }
 
+    /// Yields the identities that are not complete yet.
+    fn incomplete(&self) -> impl Iterator<Item = &'a Identity<Expression<T>>> + '_ {
+        self.identities_with_complete
+            .iter()
+            .filter(|(_, complete)| !complete)
+            .map(|(identity, _)| *identity)
+    }
+
     /// Yields immutable references to the identity and mutable references to the complete flag.
     fn iter_mut(&mut self) -> impl Iterator<Item = (&'a Identity<Expression<T>>, &mut bool)> {
         self.identities_with_complete
@@ -60,6 +131,41 @@ pub struct VmProcessor<'a, 'b, 'c, T: FieldElement, Q: QueryCallback<T>> {
     row_factory: RowFactory<'a, T>,
     processor: Processor<'a, 'b, 'c, T, Q>,
     progress_bar: ProgressBar,
+    /// The name of the machine and the reporter for user-supplied progress callbacks.
+    progress: Option<(&'c str, ProgressReporter<'c>)>,
+    checkpoints: Option<CheckpointWriter<'c, T>>,
+    /// The calls into other machines (connecting identity and global row) made since the
+    /// last checkpoint was written, only recorded if checkpoints are written.
+    calls: Vec<(u64, DegreeType)>,
+    /// The user-supplied row observer and the buffer for the values passed to it.
+    row_observer: Option<(&'c mut dyn RowObserver<T>, WitnessColumnMap<Option<T>>)>,
+    /// Receives the known cells of each row once the row is complete.
+    cell_sender: Option<&'a Sender<(PolyID, DegreeType, T)>>,
+    /// If set, rows are discarded once they are not needed anymore.
+    validate_only: bool,
+    /// If set, finalized rows are moved out of memory, see [VmProcessor::with_spill].
+    spill: Option<&'c SpillConfig>,
+    /// If set, finalized rows are sent, see [VmProcessor::with_row_sender].
+    row_sender: Option<&'c RowSender<T>>,
+    /// If set, failures are returned instead of panicking.
+    return_errors: bool,
+    /// The values of the first row, saved before it is discarded in validate-only mode.
+    first_row: Option<(Vec<T>, BitVec)>,
+    limits: Limits<'c>,
+    /// The number of consecutive rows so far in which no cell could be determined.
+    rows_without_progress: DegreeType,
+    direction: SolvingDirection,
+    /// The number of rows that were solved successfully.
+    rows_solved: DegreeType,
+    /// The row after the last row to solve.
+    end_row: DegreeType,
+    /// If set, the rows after the end of the program are not solved but padded.
+    padding: Option<PaddingStrategy<'a, T>>,
+    /// The global index of the first padding row, once the end of the program is detected.
+    program_end: Option<DegreeType>,
+    /// The number of rows whose polynomial identities are processed when solving a row,
+    /// see [VmProcessor::with_window_size].
+    window_size: usize,
 }
 
 impl<'a, 'b, 'c, T: FieldElement, Q: QueryCallback<T>> VmProcessor<'a, 'b, 'c, T, Q> {
@@ -96,6 +202,24 @@ impl<'a, 'b, 'c, T: FieldElement, Q: QueryCallback<T>> VmProcessor<'a, 'b, 'c, T
             last_report_time: Instant::now(),
             processor,
             progress_bar,
+            progress: None,
+            checkpoints: None,
+            calls: vec![],
+            row_observer: None,
+            cell_sender: None,
+            validate_only: false,
+            spill: None,
+            row_sender: None,
+            return_errors: false,
+            first_row: None,
+            limits: Limits::default(),
+            rows_without_progress: 0,
+            direction: SolvingDirection::Forward,
+            rows_solved: 0,
+            end_row: fixed_data.degree,
+            padding: None,
+            program_end: None,
+            window_size: DEFAULT_WINDOW_SIZE,
         }
     }
 
@@ -104,15 +228,147 @@ impl<'a, 'b, 'c, T: FieldElement, Q: QueryCallback<T>> VmProcessor<'a, 'b, 'c, T
         Self { processor, ..self }
     }
 
+    pub fn with_progress(self, machine_name: &'c str, progress: ProgressReporter<'c>) -> Self {
+        Self {
+            progress: Some((machine_name, progress)),
+            ..self
+        }
+    }
+
+    pub fn with_checkpoints(self, checkpoints: CheckpointWriter<'c, T>) -> Self {
+        assert!(checkpoints.rows_written() >= self.row_offset);
+        Self {
+            checkpoints: Some(checkpoints),
+            ..self
+        }
+    }
+
+    pub fn with_row_observer(self, row_observer: &'c mut dyn RowObserver<T>) -> Self {
+        let values = self.fixed_data.witness_map_with(None);
+        Self {
+            row_observer: Some((row_observer, values)),
+            ..self
+        }
+    }
+
+    /// Sends the known cells of each row (by column and global row index) to `sender`
+    /// as soon as the row is complete, like the values passed to the row observer.
+    pub fn with_cell_sender(self, sender: &'a Sender<(PolyID, DegreeType, T)>) -> Self {
+        Self {
+            cell_sender: Some(sender),
+            ..self
+        }
+    }
+
+    pub fn with_validate_only(self) -> Self {
+        Self {
+            validate_only: true,
+            return_errors: true,
+            ..self
+        }
+    }
+
+    /// Moves the rows that are finalized while solving (except for the first row) into
+    /// files, see [FinalizableData::spill_until].
+    /// Rows that have not been written to a checkpoint yet are kept in memory.
+    pub fn with_spill(self, spill: &'c SpillConfig) -> Self {
+        Self {
+            spill: Some(spill),
+            ..self
+        }
+    }
+
+    /// Moves the rows that are finalized while solving (except for the first row) out of
+    /// memory and sends them, see [FinalizableData::send_until]. Takes precedence over
+    /// [VmProcessor::with_spill].
+    /// Rows that have not been written to a checkpoint yet are kept in memory.
+    pub fn with_row_sender(self, row_sender: &'c RowSender<T>) -> Self {
+        Self {
+            row_sender: Some(row_sender),
+            ..self
+        }
+    }
+
+    pub fn with_errors_returned(self) -> Self {
+        Self {
+            return_errors: true,
+            ..self
+        }
+    }
+
+    pub fn with_limits(self, limits: Limits<'c>) -> Self {
+        Self { limits, ..self }
+    }
+
+    /// Continues counting the rows without progress (see [Limits]) from the given number,
+    /// e.g. when resuming from a checkpoint.
+    pub fn with_rows_without_progress(self, rows_without_progress: DegreeType) -> Self {
+        Self {
+            rows_without_progress,
+            ..self
+        }
+    }
+
+    pub fn with_direction(self, direction: SolvingDirection) -> Self {
+        Self { direction, ..self }
+    }
+
+    /// Only solves the rows up to (excluding) `end_row` instead of all rows.
+    /// Row `end_row` itself is created from the external witness values and is only
+    /// used as the next row of the last solved row.
+    pub fn with_end_row(self, end_row: DegreeType) -> Self {
+        assert!(end_row <= self.fixed_data.degree);
+        Self { end_row, ..self }
+    }
+
+    /// Pads the rows after the end of the program instead of solving them, see
+    /// [crate::witgen::WitnessGenerator::with_padding_strategy].
+    /// Only applies if all rows are solved, starting from the first row.
+    /// Also processes the polynomial identities of the next `window_size - 2` rows (and
+    /// the row after them) if a row cannot be completed otherwise, so that cells that are
+    /// only determined by later rows become known. Only used when solving forward.
+    pub fn with_window_size(self, window_size: usize) -> Self {
+        Self {
+            window_size,
+            ..self
+        }
+    }
+
+    pub fn with_padding(self, padding: PaddingStrategy<'a, T>) -> Self {
+        Self {
+            padding: Some(padding),
+            ..self
+        }
+    }
+
+    pub fn rows_solved(&self) -> DegreeType {
+        self.rows_solved
+    }
+
+    /// The global index of the first padding row, i.e. the number of rows used by the
+    /// program, if padding is enabled and the program ended.
+    pub fn program_end(&self) -> Option<DegreeType> {
+        self.program_end
+    }
+
     pub fn finish(self) -> FinalizableData<'a, T> {
         self.processor.finish()
     }
 
     /// Starting out with a single row (at a given offset), iteratively append rows
     /// until we have exhausted the rows or the latch expression (if available) evaluates to 1.
-    pub fn run(&mut self, is_main_run: bool) -> EvalValue<&'a AlgebraicReference, T> {
+    /// Only returns an error if errors are returned (e.g. in validate-only mode) or if
+    /// one of the limits is exceeded, otherwise failures lead to a panic.
+    pub fn run(
+        &mut self,
+        is_main_run: bool,
+    ) -> Result<EvalValue<&'a AlgebraicReference, T>, EvalError<T>> {
         assert!(self.processor.len() == 1);
 
+        if self.direction == SolvingDirection::Backward {
+            return self.run_backward(is_main_run);
+        }
+
         if is_main_run {
             log::info!("Running main machine for {} rows", self.fixed_data.degree);
             self.progress_bar.reset();
@@ -129,18 +385,50 @@ impl<'a, 'b, 'c, T: FieldElement, Q: QueryCallback<T>> VmProcessor<'a, 'b, 'c, T
         } else {
             log::Level::Debug
         };
-        let rows_left = self.fixed_data.degree - self.row_offset + 1;
+        let rows_left = self.end_row - self.row_offset + 1;
         let mut finalize_start = 1;
         for row_index in 0..rows_left {
             if is_main_run {
                 self.maybe_log_performance(row_index);
             }
+            if row_index > 0 {
+                // The previous row is not modified anymore.
+                self.maybe_observe_row(row_index - 1);
+            }
+            if let Some((machine_name, progress)) = &mut self.progress {
+                progress.row(
+                    machine_name,
+                    row_index + self.row_offset,
+                    self.fixed_data.degree,
+                );
+            }
+            self.maybe_write_checkpoint(row_index);
+            self.check_deadline(row_index)?;
 
             if (row_index + 1) % 10000 == 0 {
                 // Periodically make sure most rows are finalized.
                 // Row 0 and the last MAX_PERIOD rows might be needed later, so they are not finalized.
                 let finalize_end = row_index as usize - MAX_PERIOD;
-                self.processor.finalize_range(finalize_start..finalize_end);
+                if self.validate_only {
+                    // Row 0 is needed to check the wrap-around, so we keep a copy of it.
+                    self.first_row
+                        .get_or_insert_with(|| self.processor.known_values(0));
+                    self.processor.discard_until(finalize_end);
+                } else {
+                    self.processor.finalize_range(finalize_start..finalize_end);
+                    let end =
+                        match &self.checkpoints {
+                            Some(checkpoints) => finalize_end
+                                .min(checkpoints.rows_written().saturating_sub(self.row_offset)
+                                    as usize),
+                            None => finalize_end,
+                        };
+                    if let Some(row_sender) = self.row_sender {
+                        self.processor.send_until(end, row_sender);
+                    } else if let Some(spill) = self.spill {
+                        self.processor.spill_until(end, spill);
+                    }
+                }
                 finalize_start = finalize_end;
             }
 
@@ -162,7 +450,7 @@ impl<'a, 'b, 'c, T: FieldElement, Q: QueryCallback<T>> VmProcessor<'a, 'b, 'c, T
             }
             if let Some(period) = looping_period {
                 let proposed_row = self.processor.row(row_index as usize - period).clone();
-                if !self.try_proposed_row(row_index, proposed_row) {
+                if !self.try_proposed_row(row_index, proposed_row)? {
                     log::log!(
                         loop_detection_log_level,
                         "Looping failed. Trying to generate regularly again. (Use RUST_LOG=debug to see whether this happens more often.) {row_index} {rows_left}"
@@ -178,40 +466,363 @@ impl<'a, 'b, 'c, T: FieldElement, Q: QueryCallback<T>> VmProcessor<'a, 'b, 'c, T
             // add and compute some values for the next row as well.
             if looping_period.is_none() && row_index != rows_left - 1 {
                 self.ensure_has_next_row(row_index);
-                outer_assignments.extend(self.compute_row(row_index).into_iter());
+                outer_assignments.extend(self.compute_row(row_index)?.into_iter());
+                self.check_progress(row_index)?;
+
+                if is_main_run && self.program_has_ended(row_index) {
+                    self.pad_rows(row_index)?;
+                    break;
+                }
 
                 // Evaluate latch expression and return if it evaluates to 1.
                 if let Some(latch) = self.processor.latch_value(row_index as usize) {
                     if latch {
                         log::trace!("Machine returns!");
                         if self.processor.finshed_outer_query() {
-                            return EvalValue::complete(outer_assignments);
+                            return Ok(EvalValue::complete(outer_assignments));
                         } else {
-                            return EvalValue::incomplete_with_constraints(
+                            return Ok(EvalValue::incomplete_with_constraints(
                                 outer_assignments,
                                 IncompleteCause::BlockMachineLookupIncomplete,
-                            );
+                            ));
                         }
                     }
                 } else if self.processor.has_outer_query() {
                     // If we have an outer query (and therefore a latch expression),
                     // its value should be known at this point.
                     // Probably, we don't have all the necessary inputs.
-                    return EvalValue::incomplete(IncompleteCause::UnknownLatch);
+                    return Ok(EvalValue::incomplete(
+                        IncompleteCause::UnknownLatch.combine(IncompleteCause::PendingUnknowns(
+                            self.pending_unknowns(row_index),
+                        )),
+                    ));
                 }
             };
         }
 
         assert_eq!(
             self.processor.len() as DegreeType + self.row_offset,
-            self.fixed_data.degree + 1
+            self.end_row + 1
         );
 
         if is_main_run {
             self.progress_bar.finish();
         }
+        if self.validate_only || (is_main_run && self.wraps_around()) {
+            self.check_wrap_around()?;
+        }
+
+        Ok(EvalValue::complete(outer_assignments))
+    }
+
+    /// Solves all rows from the last to the first row, see [SolvingDirection::Backward].
+    fn run_backward(
+        &mut self,
+        is_main_run: bool,
+    ) -> Result<EvalValue<&'a AlgebraicReference, T>, EvalError<T>> {
+        assert!(
+            !self.processor.has_outer_query()
+                && self.row_offset == 0
+                && self.end_row == self.fixed_data.degree,
+            "Solving backward is only supported for machines that are run on all rows without arguments."
+        );
+        assert!(
+            self.checkpoints.is_none() && self.row_observer.is_none() && self.cell_sender.is_none(),
+            "Checkpoints, row observers and cell senders are not supported when solving backward."
+        );
+        let degree = self.fixed_data.degree;
+        if is_main_run {
+            log::info!("Running main machine backward for {degree} rows");
+            self.progress_bar.reset();
+            self.progress_bar.set_message("Starting...");
+            self.progress_bar.tick();
+        }
+
+        // The last row is the first row again, which is merged with the first row later.
+        for row_index in 1..=degree {
+            self.processor
+                .set_row(row_index as usize, self.row_factory.fresh_row(row_index));
+        }
+        for (rows_done, row_index) in (0..degree).rev().enumerate() {
+            let rows_done = rows_done as DegreeType;
+            if is_main_run {
+                self.maybe_log_performance(rows_done);
+            }
+            if let Some((machine_name, progress)) = &mut self.progress {
+                progress.row(machine_name, rows_done, degree);
+            }
+            self.check_deadline(row_index)?;
+            // Since there is no outer query, there are no outer assignments.
+            self.compute_row(row_index)?;
+            self.check_progress(row_index)?;
+        }
+
+        if is_main_run {
+            self.progress_bar.finish();
+        }
+        if self.validate_only || (is_main_run && self.wraps_around()) {
+            self.check_wrap_around()?;
+        }
+        Ok(EvalValue::complete(vec![]))
+    }
+
+    /// Returns true if the rows solved are all rows of the trace, starting with row 0,
+    /// so that the last row is the first row again.
+    fn wraps_around(&self) -> bool {
+        self.row_offset == 0 && self.end_row == self.fixed_data.degree
+    }
+
+    /// Checks that the first row and the last row (which is the first row again)
+    /// agree on all known cells.
+    fn check_wrap_around(&self) -> Result<(), EvalError<T>> {
+        let (first_values, first_known) = self
+            .first_row
+            .clone()
+            .unwrap_or_else(|| self.processor.known_values(0));
+        let (last_values, last_known) = self.processor.known_values(self.processor.len() - 1);
+        let conflicts = izip!(
+            self.processor.column_ids(),
+            first_values.iter().zip(&first_known),
+            last_values.iter().zip(&last_known)
+        )
+        .filter(|(_, (v1, k1), (v2, k2))| *k1 && *k2 && v1 != v2)
+        .map(|(id, (v1, _), (v2, _))| {
+            format!(
+                "Column {} is {v1} in the first row, but {v2} after wrapping around.",
+                self.fixed_data.column_name(id)
+            )
+            .into()
+        })
+        .collect::<Vec<_>>();
+        if conflicts.is_empty() {
+            Ok(())
+        } else {
+            Err(EvalError::RowFailed {
+                row: self.row_offset,
+                errors: conflicts,
+            })
+        }
+    }
+
+    /// Returns an error if the cancellation flag is set or the deadline has passed.
+    /// To keep it cheap, this is only checked every [DEADLINE_CHECK_FREQUENCY] rows.
+    fn check_deadline(&self, row_index: DegreeType) -> Result<(), EvalError<T>> {
+        if row_index % DEADLINE_CHECK_FREQUENCY != 0 {
+            return Ok(());
+        }
+        if let Some(cancellation) = self.limits.cancellation {
+            if cancellation.load(Ordering::Relaxed) {
+                return Err(EvalError::Cancelled {
+                    row: row_index + self.row_offset,
+                });
+            }
+        }
+        match self.limits.deadline {
+            Some(deadline) if Instant::now() >= deadline => Err(EvalError::Timeout {
+                row: row_index + self.row_offset,
+                reason: "The deadline has passed.".to_string(),
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Returns an error if no cell could be determined in the given row and the
+    /// preceding rows, for more than the maximal number of rows without progress.
+    fn check_progress(&mut self, row_index: DegreeType) -> Result<(), EvalError<T>> {
+        let Some(max_rows) = self.limits.max_rows_without_progress else {
+            return Ok(());
+        };
+        let progress = self
+            .processor
+            .row(row_index as usize)
+            .values()
+            .any(|cell| cell.value.is_known());
+        self.rows_without_progress = if progress {
+            0
+        } else {
+            log::trace!(
+                "  No cell could be determined in row {}",
+                row_index + self.row_offset
+            );
+            self.rows_without_progress + 1
+        };
+        if self.rows_without_progress > max_rows {
+            log::debug!(
+                "Stall detected in row {}: No cell could be determined in the last {max_rows} rows.",
+                row_index + self.row_offset
+            );
+            Err(EvalError::Timeout {
+                row: row_index + self.row_offset,
+                reason: format!("No cell could be determined in the last {max_rows} rows."),
+            })
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Passes the values of the given row to the row observer and the cell sender,
+    /// if there are any.
+    fn maybe_observe_row(&mut self, row_index: DegreeType) {
+        if self.row_observer.is_none() && self.cell_sender.is_none() {
+            return;
+        }
+        let row = row_index + self.row_offset;
+        let (row_values, known) = self.processor.known_values(row_index as usize);
+        if let Some(sender) = self.cell_sender {
+            for (id, value, known) in izip!(self.processor.column_ids(), &row_values, known.iter())
+            {
+                // The receiver might have stopped listening, which is not an error.
+                if known && sender.send((*id, row, *value)).is_err() {
+                    break;
+                }
+            }
+        }
+        if let Some((row_observer, values)) = &mut self.row_observer {
+            for (id, value, known) in izip!(self.processor.column_ids(), row_values, known.iter()) {
+                values[id] = known.then_some(value);
+            }
+            row_observer(row, values);
+        }
+    }
+
+    /// Writes all rows before the given row to the checkpoint writer, if a checkpoint is due.
+    fn maybe_write_checkpoint(&mut self, row_index: DegreeType) {
+        let Some(checkpoints) = &mut self.checkpoints else {
+            return;
+        };
+        let row = row_index + self.row_offset;
+        if !checkpoints.is_due(row) {
+            return;
+        }
+        let rows = (checkpoints.rows_written()..row)
+            .map(|r| {
+                let i = (r - self.row_offset) as usize;
+                let (values, known_cells) = self.processor.known_values(i);
+                CheckpointRow {
+                    values,
+                    known_cells,
+                    range_constraints: self.processor.range_constraints(i),
+                }
+            })
+            .collect::<Vec<_>>();
+        let column_names = || {
+            self.processor
+                .column_ids()
+                .iter()
+                .map(|id| self.fixed_data.column_name(id).to_string())
+                .collect()
+        };
+        // Solving continues from the last written row, which already counts in
+        // `rows_without_progress` if no cell of it could be determined.
+        checkpoints
+            .write_rows(
+                column_names,
+                &rows,
+                &std::mem::take(&mut self.calls),
+                self.rows_without_progress.saturating_sub(1),
+            )
+            .unwrap_or_else(|e| panic!("Failed to write checkpoint at row {row}: {e}"));
+        log::debug!("Wrote checkpoint at row {row}");
+    }
+
+    /// Returns true if padding is enabled and the given row is equal to the row
+    /// before it, which means that the program has ended.
+    fn program_has_ended(&self, row_index: DegreeType) -> bool {
+        self.padding.is_some()
+            && self.wraps_around()
+            && row_index > 0
+            && self
+                .processor
+                .row(row_index as usize - 1)
+                .values()
+                .zip(self.processor.row(row_index as usize).values())
+                .all(|(a, b)| a.value == b.value)
+    }
 
-        EvalValue::complete(outer_assignments)
+    /// Replaces the rows from `start` up to the last row by padding rows, computed from
+    /// the row before `start`. Each padding row is checked against the identities, the
+    /// last one also against the first row, which follows it after wrapping around.
+    fn pad_rows(&mut self, start: DegreeType) -> Result<(), EvalError<T>> {
+        let padding = self.padding.unwrap();
+        let last_program_row = self.processor.row(start as usize - 1).clone();
+        // The index of the last row, which is the first row again.
+        let wrap_row = self.end_row - self.row_offset;
+        log::info!(
+            "Program ended in row {}, padding the remaining {} rows",
+            start - 1 + self.row_offset,
+            wrap_row - start
+        );
+        self.program_end = Some(start + self.row_offset);
+        for row_index in start..wrap_row {
+            let row = self.padding_row(padding, &last_program_row, row_index);
+            self.check_padding_row(row_index, &row, true)?;
+            self.processor.set_row(row_index as usize, row);
+            self.maybe_observe_row(row_index);
+        }
+        // Identities that do not reference the next row have already been checked
+        // on the first row.
+        let first_row = self.processor.row(0).clone();
+        self.check_padding_row(wrap_row, &first_row, false)?;
+        self.processor.set_row(wrap_row as usize, first_row);
+        Ok(())
+    }
+
+    fn padding_row(
+        &self,
+        padding: PaddingStrategy<'a, T>,
+        last_program_row: &Row<'a, T>,
+        row_index: DegreeType,
+    ) -> Row<'a, T> {
+        let row = row_index + self.row_offset;
+        if let PaddingStrategy::RepeatLastRow = padding {
+            return last_program_row.clone();
+        }
+        let mut padding_row = self.row_factory.fresh_row(row);
+        for poly_id in &self.witnesses {
+            let value = match padding {
+                PaddingStrategy::Custom(padding_function) => padding_function(
+                    self.fixed_data.column_name(poly_id),
+                    row,
+                    last_program_row[poly_id].value.unwrap_or_default(),
+                ),
+                _ => T::zero(),
+            };
+            padding_row[poly_id].value = CellValue::Known(value);
+        }
+        padding_row
+    }
+
+    /// Checks that the identities hold on the padding row (if `check_row` is set) and
+    /// between the row before it and the padding row.
+    fn check_padding_row(
+        &mut self,
+        row_index: DegreeType,
+        padding_row: &Row<'a, T>,
+        check_row: bool,
+    ) -> Result<(), EvalError<T>> {
+        let identities_with_next_ref = self.identities_with_next_ref.iter().map(|i| (*i, true));
+        let identities_without_next_ref = self
+            .identities_without_next_ref
+            .iter()
+            .filter(|_| check_row)
+            .map(|i| (*i, false));
+        let errors = identities_with_next_ref
+            .chain(identities_without_next_ref)
+            .filter(|(identity, has_next_reference)| {
+                !self.processor.check_row_pair(
+                    row_index as usize,
+                    padding_row,
+                    identity,
+                    *has_next_reference,
+                )
+            })
+            .map(|(identity, _)| format!("The padding row does not satisfy {identity}").into())
+            .collect::<Vec<_>>();
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(self.row_failed(row_index, errors))
+        }
     }
 
     /// Checks if the last rows are repeating and returns the period.
@@ -243,7 +854,10 @@ impl<'a, 'b, 'c, T: FieldElement, Q: QueryCallback<T>> VmProcessor<'a, 'b, 'c, T
         }
     }
 
-    fn compute_row(&mut self, row_index: DegreeType) -> Constraints<&'a AlgebraicReference, T> {
+    fn compute_row(
+        &mut self,
+        row_index: DegreeType,
+    ) -> Result<Constraints<&'a AlgebraicReference, T>, EvalError<T>> {
         log::trace!(
             "===== Starting to process row: {}",
             row_index + self.row_offset
@@ -256,16 +870,23 @@ impl<'a, 'b, 'c, T: FieldElement, Q: QueryCallback<T>> VmProcessor<'a, 'b, 'c, T
             CompletableIdentities::new(self.identities_without_next_ref.iter().cloned());
         let mut identities_with_next_ref =
             CompletableIdentities::new(self.identities_with_next_ref.iter().cloned());
-        let outer_assignments = self
-            .loop_until_no_progress(row_index, &mut identities_without_next_ref)
-            .and_then(|outer_assignments| {
-                Ok(outer_assignments
-                    .into_iter()
-                    .chain(self.loop_until_no_progress(row_index, &mut identities_with_next_ref)?)
-                    .collect::<Vec<_>>())
-            })
-            .map_err(|e| self.report_failure_and_panic_unsatisfiable(row_index, e))
-            .unwrap();
+        let mut outer_assignments = self.solve_row(
+            row_index,
+            &mut identities_without_next_ref,
+            &mut identities_with_next_ref,
+        )?;
+        let incomplete = identities_without_next_ref
+            .incomplete()
+            .chain(identities_with_next_ref.incomplete())
+            .next()
+            .is_some();
+        if incomplete && self.look_ahead(row_index) {
+            outer_assignments.extend(self.solve_row(
+                row_index,
+                &mut identities_without_next_ref,
+                &mut identities_with_next_ref,
+            )?);
+        }
 
         // Check that the computed row is "final" by asserting that all unknown values can
         // be set to 0.
@@ -287,8 +908,26 @@ impl<'a, 'b, 'c, T: FieldElement, Q: QueryCallback<T>> VmProcessor<'a, 'b, 'c, T
                     UnknownStrategy::Zero,
                 )
             })
-            .map_err(|e| self.report_failure_and_panic_underconstrained(row_index, e))
-            .unwrap();
+            .map_err(|e| {
+                let stuck_identities = identities_without_next_ref
+                    .incomplete()
+                    .chain(identities_with_next_ref.incomplete())
+                    .map(|identity| self.stuck_identity(row_index, identity))
+                    .collect::<Vec<_>>();
+                if self.return_errors {
+                    let unanswered_queries = self.processor.unanswered_queries(row_index as usize);
+                    self.row_failed(
+                        row_index,
+                        unanswered_queries
+                            .into_iter()
+                            .chain(stuck_identities)
+                            .chain(e)
+                            .collect(),
+                    )
+                } else {
+                    self.report_failure_and_panic_underconstrained(row_index, e, stuck_identities)
+                }
+            })?;
         }
 
         log::trace!(
@@ -303,7 +942,70 @@ impl<'a, 'b, 'c, T: FieldElement, Q: QueryCallback<T>> VmProcessor<'a, 'b, 'c, T
             )
         );
 
-        outer_assignments
+        self.rows_solved += 1;
+        Ok(outer_assignments)
+    }
+
+    /// Goes over the identities that don't reference the next row and then over those that
+    /// do, each until no more progress is made.
+    fn solve_row(
+        &mut self,
+        row_index: DegreeType,
+        identities_without_next_ref: &mut CompletableIdentities<'a, T>,
+        identities_with_next_ref: &mut CompletableIdentities<'a, T>,
+    ) -> Result<Constraints<&'a AlgebraicReference, T>, EvalError<T>> {
+        self.loop_until_no_progress(row_index, identities_without_next_ref)
+            .and_then(|outer_assignments| {
+                Ok(outer_assignments
+                    .into_iter()
+                    .chain(self.loop_until_no_progress(row_index, identities_with_next_ref)?)
+                    .collect::<Vec<_>>())
+            })
+            .map_err(|e| {
+                if self.return_errors {
+                    self.row_failed(row_index, e)
+                } else {
+                    self.report_failure_and_panic_unsatisfiable(row_index, e)
+                }
+            })
+    }
+
+    /// Processes the polynomial identities of the rows after `row_index` within the
+    /// window (see [VmProcessor::with_window_size]) until no more progress is made.
+    /// Lookups and queries are not processed, so that machines and the query callback
+    /// are still called in the order of the rows. Errors are ignored, they are reported
+    /// once the rows are solved.
+    /// Returns true if a cell became known.
+    fn look_ahead(&mut self, row_index: DegreeType) -> bool {
+        // The next row of the last row we process is the last row we can solve.
+        let last_row = (row_index + self.window_size as DegreeType)
+            .saturating_sub(2)
+            .min(self.end_row - self.row_offset - 1);
+        if self.direction != SolvingDirection::Forward || last_row <= row_index {
+            return false;
+        }
+        let identities = self
+            .identities_without_next_ref
+            .iter()
+            .chain(&self.identities_with_next_ref)
+            .filter(|identity| identity.kind == IdentityKind::Polynomial)
+            .cloned()
+            .collect::<Vec<_>>();
+        let mut progress = false;
+        loop {
+            let mut round_progress = false;
+            for row in row_index + 1..=last_row {
+                self.ensure_has_next_row(row);
+                let mut identities = CompletableIdentities::new(identities.iter().cloned());
+                round_progress |= self
+                    .process_identities(row, &mut identities, UnknownStrategy::Unknown)
+                    .unwrap_or(false);
+            }
+            if !round_progress {
+                return progress;
+            }
+            progress = true;
+        }
     }
 
     /// Loops over all identities and queries, until no further progress is made.
@@ -380,6 +1082,9 @@ impl<'a, 'b, 'c, T: FieldElement, Q: QueryCallback<T>> VmProcessor<'a, 'b, 'c, T
 
             match result {
                 Ok(res) => {
+                    if res.is_complete && is_machine_call {
+                        self.record_call(identity, row_index);
+                    }
                     *is_complete = res.is_complete;
                     progress |= res.progress;
                 }
@@ -396,6 +1101,81 @@ impl<'a, 'b, 'c, T: FieldElement, Q: QueryCallback<T>> VmProcessor<'a, 'b, 'c, T
         }
     }
 
+    /// Records that the given row called into another machine through the identity, so
+    /// that the call can be replayed when resuming from a checkpoint.
+    fn record_call(&mut self, identity: &'a Identity<Expression<T>>, row_index: DegreeType) {
+        if self.checkpoints.is_some() {
+            self.calls.push((identity.id, row_index + self.row_offset));
+        }
+    }
+
+    /// Returns the witness cells referenced by the identity (on the given row) that are
+    /// not known yet.
+    fn unknown_cells(
+        &self,
+        row_index: DegreeType,
+        identity: &'a Identity<Expression<T>>,
+    ) -> Vec<AlgebraicReference> {
+        let mut unknown_cells: Vec<AlgebraicReference> = vec![];
+        identity.pre_visit_expressions(&mut |expr| {
+            if let Expression::Reference(poly) = expr {
+                let row = row_index as usize + poly.next as usize;
+                if poly.is_witness()
+                    && !self.processor.row(row)[&poly.poly_id].value.is_known()
+                    && !unknown_cells.contains(poly)
+                {
+                    unknown_cells.push(poly.clone());
+                }
+            }
+        });
+        unknown_cells
+    }
+
+    /// Returns an error describing an identity that could not be completed in the given row,
+    /// including the cells it references that are still unknown.
+    fn stuck_identity(
+        &self,
+        row_index: DegreeType,
+        identity: &'a Identity<Expression<T>>,
+    ) -> EvalError<T> {
+        EvalError::IdentityStuck {
+            row: row_index + self.row_offset,
+            identity: identity.to_string(),
+            source: identity.source.clone(),
+            unknown_cells: self.unknown_cells(row_index, identity),
+        }
+    }
+
+    /// Returns the cells of the given row (and the next row) that are still unknown,
+    /// each with the identities that reference it, in the order of the identities.
+    fn pending_unknowns(&self, row_index: DegreeType) -> Vec<PendingUnknown> {
+        let mut pending: Vec<PendingUnknown> = vec![];
+        for identity in self
+            .identities_without_next_ref
+            .iter()
+            .chain(&self.identities_with_next_ref)
+            .copied()
+        {
+            for cell in self.unknown_cells(row_index, identity) {
+                match pending.iter_mut().find(|p| p.cell == cell) {
+                    Some(p) => p.identities.push(identity.to_string()),
+                    None => pending.push(PendingUnknown {
+                        cell,
+                        identities: vec![identity.to_string()],
+                    }),
+                }
+            }
+        }
+        pending
+    }
+
+    fn row_failed(&self, row_index: DegreeType, errors: Vec<EvalError<T>>) -> EvalError<T> {
+        EvalError::RowFailed {
+            row: row_index + self.row_offset,
+            errors,
+        }
+    }
+
     fn report_failure_and_panic_unsatisfiable(
         &self,
         row_index: DegreeType,
@@ -438,11 +1218,36 @@ impl<'a, 'b, 'c, T: FieldElement, Q: QueryCallback<T>> VmProcessor<'a, 'b, 'c, T
         &self,
         row_index: DegreeType,
         failures: Vec<EvalError<T>>,
+        stuck_identities: Vec<EvalError<T>>,
     ) -> ! {
         log::error!(
             "\nError: Row {} failed. Set RUST_LOG=debug for more information.\n",
             row_index + self.row_offset
         );
+        log::error!(
+            "The following identities could not be completed:\n{}\n",
+            stuck_identities
+                .iter()
+                .map(|e| indent(&e.to_string(), "    "))
+                .join("\n")
+        );
+        let unanswered_queries = self.processor.unanswered_queries(row_index as usize);
+        if !unanswered_queries.is_empty() {
+            log::error!(
+                "The following queries were not answered:\n{}\n",
+                unanswered_queries
+                    .iter()
+                    .map(|e| indent(&e.to_string(), "    "))
+                    .join("\n")
+            );
+        }
+        log::error!(
+            "The following cells could not be determined:\n{}\n",
+            self.pending_unknowns(row_index)
+                .iter()
+                .map(|p| indent(&p.to_string(), "    "))
+                .join("\n")
+        );
         let row_index = row_index as usize;
 
         log::debug!("Some columns could not be determined, but setting them to zero does not satisfy the constraints. This typically means that the system is underconstrained!");
@@ -476,14 +1281,40 @@ impl<'a, 'b, 'c, T: FieldElement, Q: QueryCallback<T>> VmProcessor<'a, 'b, 'c, T
     /// Verifies the proposed values for the next row.
     /// TODO this is bad for machines because we might introduce rows in the machine that are then
     /// not used.
-    fn try_proposed_row(&mut self, row_index: DegreeType, proposed_row: Row<'a, T>) -> bool {
-        let constraints_valid = self.identities_with_next_ref.iter().all(|i| {
-            self.processor
-                .check_row_pair(row_index as usize, &proposed_row, i, true)
-        }) && self.identities_without_next_ref.iter().all(|i| {
-            self.processor
-                .check_row_pair(row_index as usize, &proposed_row, i, false)
-        });
+    fn try_proposed_row(
+        &mut self,
+        row_index: DegreeType,
+        proposed_row: Row<'a, T>,
+    ) -> Result<bool, EvalError<T>> {
+        let (processor, calls) = (&mut self.processor, &mut self.calls);
+        let record_calls = self.checkpoints.is_some();
+        let row_offset = self.row_offset;
+        let mut check = |identity: &'a Identity<Expression<T>>, has_next_reference: bool| {
+            let valid = processor.check_row_pair(
+                row_index as usize,
+                &proposed_row,
+                identity,
+                has_next_reference,
+            );
+            // Checking a lookup calls the machine it is routed to, see [VmProcessor::record_call].
+            let is_machine_call = matches!(
+                identity.kind,
+                IdentityKind::Plookup | IdentityKind::Permutation
+            );
+            if valid && is_machine_call && record_calls {
+                let row = row_index + row_offset - has_next_reference as DegreeType;
+                calls.push((identity.id, row));
+            }
+            valid
+        };
+        let constraints_valid = self
+            .identities_with_next_ref
+            .iter()
+            .all(|i| check(*i, true))
+            && self
+                .identities_without_next_ref
+                .iter()
+                .all(|i| check(*i, false));
 
         if constraints_valid {
             self.processor.set_row(row_index as usize, proposed_row);
@@ -492,9 +1323,9 @@ impl<'a, 'b, 'c, T: FieldElement, Q: QueryCallback<T>> VmProcessor<'a, 'b, 'c, T
             // If it doesn't, we re-run compute_next_row on the previous row in order to
             // correctly forward-propagate values via next references.
             self.ensure_has_next_row(row_index - 1);
-            self.compute_row(row_index - 1);
+            self.compute_row(row_index - 1)?;
         }
-        constraints_valid
+        Ok(constraints_valid)
     }
 
     fn maybe_log_performance(&mut self, row_index: DegreeType) {
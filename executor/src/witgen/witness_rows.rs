@@ -0,0 +1,270 @@
+use std::sync::mpsc::{channel, Receiver, Sender};
+
+use powdr_number::{DegreeType, FieldElement};
+
+use super::{interleave_columns, merge_machine_witnesses, RunMode, RunOutput, WitnessGenerator};
+
+/// A row of the witness, see [WitnessRows].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WitnessRow<T> {
+    /// The index of the row.
+    pub row: DegreeType,
+    /// The values of the columns in the row, in the order of [WitnessRows::column_names].
+    pub values: Vec<T>,
+}
+
+/// An iterator over the rows of a witness while it is generated, see
+/// [WitnessGenerator::generate_rows].
+/// The rows are returned in the order in which they are finalized, which is not
+/// necessarily the order of their indices.
+pub struct WitnessRows<T> {
+    names: Vec<String>,
+    rows: Receiver<WitnessRow<T>>,
+    remaining: usize,
+}
+
+impl<T: Copy> WitnessRows<T> {
+    /// The names of the columns, in the order of [WitnessRow::values].
+    pub fn column_names(&self) -> &[String] {
+        &self.names
+    }
+
+    /// Collects the rows into columns, in the order of their indices.
+    pub fn into_columns(mut self) -> Vec<(String, Vec<T>)> {
+        let mut rows = vec![None; self.remaining];
+        let names = std::mem::take(&mut self.names);
+        for WitnessRow { row, values } in self {
+            rows[row as usize] = Some(values);
+        }
+        let mut columns = names
+            .into_iter()
+            .map(|name| (name, Vec::with_capacity(rows.len())))
+            .collect::<Vec<_>>();
+        for values in rows {
+            let values = values.expect("Not all rows of the witness were generated.");
+            for ((_, column), value) in columns.iter_mut().zip(values) {
+                column.push(value);
+            }
+        }
+        columns
+    }
+}
+
+impl<T> Iterator for WitnessRows<T> {
+    type Item = WitnessRow<T>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        match self.rows.recv() {
+            Ok(row) => {
+                self.remaining -= 1;
+                Some(row)
+            }
+            // Generation failed, its error is reported by the generating thread.
+            Err(_) => {
+                self.remaining = 0;
+                None
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<T> ExactSizeIterator for WitnessRows<T> {}
+
+/// The channel through which [WitnessGenerator::generate_rows] passes the witness to the
+/// consumer: First the names of the columns and the number of rows, then the rows.
+#[derive(Clone)]
+pub(crate) struct RowChannel<T> {
+    header: Sender<(Vec<String>, usize)>,
+    rows: Sender<WitnessRow<T>>,
+}
+
+impl<T: Copy> RowChannel<T> {
+    /// Starts sending the rows of the main machine while it is solved. `positions` are
+    /// the positions of the columns (in the order of `names`) in the rows of the machine.
+    pub(crate) fn sender(
+        &self,
+        names: Vec<String>,
+        positions: Vec<usize>,
+        row_count: usize,
+    ) -> RowSender<T> {
+        // The consumer might have stopped listening, which is not an error.
+        let _ = self.header.send((names, row_count));
+        RowSender {
+            rows: self.rows.clone(),
+            positions,
+        }
+    }
+
+    /// Sends the rows of the given columns, which all have to have the same number of rows.
+    fn send_columns(&self, columns: Vec<(String, Vec<T>)>) {
+        let (names, columns): (Vec<_>, Vec<_>) = columns.into_iter().unzip();
+        assert!(
+            columns.windows(2).all(|w| w[0].len() == w[1].len()),
+            "All witness columns have to have the same number of rows."
+        );
+        let row_count = columns.first().map_or(0, |column| column.len());
+        let sender = self.sender(names, (0..columns.len()).collect(), row_count);
+        for row in 0..row_count {
+            let values = columns.iter().map(|column| column[row]).collect::<Vec<_>>();
+            sender.send(row as DegreeType, &values);
+        }
+    }
+}
+
+/// Sends the rows of the main machine as they are finalized, see [RowChannel::sender].
+pub(crate) struct RowSender<T> {
+    rows: Sender<WitnessRow<T>>,
+    positions: Vec<usize>,
+}
+
+impl<T: Copy> RowSender<T> {
+    /// Sends a row, given by the values of the columns of the machine.
+    pub(crate) fn send(&self, row: DegreeType, values: &[T]) {
+        let values = self.positions.iter().map(|p| values[*p]).collect();
+        // The consumer might have stopped listening, which is not an error.
+        let _ = self.rows.send(WitnessRow { row, values });
+    }
+}
+
+impl<'a, 'b, T: FieldElement> WitnessGenerator<'a, 'b, T> {
+    /// Like [WitnessGenerator::generate], but passes the witness row by row (with the
+    /// values in the order of [WitnessRows::column_names]) to `consume`, e.g. for
+    /// provers that consume the trace in row-major order, and returns its result.
+    /// `consume` runs on another thread while the witness is generated. If the main
+    /// machine is the only machine, its rows are passed on as soon as they are finalized,
+    /// so the first row is passed last (it is only fixed after wrapping around).
+    /// Otherwise, the rows are only complete once the other machines are finalized, so
+    /// they are passed on after solving, in the order of their indices. This is also the
+    /// case if columns are interleaved, only some columns are selected or the witness
+    /// is resumed from a checkpoint.
+    /// Panics if witness generation fails, like [WitnessGenerator::generate].
+    pub fn generate_rows<R: Send>(mut self, consume: impl FnOnce(WitnessRows<T>) -> R + Send) -> R {
+        let (header, header_receiver) = channel();
+        let (rows, rows_receiver) = channel();
+        let row_channel = RowChannel { header, rows };
+        std::thread::scope(|scope| {
+            let consumer = scope.spawn(move || {
+                // Nothing is sent if generation fails.
+                let (names, remaining) = header_receiver.recv().ok()?;
+                Some(consume(WitnessRows {
+                    names,
+                    rows: rows_receiver,
+                    remaining,
+                }))
+            });
+            let analyzed = self.analyzed;
+            let ordering = self.ordering.clone();
+            let interleavings = std::mem::take(&mut self.interleavings);
+            // Interleaved columns have more rows than the main machine.
+            if interleavings.is_empty() {
+                self.row_channel = Some(row_channel.clone());
+            }
+            match self
+                .run_machines(RunMode::Generate)
+                .unwrap_or_else(|e| panic!("Witness generation failed:\n{e}"))
+            {
+                RunOutput::Sent => {}
+                output => row_channel.send_columns(interleave_columns(
+                    merge_machine_witnesses(analyzed, &ordering, output.complete()),
+                    &interleavings,
+                )),
+            }
+            // The consumer stops once all senders are dropped.
+            drop(row_channel);
+            match consumer.join() {
+                Ok(result) => result.unwrap(),
+                Err(e) => std::panic::resume_unwind(e),
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::channel;
+    use std::time::Duration;
+
+    use powdr_number::GoldilocksField;
+    use powdr_pil_analyzer::analyze_string;
+    use pretty_assertions::assert_eq;
+    use test_log::test;
+
+    use crate::constant_evaluator;
+    use crate::witgen::tests::{generate_witness, ARRAY_SRC};
+    use crate::witgen::{unused_query_callback, ProgressEvent};
+
+    use super::*;
+
+    #[test]
+    fn generate_rows() {
+        let analyzed = analyze_string::<GoldilocksField>(ARRAY_SRC);
+        let fixed_cols = constant_evaluator::generate(&analyzed);
+        let query_callback = unused_query_callback();
+        let witness = generate_witness(ARRAY_SRC);
+        let (names, columns) = WitnessGenerator::new(&analyzed, &fixed_cols, &query_callback)
+            .generate_rows(|rows| {
+                assert_eq!(rows.len(), 8);
+                (rows.column_names().to_vec(), rows.into_columns())
+            });
+        assert_eq!(
+            names,
+            witness
+                .iter()
+                .map(|(name, _)| name.clone())
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(columns, witness);
+    }
+
+    #[test]
+    fn generate_rows_while_solving() {
+        let src = r"
+namespace main(16384);
+    col fixed FIRST = [1] + [0]*;
+    col witness x;
+    FIRST * x = 0;
+    (1 - FIRST') * (x' - x - 1) = 0;
+";
+        let analyzed = analyze_string::<GoldilocksField>(src);
+        let fixed_cols = constant_evaluator::generate(&analyzed);
+        let query_callback = unused_query_callback();
+        let (first_row_read, first_row_received) = channel();
+        // Solving only continues after the row 15000 once a row was read, so the rows
+        // finalized before have to be passed on while solving.
+        let mut progress = move |event: ProgressEvent<'_>| {
+            if let ProgressEvent::Row { row: 15000, .. } = event {
+                first_row_received
+                    .recv_timeout(Duration::from_secs(60))
+                    .expect("No row was passed on while solving.");
+            }
+        };
+        let rows = WitnessGenerator::new(&analyzed, &fixed_cols, &query_callback)
+            .with_progress_callback(&mut progress, 1)
+            .generate_rows(move |mut rows| {
+                let first = rows.next().unwrap();
+                first_row_read.send(()).unwrap();
+                std::iter::once(first).chain(rows).collect::<Vec<_>>()
+            });
+        // Rows are passed on as they are finalized, the first row last.
+        assert_eq!(rows[0].row, 1);
+        assert_eq!(rows.last().unwrap().row, 0);
+        let mut rows = rows
+            .into_iter()
+            .map(|WitnessRow { row, values }| (row, values))
+            .collect::<Vec<_>>();
+        rows.sort_by_key(|(row, _)| *row);
+        assert_eq!(
+            rows,
+            (0..16384)
+                .map(|row| (row, vec![GoldilocksField::from(row)]))
+                .collect::<Vec<_>>()
+        );
+    }
+}
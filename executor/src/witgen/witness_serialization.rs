@@ -0,0 +1,136 @@
+//! A binary format to store generated witnesses, e.g. to cache them between prover runs.
+//!
+//! The file starts with a header containing the format version and the modulus of the
+//! field, followed by the degree and the witness columns with their names.
+//! The header is checked before any field element is decoded, so loading a witness
+//! of a different field fails instead of producing wrong values.
+
+use std::io::{self, ErrorKind, Read, Write};
+
+use powdr_number::{BigInt, DegreeType, FieldElement};
+use serde::{Deserialize, Serialize};
+
+/// The version of the format, incremented on every incompatible change.
+const FORMAT_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize)]
+struct Header {
+    version: u32,
+    /// The little-endian bytes of the modulus of the field.
+    modulus: Vec<u8>,
+}
+
+impl Header {
+    fn for_field<T: FieldElement>() -> Self {
+        Self {
+            version: FORMAT_VERSION,
+            modulus: T::modulus().to_arbitrary_integer().to_bytes_le(),
+        }
+    }
+}
+
+/// Writes the witness columns (each with `degree` values) to `writer`.
+pub fn serialize_witness<T: FieldElement>(
+    writer: &mut impl Write,
+    degree: DegreeType,
+    columns: &[(String, Vec<T>)],
+) -> io::Result<()> {
+    if let Some((name, values)) = columns
+        .iter()
+        .find(|(_, values)| values.len() as DegreeType != degree)
+    {
+        return Err(io::Error::new(
+            ErrorKind::InvalidInput,
+            format!(
+                "Column {name} has {} rows, but the degree is {degree}.",
+                values.len()
+            ),
+        ));
+    }
+    bincode::serialize_into(&mut *writer, &Header::for_field::<T>()).map_err(into_io_error)?;
+    bincode::serialize_into(&mut *writer, &(degree, columns)).map_err(into_io_error)
+}
+
+/// Reads witness columns written by [serialize_witness], together with their degree.
+/// Fails if the witness was written by a different version of the format or for a
+/// different field.
+pub fn deserialize_witness<T: FieldElement>(
+    reader: &mut impl Read,
+) -> io::Result<(Vec<(String, Vec<T>)>, DegreeType)> {
+    let header: Header = bincode::deserialize_from(&mut *reader).map_err(into_io_error)?;
+    if header.version != FORMAT_VERSION {
+        return Err(io::Error::new(
+            ErrorKind::InvalidData,
+            format!(
+                "Unsupported witness format version {} (expected {FORMAT_VERSION}).",
+                header.version
+            ),
+        ));
+    }
+    if header.modulus != Header::for_field::<T>().modulus {
+        return Err(io::Error::new(
+            ErrorKind::InvalidData,
+            format!(
+                "The witness was generated for a field with modulus 0x{:x}, but the expected modulus is 0x{:x}.",
+                num_bigint::BigUint::from_bytes_le(&header.modulus),
+                T::modulus().to_arbitrary_integer()
+            ),
+        ));
+    }
+    let (degree, columns): (DegreeType, Vec<(String, Vec<T>)>) =
+        bincode::deserialize_from(&mut *reader).map_err(into_io_error)?;
+    if let Some((name, _)) = columns
+        .iter()
+        .find(|(_, values)| values.len() as DegreeType != degree)
+    {
+        return Err(io::Error::new(
+            ErrorKind::InvalidData,
+            format!("Column {name} does not have {degree} rows."),
+        ));
+    }
+    Ok((columns, degree))
+}
+
+pub(crate) fn into_io_error(error: bincode::Error) -> io::Error {
+    match *error {
+        bincode::ErrorKind::Io(error) => error,
+        error => io::Error::new(ErrorKind::InvalidData, error),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use powdr_number::{Bn254Field, GoldilocksField};
+
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let columns = vec![
+            ("main.a".to_string(), (0..8).map(Bn254Field::from).collect()),
+            (
+                "main.b".to_string(),
+                (-8..0).map(Bn254Field::from).collect(),
+            ),
+        ];
+        let mut buf = vec![];
+        serialize_witness(&mut buf, 8, &columns).unwrap();
+        let (read_columns, degree) =
+            deserialize_witness::<Bn254Field>(&mut buf.as_slice()).unwrap();
+        assert_eq!(read_columns, columns);
+        assert_eq!(degree, 8);
+    }
+
+    #[test]
+    fn mismatched_modulus() {
+        let columns = vec![(
+            "main.a".to_string(),
+            (0..4).map(GoldilocksField::from).collect(),
+        )];
+        let mut buf = vec![];
+        serialize_witness(&mut buf, 4, &columns).unwrap();
+        let err = deserialize_witness::<Bn254Field>(&mut buf.as_slice()).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        assert!(err.to_string().contains("modulus"), "{err}");
+    }
+}
@@ -0,0 +1,259 @@
+use std::fmt;
+use std::ops::{Add, Div, Mul, Neg, Sub};
+
+use num_traits::{One, Zero};
+
+use crate::{FieldElement, GoldilocksField};
+
+/// A field that has an extension of degree `D` of the form `F[X] / (X^D - W)`.
+pub trait Extendable<const D: usize>: FieldElement {
+    /// The constant such that `X^D - W` is irreducible over the field.
+    fn w() -> Self;
+}
+
+/// The field itself, as the extension `F[X] / (X - 7)`.
+impl Extendable<1> for GoldilocksField {
+    fn w() -> Self {
+        7.into()
+    }
+}
+
+/// 7 generates the multiplicative group of the Goldilocks field, so it is neither
+/// a square nor a cube, which makes `X^2 - 7` and `X^3 - 7` irreducible.
+impl Extendable<2> for GoldilocksField {
+    fn w() -> Self {
+        7.into()
+    }
+}
+
+impl Extendable<3> for GoldilocksField {
+    fn w() -> Self {
+        7.into()
+    }
+}
+
+/// An element of the extension field `T[X] / (X^D - W)` of degree `D`, see [Extendable],
+/// e.g. for columns that hold extension field elements during FRI folding.
+/// The base field is embedded as the constant polynomials.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct ExtensionFieldElement<T, const D: usize> {
+    /// The coefficients of the polynomial in `X`, lowest degree first.
+    coefficients: [T; D],
+}
+
+impl<T: Extendable<D>, const D: usize> ExtensionFieldElement<T, D> {
+    pub fn new(coefficients: [T; D]) -> Self {
+        Self { coefficients }
+    }
+
+    /// The generator `X` of the extension, which is the constant `W` for `D = 1`.
+    pub fn x() -> Self {
+        let mut coefficients = [T::zero(); D];
+        if D == 1 {
+            coefficients[0] = T::w();
+        } else {
+            coefficients[1] = T::one();
+        }
+        Self { coefficients }
+    }
+
+    pub fn coefficients(&self) -> &[T; D] {
+        &self.coefficients
+    }
+
+    /// Returns the element as an element of the base field, if it is one.
+    pub fn to_base(&self) -> Option<T> {
+        self.coefficients[1..]
+            .iter()
+            .all(|c| c.is_zero())
+            .then_some(self.coefficients[0])
+    }
+
+    /// Returns the multiplicative inverse, or `None` for zero.
+    pub fn inverse(&self) -> Option<Self> {
+        // The inverse `v` solves the linear system `self * v = 1` over the base field,
+        // whose matrix has `self * X^j` as its `j`-th column.
+        let mut columns = vec![*self];
+        for _ in 1..D {
+            columns.push(*columns.last().unwrap() * Self::x());
+        }
+        let mut rows = (0..D)
+            .map(|i| {
+                let mut row = columns
+                    .iter()
+                    .map(|c| c.coefficients[i])
+                    .collect::<Vec<_>>();
+                row.push(if i == 0 { T::one() } else { T::zero() });
+                row
+            })
+            .collect::<Vec<_>>();
+        // Gauss-Jordan elimination.
+        for col in 0..D {
+            let pivot = (col..D).find(|r| !rows[*r][col].is_zero())?;
+            rows.swap(col, pivot);
+            let factor = T::one() / rows[col][col];
+            for value in &mut rows[col] {
+                *value = *value * factor;
+            }
+            let pivot_row = rows[col].clone();
+            for (r, row) in rows.iter_mut().enumerate() {
+                if r != col && !row[col].is_zero() {
+                    let factor = row[col];
+                    for (value, pivot) in row.iter_mut().zip(&pivot_row) {
+                        *value = *value - factor * *pivot;
+                    }
+                }
+            }
+        }
+        let mut coefficients = [T::zero(); D];
+        for (coefficient, row) in coefficients.iter_mut().zip(&rows) {
+            *coefficient = row[D];
+        }
+        Some(Self { coefficients })
+    }
+}
+
+impl<T: Extendable<D>, const D: usize> From<T> for ExtensionFieldElement<T, D> {
+    fn from(value: T) -> Self {
+        let mut coefficients = [T::zero(); D];
+        coefficients[0] = value;
+        Self { coefficients }
+    }
+}
+
+impl<T: Extendable<D>, const D: usize> Zero for ExtensionFieldElement<T, D> {
+    fn zero() -> Self {
+        T::zero().into()
+    }
+
+    fn is_zero(&self) -> bool {
+        self.coefficients.iter().all(|c| c.is_zero())
+    }
+}
+
+impl<T: Extendable<D>, const D: usize> One for ExtensionFieldElement<T, D> {
+    fn one() -> Self {
+        T::one().into()
+    }
+}
+
+impl<T: Extendable<D>, const D: usize> Add for ExtensionFieldElement<T, D> {
+    type Output = Self;
+
+    fn add(mut self, rhs: Self) -> Self {
+        for (l, r) in self.coefficients.iter_mut().zip(rhs.coefficients) {
+            *l = *l + r;
+        }
+        self
+    }
+}
+
+impl<T: Extendable<D>, const D: usize> Sub for ExtensionFieldElement<T, D> {
+    type Output = Self;
+
+    fn sub(self, rhs: Self) -> Self {
+        self + -rhs
+    }
+}
+
+impl<T: Extendable<D>, const D: usize> Neg for ExtensionFieldElement<T, D> {
+    type Output = Self;
+
+    fn neg(mut self) -> Self {
+        for c in &mut self.coefficients {
+            *c = -*c;
+        }
+        self
+    }
+}
+
+impl<T: Extendable<D>, const D: usize> Mul for ExtensionFieldElement<T, D> {
+    type Output = Self;
+
+    fn mul(self, rhs: Self) -> Self {
+        let mut coefficients = [T::zero(); D];
+        for (i, l) in self.coefficients.iter().enumerate() {
+            for (j, r) in rhs.coefficients.iter().enumerate() {
+                // X^(i + j) = W * X^(i + j - D) for i + j >= D.
+                let product = *l * *r;
+                if i + j < D {
+                    coefficients[i + j] = coefficients[i + j] + product;
+                } else {
+                    coefficients[i + j - D] = coefficients[i + j - D] + T::w() * product;
+                }
+            }
+        }
+        Self { coefficients }
+    }
+}
+
+impl<T: Extendable<D>, const D: usize> Div for ExtensionFieldElement<T, D> {
+    type Output = Self;
+
+    /// Panics on division by zero.
+    fn div(self, rhs: Self) -> Self {
+        self * rhs.inverse().expect("Division by zero.")
+    }
+}
+
+impl<T: FieldElement, const D: usize> fmt::Display for ExtensionFieldElement<T, D> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.coefficients[0])?;
+        for (i, c) in self.coefficients.iter().enumerate().skip(1) {
+            match i {
+                1 => write!(f, " + {c} * X")?,
+                _ => write!(f, " + {c} * X^{i}")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use test_log::test;
+
+    use super::*;
+
+    type Base = ExtensionFieldElement<GoldilocksField, 1>;
+    type Quadratic = ExtensionFieldElement<GoldilocksField, 2>;
+    type Cubic = ExtensionFieldElement<GoldilocksField, 3>;
+
+    #[test]
+    fn generator() {
+        let seven = GoldilocksField::from(7);
+        assert_eq!(Quadratic::x() * Quadratic::x(), Quadratic::from(seven));
+        assert_eq!(Cubic::x() * Cubic::x() * Cubic::x(), Cubic::from(seven));
+        assert_eq!(Quadratic::x().to_base(), None);
+        assert_eq!(Quadratic::from(seven).to_base(), Some(seven));
+        assert_eq!(Base::x(), Base::from(seven));
+        assert_eq!(Base::x().to_base(), Some(seven));
+    }
+
+    #[test]
+    fn inverse() {
+        let a = Cubic::new([3.into(), 0.into(), 11.into()]);
+        assert_eq!(a * a.inverse().unwrap(), Cubic::one());
+        assert_eq!(Cubic::zero().inverse(), None);
+        let b = Quadratic::new([1.into(), 1.into()]);
+        assert_eq!(b * b.inverse().unwrap(), Quadratic::one());
+        let c = Base::from(GoldilocksField::from(5));
+        assert_eq!(c * c.inverse().unwrap(), Base::one());
+    }
+
+    #[test]
+    fn division() {
+        // a * x + b = 0 with coefficients in the extension field.
+        let a = Quadratic::new([2.into(), 5.into()]);
+        let b = Quadratic::new([9.into(), GoldilocksField::from(-4)]);
+        let x = -b / a;
+        assert!((a * x + b).is_zero());
+        assert_eq!(x * a, -b);
+    }
+
+    #[test]
+    fn display() {
+        let a = Cubic::new([1.into(), 0.into(), 2.into()]);
+        assert_eq!(a.to_string(), "1 + 0 * X + 2 * X^2");
+    }
+}
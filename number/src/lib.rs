@@ -5,6 +5,7 @@
 #[macro_use]
 mod macros;
 mod bn254;
+mod extension;
 mod goldilocks;
 mod serialize;
 mod traits;
@@ -14,6 +15,7 @@ pub use serialize::{
 };
 
 pub use bn254::Bn254Field;
+pub use extension::{Extendable, ExtensionFieldElement};
 pub use goldilocks::GoldilocksField;
 pub use traits::KnownField;
 
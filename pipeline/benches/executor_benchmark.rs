@@ -1,5 +1,9 @@
+use std::collections::HashMap;
+
 use ::powdr_pipeline::{inputs_to_query_callback, Pipeline};
 use powdr_ast::analyzed::Analyzed;
+use powdr_executor::constant_evaluator;
+use powdr_executor::witgen::{WitnessGenerator, DEFAULT_CACHE_CAPACITY};
 use powdr_number::{FieldElement, GoldilocksField};
 
 use powdr_pipeline::test_util::{evaluate_integer_function, std_analyzed};
@@ -18,13 +22,45 @@ fn run_witgen<T: FieldElement>(
     analyzed: &Analyzed<T>,
     constants: &[(String, Vec<T>)],
     external_witness_values: Vec<(String, Vec<T>)>,
+    fixed_lookup_cache_capacity: usize,
 ) {
     let query_callback = inputs_to_query_callback(vec![]);
     powdr_executor::witgen::WitnessGenerator::new(analyzed, constants, &query_callback)
         .with_external_witness_values(external_witness_values)
+        .with_fixed_lookup_cache_capacity(fixed_lookup_cache_capacity)
         .generate();
 }
 
+/// A program with lookups into a fixed table by two key columns, each key appearing in
+/// two rows of the table.
+const MULTI_KEY_LOOKUP: &str = r"
+namespace main(4096);
+    col fixed A(i) { i & 0x3f };
+    col fixed B(i) { (i >> 6) & 0x1f };
+    col fixed C(i) { (i & 0x3f) * ((i >> 6) & 0x1f) };
+    col fixed X(i) { (i * 7) & 0x3f };
+    col fixed Y(i) { (i * 13) & 0x1f };
+    col witness a, b, c;
+    a = X;
+    b = Y;
+    { a, b, c } in { A, B, C };
+";
+
+/// Determines the values of `main.c` in [MULTI_KEY_LOOKUP] by scanning the table for the
+/// keys of each row, which is what the index of the fixed lookup machine avoids.
+fn scan_multi_key_lookup(fixed_cols: &[(String, Vec<T>)]) -> Vec<T> {
+    let column = |name: &str| &fixed_cols.iter().find(|(n, _)| n == name).unwrap().1;
+    let (a, b, c) = (column("main.A"), column("main.B"), column("main.C"));
+    column("main.X")
+        .iter()
+        .zip(column("main.Y"))
+        .map(|(x, y)| {
+            let row = (0..a.len()).find(|row| a[*row] == *x && b[*row] == *y);
+            c[row.unwrap()]
+        })
+        .collect()
+}
+
 fn executor_benchmark(c: &mut Criterion) {
     let mut group = c.benchmark_group("executor-benchmark");
     group.sample_size(10);
@@ -45,10 +81,108 @@ fn executor_benchmark(c: &mut Criterion) {
                 &pil_with_constants.pil,
                 &pil_with_constants.fixed_cols,
                 vec![],
+                DEFAULT_CACHE_CAPACITY,
+            )
+        })
+    });
+
+    // With a machine witness callback, the secondary machines are finalized one after the
+    // other instead of in parallel.
+    group.bench_function("keccak_sequential_finalization", |b| {
+        b.iter(|| {
+            let query_callback = inputs_to_query_callback(vec![]);
+            let mut callback = |_: &str, _: HashMap<String, Vec<T>>| {};
+            WitnessGenerator::new(
+                &pil_with_constants.pil,
+                &pil_with_constants.fixed_cols,
+                &query_callback,
+            )
+            .with_machine_witness_callback(&mut callback)
+            .generate();
+        })
+    });
+
+    // The part of the analysis that is done only once by prepared generators.
+    group.bench_function("keccak_inline_intermediate_polynomials", |b| {
+        b.iter(|| {
+            pil_with_constants
+                .pil
+                .identities_with_inlined_intermediate_polynomials()
+        })
+    });
+
+    // Evaluating the fixed columns serially and evaluating the columns that do not depend
+    // on other fixed columns concurrently.
+    group.bench_function("keccak_fixed_columns", |b| {
+        b.iter(|| constant_evaluator::generate(&pil_with_constants.pil))
+    });
+    group.bench_function("keccak_fixed_columns_parallel", |b| {
+        b.iter(|| {
+            constant_evaluator::evaluate_fixed_columns_parallel(
+                &pil_with_constants.pil,
+                pil_with_constants.pil.degree(),
             )
         })
     });
 
+    // Keccak mostly consists of byte operations, whose results can be reused.
+    group.bench_function("keccak_with_pure_machine_memoization", |b| {
+        b.iter(|| {
+            let query_callback = inputs_to_query_callback(vec![]);
+            WitnessGenerator::new(
+                &pil_with_constants.pil,
+                &pil_with_constants.fixed_cols,
+                &query_callback,
+            )
+            .with_pure_machine_memoization()
+            .generate()
+        })
+    });
+
+    // Run with RUST_LOG=debug to see the hit rate of the fixed lookup cache.
+    group.bench_function("keccak_without_fixed_lookup_cache", |b| {
+        b.iter(|| {
+            run_witgen(
+                &pil_with_constants.pil,
+                &pil_with_constants.fixed_cols,
+                vec![],
+                0,
+            )
+        })
+    });
+
+    // The fixed lookup machine answers each lookup from the index of the key columns.
+    let pil_with_constants = Pipeline::<T>::default()
+        .from_pil_string(MULTI_KEY_LOOKUP.to_string())
+        .pil_with_evaluated_fixed_cols()
+        .unwrap();
+    let query_callback = inputs_to_query_callback(vec![]);
+    let witness = WitnessGenerator::new(
+        &pil_with_constants.pil,
+        &pil_with_constants.fixed_cols,
+        &query_callback,
+    )
+    .generate();
+    assert_eq!(
+        witness.iter().find(|(name, _)| name == "main.c").unwrap().1,
+        scan_multi_key_lookup(&pil_with_constants.fixed_cols)
+    );
+
+    group.bench_function("multi_key_lookup", |b| {
+        b.iter(|| {
+            run_witgen(
+                &pil_with_constants.pil,
+                &pil_with_constants.fixed_cols,
+                vec![],
+                0,
+            )
+        })
+    });
+
+    group.bench_function("multi_key_lookup_scan", |b| {
+        b.iter(|| scan_multi_key_lookup(&pil_with_constants.fixed_cols))
+    });
+
     // The first chunk of `many_chunks`, with Poseidon co-processor & bootloader
     let riscv_asm_files =
         compile_rust_to_riscv_asm("../riscv/tests/riscv_data/many_chunks.rs", &tmp_dir);
@@ -67,6 +201,7 @@ fn executor_benchmark(c: &mut Criterion) {
                     "main.bootloader_input_value".to_string(),
                     default_input(&[63, 64, 65]),
                 )],
+                DEFAULT_CACHE_CAPACITY,
             )
         })
     });